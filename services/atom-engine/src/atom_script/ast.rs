@@ -1,16 +1,33 @@
-#[derive(Debug, PartialEq, Clone)]
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum BinaryOp {
     Add,
     Sub,
     Mul,
     Div,
+    Mod,
+    // Comparisons (produce Bool)
+    Eq,
+    NotEq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expr {
     Literal(f64),
+    StringLiteral(String),
     Identifier(String),
     DimensionRef(String), // e.g. [Region]
+    // Multi-dimension reference: CELL([Region]=USA, [Time]=2024), as dimension=member pairs
+    CellRef(Vec<(String, String)>),
     Binary {
         op: BinaryOp,
         lhs: Box<Expr>,
@@ -30,6 +47,11 @@ pub enum Expr {
         lhs: Box<Expr>, // e.g. [Revenue]
         rhs: Box<Expr>, // previously [PrevMonth], now we transition to TimeModifier
     },
+    // Membership: value IN (candidate, ...)
+    In {
+        value: Box<Expr>,
+        candidates: Vec<Expr>,
+    },
     // Phase 3: Core Time Modifiers (PY, PQ, YTD, etc)
     TimeModifier {
         base: Box<Expr>, // The metric to shift: [Revenue]
@@ -37,7 +59,14 @@ pub enum Expr {
     },
 }
 
+/// A top-level input: a formula, or a `let` binding evaluated once and then usable by name.
 #[derive(Debug, PartialEq, Clone)]
+pub enum Statement {
+    Let { name: String, value: Expr },
+    Expr(Expr),
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum TimeShiftType {
     PriorYear,
     PriorQuarter,
@@ -45,3 +74,197 @@ pub enum TimeShiftType {
     QuarterToDate,
     PeriodToDate,
 }
+
+/// Structural hash of an expression: formatting (whitespace, redundant parentheses) does not
+/// affect it, since it is computed from the parsed tree. Stable within a build, not across
+/// versions, so it must not be persisted. Distinct trees can collide; callers that key on it
+/// must confirm a hit by comparing the trees.
+pub fn canonical_hash(expr: &Expr) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_expr(expr, &mut hasher);
+    hasher.finish()
+}
+
+fn hash_expr(expr: &Expr, h: &mut impl Hasher) {
+    std::mem::discriminant(expr).hash(h);
+    match expr {
+        Expr::Literal(val) => val.to_bits().hash(h),
+        Expr::StringLiteral(s) | Expr::Identifier(s) | Expr::DimensionRef(s) => s.hash(h),
+        Expr::CellRef(pairs) => pairs.hash(h),
+        Expr::Binary { op, lhs, rhs } => {
+            op.hash(h);
+            hash_expr(lhs, h);
+            hash_expr(rhs, h);
+        }
+        Expr::FunctionCall { name, args } | Expr::HierarchyCall { name, args } => {
+            name.hash(h);
+            args.len().hash(h);
+            args.iter().for_each(|arg| hash_expr(arg, h));
+        }
+        Expr::TimeTravel { lhs, rhs } => {
+            hash_expr(lhs, h);
+            hash_expr(rhs, h);
+        }
+        Expr::In { value, candidates } => {
+            hash_expr(value, h);
+            candidates.len().hash(h);
+            candidates.iter().for_each(|c| hash_expr(c, h));
+        }
+        Expr::TimeModifier { base, shift_type } => {
+            shift_type.hash(h);
+            hash_expr(base, h);
+        }
+    }
+}
+
+/// Byte ranges of the source each parsed expression came from, recorded by
+/// `Parser::parse_with_spans` for runtime error locations (see `Compiler::with_source_map`).
+/// Spans belong to nodes of the parsed tree, by preorder position, so repeated identical
+/// subexpressions each keep their own span.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    spans: Vec<Option<Range<usize>>>, // Indexed by position in `preorder(root)`
+}
+
+impl SourceMap {
+    /// Span of the node at `index` in `preorder` of the parsed tree (the root is 0).
+    pub fn span_at(&self, index: usize) -> Option<Range<usize>> {
+        self.spans.get(index).cloned().flatten()
+    }
+
+    /// Assigns `recorded` spans, given as (`canonical_hash`, span) in the order the parser
+    /// finished each expression, to the nodes of `root`. A node's span is the last matching
+    /// record that lies within its parent's span and before its right sibling's, so the
+    /// re-record of a parenthesized expression or a desugared copy is never mistaken for a
+    /// different occurrence. Nodes the parser synthesized (desugaring) may get no span.
+    pub(crate) fn from_recorded(root: &Expr, recorded: &[(u64, Range<usize>)]) -> Self {
+        let nodes = preorder(root);
+        let ids: HashMap<*const Expr, usize> = nodes.iter().enumerate().map(|(i, node)| (*node as *const Expr, i)).collect();
+        let mut map = Self { spans: vec![None; nodes.len()] };
+        map.assign(root, recorded, recorded.len(), 0..usize::MAX, &ids);
+        map
+    }
+
+    // Walks right to left, so each node searches backwards from where its right sibling (or
+    // its parent) was recorded. Returns the earliest record the subtree used.
+    fn assign(&mut self, node: &Expr, recorded: &[(u64, Range<usize>)], mut before: usize, mut within: Range<usize>, ids: &HashMap<*const Expr, usize>) -> usize {
+        let hash = canonical_hash(node);
+        let found = recorded[..before]
+            .iter()
+            .rposition(|(h, span)| *h == hash && within.start <= span.start && span.end <= within.end);
+        if let Some(index) = found {
+            let span = recorded[index].1.clone();
+            self.spans[ids[&(node as *const Expr)]] = Some(span.clone());
+            before = index;
+            within = span;
+        }
+        for child in children(node).into_iter().rev() {
+            before = self.assign(child, recorded, before, within.clone(), ids);
+            if let Some(span) = &self.spans[ids[&(child as *const Expr)]] {
+                within.end = span.start;
+            }
+        }
+        before
+    }
+}
+
+/// Direct subexpressions of `expr`, left to right.
+pub fn children(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Literal(_) | Expr::StringLiteral(_) | Expr::Identifier(_) | Expr::DimensionRef(_) | Expr::CellRef(_) => Vec::new(),
+        Expr::Binary { lhs, rhs, .. } | Expr::TimeTravel { lhs, rhs } => vec![&**lhs, &**rhs],
+        Expr::FunctionCall { args, .. } | Expr::HierarchyCall { args, .. } => args.iter().collect(),
+        Expr::In { value, candidates } => std::iter::once(&**value).chain(candidates).collect(),
+        Expr::TimeModifier { base, .. } => vec![&**base],
+    }
+}
+
+/// Every node of `expr` in preorder: the node itself, then each child's subtree left to right.
+pub fn preorder(expr: &Expr) -> Vec<&Expr> {
+    let mut nodes = Vec::new();
+    let mut pending = vec![expr];
+    while let Some(node) = pending.pop() {
+        nodes.push(node);
+        pending.extend(children(node).into_iter().rev());
+    }
+    nodes
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Eq => "==",
+            BinaryOp::NotEq => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Lte => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Gte => ">=",
+        };
+        f.write_str(symbol)
+    }
+}
+
+fn write_args(f: &mut fmt::Formatter<'_>, args: &[Expr]) -> fmt::Result {
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}", arg)?;
+    }
+    Ok(())
+}
+
+/// Renders the expression back to AtomScript source. Compound expressions are fully
+/// parenthesized, so the output re-parses to the same tree.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Literal(val) => write!(f, "{}", val),
+            Expr::StringLiteral(text) => write!(f, "\"{}\"", text),
+            Expr::Identifier(name) => write!(f, "{}", name),
+            Expr::DimensionRef(name) => write!(f, "[{}]", name),
+            Expr::CellRef(pairs) => {
+                f.write_str("CELL(")?;
+                for (i, (dimension, member)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "[{}]=[{}]", dimension, member)?;
+                }
+                f.write_str(")")
+            }
+            Expr::Binary { op, lhs, rhs } => write!(f, "({} {} {})", lhs, op, rhs),
+            Expr::FunctionCall { name, args } => {
+                write!(f, "{}(", name)?;
+                write_args(f, args)?;
+                f.write_str(")")
+            }
+            Expr::HierarchyCall { name, args } => {
+                write!(f, "@{}(", name)?;
+                write_args(f, args)?;
+                f.write_str(")")
+            }
+            Expr::TimeTravel { lhs, rhs } => write!(f, "({} -> {})", lhs, rhs),
+            Expr::In { value, candidates } => {
+                write!(f, "({} IN (", value)?;
+                write_args(f, candidates)?;
+                f.write_str("))")
+            }
+            Expr::TimeModifier { base, shift_type } => {
+                let keyword = match shift_type {
+                    TimeShiftType::PriorYear => "PY",
+                    TimeShiftType::PriorQuarter => "PQ",
+                    TimeShiftType::YearToDate => "YTD",
+                    TimeShiftType::QuarterToDate => "QTD",
+                    TimeShiftType::PeriodToDate => "PTD",
+                };
+                write!(f, "{}({})", keyword, base)
+            }
+        }
+    }
+}
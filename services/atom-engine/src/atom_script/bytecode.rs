@@ -0,0 +1,473 @@
+//! Compact byte encoding of a chunk's code (`Chunk::to_bytecode`): one tag byte per
+//! instruction followed by its operands as LEB128 varints, so the small pool indices and
+//! counts formulas use take one byte each instead of the fixed-size slots of `OpCode`.
+//! `OpCode` stays the compiler's IR; the bytes are for shipping plans and for the VM's byte
+//! dispatch (`VM::with_bytecode`).
+//!
+//! Jump targets are byte offsets written as fixed 4-byte little-endian integers, so every
+//! instruction's offset is known before any target is encoded.
+
+use thiserror::Error;
+use crate::atom_script::chunk::{AggregateKind, Chunk, DatePartKind, MatchMode, OpCode, SearchMode};
+
+/// Why `Chunk::decode_bytecode` rejected a byte string.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum BytecodeError {
+    #[error("byte {at}: instruction is truncated")]
+    Truncated { at: usize },
+    #[error("byte {at}: unknown opcode {tag:#04x}")]
+    UnknownOpcode { at: usize, tag: u8 },
+    #[error("byte {at}: operand out of range")]
+    BadOperand { at: usize },
+    #[error("byte {at}: jump target {target} is not an instruction boundary")]
+    BadJumpTarget { at: usize, target: usize },
+}
+
+const JUMP_WIDTH: usize = 4;
+
+mod tag {
+    pub const RETURN: u8 = 0;
+    pub const CONSTANT: u8 = 1;
+    pub const LOAD_DIMENSION: u8 = 2;
+    pub const ERROR_CONSTANT: u8 = 3;
+    pub const ADD: u8 = 4;
+    pub const SUB: u8 = 5;
+    pub const MUL: u8 = 6;
+    pub const DIV: u8 = 7;
+    pub const MOD: u8 = 8;
+    pub const RATIO: u8 = 9;
+    pub const NEGATE: u8 = 10;
+    pub const SQRT: u8 = 11;
+    pub const EXP: u8 = 12;
+    pub const LN: u8 = 13;
+    pub const LOG: u8 = 14;
+    pub const EQUAL: u8 = 15;
+    pub const NOT_EQUAL: u8 = 16;
+    pub const LESS: u8 = 17;
+    pub const LESS_EQUAL: u8 = 18;
+    pub const GREATER: u8 = 19;
+    pub const GREATER_EQUAL: u8 = 20;
+    pub const IN: u8 = 21;
+    pub const JUMP_IF_FALSE: u8 = 22;
+    pub const JUMP: u8 = 23;
+    pub const SUM: u8 = 24;
+    pub const AVG: u8 = 25;
+    pub const MIN: u8 = 26;
+    pub const MAX: u8 = 27;
+    pub const FILTER: u8 = 28;
+    pub const COALESCE: u8 = 29;
+    pub const ACC_BEGIN: u8 = 30;
+    pub const ACC_FEED: u8 = 31;
+    pub const ACC_END: u8 = 32;
+    pub const LOOKUP: u8 = 33;
+    pub const XLOOKUP: u8 = 34;
+    pub const SHIFT: u8 = 35;
+    pub const SET_PERIOD: u8 = 36;
+    pub const ROLLING_AVG: u8 = 37;
+    pub const EMA: u8 = 38;
+    pub const PERIOD_OFFSET: u8 = 39;
+    pub const TIME_SHIFT: u8 = 40;
+    pub const STRING_CONSTANT: u8 = 41;
+    pub const CONCAT: u8 = 42;
+    pub const TEXT: u8 = 43;
+    pub const DATE_PART: u8 = 44;
+    pub const CALL_NATIVE: u8 = 45;
+    pub const IS_BLANK: u8 = 46;
+    pub const COUNT_A: u8 = 47;
+    pub const DUP: u8 = 48;
+    pub const POP: u8 = 49;
+    pub const CALENDAR_OFFSET: u8 = 50;
+}
+
+impl Chunk {
+    /// Encodes the code as bytes (see the module docs); the pools are not included.
+    /// A jump whose target is out of range encodes as one that `decode_bytecode` rejects.
+    pub fn to_bytecode(&self) -> Vec<u8> {
+        // Jumps have a fixed width, so sizing every instruction needs no targets
+        let mut offsets = Vec::with_capacity(self.code.len());
+        let mut scratch = Vec::new();
+        let mut len = 0;
+        for op in &self.code {
+            offsets.push(len);
+            scratch.clear();
+            encode(op, &mut scratch, |_| 0);
+            len += scratch.len();
+        }
+
+        let mut bytecode = Vec::with_capacity(len);
+        for op in &self.code {
+            encode(op, &mut bytecode, |target| offsets.get(target).map_or(u32::MAX, |&o| o as u32));
+        }
+        bytecode
+    }
+
+    /// Decodes the output of `to_bytecode` back to `OpCode`s, jump targets included.
+    /// Only the encoding is checked: install the result as a chunk's `code` and `validate` it
+    /// before running it.
+    pub fn decode_bytecode(bytecode: &[u8]) -> Result<Vec<OpCode>, BytecodeError> {
+        let mut offsets = Vec::new();
+        let mut code = Vec::new();
+        let mut at = 0;
+        while at < bytecode.len() {
+            let (op, next) = decode_at(bytecode, at)?;
+            offsets.push(at);
+            code.push(op);
+            at = next;
+        }
+
+        // Targets are byte offsets in the encoding and instruction indices in the IR
+        for (op, &at) in code.iter_mut().zip(&offsets) {
+            if let OpCode::Jump(target) | OpCode::JumpIfFalse(target) = op {
+                *target = offsets
+                    .binary_search(target)
+                    .map_err(|_| BytecodeError::BadJumpTarget { at, target: *target })?;
+            }
+        }
+        Ok(code)
+    }
+}
+
+fn encode(op: &OpCode, out: &mut Vec<u8>, jump: impl Fn(usize) -> u32) {
+    let simple = |out: &mut Vec<u8>, tag: u8| out.push(tag);
+    let with = |out: &mut Vec<u8>, tag: u8, operand: usize| {
+        out.push(tag);
+        write_varint(out, operand as u64);
+    };
+    match *op {
+        OpCode::Return => simple(out, tag::RETURN),
+        OpCode::Constant(i) => with(out, tag::CONSTANT, i),
+        OpCode::LoadDimension(i) => with(out, tag::LOAD_DIMENSION, i),
+        OpCode::ErrorConstant(i) => with(out, tag::ERROR_CONSTANT, i),
+        OpCode::Add => simple(out, tag::ADD),
+        OpCode::Sub => simple(out, tag::SUB),
+        OpCode::Mul => simple(out, tag::MUL),
+        OpCode::Div => simple(out, tag::DIV),
+        OpCode::Mod => simple(out, tag::MOD),
+        OpCode::Ratio => simple(out, tag::RATIO),
+        OpCode::Negate => simple(out, tag::NEGATE),
+        OpCode::Sqrt => simple(out, tag::SQRT),
+        OpCode::Exp => simple(out, tag::EXP),
+        OpCode::Ln => simple(out, tag::LN),
+        OpCode::Log => simple(out, tag::LOG),
+        OpCode::Equal => simple(out, tag::EQUAL),
+        OpCode::NotEqual => simple(out, tag::NOT_EQUAL),
+        OpCode::Less => simple(out, tag::LESS),
+        OpCode::LessEqual => simple(out, tag::LESS_EQUAL),
+        OpCode::Greater => simple(out, tag::GREATER),
+        OpCode::GreaterEqual => simple(out, tag::GREATER_EQUAL),
+        OpCode::In(n) => with(out, tag::IN, n),
+        OpCode::JumpIfFalse(target) => {
+            out.push(tag::JUMP_IF_FALSE);
+            out.extend_from_slice(&jump(target).to_le_bytes());
+        }
+        OpCode::Jump(target) => {
+            out.push(tag::JUMP);
+            out.extend_from_slice(&jump(target).to_le_bytes());
+        }
+        OpCode::Dup => simple(out, tag::DUP),
+        OpCode::Pop => simple(out, tag::POP),
+        OpCode::Sum(n) => with(out, tag::SUM, n),
+        OpCode::Avg(n) => with(out, tag::AVG, n),
+        OpCode::Min(n) => with(out, tag::MIN, n),
+        OpCode::Max(n) => with(out, tag::MAX, n),
+        OpCode::Filter(n) => with(out, tag::FILTER, n),
+        OpCode::Coalesce(n) => with(out, tag::COALESCE, n),
+        OpCode::IsBlank => simple(out, tag::IS_BLANK),
+        OpCode::CountA(n) => with(out, tag::COUNT_A, n),
+        OpCode::AccBegin(kind) => {
+            let kind = match kind {
+                AggregateKind::Sum => 0,
+                AggregateKind::Avg => 1,
+                AggregateKind::Min => 2,
+                AggregateKind::Max => 3,
+            };
+            out.extend_from_slice(&[tag::ACC_BEGIN, kind]);
+        }
+        OpCode::AccFeed(n) => with(out, tag::ACC_FEED, n),
+        OpCode::AccEnd => simple(out, tag::ACC_END),
+        OpCode::Lookup => simple(out, tag::LOOKUP),
+        OpCode::XLookup(n, match_mode, search_mode) => {
+            with(out, tag::XLOOKUP, n);
+            out.push(match match_mode {
+                MatchMode::Exact => 0,
+                MatchMode::ExactOrNextSmaller => 1,
+                MatchMode::ExactOrNextLarger => 2,
+            });
+            out.push(match search_mode {
+                SearchMode::FirstToLast => 0,
+                SearchMode::LastToFirst => 1,
+            });
+        }
+        OpCode::Shift => simple(out, tag::SHIFT),
+        OpCode::SetPeriod(i) => with(out, tag::SET_PERIOD, i),
+        OpCode::RollingAvg(i, window) => {
+            with(out, tag::ROLLING_AVG, i);
+            write_varint(out, window as u64);
+        }
+        OpCode::Ema(i, alpha) => {
+            with(out, tag::EMA, i);
+            write_varint(out, alpha as u64);
+        }
+        OpCode::PeriodOffset(i, offset) => {
+            with(out, tag::PERIOD_OFFSET, i);
+            write_zigzag(out, offset);
+        }
+        OpCode::CalendarOffset(i, months) => {
+            with(out, tag::CALENDAR_OFFSET, i);
+            write_zigzag(out, months);
+        }
+        OpCode::TimeShift(kind) => out.extend_from_slice(&[tag::TIME_SHIFT, kind]),
+        OpCode::StringConstant(i) => with(out, tag::STRING_CONSTANT, i),
+        OpCode::Concat(n) => with(out, tag::CONCAT, n),
+        OpCode::Text => simple(out, tag::TEXT),
+        OpCode::DatePart(kind) => {
+            let kind = match kind {
+                DatePartKind::Year => 0,
+                DatePartKind::Month => 1,
+                DatePartKind::Day => 2,
+                DatePartKind::Quarter => 3,
+            };
+            out.extend_from_slice(&[tag::DATE_PART, kind]);
+        }
+        OpCode::CallNative(id, argc) => {
+            with(out, tag::CALL_NATIVE, id);
+            write_varint(out, argc as u64);
+        }
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Zigzag-encodes a signed operand, so small negative values stay one byte.
+fn write_zigzag(out: &mut Vec<u8>, value: i64) {
+    write_varint(out, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+/// Reads the operands of the instruction starting at `at`.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        let byte = *self.bytes.get(self.pos).ok_or(BytecodeError::Truncated { at: self.at })?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<u64, BytecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let low = (byte & 0x7F) as u64;
+            // The tenth byte holds the single remaining bit
+            if shift == 63 && low > 1 {
+                return Err(self.bad_operand());
+            }
+            value |= low << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(BytecodeError::BadOperand { at: self.at })
+    }
+
+    fn zigzag(&mut self) -> Result<i64, BytecodeError> {
+        let zigzag = self.varint()?;
+        Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64))
+    }
+
+    fn index(&mut self) -> Result<usize, BytecodeError> {
+        let value = self.varint()?;
+        usize::try_from(value).map_err(|_| BytecodeError::BadOperand { at: self.at })
+    }
+
+    fn jump(&mut self) -> Result<usize, BytecodeError> {
+        let mut target = [0; JUMP_WIDTH];
+        for byte in &mut target {
+            *byte = self.byte()?;
+        }
+        Ok(u32::from_le_bytes(target) as usize)
+    }
+
+    fn bad_operand(&self) -> BytecodeError {
+        BytecodeError::BadOperand { at: self.at }
+    }
+}
+
+/// Decodes the instruction starting at byte `at`, returning it with the offset of the next
+/// one. Jump targets are left as byte offsets, which is what the VM's byte dispatch needs.
+pub(crate) fn decode_at(bytecode: &[u8], at: usize) -> Result<(OpCode, usize), BytecodeError> {
+    let mut r = Reader { bytes: bytecode, at, pos: at };
+    let op = match r.byte()? {
+        tag::RETURN => OpCode::Return,
+        tag::CONSTANT => OpCode::Constant(r.index()?),
+        tag::LOAD_DIMENSION => OpCode::LoadDimension(r.index()?),
+        tag::ERROR_CONSTANT => OpCode::ErrorConstant(r.index()?),
+        tag::ADD => OpCode::Add,
+        tag::SUB => OpCode::Sub,
+        tag::MUL => OpCode::Mul,
+        tag::DIV => OpCode::Div,
+        tag::MOD => OpCode::Mod,
+        tag::RATIO => OpCode::Ratio,
+        tag::NEGATE => OpCode::Negate,
+        tag::SQRT => OpCode::Sqrt,
+        tag::EXP => OpCode::Exp,
+        tag::LN => OpCode::Ln,
+        tag::LOG => OpCode::Log,
+        tag::EQUAL => OpCode::Equal,
+        tag::NOT_EQUAL => OpCode::NotEqual,
+        tag::LESS => OpCode::Less,
+        tag::LESS_EQUAL => OpCode::LessEqual,
+        tag::GREATER => OpCode::Greater,
+        tag::GREATER_EQUAL => OpCode::GreaterEqual,
+        tag::IN => OpCode::In(r.index()?),
+        tag::JUMP_IF_FALSE => OpCode::JumpIfFalse(r.jump()?),
+        tag::JUMP => OpCode::Jump(r.jump()?),
+        tag::DUP => OpCode::Dup,
+        tag::POP => OpCode::Pop,
+        tag::SUM => OpCode::Sum(r.index()?),
+        tag::AVG => OpCode::Avg(r.index()?),
+        tag::MIN => OpCode::Min(r.index()?),
+        tag::MAX => OpCode::Max(r.index()?),
+        tag::FILTER => OpCode::Filter(r.index()?),
+        tag::COALESCE => OpCode::Coalesce(r.index()?),
+        tag::IS_BLANK => OpCode::IsBlank,
+        tag::COUNT_A => OpCode::CountA(r.index()?),
+        tag::ACC_BEGIN => OpCode::AccBegin(match r.byte()? {
+            0 => AggregateKind::Sum,
+            1 => AggregateKind::Avg,
+            2 => AggregateKind::Min,
+            3 => AggregateKind::Max,
+            _ => return Err(r.bad_operand()),
+        }),
+        tag::ACC_FEED => OpCode::AccFeed(r.index()?),
+        tag::ACC_END => OpCode::AccEnd,
+        tag::LOOKUP => OpCode::Lookup,
+        tag::XLOOKUP => {
+            let n = r.index()?;
+            let match_mode = match r.byte()? {
+                0 => MatchMode::Exact,
+                1 => MatchMode::ExactOrNextSmaller,
+                2 => MatchMode::ExactOrNextLarger,
+                _ => return Err(r.bad_operand()),
+            };
+            let search_mode = match r.byte()? {
+                0 => SearchMode::FirstToLast,
+                1 => SearchMode::LastToFirst,
+                _ => return Err(r.bad_operand()),
+            };
+            OpCode::XLookup(n, match_mode, search_mode)
+        }
+        tag::SHIFT => OpCode::Shift,
+        tag::SET_PERIOD => OpCode::SetPeriod(r.index()?),
+        tag::ROLLING_AVG => OpCode::RollingAvg(r.index()?, r.index()?),
+        tag::EMA => OpCode::Ema(r.index()?, r.index()?),
+        tag::PERIOD_OFFSET => OpCode::PeriodOffset(r.index()?, r.zigzag()?),
+        tag::CALENDAR_OFFSET => OpCode::CalendarOffset(r.index()?, r.zigzag()?),
+        tag::TIME_SHIFT => OpCode::TimeShift(r.byte()?),
+        tag::STRING_CONSTANT => OpCode::StringConstant(r.index()?),
+        tag::CONCAT => OpCode::Concat(r.index()?),
+        tag::TEXT => OpCode::Text,
+        tag::DATE_PART => OpCode::DatePart(match r.byte()? {
+            0 => DatePartKind::Year,
+            1 => DatePartKind::Month,
+            2 => DatePartKind::Day,
+            3 => DatePartKind::Quarter,
+            _ => return Err(r.bad_operand()),
+        }),
+        tag::CALL_NATIVE => OpCode::CallNative(r.index()?, r.index()?),
+        other => return Err(BytecodeError::UnknownOpcode { at, tag: other }),
+    };
+    Ok((op, r.pos))
+}
+
+/// Index in `Chunk::code` of the instruction starting at byte `offset`, or None if no
+/// instruction starts there. Walks from the start, so it is for diagnostics only.
+pub(crate) fn instruction_index(bytecode: &[u8], offset: usize) -> Option<usize> {
+    let mut at = 0;
+    for index in 0.. {
+        if at == offset {
+            return Some(index);
+        }
+        if at > offset {
+            return None;
+        }
+        at = decode_at(bytecode, at).ok()?.1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atom_script::compiler::Compiler;
+    use crate::atom_script::parser::Parser;
+
+    #[test]
+    fn test_bytecode_round_trips_every_opcode() {
+        let mut chunk = Chunk::new();
+        chunk.code = vec![
+            OpCode::Constant(0),
+            OpCode::JumpIfFalse(9),
+            OpCode::LoadDimension(300),
+            OpCode::XLookup(4, MatchMode::ExactOrNextLarger, SearchMode::LastToFirst),
+            OpCode::PeriodOffset(1, -12),
+            OpCode::PeriodOffset(1, i64::MIN),
+            OpCode::CalendarOffset(2, -3),
+            OpCode::Jump(0),
+            OpCode::Dup,
+            OpCode::Pop,
+            OpCode::RollingAvg(2, 3),
+            OpCode::Ema(0, usize::MAX),
+            OpCode::AccBegin(AggregateKind::Avg),
+            OpCode::AccFeed(1000),
+            OpCode::AccEnd,
+            OpCode::TimeShift(4),
+            OpCode::DatePart(DatePartKind::Quarter),
+            OpCode::CallNative(7, 2),
+            OpCode::SetPeriod(5),
+            OpCode::In(3),
+            OpCode::Coalesce(2),
+            OpCode::IsBlank,
+            OpCode::CountA(4),
+            OpCode::Negate,
+            OpCode::Return,
+        ];
+        let bytecode = chunk.to_bytecode();
+        assert_eq!(Chunk::decode_bytecode(&bytecode), Ok(chunk.code.clone()));
+
+        // Truncation and unknown tags are reported with their byte offset
+        assert_eq!(Chunk::decode_bytecode(&bytecode[..4]), Err(BytecodeError::Truncated { at: 2 }));
+        assert_eq!(Chunk::decode_bytecode(&[0xFF]), Err(BytecodeError::UnknownOpcode { at: 0, tag: 0xFF }));
+        // A jump into the middle of an instruction
+        assert_eq!(
+            Chunk::decode_bytecode(&[tag::JUMP, 1, 0, 0, 0, tag::RETURN]),
+            Err(BytecodeError::BadJumpTarget { at: 0, target: 1 })
+        );
+    }
+
+    #[test]
+    fn test_bytecode_is_smaller_than_enum_encoding() {
+        let formula = "IF([Revenue] > 1000, SUM(@Children([Region], [North America])) * 1.1, [COGS] - [Opex])";
+        let chunk = Compiler::new().compile(&Parser::new(formula).parse().expect("Parse failed"));
+        let bytecode = chunk.to_bytecode();
+
+        let enum_size = chunk.code.len() * std::mem::size_of::<OpCode>();
+        assert!(
+            bytecode.len() * 4 < enum_size,
+            "{} bytes of bytecode vs {} bytes of OpCode",
+            bytecode.len(),
+            enum_size
+        );
+        assert_eq!(Chunk::decode_bytecode(&bytecode), Ok(chunk.code));
+    }
+}
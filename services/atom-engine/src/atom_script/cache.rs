@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use parking_lot::Mutex;
+use crate::atom_script::ast::{canonical_hash, Expr};
+use crate::atom_script::chunk::Chunk;
+use crate::atom_script::compiler::Compiler;
+use crate::atom_script::error::EngineError;
+use crate::atom_script::parser::{Parser, DEFAULT_MAX_FORMULA_LEN};
+
+pub const DEFAULT_CACHE_CAPACITY: usize = 10_000;
+
+/// Cache key: the formula's `canonical_hash` plus the version of the metadata it was compiled
+/// against, since hierarchy expansion and alias resolution are baked into the chunk.
+/// Keying on the parsed tree rather than the text lets `1+2` and `1 + 2` share an entry.
+type CacheKey = (u64, u64);
+
+struct CacheEntry {
+    expr: Expr, // Compared on lookup, so a hash collision is a miss rather than a wrong chunk
+    chunk: Arc<Chunk>,
+    last_used: u64,
+}
+
+struct CacheState {
+    entries: HashMap<CacheKey, CacheEntry>,
+    tick: u64,
+    latest_version: u64,
+}
+
+/// Compile-once cache for formula text shared across grid cells.
+/// Bounded by LRU eviction. Resolver versions are expected to increase: the first lookup with
+/// a newer version drops every entry compiled against an older one. `invalidate_all` clears
+/// the cache outright.
+pub struct FormulaCache {
+    capacity: usize,
+    state: Mutex<CacheState>,
+    compiles: AtomicUsize,
+}
+
+impl Default for FormulaCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_CAPACITY)
+    }
+}
+
+impl FormulaCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(CacheState {
+                entries: HashMap::new(),
+                tick: 0,
+                latest_version: 0,
+            }),
+            compiles: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the cached chunk for `formula`, or compiles it with the compiler produced by
+    /// `make_compiler` on a miss. The formula is always parsed (that is what makes the key
+    /// formatting-independent); only compilation is cached. Compilation runs outside the lock,
+    /// so two threads missing on the same formula may both compile it; the last insert wins.
+    /// Formulas longer than `DEFAULT_MAX_FORMULA_LEN` are rejected before lexing.
+    pub fn get_or_compile(
+        &self,
+        formula: &str,
+        resolver_version: u64,
+        make_compiler: impl FnOnce() -> Compiler,
+    ) -> Result<Arc<Chunk>, EngineError> {
+        let expr = Parser::new_bounded(formula, DEFAULT_MAX_FORMULA_LEN).parse()?;
+        let key = (canonical_hash(&expr), resolver_version);
+        {
+            let mut state = self.state.lock();
+            if resolver_version > state.latest_version {
+                state.latest_version = resolver_version;
+                state.entries.retain(|&(_, version), _| version >= resolver_version);
+            }
+            state.tick += 1;
+            let tick = state.tick;
+            if let Some(entry) = state.entries.get_mut(&key) {
+                if entry.expr == expr {
+                    entry.last_used = tick;
+                    return Ok(Arc::clone(&entry.chunk));
+                }
+            }
+        }
+
+        let chunk = Arc::new(make_compiler().try_compile(&expr)?);
+        self.compiles.fetch_add(1, Ordering::Relaxed);
+
+        let mut state = self.state.lock();
+        if state.entries.len() >= self.capacity && !state.entries.contains_key(&key) {
+            // Linear scan for the least recently used entry: eviction only happens on a miss
+            if let Some(lru) = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(k, _)| *k)
+            {
+                state.entries.remove(&lru);
+            }
+        }
+        let tick = state.tick;
+        state.entries.insert(key, CacheEntry { expr, chunk: Arc::clone(&chunk), last_used: tick });
+        Ok(chunk)
+    }
+
+    /// Drops every cached chunk, e.g. after a hierarchy or alias change.
+    pub fn invalidate_all(&self) {
+        self.state.lock().entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of actual compilations performed (cache misses that compiled successfully).
+    pub fn compile_count(&self) -> usize {
+        self.compiles.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atom_script::vm::{InterpretResult, VM};
+
+    #[test]
+    fn test_second_compile_is_cache_hit() {
+        let cache = FormulaCache::new(8);
+        let first = cache.get_or_compile("[A] * 2 + 1", 0, Compiler::new).unwrap();
+        let second = cache.get_or_compile("[A] * 2 + 1", 0, Compiler::new).unwrap();
+
+        assert_eq!(cache.compile_count(), 1);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(VM::new(second).run(), InterpretResult::Ok(1.0));
+
+        // A metadata change is a different key, and retires the entries of the old version
+        cache.get_or_compile("[A] * 2 + 1", 1, Compiler::new).unwrap();
+        assert_eq!(cache.compile_count(), 2);
+        assert_eq!(cache.len(), 1);
+
+        cache.invalidate_all();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_lru_eviction_bound() {
+        let cache = FormulaCache::new(2);
+        cache.get_or_compile("1 + 1", 0, Compiler::new).unwrap();
+        cache.get_or_compile("2 + 2", 0, Compiler::new).unwrap();
+        cache.get_or_compile("1 + 1", 0, Compiler::new).unwrap(); // touch: "2 + 2" is now LRU
+        cache.get_or_compile("3 + 3", 0, Compiler::new).unwrap();
+
+        assert_eq!(cache.len(), 2);
+        cache.get_or_compile("1 + 1", 0, Compiler::new).unwrap();
+        assert_eq!(cache.compile_count(), 3);
+        cache.get_or_compile("2 + 2", 0, Compiler::new).unwrap();
+        assert_eq!(cache.compile_count(), 4);
+    }
+
+    #[test]
+    fn test_cache_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<FormulaCache>();
+    }
+
+    #[test]
+    fn test_differently_formatted_formulas_share_an_entry() {
+        let cache = FormulaCache::new(8);
+        let compact = cache.get_or_compile("[A]*2+1", 0, Compiler::new).unwrap();
+        let spaced = cache.get_or_compile(" ( [A] * 2 )  +  1 ", 0, Compiler::new).unwrap();
+
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.compile_count(), 1);
+        assert!(Arc::ptr_eq(&compact, &spaced));
+
+        cache.get_or_compile("[A] * (2 + 1)", 0, Compiler::new).unwrap();
+        assert_eq!(cache.len(), 2);
+    }
+}
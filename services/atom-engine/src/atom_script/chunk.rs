@@ -1,30 +1,222 @@
-#[derive(Debug, Clone, Copy, PartialEq)]
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::ops::Range;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Why `Chunk::validate` rejected a chunk.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ValidationError {
+    #[error("chunk does not end with Return")]
+    MissingReturn,
+    #[error("instruction {at}: jump target {target} out of range")]
+    BadJumpTarget { at: usize, target: usize },
+    #[error("instruction {at}: constant index {index} out of range")]
+    BadConstantIndex { at: usize, index: usize },
+    #[error("instruction {at}: string index {index} out of range")]
+    BadStringIndex { at: usize, index: usize },
+    #[error("instruction {at}: coordinate index {index} out of range")]
+    BadCoordinateIndex { at: usize, index: usize },
+    #[error("instruction {at}: pops {needed} values but only {available} are on the stack")]
+    StackUnderflow { at: usize, needed: usize, available: usize },
+    #[error("instruction {at}: reached with stack depths {first} and {second}")]
+    InconsistentStack { at: usize, first: usize, second: usize },
+    #[error("no Return is reachable from the entry point")]
+    UnreachableReturn,
+    #[error("instruction {at}: operand count overflows the stack")]
+    OperandCountOverflow { at: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum OpCode {
     Return,
     Constant(usize), // Index in constants pool
+    LoadDimension(usize), // Index in coordinates pool; loads the referenced cell from the arena
+    ErrorConstant(usize), // Index in strings pool; pushes an error value (e.g. #VALUE!)
     Add,
     Sub,
     Mul,
     Div,
+    Mod,
+    Ratio, // Pops 2 (part, whole); pushes part / whole, or #DIV/0! when whole is zero or empty
     Negate,
+    // Transcendental functions: Pop 1 (Log pops value then base), push the result or a domain error
+    Sqrt,
+    Exp,
+    Ln,
+    Log,
+    // Comparisons: Pop 2, push Bool
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    In(usize), // Pops N candidates then the test value, pushes Bool
+
+    // Control Flow (absolute instruction targets)
+    JumpIfFalse(usize), // Pops the condition
+    Jump(usize),
+    Dup, // Pushes a copy of the top value (SWITCH compares its subject against each case)
+    Pop, // Discards the top value
     // Ultra Diamond: Aggregation Ops
     Sum(usize), // Pops N items from stack
     Avg(usize),
     Min(usize),
     Max(usize),
+    Filter(usize), // Pops N (value, mask) pairs, pushes N values with masked-out ones as Empty
+    Coalesce(usize), // Pops N values, pushes the first that is neither Empty nor an error
+    IsBlank, // Pops 1, pushes 1 if it was Empty (a never-set cell) and 0 otherwise
+    CountA(usize), // Pops N values, pushes how many are not Empty
+    // Streaming aggregation for operand sets larger than the compiler's aggregate budget:
+    // AccBegin opens an accumulator, each AccFeed folds a block of operands into it, AccEnd pushes the result
+    AccBegin(AggregateKind),
+    AccFeed(usize), // Pops N
+    AccEnd,
 
     // Ultra Diamond: Lookups & Time Travel
-    Lookup, // Pops 3: range, search_val, return_range
-    XLookup(usize), // Pops N (Standard args)
+    Lookup, // Pops 3: value, search range, return range (see `LookupBackend`)
+    XLookup(usize, MatchMode, SearchMode), // (array length N): pops the lookup value, N keys, N results and the if-not-found default
     Shift, // Pops 2: Dimension, Offset/Target
+    SetPeriod(usize), // Index in strings pool; anchors the next PeriodOffset or CalendarOffset at this absolute period
     
+    // Time-Series Windows
+    RollingAvg(usize, usize), // (coordinates index, window): averages the current and prior window-1 periods
+    Ema(usize, usize), // (coordinates index, constant index of alpha): exponential moving average up to the current period
+    PeriodOffset(usize, i64), // (coordinates index, offset): the referenced cell `offset` periods away (LAG/LEAD)
+    CalendarOffset(usize, i64), // (coordinates index, months): PeriodOffset by calendar months, converted by the period resolver (`->` chains)
+
     // Phase 3: Time-Intelligence Shifts
     TimeShift(u8), // Pops 1 (base), arg is an enum mapping to TimeShiftType
+
+    // Commentary Formulas: String Ops
+    StringConstant(usize), // Index in strings pool
+    Concat(usize), // Pops N values, pushes their concatenation
+    Text, // Pops 2: number, format
+
+    // Date Cells: Unix millis in, calendar component out (UTC)
+    DatePart(DatePartKind), // Pops 1 (millis), pushes the component
+
+    // Host-defined functions (see FunctionRegistry)
+    CallNative(usize, usize), // (function id, argc): pops argc args, pushes the result
+}
+
+/// Aggregation computed by a streaming accumulator (see `OpCode::AccBegin`).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AggregateKind {
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl OpCode {
+    /// How many values the instruction pops and then pushes, or None when an operand count is
+    /// too large for any stack to hold.
+    pub fn stack_effect(&self) -> Option<(usize, usize)> {
+        let effect = match *self {
+            OpCode::Return | OpCode::JumpIfFalse(_) | OpCode::Pop => (1, 0),
+            OpCode::Dup => (1, 2),
+            OpCode::Jump(_) | OpCode::AccBegin(_) | OpCode::SetPeriod(_) => (0, 0),
+            OpCode::AccFeed(count) => (count, 0),
+            OpCode::AccEnd => (0, 1),
+            OpCode::Constant(_)
+            | OpCode::LoadDimension(_)
+            | OpCode::ErrorConstant(_)
+            | OpCode::StringConstant(_)
+            | OpCode::RollingAvg(..)
+            | OpCode::Ema(..)
+            | OpCode::PeriodOffset(..)
+            | OpCode::CalendarOffset(..) => (0, 1),
+            OpCode::Negate
+            | OpCode::Sqrt
+            | OpCode::Exp
+            | OpCode::Ln
+            | OpCode::TimeShift(_)
+            | OpCode::DatePart(_)
+            | OpCode::IsBlank => (1, 1),
+            OpCode::Add
+            | OpCode::Sub
+            | OpCode::Mul
+            | OpCode::Div
+            | OpCode::Mod
+            | OpCode::Log
+            | OpCode::Ratio
+            | OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Shift
+            | OpCode::Text => (2, 1),
+            OpCode::Lookup => (3, 1),
+            OpCode::In(count) => (count.checked_add(1)?, 1),
+            OpCode::Sum(count)
+            | OpCode::Avg(count)
+            | OpCode::Min(count)
+            | OpCode::Max(count)
+            | OpCode::Coalesce(count)
+            | OpCode::CountA(count)
+            | OpCode::Concat(count)
+            | OpCode::CallNative(_, count) => (count, 1),
+            OpCode::Filter(count) => (count.checked_mul(2)?, count),
+            OpCode::XLookup(count, ..) => (count.checked_mul(2)?.checked_add(2)?, 1),
+        };
+        Some(effect)
+    }
+}
+
+/// How XLOOKUP matches the lookup value (its `match_mode` argument).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum MatchMode {
+    Exact,
+    /// An exact match, else the largest key below the lookup value.
+    ExactOrNextSmaller,
+    /// An exact match, else the smallest key above the lookup value.
+    ExactOrNextLarger,
+}
+
+/// Order in which XLOOKUP scans the lookup array (its `search_mode` argument); decides which
+/// of several equally good keys wins.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SearchMode {
+    FirstToLast,
+    LastToFirst,
+}
+
+/// Calendar component extracted by YEAR/MONTH/DAY/QUARTER.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DatePartKind {
+    Year,
+    Month,
+    Day,
+    Quarter,
+}
+
+/// Static cost of running a chunk once, from `Chunk::estimate_cost`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CostEstimate {
+    pub instructions: usize,
+    /// Arena cells read per evaluation. EMA reads the whole series up to the current period,
+    /// which is only known at runtime, so it counts as one read here.
+    pub arena_reads: usize,
+    /// Deepest the VM stack gets on any path.
+    pub max_stack: usize,
 }
 
 pub struct Chunk {
     pub code: Vec<OpCode>,
     pub constants: Vec<f64>,
+    pub strings: Vec<String>,
+    pub coordinates: Vec<Vec<(String, String)>>, // Dimension=member overrides per cell reference
+    pub spans: Vec<Range<usize>>, // Source range per instruction; empty unless compiled with a source map
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chunk {
@@ -32,6 +224,9 @@ impl Chunk {
         Self {
             code: Vec::new(),
             constants: Vec::new(),
+            strings: Vec::new(),
+            coordinates: Vec::new(),
+            spans: Vec::new(),
         }
     }
 
@@ -43,4 +238,306 @@ impl Chunk {
         self.constants.push(value);
         self.constants.len() - 1
     }
+
+    pub fn add_coordinate(&mut self, pairs: Vec<(String, String)>) -> usize {
+        self.coordinates.push(pairs);
+        self.coordinates.len() - 1
+    }
+
+    pub fn add_string(&mut self, value: &str) -> usize {
+        self.strings.push(value.to_string());
+        self.strings.len() - 1
+    }
+
+    /// Whether the chunk is straight-line arithmetic over numbers: constants, cell loads,
+    /// `+ - * / %`, negation and SUM/AVG/MIN/MAX, with no strings, booleans, error constants or
+    /// jumps. The VM runs such chunks on an untagged f64 stack (see `VM::run`).
+    pub fn is_numeric_only(&self) -> bool {
+        self.code.iter().all(|op| {
+            matches!(
+                op,
+                OpCode::Return
+                    | OpCode::Constant(_)
+                    | OpCode::LoadDimension(_)
+                    | OpCode::Add
+                    | OpCode::Sub
+                    | OpCode::Mul
+                    | OpCode::Div
+                    | OpCode::Mod
+                    | OpCode::Negate
+                    | OpCode::Sum(_)
+                    | OpCode::Avg(_)
+                    | OpCode::Min(_)
+                    | OpCode::Max(_)
+            )
+        })
+    }
+
+    /// Verifies a chunk before execution: every pool index and jump target is in range, the
+    /// code ends with `Return`, and a static pass over all paths proves no instruction pops more
+    /// values than are on the stack and that a `Return` is reachable.
+    /// A chunk that passes can never move the instruction pointer out of bounds, which lets the
+    /// VM fetch instructions without a per-instruction bounds check.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.code.last() != Some(&OpCode::Return) {
+            return Err(ValidationError::MissingReturn);
+        }
+        let check = |index: usize, len: usize, error: ValidationError| {
+            if index < len { Ok(()) } else { Err(error) }
+        };
+        for (at, op) in self.code.iter().enumerate() {
+            match *op {
+                OpCode::Jump(target) | OpCode::JumpIfFalse(target) => {
+                    check(target, self.code.len(), ValidationError::BadJumpTarget { at, target })?
+                }
+                OpCode::Constant(index) => {
+                    check(index, self.constants.len(), ValidationError::BadConstantIndex { at, index })?
+                }
+                OpCode::StringConstant(index) | OpCode::ErrorConstant(index) | OpCode::SetPeriod(index) => {
+                    check(index, self.strings.len(), ValidationError::BadStringIndex { at, index })?
+                }
+                OpCode::LoadDimension(index)
+                | OpCode::RollingAvg(index, _)
+                | OpCode::PeriodOffset(index, _)
+                | OpCode::CalendarOffset(index, _) => {
+                    check(index, self.coordinates.len(), ValidationError::BadCoordinateIndex { at, index })?
+                }
+                OpCode::Ema(index, alpha) => {
+                    check(index, self.coordinates.len(), ValidationError::BadCoordinateIndex { at, index })?;
+                    check(alpha, self.constants.len(), ValidationError::BadConstantIndex { at, index: alpha })?
+                }
+                _ => {}
+            }
+        }
+        self.stack_depths().map(|_| ())
+    }
+
+    /// Walks every reachable path tracking the stack depth at each instruction (None for
+    /// unreachable ones). Paths that meet (e.g. the two branches of an IF) must agree on the depth.
+    fn stack_depths(&self) -> Result<Vec<Option<usize>>, ValidationError> {
+        let mut depths: Vec<Option<usize>> = vec![None; self.code.len()];
+        let mut pending = vec![(0, 0)];
+        let mut returns = false;
+
+        while let Some((at, depth)) = pending.pop() {
+            match depths[at] {
+                Some(first) if first != depth => {
+                    return Err(ValidationError::InconsistentStack { at, first, second: depth })
+                }
+                Some(_) => continue,
+                None => depths[at] = Some(depth),
+            }
+
+            let op = self.code[at];
+            let (pops, pushes) = op.stack_effect().ok_or(ValidationError::OperandCountOverflow { at })?;
+            if depth < pops {
+                return Err(ValidationError::StackUnderflow { at, needed: pops, available: depth });
+            }
+            let next = (depth - pops).checked_add(pushes).ok_or(ValidationError::OperandCountOverflow { at })?;
+            match op {
+                OpCode::Return => returns = true,
+                OpCode::Jump(target) => pending.push((target, next)),
+                OpCode::JumpIfFalse(target) => {
+                    pending.push((target, next));
+                    pending.push((at + 1, next));
+                }
+                // Validated chunks end with Return, so any other instruction has a successor
+                _ => pending.push((at + 1, next)),
+            }
+        }
+
+        if returns { Ok(depths) } else { Err(ValidationError::UnreachableReturn) }
+    }
+
+    /// Estimates the work of one evaluation. Fails if the chunk does not validate.
+    pub fn estimate_cost(&self) -> Result<CostEstimate, ValidationError> {
+        self.validate()?;
+        let max_stack = self.stack_depths()?.into_iter().flatten().max().unwrap_or(0);
+        let arena_reads = self
+            .code
+            .iter()
+            .map(|op| match *op {
+                OpCode::LoadDimension(_) | OpCode::PeriodOffset(..) | OpCode::CalendarOffset(..) | OpCode::Ema(..) => 1,
+                OpCode::RollingAvg(_, window) => window,
+                _ => 0,
+            })
+            .sum();
+        Ok(CostEstimate { instructions: self.code.len(), arena_reads, max_stack })
+    }
+
+    /// Human-readable listing, one instruction per line: offset, opcode, and the pool entry it
+    /// refers to, e.g. `0002 LoadDimension(1)  ; Region=USA, Measure=Revenue`.
+    pub fn disassemble(&self) -> Vec<String> {
+        self.code
+            .iter()
+            .enumerate()
+            .map(|(at, op)| {
+                let operand = match *op {
+                    OpCode::Constant(i) => self.constants.get(i).map(|c| c.to_string()),
+                    OpCode::StringConstant(i) | OpCode::ErrorConstant(i) | OpCode::SetPeriod(i) => {
+                        self.strings.get(i).map(|s| format!("{:?}", s))
+                    }
+                    OpCode::LoadDimension(i)
+                    | OpCode::RollingAvg(i, _)
+                    | OpCode::Ema(i, _)
+                    | OpCode::PeriodOffset(i, _)
+                    | OpCode::CalendarOffset(i, _) => {
+                        self.coordinates.get(i).map(|pairs| {
+                            pairs.iter().map(|(d, m)| format!("{}={}", d, m)).collect::<Vec<_>>().join(", ")
+                        })
+                    }
+                    _ => None,
+                };
+                match operand {
+                    Some(operand) => format!("{:04} {:?}  ; {}", at, op, operand),
+                    None => format!("{:04} {:?}", at, op),
+                }
+            })
+            .collect()
+    }
+
+    /// Exports the chunk as JSON for debugging tools: `{ "code": [...], "constants": [...], "strings": [...], "coordinates": [...] }`.
+    /// Opcodes use serde's externally-tagged form, e.g. `"Return"` or `{ "Sum": 3 }`.
+    /// Note: non-finite constants have no JSON representation and export as `null`, and
+    /// source spans are not exported.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "code": self.code,
+            "constants": self.constants,
+            "strings": self.strings,
+            "coordinates": self.coordinates,
+        })
+    }
+
+    /// Rebuilds a chunk from the output of `to_json`. The result is validated, so a corrupt or
+    /// hand-edited export is rejected here rather than when it runs.
+    pub fn from_json(value: &serde_json::Value) -> Result<Self, String> {
+        let code = serde_json::from_value(value["code"].clone()).map_err(|e| e.to_string())?;
+        let constants = serde_json::from_value(value["constants"].clone()).map_err(|e| e.to_string())?;
+        let strings = match value.get("strings") {
+            Some(strings) => serde_json::from_value(strings.clone()).map_err(|e| e.to_string())?,
+            None => Vec::new(),
+        };
+        let coordinates = match value.get("coordinates") {
+            Some(coordinates) => serde_json::from_value(coordinates.clone()).map_err(|e| e.to_string())?,
+            None => Vec::new(),
+        };
+        let chunk = Self { code, constants, strings, coordinates, spans: Vec::new() };
+        chunk.validate().map_err(|e| e.to_string())?;
+        Ok(chunk)
+    }
+}
+
+/// A shared chunk together with the outcome of `Chunk::validate` and `Chunk::is_numeric_only`,
+/// run once here so that every VM built from it (`VM::new`, `VmPool::run`, a batch worker)
+/// skips the walks. The chunk cannot change behind the results: it is only reachable through
+/// the `Arc`. Cloning shares all three.
+#[derive(Clone)]
+pub struct PreparedChunk {
+    chunk: Arc<Chunk>,
+    validation: Result<(), ValidationError>,
+    numeric_only: bool,
+}
+
+impl PreparedChunk {
+    pub fn new(chunk: impl Into<Arc<Chunk>>) -> Self {
+        let chunk = chunk.into();
+        let validation = chunk.validate();
+        let numeric_only = chunk.is_numeric_only();
+        Self { chunk, validation, numeric_only }
+    }
+
+    pub fn chunk(&self) -> &Arc<Chunk> {
+        &self.chunk
+    }
+
+    /// What `Chunk::validate` returned for the chunk.
+    pub fn validation(&self) -> &Result<(), ValidationError> {
+        &self.validation
+    }
+
+    /// What `Chunk::is_numeric_only` returned for the chunk.
+    pub fn is_numeric_only(&self) -> bool {
+        self.numeric_only
+    }
+}
+
+impl From<Arc<Chunk>> for PreparedChunk {
+    fn from(chunk: Arc<Chunk>) -> Self {
+        Self::new(chunk)
+    }
+}
+
+impl From<Chunk> for PreparedChunk {
+    fn from(chunk: Chunk) -> Self {
+        Self::new(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk_of(code: Vec<OpCode>) -> Chunk {
+        let mut chunk = Chunk::new();
+        chunk.add_constant(1.0);
+        chunk.code = code;
+        chunk
+    }
+
+    #[test]
+    fn test_validate_accepts_well_formed_chunk() {
+        // IF(1, 1, 1): both branches meet at Return with one value on the stack
+        let chunk = chunk_of(vec![
+            OpCode::Constant(0),
+            OpCode::JumpIfFalse(4),
+            OpCode::Constant(0),
+            OpCode::Jump(5),
+            OpCode::Constant(0),
+            OpCode::Return,
+        ]);
+        assert_eq!(chunk.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_rejects_malformed_chunks() {
+        let cases = [
+            (vec![OpCode::Constant(3), OpCode::Return], ValidationError::BadConstantIndex { at: 0, index: 3 }),
+            (vec![OpCode::Jump(9), OpCode::Return], ValidationError::BadJumpTarget { at: 0, target: 9 }),
+            (vec![OpCode::Constant(0)], ValidationError::MissingReturn),
+            (
+                vec![OpCode::Constant(0), OpCode::Sum(3), OpCode::Return],
+                ValidationError::StackUnderflow { at: 1, needed: 3, available: 1 },
+            ),
+            (
+                vec![OpCode::Constant(0), OpCode::JumpIfFalse(3), OpCode::Constant(0), OpCode::Return],
+                ValidationError::InconsistentStack { at: 3, first: 1, second: 0 },
+            ),
+            (vec![OpCode::Jump(0), OpCode::Return], ValidationError::UnreachableReturn),
+            (vec![OpCode::Filter(usize::MAX), OpCode::Return], ValidationError::OperandCountOverflow { at: 0 }),
+            (
+                vec![OpCode::XLookup(usize::MAX / 2, MatchMode::Exact, SearchMode::FirstToLast), OpCode::Return],
+                ValidationError::OperandCountOverflow { at: 0 },
+            ),
+        ];
+        for (code, expected) in cases {
+            let chunk = Arc::new(chunk_of(code));
+            assert_eq!(chunk.validate(), Err(expected.clone()));
+            assert_eq!(PreparedChunk::new(chunk).validation(), &Err(expected));
+        }
+    }
+
+    #[test]
+    fn test_disassemble_and_estimate_cost() {
+        let mut chunk = Chunk::new();
+        let c = chunk.add_constant(2.0);
+        let r = chunk.add_coordinate(vec![("Measure".to_string(), "Revenue".to_string())]);
+        chunk.code = vec![OpCode::LoadDimension(r), OpCode::Constant(c), OpCode::Mul, OpCode::Return];
+
+        assert_eq!(
+            chunk.disassemble(),
+            vec!["0000 LoadDimension(0)  ; Measure=Revenue", "0001 Constant(0)  ; 2", "0002 Mul", "0003 Return"]
+        );
+        assert_eq!(chunk.estimate_cost(), Ok(CostEstimate { instructions: 4, arena_reads: 1, max_stack: 2 }));
+    }
 }
@@ -1,23 +1,219 @@
-use crate::atom_script::ast::{BinaryOp, Expr, TimeShiftType};
-use crate::atom_script::chunk::{Chunk, OpCode};
+use crate::atom_script::ast::{preorder, BinaryOp, Expr, SourceMap, TimeShiftType};
+use crate::atom_script::chunk::{AggregateKind, Chunk, DatePartKind, MatchMode, OpCode, SearchMode};
+use crate::atom_script::error::EngineError;
+use crate::atom_script::parser::{Parser, DEFAULT_MAX_FORMULA_LEN};
+use crate::atom_script::registry::FunctionRegistry;
+use crate::atom_script::simplify::simplify;
+use crate::atom_script::vm::DEFAULT_STACK_SIZE;
+use crate::atom_script::typecheck::{self, Type};
+use crate::atom_script::value::{self, modulo};
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+use rayon::prelude::*;
+use crate::lattice::coordinate::{normalize_name, Member, DEFAULT_REF_DIMENSION};
 use crate::lattice::metadata::{HierarchyResolver, MockHierarchyResolver};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CompileError {
+    #[error("type mismatch in `{expr}`: expected {expected:?}, found {found:?}")]
+    TypeMismatch { expected: Type, found: Type, expr: String },
+    #[error("hierarchy resolver failed: {0}")]
+    Resolver(String),
+    #[error("unknown member [{member}] in dimension {dimension}")]
+    UnknownMember { dimension: String, member: String },
+    #[error("{function} cannot take {found} arguments")]
+    ArgumentCount { function: String, found: usize },
+}
+
+// Relative steps of a `->` chain, in calendar months; the period resolver converts them to
+// periods at run time (see `OpCode::CalendarOffset`).
+const RELATIVE_SHIFTS: &[(&str, i64)] = &[
+    ("PrevMonth", -1),
+    ("NextMonth", 1),
+    ("PrevQuarter", -3),
+    ("NextQuarter", 3),
+    ("PrevYear", -12),
+    ("NextYear", 12),
+];
+
+// Largest LAG/LEAD offset: far beyond any real series, and small enough that the offset
+// never overflows when a resolver adds it to a position.
+const MAX_PERIOD_OFFSET: f64 = 1_000_000.0;
+
+// Half the VM's default stack, leaving room for the operands of enclosing expressions.
+pub const DEFAULT_AGGREGATE_BUDGET: usize = DEFAULT_STACK_SIZE / 2;
+
+/// Optimization passes the compiler runs. All are on by default; turning them off keeps the
+/// emitted opcodes a literal transcription of the formula, which is easier to inspect.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompilerOptions {
+    /// Evaluates arithmetic on two literals at compile time (`1 + 2` emits `Constant(3)`).
+    pub fold_constants: bool,
+    /// Shares one pool entry between repeated literals and cell references (`[A] + [A]`).
+    pub cse: bool,
+    /// Threads jumps that land on another jump straight to the final target (nested IFs).
+    pub peephole: bool,
+    /// Lowercases member names in cell references, so `[usa]` and `[USA]` address the same cell.
+    /// Off by default because some models are case-sensitive; see `normalize_name`.
+    pub fold_case: bool,
+    /// Most operands SUM/AVG/MIN/MAX may hold on the VM stack at once. Larger operand sets
+    /// (e.g. a rollup over thousands of leaves) are streamed through an accumulator in blocks.
+    pub aggregate_budget: usize,
+    /// Rejects (in `try_compile`) any member reference the resolver does not confirm exists,
+    /// so a typo like `[Atlantis]` fails to compile instead of reading an empty cell.
+    /// Off by default: cells need not be described by metadata to be referenced.
+    pub strict_members: bool,
+    /// What AVG compiles to when its operands expand to nothing (e.g. `@Children` of an
+    /// unknown member): NaN when set, `#DIV/0!` otherwise. SUM of nothing is always 0 and
+    /// MIN/MAX of nothing always `#N/A`.
+    pub empty_avg_nan: bool,
+    /// Applies algebraic identities before compiling (`([A] + [B]) * 1` → `[A] + [B]`, `[A] - [A]` → 0;
+    /// see `simplify`). Off by default: a cancelled operand no longer surfaces its error
+    /// value or NaN, so `[A] * 0` is 0 even when `[A]` is `#N/A`.
+    pub simplify: bool,
+}
+
+impl Default for CompilerOptions {
+    fn default() -> Self {
+        Self { fold_constants: true, cse: true, peephole: true, fold_case: false, aggregate_budget: DEFAULT_AGGREGATE_BUDGET, strict_members: false, empty_avg_nan: false, simplify: false }
+    }
+}
 
 pub struct Compiler {
     chunk: Chunk,
-    resolver: Box<dyn HierarchyResolver>,
+    options: CompilerOptions,
+    resolver: Arc<dyn HierarchyResolver + Send + Sync>, // Shared so formulas can compile in parallel
+    scope: Vec<(String, String)>, // Member pinned while compiling a FILTER predicate
+    functions: Arc<FunctionRegistry>,
+    bindings: Arc<HashMap<String, f64>>, // Values of bare identifiers (`let` bindings)
+    resolver_error: OnceCell<String>, // First failed hierarchy lookup; reported by `try_compile`
+    unknown_member: OnceCell<(String, String)>, // First unconfirmed member under `strict_members`
+    bad_arity: OnceCell<(String, usize)>, // First host function called with the wrong argument count
+    source_map: Option<SourceMap>, // Fills `Chunk::spans` when set
+    node_ids: HashMap<usize, usize>, // Address of each node being compiled -> its `SourceMap` index
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Compiler {
     pub fn new() -> Self {
         Self {
             chunk: Chunk::new(),
-            resolver: Box::new(MockHierarchyResolver), // Default to Mock for now
+            options: CompilerOptions::default(),
+            resolver: Arc::new(MockHierarchyResolver), // Default to Mock for now
+            scope: Vec::new(),
+            functions: Arc::new(FunctionRegistry::new()),
+            bindings: Arc::new(HashMap::new()),
+            resolver_error: OnceCell::new(),
+            unknown_member: OnceCell::new(),
+            bad_arity: OnceCell::new(),
+            source_map: None,
+            node_ids: HashMap::new(),
         }
     }
 
+    /// Creates a compiler that resolves hierarchies and member aliases through `resolver`.
+    /// One resolver can back any number of compilers, including on different threads.
+    pub fn with_resolver(resolver: Arc<dyn HierarchyResolver + Send + Sync>) -> Self {
+        Self {
+            resolver,
+            ..Self::new()
+        }
+    }
+
+    /// Resolves function names the compiler does not know natively through `functions`.
+    /// The VM running the chunk must be given the same registry.
+    pub fn with_functions(mut self, functions: Arc<FunctionRegistry>) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    /// Resolves bare identifiers (`a + 1`) to the given values; unbound names compile to `#NAME?`.
+    pub fn with_bindings(mut self, bindings: Arc<HashMap<String, f64>>) -> Self {
+        self.bindings = bindings;
+        self
+    }
+
+    /// Selects which optimization passes run.
+    pub fn with_options(mut self, options: CompilerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Records the source range of every emitted instruction in `Chunk::spans`, so the VM can
+    /// locate a runtime error (`VM::error_span`). `source_map` comes from
+    /// `Parser::parse_with_spans` on the formula being compiled.
+    pub fn with_source_map(mut self, source_map: SourceMap) -> Self {
+        self.source_map = Some(source_map);
+        self
+    }
+
+    /// Type-checks `expr` before compiling it, so obviously-wrong formulas are rejected
+    /// up-front instead of failing on every cell at runtime. A hierarchy the resolver failed
+    /// to expand is an error here, where `compile` would expand it to no members, and so is a
+    /// member the resolver cannot confirm under `CompilerOptions::strict_members`.
+    pub fn try_compile(mut self, expr: &Expr) -> Result<Chunk, CompileError> {
+        typecheck::infer(expr)?;
+        let simplified = self.simplified(expr);
+        self.index_nodes(expr, &simplified);
+        self.compile_expr(&simplified);
+        if let Some(error) = self.resolver_error.take() {
+            return Err(CompileError::Resolver(error));
+        }
+        if let Some((dimension, member)) = self.unknown_member.take() {
+            return Err(CompileError::UnknownMember { dimension, member });
+        }
+        if let Some((function, found)) = self.bad_arity.take() {
+            return Err(CompileError::ArgumentCount { function, found });
+        }
+        Ok(self.finish())
+    }
+
     pub fn compile(mut self, expr: &Expr) -> Chunk {
-        self.compile_expr(expr);
+        let simplified = self.simplified(expr);
+        self.index_nodes(expr, &simplified);
+        self.compile_expr(&simplified);
+        self.finish()
+    }
+
+    fn simplified<'e>(&self, expr: &'e Expr) -> Cow<'e, Expr> {
+        if self.options.simplify {
+            Cow::Owned(simplify(expr.clone()))
+        } else {
+            Cow::Borrowed(expr)
+        }
+    }
+
+    /// Numbers the nodes of `compiled` in preorder, as the source map does for the parsed tree.
+    /// Simplification rewrites the tree so its nodes no longer line up with the parse; then
+    /// only the root keeps its span.
+    fn index_nodes(&mut self, parsed: &Expr, compiled: &Expr) {
+        if self.source_map.is_none() {
+            return;
+        }
+        self.node_ids = if std::ptr::eq(parsed, compiled) || parsed == compiled {
+            preorder(compiled).into_iter().enumerate().map(|(i, node)| (node as *const Expr as usize, i)).collect()
+        } else {
+            HashMap::from([(compiled as *const Expr as usize, 0)])
+        };
+    }
+
+    fn finish(mut self) -> Chunk {
         self.chunk.write_chunk(OpCode::Return);
+        if self.source_map.is_some() {
+            let root = self.chunk.spans.last().cloned().unwrap_or_default();
+            self.chunk.spans.resize(self.chunk.code.len(), root);
+        }
+        if self.options.peephole {
+            thread_jumps(&mut self.chunk.code);
+        }
         self.chunk
     }
 
@@ -28,24 +224,62 @@ impl Compiler {
     /// Compiles an expression and returns the number of values pushed to the stack.
     /// Usually 1, but can be N for Hierarchy Expansions.
     fn compile_expr_with_count(&mut self, expr: &Expr) -> usize {
+        let count = self.compile_node(expr);
+        self.claim_span(expr);
+        count
+    }
+
+    // Compiles an argument of SUM/AVG/MIN/MAX, the one place a FILTER may appear: its masked
+    // operands are left on the stack for the aggregation to fold.
+    fn compile_operand(&mut self, arg: &Expr) -> usize {
+        match arg {
+            Expr::FunctionCall { name, args } if name == "FILTER" => {
+                let count = self.compile_filter(args);
+                self.claim_span(arg);
+                count
+            }
+            _ => self.compile_expr_with_count(arg),
+        }
+    }
+
+    fn claim_span(&mut self, expr: &Expr) {
+        // Subexpressions claimed their own instructions first; the rest belong to `expr`
+        // Nodes the compiler builds itself (e.g. hierarchy expansions) are not in the parse
+        let index = self.node_ids.get(&(expr as *const Expr as usize));
+        if let Some(span) = self.source_map.as_ref().zip(index).and_then(|(map, &i)| map.span_at(i)) {
+            self.chunk.spans.resize(self.chunk.code.len(), span);
+        }
+    }
+
+    fn compile_node(&mut self, expr: &Expr) -> usize {
         match expr {
             Expr::Literal(val) => {
-                let idx = self.chunk.add_constant(*val);
+                let idx = self.constant(*val);
                 self.chunk.write_chunk(OpCode::Constant(idx));
                 1
             }
+            Expr::StringLiteral(text) => {
+                let idx = self.chunk.add_string(text);
+                self.chunk.write_chunk(OpCode::StringConstant(idx));
+                1
+            }
             Expr::Binary { op, lhs, rhs } => {
                 // Optimization: Constant Folding
-                if let (Expr::Literal(l), Expr::Literal(r)) = (lhs.as_ref(), rhs.as_ref()) {
-                     let val = match op {
-                         BinaryOp::Add => l + r,
-                         BinaryOp::Sub => l - r,
-                         BinaryOp::Mul => l * r,
-                         BinaryOp::Div => l / r,
+                // (Comparisons produce Bool, so only arithmetic is folded into the numeric pool)
+                if let (true, Expr::Literal(l), Expr::Literal(r)) = (self.options.fold_constants, lhs.as_ref(), rhs.as_ref()) {
+                     let folded = match op {
+                         BinaryOp::Add => Some(l + r),
+                         BinaryOp::Sub => Some(l - r),
+                         BinaryOp::Mul => Some(l * r),
+                         BinaryOp::Div => Some(l / r),
+                         BinaryOp::Mod => Some(modulo(*l, *r)),
+                         _ => None,
                      };
-                     let idx = self.chunk.add_constant(val);
-                     self.chunk.write_chunk(OpCode::Constant(idx));
-                     return 1;
+                     if let Some(val) = folded {
+                         let idx = self.constant(val);
+                         self.chunk.write_chunk(OpCode::Constant(idx));
+                         return 1;
+                     }
                 }
 
                 self.compile_expr(lhs);
@@ -55,52 +289,145 @@ impl Compiler {
                     BinaryOp::Sub => self.chunk.write_chunk(OpCode::Sub),
                     BinaryOp::Mul => self.chunk.write_chunk(OpCode::Mul),
                     BinaryOp::Div => self.chunk.write_chunk(OpCode::Div),
+                    BinaryOp::Mod => self.chunk.write_chunk(OpCode::Mod),
+                    BinaryOp::Eq => self.chunk.write_chunk(OpCode::Equal),
+                    BinaryOp::NotEq => self.chunk.write_chunk(OpCode::NotEqual),
+                    BinaryOp::Lt => self.chunk.write_chunk(OpCode::Less),
+                    BinaryOp::Lte => self.chunk.write_chunk(OpCode::LessEqual),
+                    BinaryOp::Gt => self.chunk.write_chunk(OpCode::Greater),
+                    BinaryOp::Gte => self.chunk.write_chunk(OpCode::GreaterEqual),
                 }
                 1
             }
-            Expr::Identifier(_) => {
-                // TODO: Load variable
+            Expr::Identifier(name) => {
+                match self.bindings.get(name) {
+                    Some(&val) => {
+                        let idx = self.constant(val);
+                        self.chunk.write_chunk(OpCode::Constant(idx));
+                    }
+                    None => self.emit_error("#NAME?"),
+                }
                 1
             }
             Expr::DimensionRef(name) => {
-                // TODO: Emit OpCode::LoadDimension(name)
-                // For now, push 0.0 placeholder
-                let idx = self.chunk.add_constant(0.0);
-                self.chunk.write_chunk(OpCode::Constant(idx));
+                self.emit_load(DEFAULT_REF_DIMENSION, name);
                 1
             }
-            Expr::FunctionCall { name, args } => {
+            Expr::CellRef(pairs) => {
+                self.emit_cell_load(pairs);
+                1
+            }
+            // Anywhere but an aggregation argument (`compile_operand`) nothing would fold the subset
+            Expr::FunctionCall { name, .. } if name == "FILTER" => {
+                self.emit_error("#VALUE!");
+                1
+            }
+            // Conditional aggregation: SUMIF/AVERAGEIF(set, condition) aggregate a FILTER mask
+            Expr::FunctionCall { name, args } if name == "SUMIF" || name == "AVERAGEIF" => {
+                let count = self.compile_filter(args);
+                let op = if name == "SUMIF" { OpCode::Sum(count) } else { OpCode::Avg(count) };
+                self.chunk.write_chunk(op);
+                1
+            }
+            Expr::FunctionCall { name, args } if name == "PCT_OF_PARENT" => {
+                self.compile_pct_of_parent(args);
+                1
+            }
+            Expr::FunctionCall { name, args } if name == "PCT_OF_TOTAL" => {
+                if let [part, total] = args.as_slice() {
+                    self.compile_expr(part);
+                    self.compile_expr(total);
+                    self.chunk.write_chunk(OpCode::Ratio);
+                } else {
+                    self.emit_error("#VALUE!");
+                }
+                1
+            }
+            // ISBLANK tests a single cell; a hierarchy expansion is counted with COUNTA instead
+            Expr::FunctionCall { name, args } if name == "ISBLANK" => {
+                match args.as_slice() {
+                    [arg] if !matches!(arg, Expr::HierarchyCall { .. }) => {
+                        self.compile_expr(arg);
+                        self.chunk.write_chunk(OpCode::IsBlank);
+                    }
+                    _ => self.emit_error("#VALUE!"),
+                }
+                1
+            }
+            Expr::FunctionCall { name, args } if name == "XLOOKUP" => {
+                self.compile_xlookup(args);
+                1
+            }
+            Expr::FunctionCall { name, args } if name == "IF" => {
+                self.compile_if(args);
+                1
+            }
+            Expr::FunctionCall { name, args } if name == "SWITCH" => {
+                self.compile_switch(args);
+                1
+            }
+            Expr::FunctionCall { name, args } if name == "ROLLING_AVG" => {
+                self.compile_rolling_avg(args);
+                1
+            }
+            Expr::FunctionCall { name, args } if name == "EMA" => {
+                self.compile_ema(args);
+                1
+            }
+            Expr::FunctionCall { name, args } if name == "LAG" || name == "LEAD" => {
+                self.compile_period_offset(args, if name == "LAG" { -1 } else { 1 });
+                1
+            }
+            Expr::FunctionCall { name, args } if matches!(name.as_str(), "SQRT" | "EXP" | "LN" | "LOG") => {
+                self.compile_math(name, args);
+                1
+            }
+            Expr::FunctionCall { name, args } if matches!(name.as_str(), "SUM" | "AVG" | "MIN" | "MAX") => {
+                self.compile_aggregate(name, args);
+                1
+            }
+            Expr::FunctionCall { name, args }
+                if matches!(
+                    name.as_str(),
+                    "LOOKUP" | "CONCAT" | "COALESCE" | "COUNTA" | "TEXT" | "YEAR" | "MONTH" | "DAY" | "QUARTER"
+                ) =>
+            {
                 let mut arg_count = 0;
                 for arg in args {
                     arg_count += self.compile_expr_with_count(arg);
                 }
                 
                 match name.as_str() {
-                    "SUM" => self.chunk.write_chunk(OpCode::Sum(arg_count)),
-                    "AVG" => self.chunk.write_chunk(OpCode::Avg(arg_count)),
-                    "MIN" => self.chunk.write_chunk(OpCode::Min(arg_count)),
-                    "MAX" => self.chunk.write_chunk(OpCode::Max(arg_count)),
                     "LOOKUP" => self.chunk.write_chunk(OpCode::Lookup),
-                    "XLOOKUP" => self.chunk.write_chunk(OpCode::XLookup(arg_count)),
-                    _ => {
-                        // TODO: Unknown function
-                    }
+                    "CONCAT" => self.chunk.write_chunk(OpCode::Concat(arg_count)),
+                    "COALESCE" => self.chunk.write_chunk(OpCode::Coalesce(arg_count)),
+                    "COUNTA" => self.chunk.write_chunk(OpCode::CountA(arg_count)),
+                    "TEXT" => self.chunk.write_chunk(OpCode::Text),
+                    "YEAR" => self.chunk.write_chunk(OpCode::DatePart(DatePartKind::Year)),
+                    "MONTH" => self.chunk.write_chunk(OpCode::DatePart(DatePartKind::Month)),
+                    "DAY" => self.chunk.write_chunk(OpCode::DatePart(DatePartKind::Day)),
+                    _ => self.chunk.write_chunk(OpCode::DatePart(DatePartKind::Quarter)),
                 }
                 1 
             }
+            Expr::FunctionCall { name, args } => {
+                self.compile_native_call(name, args);
+                1
+            }
             // Ultra Diamond: Hierarchy Expansion
+            // An anchor only means something as a step of a `->` chain
+            Expr::HierarchyCall { name, .. } if name == "Period" => {
+                self.emit_error("#VALUE!");
+                1
+            }
             Expr::HierarchyCall { name, args } => {
-                if name == "Children" && args.len() == 2 {
-                    if let (Expr::DimensionRef(dim), Expr::DimensionRef(member)) = (&args[0], &args[1]) {
-                        let children = self.resolver.get_children(dim, member);
-                        let count = children.len();
-                        for child in children {
-                             // Emit Load for each child
-                             // Re-using compile logic for DimensionRef
-                             self.compile_expr_with_count(&Expr::DimensionRef(child));
-                        }
-                        return count;
+                if let Some((dim, children)) = self.expand_hierarchy(name, args) {
+                    let count = children.len();
+                    for child in children {
+                         // Emit Load for each child, addressed within the expanded dimension
+                         self.emit_load(&dim, &child);
                     }
+                    return count;
                 }
                 0 // Error or empty
             }
@@ -119,11 +446,548 @@ impl Compiler {
             }
             // Ultra Diamond: Time Travel (Shift) (->)
             Expr::TimeTravel { lhs, rhs } => {
+                if let Some((metric, anchor, offset)) = resolve_time_chain(expr) {
+                    self.compile_time_chain(metric, anchor, offset);
+                    return 1;
+                }
                 self.compile_expr(lhs);
                 self.compile_expr(rhs);
                 self.chunk.write_chunk(OpCode::Shift);
                 1
             }
+            Expr::In { value, candidates } => {
+                self.compile_expr(value);
+                let mut count = 0;
+                for candidate in candidates {
+                    count += self.compile_expr_with_count(candidate);
+                }
+                self.chunk.write_chunk(OpCode::In(count));
+                1
+            }
+        }
+    }
+
+    /// Normalizes a member display name to its canonical key, so `[United States]` and `[US]`
+    /// address the same cell. Every member reference passes through here, so this is also where
+    /// `strict_members` checks that the resolver knows the member.
+    fn canonical_member(&self, dimension: &str, member: &str) -> String {
+        if self.options.strict_members && self.resolver.member_exists(&dimension.into(), &member.into()) != Some(true) {
+            let _ = self.unknown_member.set((dimension.to_string(), member.to_string()));
+        }
+        self.resolver
+            .resolve_alias(&dimension.into(), member)
+            .map(Member::into_string)
+            .unwrap_or_else(|| member.to_string())
+    }
+
+    /// Resolves a hierarchy macro such as `@Children([Region], [North America])`
+    /// to its dimension and member list.
+    fn expand_hierarchy(&self, name: &str, args: &[Expr]) -> Option<(String, Vec<String>)> {
+        if name == "Children" && args.len() == 2 {
+            if let (Expr::DimensionRef(dim), Expr::DimensionRef(member)) = (&args[0], &args[1]) {
+                let member = self.canonical_member(dim, member);
+                let children = match self.resolver.try_get_children(&dim.as_str().into(), &member.into()) {
+                    Ok(children) => children,
+                    Err(e) => {
+                        let _ = self.resolver_error.set(format!("{:#}", e));
+                        Vec::new()
+                    }
+                };
+                return Some((dim.clone(), children.into_iter().map(Member::into_string).collect()));
+            }
+        }
+        None
+    }
+
+    /// Emits a load of the cell at `dimension=member`, relative to the cell being evaluated
+    /// (and to the member pinned by an enclosing FILTER).
+    fn emit_load(&mut self, dimension: &str, member: &str) {
+        let member = self.canonical_member(dimension, member);
+        let mut pairs = self.scope.clone();
+        pairs.retain(|(d, _)| d != dimension);
+        pairs.push((dimension.to_string(), member));
+        let idx = self.coordinate(pairs);
+        self.chunk.write_chunk(OpCode::LoadDimension(idx));
+    }
+
+    /// Emits a load of the cell addressed by a `CELL(...)` reference. Dimensions the pairs do
+    /// not name follow the evaluated cell (and an enclosing FILTER pin) as for `[X]`, so a
+    /// reference naming every dimension reads the same cell wherever it is evaluated.
+    fn emit_cell_load(&mut self, pairs: &[(String, String)]) {
+        let mut coordinate = self.scope.clone();
+        coordinate.retain(|(d, _)| !pairs.iter().any(|(named, _)| named == d));
+        for (dimension, member) in pairs {
+            coordinate.push((dimension.clone(), self.canonical_member(dimension, member)));
+        }
+        let idx = self.coordinate(coordinate);
+        self.chunk.write_chunk(OpCode::LoadDimension(idx));
+    }
+
+    /// Adds `value` to the constant pool, reusing an identical entry when CSE is on.
+    fn constant(&mut self, value: f64) -> usize {
+        let existing = self.options.cse.then(|| {
+            self.chunk.constants.iter().position(|c| c.to_bits() == value.to_bits())
+        });
+        existing.flatten().unwrap_or_else(|| self.chunk.add_constant(value))
+    }
+
+    /// Adds a cell reference to the coordinate pool, reusing an identical entry when CSE is on.
+    /// Names are normalized here, since `coordinate_hash` hashes them exactly as given.
+    fn coordinate(&mut self, mut pairs: Vec<(String, String)>) -> usize {
+        for (dimension, member) in &mut pairs {
+            *dimension = normalize_name(dimension, false);
+            *member = normalize_name(member, self.options.fold_case);
+        }
+        let existing = self.options.cse.then(|| self.chunk.coordinates.iter().position(|c| *c == pairs));
+        existing.flatten().unwrap_or_else(|| self.chunk.add_coordinate(pairs))
+    }
+
+    /// Emits an error value in place of an expression that cannot be compiled.
+    fn emit_error(&mut self, error: &str) {
+        let idx = self.chunk.add_string(error);
+        self.chunk.write_chunk(OpCode::ErrorConstant(idx));
+    }
+
+    /// Emits the result of an aggregation with no operands at all, e.g. over an expansion
+    /// that resolved to no members (see `CompilerOptions::empty_avg_nan`).
+    fn emit_empty_aggregate(&mut self, name: &str) {
+        match name {
+            "SUM" => {
+                let idx = self.constant(0.0);
+                self.chunk.write_chunk(OpCode::Constant(idx));
+            }
+            "AVG" if self.options.empty_avg_nan => {
+                let idx = self.constant(f64::NAN);
+                self.chunk.write_chunk(OpCode::Constant(idx));
+            }
+            "AVG" => self.emit_error("#DIV/0!"),
+            _ => self.emit_error("#N/A"),
+        }
+    }
+
+    // FILTER(set, predicate): for each member of the set, push its value followed by the
+    // predicate evaluated with that member pinned, then mask with OpCode::Filter.
+    // Pinning makes the element implicit: `[Revenue] > 1000` reads Revenue at each member.
+    // Returns the number of values left on the stack for the enclosing aggregation.
+    fn compile_filter(&mut self, args: &[Expr]) -> usize {
+        let expansion = match args {
+            [Expr::HierarchyCall { name, args }, _] => self.expand_hierarchy(name, args),
+            _ => None,
+        };
+        let Some((dim, members)) = expansion else {
+            self.emit_error("#VALUE!");
+            return 1;
+        };
+        let predicate = &args[1];
+
+        for member in &members {
+            self.emit_load(&dim, member);
+            self.scope.push((dim.clone(), member.clone()));
+            self.compile_expr(predicate);
+            self.scope.pop();
+        }
+        self.chunk.write_chunk(OpCode::Filter(members.len()));
+        members.len()
+    }
+
+    // SUM/AVG/MIN/MAX. Hierarchy arguments are expanded once, both to count the operands and to
+    // emit their loads; operand sets larger than the aggregate budget are streamed.
+    fn compile_aggregate(&mut self, name: &str, args: &[Expr]) {
+        let expansions: Vec<Option<(String, Vec<String>)>> = args
+            .iter()
+            .map(|arg| match arg {
+                Expr::HierarchyCall { name, args } => self.expand_hierarchy(name, args),
+                _ => None,
+            })
+            .collect();
+        let operands: usize = args
+            .iter()
+            .zip(&expansions)
+            .map(|(arg, expansion)| match (arg, expansion) {
+                (_, Some((_, members))) => members.len(),
+                (Expr::HierarchyCall { .. }, None) => 0,
+                _ => 1,
+            })
+            .sum();
+        if operands > self.options.aggregate_budget {
+            return self.compile_streaming_aggregate(name, args, expansions);
+        }
+
+        let mut count = 0;
+        for (arg, expansion) in args.iter().zip(expansions) {
+            count += match expansion {
+                Some((dim, members)) => {
+                    for member in &members {
+                        self.emit_load(&dim, member);
+                    }
+                    members.len()
+                }
+                None => self.compile_operand(arg),
+            };
+        }
+        if count == 0 {
+            return self.emit_empty_aggregate(name);
+        }
+        let op = match name {
+            "SUM" => OpCode::Sum(count),
+            "AVG" => OpCode::Avg(count),
+            "MIN" => OpCode::Min(count),
+            _ => OpCode::Max(count),
+        };
+        self.chunk.write_chunk(op);
+    }
+
+    // Streams an aggregation through an accumulator: operands are pushed in blocks of at most
+    // `aggregate_budget`, and each block is folded in by AccFeed before the next is loaded.
+    // Hierarchy expansions (from `compile_aggregate`) are split across blocks; any other
+    // argument is a single operand (a FILTER argument is fed as one block).
+    fn compile_streaming_aggregate(&mut self, name: &str, args: &[Expr], expansions: Vec<Option<(String, Vec<String>)>>) {
+        let kind = match name {
+            "SUM" => AggregateKind::Sum,
+            "AVG" => AggregateKind::Avg,
+            "MIN" => AggregateKind::Min,
+            _ => AggregateKind::Max,
+        };
+        let budget = self.options.aggregate_budget.max(1);
+        self.chunk.write_chunk(OpCode::AccBegin(kind));
+
+        let mut pending = 0;
+        for (arg, expansion) in args.iter().zip(expansions) {
+            match expansion {
+                Some((dim, members)) => {
+                    for member in &members {
+                        self.emit_load(&dim, member);
+                        pending += 1;
+                        if pending == budget {
+                            self.chunk.write_chunk(OpCode::AccFeed(pending));
+                            pending = 0;
+                        }
+                    }
+                }
+                None => {
+                    pending += self.compile_operand(arg);
+                    if pending >= budget {
+                        self.chunk.write_chunk(OpCode::AccFeed(pending));
+                        pending = 0;
+                    }
+                }
+            }
+        }
+        if pending > 0 {
+            self.chunk.write_chunk(OpCode::AccFeed(pending));
+        }
+        self.chunk.write_chunk(OpCode::AccEnd);
+    }
+
+    // PCT_OF_PARENT([Dim], [Member]) or PCT_OF_PARENT([Member]) on the default dimension:
+    // the member's value divided by its parent's, with the parent resolved at compile time.
+    fn compile_pct_of_parent(&mut self, args: &[Expr]) {
+        let (dim, member) = match args {
+            [Expr::DimensionRef(member)] => (DEFAULT_REF_DIMENSION.to_string(), member),
+            [Expr::DimensionRef(dim), Expr::DimensionRef(member)] => (dim.clone(), member),
+            _ => return self.emit_error("#VALUE!"),
+        };
+        let member = self.canonical_member(&dim, member);
+        let Some(parent) = self.resolver.get_parent(&dim.as_str().into(), &member.as_str().into()) else {
+            return self.emit_error("#N/A"); // Root members have no parent
+        };
+        self.emit_load(&dim, &member);
+        self.emit_load(&dim, parent.as_str());
+        self.chunk.write_chunk(OpCode::Ratio);
+    }
+
+    // XLOOKUP(value, lookup_array, return_array, [if_not_found], [match_mode], [search_mode]).
+    // Both arrays are hierarchy expansions of the same length, read relative to the evaluated
+    // cell; a miss gives `if_not_found` (#N/A by default). The modes must be integer constants:
+    // match_mode 0 exact, -1 exact or next smaller, 1 exact or next larger; search_mode 1 first
+    // to last, -1 last to first. The binary search modes 2/-2 scan linearly, which on the
+    // sorted arrays they require finds the same key.
+    fn compile_xlookup(&mut self, args: &[Expr]) {
+        let [value, Expr::HierarchyCall { name: keys_fn, args: keys_args }, Expr::HierarchyCall { name: results_fn, args: results_args }, optional @ ..] = args else {
+            return self.emit_error("#VALUE!");
+        };
+        let match_mode = match optional.get(1).map(integer_constant) {
+            None | Some(Some(0)) => MatchMode::Exact,
+            Some(Some(-1)) => MatchMode::ExactOrNextSmaller,
+            Some(Some(1)) => MatchMode::ExactOrNextLarger,
+            _ => return self.emit_error("#VALUE!"),
+        };
+        let search_mode = match optional.get(2).map(integer_constant) {
+            None | Some(Some(1 | 2 | -2)) => SearchMode::FirstToLast,
+            Some(Some(-1)) => SearchMode::LastToFirst,
+            _ => return self.emit_error("#VALUE!"),
+        };
+        let (Some((keys_dim, keys)), Some((results_dim, results))) =
+            (self.expand_hierarchy(keys_fn, keys_args), self.expand_hierarchy(results_fn, results_args))
+        else {
+            return self.emit_error("#VALUE!");
+        };
+        if keys.len() != results.len() || optional.len() > 3 {
+            return self.emit_error("#VALUE!");
+        }
+
+        self.compile_expr(value);
+        for key in &keys {
+            self.emit_load(&keys_dim, key);
+        }
+        for result in &results {
+            self.emit_load(&results_dim, result);
+        }
+        match optional.first() {
+            Some(default) => self.compile_expr(default),
+            None => self.emit_error("#N/A"),
+        }
+        self.chunk.write_chunk(OpCode::XLookup(keys.len(), match_mode, search_mode));
+    }
+
+    // IF(cond, then, else): cond; JumpIfFalse(else); then; Jump(end); else: ...; end:
+    fn compile_if(&mut self, args: &[Expr]) {
+        let [condition, then_branch, else_branch] = args else {
+            self.emit_error("#VALUE!");
+            return;
+        };
+        self.compile_expr(condition);
+        let jump_to_else = self.emit_placeholder();
+        self.compile_expr(then_branch);
+        let jump_to_end = self.emit_placeholder();
+
+        let else_start = self.chunk.code.len();
+        self.chunk.code[jump_to_else] = OpCode::JumpIfFalse(else_start);
+        self.compile_expr(else_branch);
+        let end = self.chunk.code.len();
+        self.chunk.code[jump_to_end] = OpCode::Jump(end);
+    }
+
+    // SWITCH(expr, val1, res1, ..., default): the subject is evaluated once and kept on the
+    // stack while each case compares against a copy of it:
+    //   expr; [Dup; val; Equal; JumpIfFalse(next); Pop; res; Jump(end); next:]...; Pop; default; end:
+    // The first matching case wins; after the subject an odd number of arguments is required
+    // (value/result pairs, then the default), so the total is even.
+    fn compile_switch(&mut self, args: &[Expr]) {
+        let [subject, rest @ .., default] = args else {
+            return self.emit_error("#VALUE!");
+        };
+        if rest.is_empty() || rest.len() % 2 != 0 {
+            return self.emit_error("#VALUE!");
+        }
+        self.compile_expr(subject);
+        let mut jumps_to_end = Vec::with_capacity(rest.len() / 2);
+        for case in rest.chunks(2) {
+            self.chunk.write_chunk(OpCode::Dup);
+            self.compile_expr(&case[0]);
+            self.chunk.write_chunk(OpCode::Equal);
+            let jump_to_next = self.emit_placeholder();
+            self.chunk.write_chunk(OpCode::Pop);
+            self.compile_expr(&case[1]);
+            jumps_to_end.push(self.emit_placeholder());
+            let next = self.chunk.code.len();
+            self.chunk.code[jump_to_next] = OpCode::JumpIfFalse(next);
+        }
+        self.chunk.write_chunk(OpCode::Pop);
+        self.compile_expr(default);
+        let end = self.chunk.code.len();
+        for jump in jumps_to_end {
+            self.chunk.code[jump] = OpCode::Jump(end);
+        }
+    }
+
+    /// Reserves an instruction slot to be patched once the jump target is known.
+    fn emit_placeholder(&mut self) -> usize {
+        self.chunk.write_chunk(OpCode::Jump(usize::MAX));
+        self.chunk.code.len() - 1
+    }
+
+    // ROLLING_AVG([Metric], window): the metric must be a cell reference so the VM can
+    // re-address it in prior periods, and the window must be a positive integer literal.
+    fn compile_rolling_avg(&mut self, args: &[Expr]) {
+        if let [Expr::DimensionRef(name), Expr::Literal(window)] = args {
+            if *window >= 1.0 && window.fract() == 0.0 {
+                let member = self.canonical_member(DEFAULT_REF_DIMENSION, name);
+                let idx = self.coordinate(vec![(DEFAULT_REF_DIMENSION.to_string(), member)]);
+                self.chunk.write_chunk(OpCode::RollingAvg(idx, *window as usize));
+                return;
+            }
+        }
+        self.emit_error("#VALUE!");
+    }
+
+    // EMA([Metric], alpha): like ROLLING_AVG the metric must be a cell reference; the smoothing
+    // factor must be a literal in (0, 1] (`EMA([Revenue], 30%)`).
+    fn compile_ema(&mut self, args: &[Expr]) {
+        if let [Expr::DimensionRef(name), Expr::Literal(alpha)] = args {
+            if *alpha > 0.0 && *alpha <= 1.0 {
+                let member = self.canonical_member(DEFAULT_REF_DIMENSION, name);
+                let idx = self.coordinate(vec![(DEFAULT_REF_DIMENSION.to_string(), member)]);
+                let alpha_idx = self.constant(*alpha);
+                self.chunk.write_chunk(OpCode::Ema(idx, alpha_idx));
+                return;
+            }
+        }
+        self.emit_error("#VALUE!");
+    }
+
+    // Host-defined functions from the registry. A name that is neither built in nor registered
+    // is `#NAME?`, like an unbound identifier. A call whose argument count (after hierarchy
+    // expansion) differs from the registered arity is `#VALUE!`, and an error in `try_compile`.
+    fn compile_native_call(&mut self, name: &str, args: &[Expr]) {
+        let Some(id) = self.functions.id(name) else {
+            self.emit_error("#NAME?");
+            return;
+        };
+        let start = self.chunk.code.len();
+        let mut arg_count = 0;
+        for arg in args {
+            arg_count += self.compile_expr_with_count(arg);
+        }
+        if self.functions.get(id).is_some_and(|function| function.arity == arg_count) {
+            self.chunk.write_chunk(OpCode::CallNative(id, arg_count));
+            return;
+        }
+        // Nothing consumes the arguments, so they must not be left on the stack
+        self.chunk.code.truncate(start);
+        self.chunk.spans.truncate(start);
+        let _ = self.bad_arity.set((name.to_string(), arg_count));
+        self.emit_error("#VALUE!");
+    }
+
+    // SQRT/EXP/LN(x) and LOG(x, base). Literal arguments inside the function's domain fold to a
+    // constant; anything else is left to the VM, which reports domain errors per cell.
+    fn compile_math(&mut self, name: &str, args: &[Expr]) {
+        let (op, arity) = match name {
+            "SQRT" => (OpCode::Sqrt, 1),
+            "EXP" => (OpCode::Exp, 1),
+            "LN" => (OpCode::Ln, 1),
+            _ => (OpCode::Log, 2),
+        };
+        if args.len() != arity {
+            self.emit_error("#VALUE!");
+            return;
+        }
+        if self.options.fold_constants {
+            let folded = match args {
+                [Expr::Literal(x)] => match op {
+                    OpCode::Sqrt => value::sqrt(*x).ok(),
+                    OpCode::Exp => value::exp(*x).ok(),
+                    _ => value::ln(*x).ok(),
+                },
+                [Expr::Literal(x), Expr::Literal(base)] => value::log(*x, *base).ok(),
+                _ => None,
+            };
+            if let Some(val) = folded {
+                let idx = self.constant(val);
+                self.chunk.write_chunk(OpCode::Constant(idx));
+                return;
+            }
+        }
+        for arg in args {
+            self.compile_expr(arg);
+        }
+        self.chunk.write_chunk(op);
+    }
+
+    // LAG([Metric], n) / LEAD([Metric], n): the metric `n` periods earlier / later. Like
+    // ROLLING_AVG the metric must be a cell reference and `n` an integer literal from 0 to
+    // `MAX_PERIOD_OFFSET`.
+    fn compile_period_offset(&mut self, args: &[Expr], direction: i64) {
+        if let [Expr::DimensionRef(name), Expr::Literal(periods)] = args {
+            if (0.0..=MAX_PERIOD_OFFSET).contains(periods) && periods.fract() == 0.0 {
+                let idx = self.metric_coordinate(name);
+                self.chunk.write_chunk(OpCode::PeriodOffset(idx, direction * *periods as i64));
+                return;
+            }
+        }
+        self.emit_error("#VALUE!");
+    }
+
+    // `[Metric] -> ... ` resolved by `resolve_time_chain`: the metric `offset` periods away from
+    // the anchor, or from the evaluated cell's period when the chain has no anchor.
+    fn compile_time_chain(&mut self, metric: &str, anchor: Option<&str>, offset: i64) {
+        let idx = self.metric_coordinate(metric);
+        if let Some(period) = anchor {
+            let period = self.chunk.add_string(&normalize_name(period, self.options.fold_case));
+            self.chunk.write_chunk(OpCode::SetPeriod(period));
+        }
+        self.chunk.write_chunk(OpCode::CalendarOffset(idx, offset));
+    }
+
+    /// Coordinate pool entry of a bare `[Metric]` read by a time-series opcode.
+    fn metric_coordinate(&mut self, name: &str) -> usize {
+        let member = self.canonical_member(DEFAULT_REF_DIMENSION, name);
+        self.coordinate(vec![(DEFAULT_REF_DIMENSION.to_string(), member)])
+    }
+}
+
+/// Parses and compiles (`try_compile`) every formula of a model load in parallel, returning
+/// the results in input order. Every compiler shares `resolver`, which must tolerate
+/// concurrent lookups. Formulas longer than `DEFAULT_MAX_FORMULA_LEN` are rejected before lexing.
+pub fn compile_batch(
+    formulas: &[String],
+    resolver: Arc<dyn HierarchyResolver + Send + Sync>,
+) -> Vec<Result<Chunk, EngineError>> {
+    formulas
+        .par_iter()
+        .map(|formula| {
+            let expr = Parser::new_bounded(formula, DEFAULT_MAX_FORMULA_LEN).parse()?;
+            Ok(Compiler::with_resolver(Arc::clone(&resolver)).try_compile(&expr)?)
+        })
+        .collect()
+}
+
+/// Resolves a `->` chain over a cell reference to `(metric, anchor, offset)`. Each relative
+/// step (`[PrevMonth]`) moves from the period before it; an absolute anchor (`@Period("2024-01")`)
+/// discards every earlier step, so the steps after it are relative to the anchor.
+/// None for chains with any other step, which keep the generic `Shift`.
+fn resolve_time_chain(expr: &Expr) -> Option<(&str, Option<&str>, i64)> {
+    match expr {
+        Expr::DimensionRef(metric) => Some((metric, None, 0)),
+        Expr::TimeTravel { lhs, rhs } => {
+            let (metric, anchor, offset) = resolve_time_chain(lhs)?;
+            match &**rhs {
+                Expr::HierarchyCall { name, args } if name == "Period" => match args.as_slice() {
+                    [Expr::StringLiteral(period)] => Some((metric, Some(period), 0)),
+                    _ => None,
+                },
+                Expr::DimensionRef(step) => {
+                    let (_, shift) = RELATIVE_SHIFTS.iter().find(|(name, _)| name == step)?;
+                    Some((metric, anchor, offset + shift))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Value of an integer constant argument such as a mode flag. There is no unary minus, so
+/// differences of literals (`0 - 1`) are accepted too.
+fn integer_constant(expr: &Expr) -> Option<i64> {
+    let value = match expr {
+        Expr::Literal(x) => *x,
+        Expr::Binary { op: BinaryOp::Sub, lhs, rhs } => match (&**lhs, &**rhs) {
+            (Expr::Literal(a), Expr::Literal(b)) => a - b,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    (value.fract() == 0.0).then_some(value as i64)
+}
+
+/// Retargets every jump whose target is an unconditional jump to that jump's own target.
+/// The compiler only emits forward jumps, so following a chain always terminates.
+fn thread_jumps(code: &mut [OpCode]) {
+    for i in 0..code.len() {
+        let (OpCode::Jump(mut target) | OpCode::JumpIfFalse(mut target)) = code[i] else {
+            continue;
+        };
+        while let Some(&OpCode::Jump(next)) = code.get(target) {
+            if next <= target {
+                break;
+            }
+            target = next;
         }
+        code[i] = match code[i] {
+            OpCode::JumpIfFalse(_) => OpCode::JumpIfFalse(target),
+            _ => OpCode::Jump(target),
+        };
     }
 }
@@ -0,0 +1,99 @@
+use thiserror::Error;
+use crate::atom_script::compiler::CompileError;
+use crate::atom_script::parser::ParseError;
+use crate::atom_script::vm::{InterpretResult, RuntimeFault};
+
+/// Unified error for the formula pipeline (parse -> compile -> evaluate).
+/// Each stage's error converts into it with `?`, and it converts into `anyhow::Error`.
+#[derive(Debug, Error, PartialEq)]
+pub enum EngineError {
+    #[error("parse error: {0}")]
+    Parse(#[from] ParseError),
+    #[error("compile error: {0}")]
+    Compile(#[from] CompileError),
+    #[error("runtime error: {0}")]
+    Runtime(#[from] RuntimeError),
+}
+
+/// Evaluation failures, mirroring the non-value outcomes of `InterpretResult`.
+#[derive(Debug, Error, PartialEq)]
+pub enum RuntimeError {
+    #[error("formula evaluated to error value {0}")]
+    ErrorValue(String),
+    #[error("type error: {0}")]
+    Type(String),
+    #[error("expected a numeric result, got text {0:?}")]
+    NotNumeric(String),
+    #[error("invalid bytecode or stack state")]
+    Invalid,
+    #[error("{0}")]
+    Fault(RuntimeFault),
+    #[error("evaluation exceeded the instruction limit")]
+    Timeout,
+    #[error("{0} is not supported by the direct interpreter")]
+    Unsupported(String),
+}
+
+impl InterpretResult {
+    /// Converts a numeric evaluation outcome into a `Result`, so callers can use `?`.
+    pub fn into_number(self) -> Result<f64, EngineError> {
+        let err = match self {
+            InterpretResult::Ok(value) => return Ok(value),
+            InterpretResult::Text(text) => RuntimeError::NotNumeric(text),
+            InterpretResult::ErrorValue(e) => RuntimeError::ErrorValue(e),
+            InterpretResult::TypeError(e) => RuntimeError::Type(e),
+            InterpretResult::CompileError => RuntimeError::Invalid,
+            InterpretResult::RuntimeError(fault) => RuntimeError::Fault(fault),
+            InterpretResult::EvaluationTimeout => RuntimeError::Timeout,
+        };
+        Err(EngineError::Runtime(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atom_script::test_util::evaluate;
+    use crate::atom_script::typecheck::Type;
+
+    #[test]
+    fn test_engine_error_display() {
+        let parse = EngineError::from(ParseError::TooDeep { limit: 4 });
+        assert_eq!(parse.to_string(), "parse error: expression nesting exceeds the maximum depth of 4");
+
+        let compile = EngineError::from(CompileError::TypeMismatch {
+            expected: Type::Num,
+            found: Type::Text,
+            expr: "\"x\"".to_string(),
+        });
+        assert_eq!(compile.to_string(), "compile error: type mismatch in `\"x\"`: expected Num, found Text");
+
+        let cases = [
+            (RuntimeError::ErrorValue("#N/A".to_string()), "runtime error: formula evaluated to error value #N/A"),
+            (RuntimeError::Type("bad".to_string()), "runtime error: type error: bad"),
+            (RuntimeError::NotNumeric("hi".to_string()), "runtime error: expected a numeric result, got text \"hi\""),
+            (RuntimeError::Invalid, "runtime error: invalid bytecode or stack state"),
+            (RuntimeError::Fault(RuntimeFault::BadConstantIndex(9)), "runtime error: constant index 9 out of range"),
+            (RuntimeError::Timeout, "runtime error: evaluation exceeded the instruction limit"),
+            (RuntimeError::Unsupported("@Children".to_string()), "runtime error: @Children is not supported by the direct interpreter"),
+        ];
+        for (err, expected) in cases {
+            assert_eq!(EngineError::from(err).to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_question_mark_conversions() {
+        assert_eq!(evaluate("1 + 2"), Ok(3.0));
+        assert!(matches!(evaluate("1 +"), Err(EngineError::Parse(_))));
+        assert!(matches!(evaluate(r#"1 + "x""#), Err(EngineError::Compile(_))));
+        assert!(matches!(evaluate(r#"CONCAT("a")"#), Err(EngineError::Runtime(RuntimeError::NotNumeric(_)))));
+
+        fn via_anyhow() -> anyhow::Result<f64> {
+            Ok(evaluate(r#"1 + "x""#)?)
+        }
+        let err = via_anyhow().unwrap_err();
+        assert!(err.downcast_ref::<EngineError>().is_some());
+        assert!(err.to_string().starts_with("compile error: type mismatch"));
+    }
+}
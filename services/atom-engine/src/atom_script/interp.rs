@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use crate::atom_script::ast::{BinaryOp, Expr};
+use crate::atom_script::chunk::{AggregateKind, OpCode};
+use crate::atom_script::error::{EngineError, RuntimeError};
+use crate::atom_script::value::{modulo, Value};
+use crate::atom_script::vm::{aggregate_operand, aggregate_values, arith_values, compare_values, truthy, InterpretResult};
+use crate::lattice::arena::LatticeArena;
+use crate::lattice::coordinate::{overlay_hash, Member, DEFAULT_REF_DIMENSION};
+use crate::lattice::metadata::HierarchyResolver;
+
+/// Lookups for `Expr::eval_direct`: cells come from the arena relative to `coordinate`, bare
+/// identifiers from `bindings`, and member aliases are resolved through `resolver`. Without an
+/// arena every cell reads as empty, as in the VM.
+#[derive(Default)]
+pub struct EvalContext<'a> {
+    arena: Option<&'a LatticeArena>,
+    bindings: Option<&'a HashMap<String, f64>>,
+    resolver: Option<&'a dyn HierarchyResolver>,
+    coordinate: Vec<(String, String)>,
+}
+
+impl<'a> EvalContext<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_arena(mut self, arena: &'a LatticeArena) -> Self {
+        self.arena = Some(arena);
+        self
+    }
+
+    pub fn with_bindings(mut self, bindings: &'a HashMap<String, f64>) -> Self {
+        self.bindings = Some(bindings);
+        self
+    }
+
+    /// Resolves member aliases (`[United States]` to `US`) as the compiler does.
+    pub fn with_resolver(mut self, resolver: &'a dyn HierarchyResolver) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    pub fn with_coordinate(mut self, coordinate: Vec<(String, String)>) -> Self {
+        self.coordinate = coordinate;
+        self
+    }
+
+    /// Reads the cell at the evaluated coordinate with `overrides` applied, e.g.
+    /// `[("Measure", "Revenue")]` for `[Revenue]`. A cell that was never set reads as Empty.
+    pub fn lookup_dimension(&self, overrides: &[(String, String)]) -> Value {
+        let resolved: Vec<(String, String)> = overrides
+            .iter()
+            .map(|(dimension, member)| {
+                let member = self
+                    .resolver
+                    .and_then(|r| r.resolve_alias(&dimension.into(), member))
+                    .map_or_else(|| member.clone(), Member::into_string);
+                (dimension.clone(), member)
+            })
+            .collect();
+        let hash = overlay_hash(&self.coordinate, &resolved);
+        let stored = self.arena.and_then(|arena| {
+            arena.get_cell_opt(hash).or_else(|| arena.get_date(hash).map(|millis| millis as f64))
+        });
+        stored.map_or(Value::Empty, Value::Num)
+    }
+
+    /// Value of a bare identifier, if it is bound.
+    pub fn lookup_var(&self, name: &str) -> Option<f64> {
+        self.bindings.and_then(|b| b.get(name)).copied()
+    }
+}
+
+impl Expr {
+    /// Evaluates the expression by walking the tree, without compiling a chunk or starting a
+    /// VM. For one-shot and test evaluations of simple formulas: literals, cell references,
+    /// identifiers, arithmetic, comparisons, SUM/AVG/MIN/MAX and IF. Anything else (hierarchy
+    /// and time functions, text) is rejected as `RuntimeError::Unsupported`.
+    /// Results are identical to the compiled path, with member aliases resolved only when the
+    /// context has a resolver.
+    pub fn eval_direct(&self, ctx: &EvalContext) -> Result<f64, EngineError> {
+        check_supported(self).map_err(|what| EngineError::Runtime(RuntimeError::Unsupported(what)))?;
+        let result: InterpretResult = match eval(self, ctx) {
+            Ok(value) => value.into(),
+            Err(result) => result,
+        };
+        result.into_number()
+    }
+}
+
+fn check_supported(expr: &Expr) -> Result<(), String> {
+    match expr {
+        Expr::Literal(_) | Expr::Identifier(_) | Expr::DimensionRef(_) | Expr::CellRef(_) => Ok(()),
+        Expr::Binary { lhs, rhs, .. } => {
+            check_supported(lhs)?;
+            check_supported(rhs)
+        }
+        Expr::FunctionCall { name, args } if matches!(name.as_str(), "SUM" | "AVG" | "MIN" | "MAX" | "IF") => {
+            args.iter().try_for_each(check_supported)
+        }
+        Expr::FunctionCall { name, .. } => Err(name.clone()),
+        Expr::HierarchyCall { name, .. } => Err(format!("@{}", name)),
+        other => Err(other.to_string()),
+    }
+}
+
+fn eval(expr: &Expr, ctx: &EvalContext) -> Result<Value, InterpretResult> {
+    match expr {
+        Expr::Literal(val) => Ok(Value::Num(*val)),
+        Expr::Identifier(name) => Ok(match ctx.lookup_var(name) {
+            Some(val) => Value::Num(val),
+            None => Value::Err("#NAME?".to_string()),
+        }),
+        Expr::DimensionRef(name) => {
+            Ok(ctx.lookup_dimension(&[(DEFAULT_REF_DIMENSION.to_string(), name.clone())]))
+        }
+        Expr::CellRef(pairs) => Ok(ctx.lookup_dimension(pairs)),
+        Expr::Binary { op, lhs, rhs } => {
+            let a = eval(lhs, ctx)?;
+            let b = eval(rhs, ctx)?;
+            match op {
+                BinaryOp::Add => arith_values(OpCode::Add, &a, &b, |x, y| x + y),
+                BinaryOp::Sub => arith_values(OpCode::Sub, &a, &b, |x, y| x - y),
+                BinaryOp::Mul => arith_values(OpCode::Mul, &a, &b, |x, y| x * y),
+                BinaryOp::Div => arith_values(OpCode::Div, &a, &b, |x, y| x / y),
+                BinaryOp::Mod => arith_values(OpCode::Mod, &a, &b, modulo),
+                BinaryOp::Eq => compare_values(OpCode::Equal, &a, &b),
+                BinaryOp::NotEq => compare_values(OpCode::NotEqual, &a, &b),
+                BinaryOp::Lt => compare_values(OpCode::Less, &a, &b),
+                BinaryOp::Lte => compare_values(OpCode::LessEqual, &a, &b),
+                BinaryOp::Gt => compare_values(OpCode::Greater, &a, &b),
+                BinaryOp::Gte => compare_values(OpCode::GreaterEqual, &a, &b),
+            }
+        }
+        Expr::FunctionCall { name, args } if name == "IF" => match args.as_slice() {
+            [condition, then_branch, else_branch] => {
+                if truthy(eval(condition, ctx)?)? {
+                    eval(then_branch, ctx)
+                } else {
+                    eval(else_branch, ctx)
+                }
+            }
+            _ => Ok(Value::Err("#VALUE!".to_string())),
+        },
+        Expr::FunctionCall { name, args } => {
+            let kind = match name.as_str() {
+                "SUM" => AggregateKind::Sum,
+                "AVG" => AggregateKind::Avg,
+                "MIN" => AggregateKind::Min,
+                _ => AggregateKind::Max,
+            };
+            // No operands at all is decided at compile time (`Compiler::emit_empty_aggregate`)
+            if args.is_empty() {
+                return Ok(match kind {
+                    AggregateKind::Sum => Value::Num(0.0),
+                    AggregateKind::Avg => Value::Err("#DIV/0!".to_string()),
+                    _ => Value::Err("#N/A".to_string()),
+                });
+            }
+            // Every operand is evaluated first (as the VM pushes them all), then read last-first
+            let values = args.iter().map(|arg| eval(arg, ctx)).collect::<Result<Vec<_>, _>>()?;
+            let mut operands = Vec::with_capacity(values.len());
+            for value in values.into_iter().rev() {
+                if let Some(v) = aggregate_operand(value)? {
+                    operands.push(v);
+                }
+            }
+            Ok(aggregate_values(kind, operands.iter().copied(), false))
+        }
+        _ => unreachable!("rejected by check_supported"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atom_script::compiler::Compiler;
+    use crate::atom_script::test_util::Rng;
+    use crate::atom_script::vm::VM;
+    use crate::lattice::coordinate::coordinate_hash;
+
+    fn random_expr(rng: &mut Rng, depth: u32) -> Expr {
+        if depth == 0 || rng.next(4) == 0 {
+            return match rng.next(3) {
+                0 => Expr::Literal(rng.next(20) as f64 / 4.0),
+                1 => Expr::DimensionRef(["A", "B", "Missing"][rng.next(3) as usize].to_string()),
+                _ => Expr::Identifier(["x", "unbound"][rng.next(2) as usize].to_string()),
+            };
+        }
+        match rng.next(3) {
+            0 => {
+                let ops = [
+                    BinaryOp::Add, BinaryOp::Sub, BinaryOp::Mul, BinaryOp::Div, BinaryOp::Mod,
+                    BinaryOp::Eq, BinaryOp::NotEq, BinaryOp::Lt, BinaryOp::Lte, BinaryOp::Gt, BinaryOp::Gte,
+                ];
+                Expr::Binary {
+                    op: ops[rng.next(ops.len() as u64) as usize].clone(),
+                    lhs: Box::new(random_expr(rng, depth - 1)),
+                    rhs: Box::new(random_expr(rng, depth - 1)),
+                }
+            }
+            1 => {
+                let name = ["SUM", "AVG", "MIN", "MAX"][rng.next(4) as usize].to_string();
+                let args = (0..rng.next(4)).map(|_| random_expr(rng, depth - 1)).collect();
+                Expr::FunctionCall { name, args }
+            }
+            _ => Expr::FunctionCall {
+                name: "IF".to_string(),
+                args: (0..3).map(|_| random_expr(rng, depth - 1)).collect(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_tree_walk_matches_compiled_path() {
+        let arena = LatticeArena::new(16);
+        arena.set_cell(coordinate_hash(&[("Measure", "A")]), 2.5);
+        arena.set_cell(coordinate_hash(&[("Measure", "B")]), 0.0);
+        let bindings = HashMap::from([("x".to_string(), 3.0)]);
+        let ctx = EvalContext::new().with_arena(&arena).with_bindings(&bindings);
+
+        let mut rng = Rng::new(164);
+        for _ in 0..2000 {
+            let expr = random_expr(&mut rng, 4);
+            let chunk = Compiler::new().with_bindings(std::sync::Arc::new(bindings.clone())).compile(&expr);
+            let compiled = VM::new(chunk).with_arena(&arena).run().into_number();
+            let direct = expr.eval_direct(&ctx);
+            match (&compiled, &direct) {
+                // NaN != NaN, so numbers compare bit for bit
+                (Ok(a), Ok(b)) => assert_eq!(a.to_bits(), b.to_bits(), "{}: {} vs {}", expr, a, b),
+                _ => assert_eq!(compiled, direct, "{}", expr),
+            }
+        }
+    }
+
+    #[test]
+    fn test_context_resolves_cells_locals_and_aliases() {
+        use crate::lattice::metadata::MapHierarchyResolver;
+
+        let arena = LatticeArena::new(16);
+        arena.set_cell(coordinate_hash(&[("Measure", "Revenue"), ("Region", "US")]), 200.0);
+        arena.set_cell(coordinate_hash(&[("Measure", "Revenue"), ("Region", "CA")]), 50.0);
+        let bindings = HashMap::from([("rate".to_string(), 0.1)]);
+        let mut resolver = MapHierarchyResolver::new();
+        resolver.add_alias("Region", "United States", "US");
+        let ctx = EvalContext::new()
+            .with_arena(&arena)
+            .with_bindings(&bindings)
+            .with_resolver(&resolver)
+            .with_coordinate(vec![("Region".to_string(), "CA".to_string())]);
+
+        assert_eq!(ctx.lookup_dimension(&[("Measure".to_string(), "Revenue".to_string())]), Value::Num(50.0));
+        assert_eq!(ctx.lookup_dimension(&[("Measure".to_string(), "COGS".to_string())]), Value::Empty);
+        assert_eq!(ctx.lookup_var("rate"), Some(0.1));
+        assert_eq!(ctx.lookup_var("missing"), None);
+
+        let eval = |input: &str| crate::atom_script::parser::Parser::new(input).parse().unwrap().eval_direct(&ctx);
+        assert_eq!(eval("[Revenue] * rate"), Ok(5.0));
+        assert_eq!(eval("CELL([Measure]=Revenue, [Region]=\"United States\") * rate"), Ok(20.0));
+    }
+
+    #[test]
+    fn test_unsupported_expressions_are_rejected() {
+        let expr = crate::atom_script::parser::Parser::new("SUM(@Children([Region], [North America]))").parse().unwrap();
+        assert_eq!(
+            expr.eval_direct(&EvalContext::new()),
+            Err(EngineError::Runtime(RuntimeError::Unsupported("@Children".to_string())))
+        );
+    }
+}
@@ -1,6 +1,18 @@
-use logos::Logos;
+use std::ops::Range;
+use logos::{Lexer, Logos};
 
-#[derive(Logos, Debug, PartialEq)]
+/// Why the lexer rejected a token; the parser reports it instead of a generic Error.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum LexError {
+    #[default]
+    Unrecognized,
+    /// A bracketed reference with a tab or line break inside the name (`[North\nAmerica]`).
+    /// Whitespace around the name is trimmed, so `[\tRegion\n]` is just `[Region]`.
+    BrokenDimensionRef(String),
+}
+
+#[derive(Logos, Debug, Clone, PartialEq)]
+#[logos(error = LexError)]
 pub enum Token {
     // Arithmetic Operators
     #[token("+")]
@@ -11,12 +23,32 @@ pub enum Token {
     Mul,
     #[token("/")]
     Div,
+    #[token("%")]
+    Percent, // Modulo between operands, postfix percent otherwise (see Parser)
     #[token("(")]
     LParen,
     #[token(")")]
     RParen,
     #[token(",")]
     Comma,
+    #[token("?")]
+    Question,
+    #[token(":")]
+    Colon,
+
+    // Comparison Operators
+    #[token("==")]
+    EqEq,
+    #[token("!=")]
+    NotEq,
+    #[token("<")]
+    Lt,
+    #[token("<=")]
+    Lte,
+    #[token(">")]
+    Gt,
+    #[token(">=")]
+    Gte,
 
     // Excel-style Functions
     #[token("SUM")]
@@ -34,6 +66,16 @@ pub enum Token {
     #[token("XLOOKUP")]
     XLookup,
 
+    // Bindings: let name = expr (see Session)
+    #[token("let")]
+    Let,
+    #[token("=")]
+    Assign,
+
+    // Membership Operator: [Region] IN ("USA", "Canada")
+    #[token("IN")]
+    In,
+
     // Time Travel Operator
     #[token("->")]
     Arrow,
@@ -64,10 +106,18 @@ pub enum Token {
     #[regex("@[a-zA-Z_][a-zA-Z0-9_]*", |lex| lex.slice()[1..].to_string())]
     AtIdentifier(String),
 
+    // `@` not followed by a function name; the parser reports it instead of a generic Error
+    #[token("@")]
+    BareAt,
+
     // Dimension References (e.g., [Region])
-    #[regex(r"\[[^\]]*\]", |lex| lex.slice().trim_matches(|c| c == '[' || c == ']').to_string())]
+    #[regex(r"\[[^\]]*\]", dimension_ref)]
     DimensionRef(String),
 
+    // String Literals (e.g., "Region: ")
+    #[regex(r#""[^"]*""#, |lex| { let s = lex.slice(); s[1..s.len() - 1].to_string() })]
+    StringLiteral(String),
+
     // Number Literals
     #[regex(r"[0-9]+(\.[0-9]+)?", |lex| lex.slice().parse().ok())]
     Number(f64),
@@ -79,3 +129,48 @@ pub enum Token {
     // Logos 0.13+ error handling
     Error,
 }
+
+fn dimension_ref(lex: &mut Lexer<Token>) -> Result<String, LexError> {
+    let name = lex.slice().trim_matches(|c| c == '[' || c == ']').trim();
+    // A name split across lines is almost always a missing `]`, so it is not normalized
+    if name.contains(['\t', '\n', '\r']) {
+        return Err(LexError::BrokenDimensionRef(name.to_string()));
+    }
+    Ok(name.to_string())
+}
+
+/// Lexes `input` the way the parser does, returning every token with its byte span, for
+/// tooling that works on the token stream (highlighters, formatters). Whitespace is skipped.
+/// Text the lexer rejects comes back as `Token::Error`, spanning the rejected text.
+pub fn tokenize(input: &str) -> Vec<(Token, Range<usize>)> {
+    Token::lexer(input)
+        .spanned()
+        .map(|(token, span)| (token.unwrap_or(Token::Error), span))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_yields_spans() {
+        assert_eq!(
+            tokenize("SUM([A], 1)"),
+            vec![
+                (Token::Sum, 0..3),
+                (Token::LParen, 3..4),
+                (Token::DimensionRef("A".to_string()), 4..7),
+                (Token::Comma, 7..8),
+                (Token::Number(1.0), 9..10),
+                (Token::RParen, 10..11),
+            ]
+        );
+
+        let input = "[A] # [B\nC]";
+        let tokens = tokenize(input);
+        let errors: Vec<&str> = tokens.iter().filter(|(t, _)| *t == Token::Error).map(|(_, span)| &input[span.clone()]).collect();
+        assert_eq!(errors, vec!["#", "[B\nC]"]);
+        assert_eq!(tokens.len(), 3);
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+use crate::atom_script::ast::{BinaryOp, Expr};
+use crate::lattice::coordinate::DEFAULT_REF_DIMENSION;
+use crate::lattice::metadata::HierarchyResolver;
+
+/// A suspicious-but-valid pattern in a formula. Warnings never block compilation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LintWarning {
+    /// `x / 0` with a literal zero denominator.
+    DivisionByZero { expr: String },
+    /// `SUM([A])`: aggregating a single value is usually a missing range.
+    SingleArgumentAggregation { function: String },
+    /// A comparison whose result is known without evaluating any cell (`1 == 1`, `[A] >= [A]`).
+    ConstantComparison { expr: String, value: bool },
+    /// A member the injected resolver does not know.
+    UnknownMember { dimension: String, member: String },
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintWarning::DivisionByZero { expr } => write!(f, "division by literal zero in `{}`", expr),
+            LintWarning::SingleArgumentAggregation { function } => {
+                write!(f, "{} over a single argument returns that argument", function)
+            }
+            LintWarning::ConstantComparison { expr, value } => {
+                write!(f, "comparison `{}` is always {}", expr, if *value { "true" } else { "false" })
+            }
+            LintWarning::UnknownMember { dimension, member } => {
+                write!(f, "unknown member [{}] in dimension {}", member, dimension)
+            }
+        }
+    }
+}
+
+/// Flags common mistakes in `expr`.
+pub fn lint(expr: &Expr) -> Vec<LintWarning> {
+    let mut warnings = Vec::new();
+    walk(expr, None, &mut warnings);
+    warnings
+}
+
+/// Like `lint`, additionally checking member references against `resolver`.
+pub fn lint_with_resolver(expr: &Expr, resolver: &dyn HierarchyResolver) -> Vec<LintWarning> {
+    let mut warnings = Vec::new();
+    walk(expr, Some(resolver), &mut warnings);
+    warnings
+}
+
+fn walk(expr: &Expr, resolver: Option<&dyn HierarchyResolver>, warnings: &mut Vec<LintWarning>) {
+    match expr {
+        Expr::Literal(_) | Expr::StringLiteral(_) | Expr::Identifier(_) => {}
+        Expr::DimensionRef(member) => check_member(DEFAULT_REF_DIMENSION, member, resolver, warnings),
+        Expr::CellRef(pairs) => {
+            for (dimension, member) in pairs {
+                check_member(dimension, member, resolver, warnings);
+            }
+        }
+        Expr::Binary { op, lhs, rhs } => {
+            if *op == BinaryOp::Div && **rhs == Expr::Literal(0.0) {
+                warnings.push(LintWarning::DivisionByZero { expr: expr.to_string() });
+            }
+            if let Some(value) = constant_comparison(op, lhs, rhs) {
+                warnings.push(LintWarning::ConstantComparison { expr: expr.to_string(), value });
+            }
+            walk(lhs, resolver, warnings);
+            walk(rhs, resolver, warnings);
+        }
+        Expr::FunctionCall { name, args } => {
+            let aggregation = matches!(name.as_str(), "SUM" | "AVG" | "MIN" | "MAX");
+            // A hierarchy call expands to several values, so it is not a single argument
+            if aggregation && args.len() == 1 && !matches!(args[0], Expr::HierarchyCall { .. }) {
+                warnings.push(LintWarning::SingleArgumentAggregation { function: name.clone() });
+            }
+            for arg in args {
+                walk(arg, resolver, warnings);
+            }
+        }
+        Expr::HierarchyCall { args, .. } => match args.as_slice() {
+            [Expr::DimensionRef(dim), Expr::DimensionRef(member)] => check_member(dim, member, resolver, warnings),
+            _ => args.iter().for_each(|arg| walk(arg, resolver, warnings)),
+        },
+        Expr::TimeTravel { lhs, rhs } => {
+            walk(lhs, resolver, warnings);
+            walk(rhs, resolver, warnings);
+        }
+        Expr::In { value, candidates } => {
+            walk(value, resolver, warnings);
+            candidates.iter().for_each(|c| walk(c, resolver, warnings));
+        }
+        Expr::TimeModifier { base, .. } => walk(base, resolver, warnings),
+    }
+}
+
+fn check_member(
+    dimension: &str,
+    member: &str,
+    resolver: Option<&dyn HierarchyResolver>,
+    warnings: &mut Vec<LintWarning>,
+) {
+    if let Some(false) = resolver.and_then(|r| r.member_exists(&dimension.into(), &member.into())) {
+        warnings.push(LintWarning::UnknownMember { dimension: dimension.to_string(), member: member.to_string() });
+    }
+}
+
+/// The fixed result of a comparison between two literals, or between an expression and itself.
+fn constant_comparison(op: &BinaryOp, lhs: &Expr, rhs: &Expr) -> Option<bool> {
+    let ordering = match (lhs, rhs) {
+        (Expr::Literal(a), Expr::Literal(b)) => a.partial_cmp(b)?,
+        (Expr::StringLiteral(a), Expr::StringLiteral(b)) => a.cmp(b),
+        _ if lhs == rhs => std::cmp::Ordering::Equal,
+        _ => return None,
+    };
+    match op {
+        BinaryOp::Eq => Some(ordering.is_eq()),
+        BinaryOp::NotEq => Some(ordering.is_ne()),
+        BinaryOp::Lt => Some(ordering.is_lt()),
+        BinaryOp::Lte => Some(ordering.is_le()),
+        BinaryOp::Gt => Some(ordering.is_gt()),
+        BinaryOp::Gte => Some(ordering.is_ge()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atom_script::parser::Parser;
+    use crate::lattice::metadata::MapHierarchyResolver;
+
+    fn lint_str(input: &str) -> Vec<LintWarning> {
+        let mut parser = Parser::new(input);
+        lint(&parser.parse().expect("Parse failed"))
+    }
+
+    #[test]
+    fn test_lint_flags_common_mistakes() {
+        assert_eq!(
+            lint_str("SUM([A])"),
+            vec![LintWarning::SingleArgumentAggregation { function: "SUM".to_string() }]
+        );
+        assert_eq!(lint_str("[X] / 0"), vec![LintWarning::DivisionByZero { expr: "([X] / 0)".to_string() }]);
+        assert_eq!(
+            lint_str("IF(1 == 1, [A], [B])"),
+            vec![LintWarning::ConstantComparison { expr: "(1 == 1)".to_string(), value: true }]
+        );
+        assert!(lint_str("SUM([A], [B]) / [C]").is_empty());
+        assert!(lint_str("SUM(@Children([Region], [North America]))").is_empty());
+    }
+
+    #[test]
+    fn test_lint_unknown_member_with_resolver() {
+        let mut resolver = MapHierarchyResolver::new();
+        resolver.add_child("Region", "North America", "USA");
+
+        let mut parser = Parser::new("SUM(@Children([Region], [Nort America]), @Children([Region], [North America]))");
+        let warnings = lint_with_resolver(&parser.parse().unwrap(), &resolver);
+        assert_eq!(
+            warnings,
+            vec![LintWarning::UnknownMember { dimension: "Region".to_string(), member: "Nort America".to_string() }]
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use parking_lot::Mutex;
+use crate::atom_script::value::Value;
+
+pub const DEFAULT_LOOKUP_CACHE_CAPACITY: usize = 100_000;
+
+/// Host-provided data source behind `LOOKUP(value, search, result)`: finds `value` in the
+/// range `search` identifies and returns the matching entry of the range `result` identifies.
+/// Without a backend (`VM::with_lookup`) LOOKUP evaluates to 0.
+pub trait LookupBackend: Sync {
+    fn lookup(&self, value: &Value, search: &Value, result: &Value) -> Value;
+}
+
+struct LookupState {
+    entries: HashMap<Vec<u8>, Value>,
+    arena_version: u64,
+}
+
+/// Memoizes `LookupBackend` results across the cells of a recalc pass, keyed on the resolved
+/// arguments, so the thousands of cells that repeat the same LOOKUP scan the range once.
+/// Share one cache between the VMs of a pass (`VM::with_lookup_cache`). Entries are tied to
+/// the `LatticeArena::version` they were computed at: the first lookup at another version
+/// drops them all. When full, the cache is cleared rather than evicting one entry at a time.
+pub struct LookupCache {
+    capacity: usize,
+    state: Mutex<LookupState>,
+    hits: AtomicUsize,
+}
+
+impl Default for LookupCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_LOOKUP_CACHE_CAPACITY)
+    }
+}
+
+impl LookupCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(LookupState { entries: HashMap::new(), arena_version: 0 }),
+            hits: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the cached result for `args` at `arena_version`, or runs `lookup` on a miss.
+    /// The lookup runs outside the lock, so two threads missing on the same arguments may
+    /// both run it.
+    pub fn get_or_lookup(&self, args: [&Value; 3], arena_version: u64, lookup: impl FnOnce() -> Value) -> Value {
+        let key = cache_key(args);
+        {
+            let mut state = self.state.lock();
+            if state.arena_version != arena_version {
+                state.entries.clear();
+                state.arena_version = arena_version;
+            }
+            if let Some(found) = state.entries.get(&key) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return found.clone();
+            }
+        }
+
+        let found = lookup();
+        let mut state = self.state.lock();
+        if state.arena_version == arena_version {
+            if state.entries.len() >= self.capacity {
+                state.entries.clear();
+            }
+            state.entries.insert(key, found.clone());
+        }
+        found
+    }
+
+    /// Number of lookups served from the cache.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Tagged so that e.g. the number 1 and the text "1" never share a key
+fn cache_key(args: [&Value; 3]) -> Vec<u8> {
+    let mut key = Vec::new();
+    for arg in args {
+        match arg {
+            Value::Num(n) => {
+                key.push(0);
+                key.extend_from_slice(&n.to_bits().to_le_bytes());
+            }
+            Value::Text(s) | Value::Err(s) => {
+                key.push(if matches!(arg, Value::Text(_)) { 1 } else { 2 });
+                key.extend_from_slice(&(s.len() as u64).to_le_bytes());
+                key.extend_from_slice(s.as_bytes());
+            }
+            Value::Bool(b) => key.extend_from_slice(&[3, *b as u8]),
+            Value::Empty => key.push(4),
+        }
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::atom_script::chunk::Chunk;
+    use crate::atom_script::compiler::Compiler;
+    use crate::atom_script::parser::Parser;
+    use crate::atom_script::vm::{InterpretResult, VM};
+    use crate::lattice::arena::LatticeArena;
+    use crate::lattice::coordinate::coordinate_hash;
+
+    /// Doubles the lookup value, counting how often it is asked.
+    struct CountingBackend {
+        calls: AtomicUsize,
+    }
+
+    impl LookupBackend for CountingBackend {
+        fn lookup(&self, value: &Value, _search: &Value, _result: &Value) -> Value {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            Value::Num(value.as_num().unwrap_or(0.0) * 2.0)
+        }
+    }
+
+    #[test]
+    fn test_repeated_lookup_is_served_from_cache() {
+        let expr = Parser::new("LOOKUP([Code], [Codes], [Names])").parse().unwrap();
+        let chunk: Arc<Chunk> = Compiler::new().compile(&expr).into();
+        let arena = LatticeArena::new(16);
+        arena.set_cell(coordinate_hash(&[("Measure", "Code")]), 21.0);
+        let backend = CountingBackend { calls: AtomicUsize::new(0) };
+        let cache = LookupCache::default();
+
+        let run = || {
+            VM::new(chunk.clone()).with_arena(&arena).with_lookup(&backend).with_lookup_cache(&cache).run()
+        };
+        assert_eq!(run(), InterpretResult::Ok(42.0));
+        assert_eq!(run(), InterpretResult::Ok(42.0));
+        assert_eq!((backend.calls.load(Ordering::Relaxed), cache.hits()), (1, 1));
+
+        // Writing to the arena invalidates the cache, even for unrelated cells
+        arena.set_cell(coordinate_hash(&[("Measure", "Other")]), 1.0);
+        assert_eq!(run(), InterpretResult::Ok(42.0));
+        assert_eq!((backend.calls.load(Ordering::Relaxed), cache.hits()), (2, 1));
+
+        // Without a cache every evaluation reaches the backend
+        VM::new(chunk.clone()).with_arena(&arena).with_lookup(&backend).run();
+        assert_eq!(backend.calls.load(Ordering::Relaxed), 3);
+    }
+}
@@ -2,8 +2,22 @@ pub mod lexer;
 pub mod ast;
 pub mod parser;
 pub mod chunk;
+pub mod bytecode;
 pub mod vm;
+pub mod value;
 pub mod compiler;
+pub mod typecheck;
+pub mod simplify;
 pub mod solver;
+pub mod cache;
+pub mod lookup;
+pub mod pool;
+pub mod registry;
+pub mod error;
+pub mod lint;
+pub mod interp;
+pub mod session;
 #[cfg(test)]
 pub mod tests;
+#[cfg(test)]
+pub mod test_util;
@@ -1,37 +1,227 @@
 use logos::{Logos, Lexer};
-use crate::atom_script::lexer::Token;
-use crate::atom_script::ast::{Expr, BinaryOp, TimeShiftType};
+use crate::atom_script::lexer::{LexError, Token};
+use crate::atom_script::ast::{canonical_hash, Expr, BinaryOp, SourceMap, Statement, TimeShiftType};
+use std::ops::Range;
+use thiserror::Error;
+
+// Stack Overflow Protection: deeply nested input must fail cleanly instead of crashing the server.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+// DoS Protection: untrusted formulas longer than this are rejected before lexing.
+pub const DEFAULT_MAX_FORMULA_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ParseError {
+    #[error("{message} at {}..{}", span.start, span.end)]
+    Syntax { message: String, span: Range<usize> }, // Byte span of the offending token
+    #[error("expression nesting exceeds the maximum depth of {limit}")]
+    TooDeep { limit: usize },
+    #[error("formula of {len} bytes exceeds the maximum length of {limit}")]
+    TooLong { len: usize, limit: usize },
+}
 
 pub struct Parser<'a> {
     lexer: Lexer<'a, Token>,
     current_token: Option<Token>,
+    depth: usize,
+    max_depth: usize,
+    rejected: Option<ParseError>, // Set by `new_bounded` when the input is never lexed
+    recovering: bool, // parse_all_errors: record argument errors and resynchronize
+    errors: Vec<ParseError>,
+    spans: Option<Vec<(u64, Range<usize>)>>, // parse_with_spans: (canonical_hash, source range) of every expression parsed
+    prev_end: usize, // Byte offset just past the last consumed token
+    lex_error: Option<LexError>, // Why the lexer produced the current Token::Error
 }
 
 impl<'a> Parser<'a> {
     pub fn new(input: &'a str) -> Self {
-        let mut lexer = Token::lexer(input);
-        let first_token = lexer.next().map(|res| res.unwrap_or(Token::Error));
+        Self::with_max_depth(input, DEFAULT_MAX_DEPTH)
+    }
+
+    /// Creates a parser that rejects expressions nested deeper than `max_depth`.
+    pub fn with_max_depth(input: &'a str, max_depth: usize) -> Self {
+        let mut parser = Self {
+            lexer: Token::lexer(input),
+            current_token: None,
+            depth: 0,
+            max_depth,
+            rejected: None,
+            recovering: false,
+            errors: Vec::new(),
+            spans: None,
+            prev_end: 0,
+            lex_error: None,
+        };
+        parser.advance();
+        parser
+    }
+
+    /// Creates a parser for untrusted input: if `input` is longer than `max_len` bytes it is
+    /// not lexed at all and `parse` fails with `ParseError::TooLong`.
+    pub fn new_bounded(input: &'a str, max_len: usize) -> Self {
+        if input.len() <= max_len {
+            return Self::new(input);
+        }
         Self {
-            lexer,
-            current_token: first_token,
+            lexer: Token::lexer(""),
+            current_token: None,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            rejected: Some(ParseError::TooLong { len: input.len(), limit: max_len }),
+            recovering: false,
+            errors: Vec::new(),
+            spans: None,
+            prev_end: 0,
+            lex_error: None,
         }
     }
 
+    fn enter(&mut self) -> Result<(), ParseError> {
+        if self.depth >= self.max_depth {
+            return Err(ParseError::TooDeep { limit: self.max_depth });
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
     fn advance(&mut self) {
-        self.current_token = self.lexer.next().map(|res| res.unwrap_or(Token::Error));
+        self.prev_end = self.lexer.span().end;
+        self.current_token = match self.lexer.next() {
+            Some(Ok(token)) => Some(token),
+            Some(Err(err)) => {
+                self.lex_error = Some(err);
+                Some(Token::Error)
+            }
+            None => None,
+        };
     }
 
-    pub fn parse(&mut self) -> Result<Expr, String> {
+    pub fn parse(&mut self) -> Result<Expr, ParseError> {
+        if let Some(err) = self.rejected.take() {
+            return Err(err);
+        }
         self.parse_expr(0)
     }
 
-    fn parse_expr(&mut self, min_bp: u8) -> Result<Expr, String> {
+    /// Like `parse`, also returning the source range of every subexpression so a compiled
+    /// chunk can map instructions back to the formula text (see `Compiler::with_source_map`).
+    pub fn parse_with_spans(&mut self) -> Result<(Expr, SourceMap), ParseError> {
+        self.spans = Some(Vec::new());
+        let expr = self.parse()?;
+        let map = SourceMap::from_recorded(&expr, &self.spans.take().unwrap_or_default());
+        Ok((expr, map))
+    }
+
+    /// Parses a formula pasted from a spreadsheet, where a leading `=` marks a formula
+    /// (`=SUM(1, 2)`). Without one this is `parse`. A leading `==` lexes as the equality
+    /// operator and stays a syntax error, as does a bare `=`.
+    pub fn parse_excel(&mut self) -> Result<Expr, ParseError> {
+        if self.rejected.is_none() && self.current_token == Some(Token::Assign) {
+            self.advance();
+        }
+        self.parse()
+    }
+
+    /// Parses a statement: `let name = expr` or a plain formula.
+    pub fn parse_statement(&mut self) -> Result<Statement, ParseError> {
+        if self.current_token != Some(Token::Let) {
+            return self.parse().map(Statement::Expr);
+        }
+        if let Some(err) = self.rejected.take() {
+            return Err(err);
+        }
+        self.advance();
+        let name = match &self.current_token {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => return Err(self.syntax_error("Expected a name after 'let'")),
+        };
+        self.advance();
+        if self.current_token != Some(Token::Assign) {
+            return Err(self.syntax_error("Expected '=' in let binding"));
+        }
+        self.advance();
+        let value = self.parse_expr(0)?;
+        Ok(Statement::Let { name, value })
+    }
+
+    /// Error-recovery parse: a syntax error inside a function argument is recorded and the
+    /// parser resynchronizes at the next `,` or `)` of that argument list, so independent
+    /// mistakes are all reported in one pass. Use `parse` on the happy path.
+    pub fn parse_all_errors(&mut self) -> Result<Expr, Vec<ParseError>> {
+        if let Some(err) = self.rejected.take() {
+            return Err(vec![err]);
+        }
+        self.recovering = true;
+        let result = self.parse_expr(0);
+        self.recovering = false;
+
+        let mut errors = std::mem::take(&mut self.errors);
+        match result {
+            Ok(expr) if errors.is_empty() => Ok(expr),
+            Ok(_) => Err(errors),
+            Err(err) => {
+                errors.push(err);
+                Err(errors)
+            }
+        }
+    }
+
+    /// Builds a syntax error spanning the current token (or the end of input).
+    fn syntax_error(&self, message: impl Into<String>) -> ParseError {
+        let span = match self.current_token {
+            Some(_) => self.lexer.span(),
+            None => self.lexer.source().len()..self.lexer.source().len(),
+        };
+        ParseError::Syntax { message: message.into(), span }
+    }
+
+    /// Builds the error for a token that cannot appear here: the lexer's reason when it
+    /// rejected the token, otherwise `message`.
+    fn unexpected(&self, message: impl Into<String>) -> ParseError {
+        match (&self.current_token, &self.lex_error) {
+            (Some(Token::Error), Some(LexError::BrokenDimensionRef(name))) => self.syntax_error(format!(
+                "dimension reference [{}] contains a tab or line break; write the name on one line",
+                name.escape_debug()
+            )),
+            _ => self.syntax_error(message),
+        }
+    }
+
+    /// Skips tokens up to the `,` or `)` that ends the current argument.
+    fn synchronize(&mut self) {
+        let mut nesting = 0;
+        loop {
+            match self.current_token {
+                None => return,
+                Some(Token::Comma) | Some(Token::RParen) if nesting == 0 => return,
+                Some(Token::LParen) => nesting += 1,
+                Some(Token::RParen) => nesting -= 1,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    fn parse_expr(&mut self, min_bp: u8) -> Result<Expr, ParseError> {
+        self.enter()?;
+        let result = self.parse_expr_inner(min_bp);
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_expr_inner(&mut self, min_bp: u8) -> Result<Expr, ParseError> {
+        let start = self.lexer.span().start;
         let mut lhs = match &self.current_token {
             Some(Token::Number(n)) => {
                 let val = *n;
                 self.advance();
                 Expr::Literal(val)
             }
+            Some(Token::StringLiteral(text)) => {
+                let text = text.clone();
+                self.advance();
+                Expr::StringLiteral(text)
+            }
             Some(Token::DimensionRef(d)) => {
                 let name = d.clone();
                 self.advance();
@@ -42,10 +232,7 @@ impl<'a> Parser<'a> {
                 self.advance();
                 // Check if function call
                 if let Some(Token::LParen) = self.current_token {
-                    self.advance();
-                    // parse args
-                    let args = self.parse_args()?;
-                    Expr::FunctionCall { name, args }
+                    self.parse_call(name)?
                 } else {
                     Expr::Identifier(name)
                 }
@@ -55,54 +242,67 @@ impl<'a> Parser<'a> {
                 let name = id.clone();
                 self.advance();
                 if self.current_token != Some(Token::LParen) {
-                    return Err("Expected '(' after hierarchy function".to_string());
+                    return Err(self.syntax_error(format!(
+                        "hierarchy functions must be called with arguments, e.g. @{}([Dimension], [Member])",
+                        name
+                    )));
                 }
                 self.advance();
                 let args = self.parse_args()?;
                 Expr::HierarchyCall { name, args }
             }
+            Some(Token::BareAt) => {
+                return Err(self.syntax_error("'@' must be followed by a hierarchy function name, e.g. @Children"))
+            }
             Some(Token::Lookup) => {
                 self.advance();
-                if self.current_token != Some(Token::LParen) { return Err("Expected '(' after LOOKUP".to_string()); }
+                if self.current_token != Some(Token::LParen) { return Err(self.syntax_error("Expected '(' after LOOKUP")); }
                 self.advance();
                 let args = self.parse_args()?;
                 Expr::FunctionCall { name: "LOOKUP".to_string(), args }
             }
             Some(Token::XLookup) => {
                 self.advance();
-                if self.current_token != Some(Token::LParen) { return Err("Expected '(' after XLOOKUP".to_string()); }
+                if self.current_token != Some(Token::LParen) { return Err(self.syntax_error("Expected '(' after XLOOKUP")); }
                 self.advance();
                 let args = self.parse_args()?;
                 Expr::FunctionCall { name: "XLOOKUP".to_string(), args }
             }
             Some(Token::Sum) => {
                 self.advance();
-                if self.current_token != Some(Token::LParen) { return Err("Expected '(' after SUM".to_string()); }
+                if self.current_token != Some(Token::LParen) { return Err(self.syntax_error("Expected '(' after SUM")); }
                 self.advance();
                 let args = self.parse_args()?;
                 Expr::FunctionCall { name: "SUM".to_string(), args }
             }
             Some(Token::Avg) => {
                 self.advance();
-                if self.current_token != Some(Token::LParen) { return Err("Expected '(' after AVG".to_string()); }
+                if self.current_token != Some(Token::LParen) { return Err(self.syntax_error("Expected '(' after AVG")); }
                 self.advance();
                 let args = self.parse_args()?;
                 Expr::FunctionCall { name: "AVG".to_string(), args }
             }
             Some(Token::Min) => {
                 self.advance();
-                if self.current_token != Some(Token::LParen) { return Err("Expected '(' after MIN".to_string()); }
+                if self.current_token != Some(Token::LParen) { return Err(self.syntax_error("Expected '(' after MIN")); }
                 self.advance();
                 let args = self.parse_args()?;
                 Expr::FunctionCall { name: "MIN".to_string(), args }
             }
             Some(Token::Max) => {
                 self.advance();
-                if self.current_token != Some(Token::LParen) { return Err("Expected '(' after MAX".to_string()); }
+                if self.current_token != Some(Token::LParen) { return Err(self.syntax_error("Expected '(' after MAX")); }
                 self.advance();
                 let args = self.parse_args()?;
                 Expr::FunctionCall { name: "MAX".to_string(), args }
             }
+            Some(Token::If) => {
+                self.advance();
+                if self.current_token != Some(Token::LParen) { return Err(self.syntax_error("Expected '(' after IF")); }
+                self.advance();
+                let args = self.parse_args()?;
+                Expr::FunctionCall { name: "IF".to_string(), args }
+            }
             // Phase 3: Time-Intelligence Modifiers
             Some(Token::PriorYear) => self.parse_time_modifier(TimeShiftType::PriorYear)?,
             Some(Token::PriorQuarter) => self.parse_time_modifier(TimeShiftType::PriorQuarter)?,
@@ -117,18 +317,28 @@ impl<'a> Parser<'a> {
                 self.advance();
                 let expr = self.parse_expr(0)?;
                 if self.current_token != Some(Token::RParen) {
-                    return Err("Expected ')'".to_string());
+                    return Err(self.syntax_error("Expected ')'"));
                 }
                 self.advance();
                 expr
             }
-            _ => return Err(format!("Unexpected token: {:?}", self.current_token)),
+            _ => return Err(self.unexpected(format!("Unexpected token: {:?}", self.current_token))),
         };
 
         loop {
+            self.record_span(&lhs, start);
+
+            // Ternary (`cond ? a : b`): lowest precedence and right-associative, so it only
+            // binds at the top of an expression. It desugars to IF and shares its jumps.
+            if let Some(Token::Question) = &self.current_token {
+                if min_bp > 0 { break; }
+                lhs = self.parse_ternary(lhs)?;
+                continue;
+            }
+
             // Ultra Diamond: Time Travel Operator (->)
             if let Some(Token::Arrow) = &self.current_token {
-                let (l_bp, r_bp) = (5, 6); // High precedence
+                let (l_bp, r_bp) = (7, 8); // High precedence
                 if l_bp < min_bp { break; }
                 self.advance();
                 let rhs = self.parse_expr(r_bp)?;
@@ -136,11 +346,40 @@ impl<'a> Parser<'a> {
                 continue;
             }
 
+            // Membership Operator (IN): binds like a comparison, RHS is a parenthesized list
+            if let Some(Token::In) = &self.current_token {
+                let l_bp = 1;
+                if l_bp < min_bp { break; }
+                self.advance();
+                if self.current_token != Some(Token::LParen) { return Err(self.syntax_error("Expected '(' after IN")); }
+                self.advance();
+                let candidates = self.parse_args()?;
+                lhs = Expr::In { value: Box::new(lhs), candidates };
+                continue;
+            }
+
+            // `%` is modulo when an operand follows (`10 % 3`) and postfix percent otherwise
+            // (`15%` == 0.15, `[Margin]% > 0.2`). Modulo binds like `*` and `/`; postfix percent
+            // binds tighter than any infix operator, so `6.5 / 15%` is `6.5 / 0.15`.
+            if let Some(Token::Percent) = &self.current_token {
+                let postfix = self.percent_is_postfix();
+                let (l_bp, r_bp) = infix_binding_power(&BinaryOp::Mod);
+                if !postfix && l_bp < min_bp { break; }
+                lhs = self.parse_percent(lhs, postfix, r_bp)?;
+                continue;
+            }
+
             let op = match &self.current_token {
                 Some(Token::Plus) => BinaryOp::Add,
                 Some(Token::Minus) => BinaryOp::Sub,
                 Some(Token::Mul) => BinaryOp::Mul,
                 Some(Token::Div) => BinaryOp::Div,
+                Some(Token::EqEq) => BinaryOp::Eq,
+                Some(Token::NotEq) => BinaryOp::NotEq,
+                Some(Token::Lt) => BinaryOp::Lt,
+                Some(Token::Lte) => BinaryOp::Lte,
+                Some(Token::Gt) => BinaryOp::Gt,
+                Some(Token::Gte) => BinaryOp::Gte,
                 _ => break,
             };
 
@@ -161,11 +400,65 @@ impl<'a> Parser<'a> {
         Ok(lhs)
     }
 
-    fn parse_args(&mut self) -> Result<Vec<Expr>, String> {
+    /// Records `expr` as spanning `start` to the end of the last consumed token.
+    #[inline(never)]
+    fn record_span(&mut self, expr: &Expr, start: usize) {
+        if let Some(spans) = self.spans.as_mut() {
+            spans.push((canonical_hash(expr), start..self.prev_end));
+        }
+    }
+
+    /// Whether the current `%` is a postfix percent, i.e. no operand follows it.
+    fn percent_is_postfix(&self) -> bool {
+        let next = self.lexer.clone().next().map(|res| res.unwrap_or(Token::Error));
+        !starts_operand(&next)
+    }
+
+    /// Parses the tail of `lhs %`: a modulo, or for a postfix percent `lhs / 100`.
+    fn parse_percent(&mut self, lhs: Expr, postfix: bool, r_bp: u8) -> Result<Expr, ParseError> {
+        self.advance();
+        if !postfix {
+            let rhs = self.parse_expr(r_bp)?;
+            return Ok(Expr::Binary { op: BinaryOp::Mod, lhs: Box::new(lhs), rhs: Box::new(rhs) });
+        }
+        Ok(match lhs {
+            Expr::Literal(n) => Expr::Literal(n / 100.0),
+            other => Expr::Binary { op: BinaryOp::Div, lhs: Box::new(other), rhs: Box::new(Expr::Literal(100.0)) },
+        })
+    }
+
+    /// Parses the `? a : b` tail of a ternary whose condition is `cond`.
+    fn parse_ternary(&mut self, cond: Expr) -> Result<Expr, ParseError> {
+        self.advance();
+        let then_branch = self.parse_expr(0)?;
+        if self.current_token != Some(Token::Colon) {
+            return Err(self.syntax_error("Expected ':' in ternary expression"));
+        }
+        self.advance();
+        let else_branch = self.parse_expr(0)?;
+        Ok(Expr::FunctionCall { name: "IF".to_string(), args: vec![cond, then_branch, else_branch] })
+    }
+
+    fn parse_args(&mut self) -> Result<Vec<Expr>, ParseError> {
+        self.enter()?;
+        let result = self.parse_args_inner();
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_args_inner(&mut self) -> Result<Vec<Expr>, ParseError> {
         let mut args = Vec::new();
         if self.current_token != Some(Token::RParen) {
             loop {
-                args.push(self.parse_expr(0)?);
+                match self.parse_expr(0) {
+                    Ok(arg) => args.push(arg),
+                    Err(err @ ParseError::Syntax { .. }) if self.recovering => {
+                        self.errors.push(err);
+                        self.synchronize();
+                        args.push(Expr::Literal(0.0)); // Placeholder; the result is discarded
+                    }
+                    Err(err) => return Err(err),
+                }
                 if self.current_token == Some(Token::Comma) {
                     self.advance();
                 } else {
@@ -174,30 +467,79 @@ impl<'a> Parser<'a> {
             }
         }
         if self.current_token != Some(Token::RParen) {
-                return Err("Expected ')'".to_string());
+                return Err(self.syntax_error("Expected ')'"));
         }
         self.advance();
         Ok(args)
     }
 
+    /// Parses a call of a named function, from its `(`. Kept out of line so its locals do not
+    /// grow every recursive `parse_expr` frame.
+    #[inline(never)]
+    fn parse_call(&mut self, name: String) -> Result<Expr, ParseError> {
+        if name == "CELL" {
+            return self.parse_cell_ref();
+        }
+        self.advance();
+        let args = self.parse_args()?;
+        Ok(Expr::FunctionCall { name, args })
+    }
+
+    /// Parses the argument list of `CELL([Region]=USA, [Time]=2024, ...)`: one or more
+    /// `[Dimension]=member` pairs, where the member is a bare name, a number, a `[bracketed]`
+    /// name or a string. Each dimension may appear once.
+    fn parse_cell_ref(&mut self) -> Result<Expr, ParseError> {
+        self.advance(); // consume '('
+        let mut pairs: Vec<(String, String)> = Vec::new();
+        loop {
+            let dimension = match &self.current_token {
+                Some(Token::DimensionRef(d)) => d.clone(),
+                _ => return Err(self.unexpected("Expected [Dimension]=member in CELL")),
+            };
+            if pairs.iter().any(|(d, _)| *d == dimension) {
+                return Err(self.syntax_error(format!("dimension [{}] appears twice in CELL", dimension)));
+            }
+            self.advance();
+            if self.current_token != Some(Token::Assign) {
+                return Err(self.syntax_error("Expected '=' after dimension in CELL"));
+            }
+            self.advance();
+            let member = match &self.current_token {
+                // The source text, so `2024` stays `2024` rather than round-tripping through f64
+                Some(Token::Number(_)) => self.lexer.slice().to_string(),
+                Some(Token::Identifier(m)) | Some(Token::DimensionRef(m)) | Some(Token::StringLiteral(m)) => m.clone(),
+                _ => return Err(self.unexpected("Expected a member after '=' in CELL")),
+            };
+            self.advance();
+            pairs.push((dimension, member));
+            match self.current_token {
+                Some(Token::Comma) => self.advance(),
+                Some(Token::RParen) => break,
+                _ => return Err(self.syntax_error("Expected ',' or ')' in CELL")),
+            }
+        }
+        self.advance();
+        Ok(Expr::CellRef(pairs))
+    }
+
     // Phase 3: Parses structures like "PY([Revenue])"
-    fn parse_time_modifier(&mut self, shift_type: TimeShiftType) -> Result<Expr, String> {
+    fn parse_time_modifier(&mut self, shift_type: TimeShiftType) -> Result<Expr, ParseError> {
         self.advance(); // consume token
-        if self.current_token != Some(Token::LParen) { return Err("Expected '(' after time modifier".to_string()); }
+        if self.current_token != Some(Token::LParen) { return Err(self.syntax_error("Expected '(' after time modifier")); }
         self.advance();
         let base = self.parse_expr(0)?;
-        if self.current_token != Some(Token::RParen) { return Err("Expected ')'".to_string()); }
+        if self.current_token != Some(Token::RParen) { return Err(self.syntax_error("Expected ')'")); }
         self.advance();
         Ok(Expr::TimeModifier { base: Box::new(base), shift_type })
     }
 
     // Phase 3: Expands YoY([Rev]) into ([Rev] - PY([Rev]))
-    fn parse_variance_macro(&mut self, base_shift: TimeShiftType) -> Result<Expr, String> {
+    fn parse_variance_macro(&mut self, base_shift: TimeShiftType) -> Result<Expr, ParseError> {
         self.advance(); // consume token
-        if self.current_token != Some(Token::LParen) { return Err("Expected '(' after variance macro".to_string()); }
+        if self.current_token != Some(Token::LParen) { return Err(self.syntax_error("Expected '(' after variance macro")); }
         self.advance();
         let base = self.parse_expr(0)?;
-        if self.current_token != Some(Token::RParen) { return Err("Expected ')'".to_string()); }
+        if self.current_token != Some(Token::RParen) { return Err(self.syntax_error("Expected ')'")); }
         self.advance();
         
         let py_shifted = Expr::TimeModifier { base: Box::new(base.clone()), shift_type: base_shift };
@@ -210,10 +552,38 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Whether `token` can begin an expression.
+fn starts_operand(token: &Option<Token>) -> bool {
+    matches!(
+        token,
+        Some(Token::Number(_))
+            | Some(Token::StringLiteral(_))
+            | Some(Token::DimensionRef(_))
+            | Some(Token::Identifier(_))
+            | Some(Token::AtIdentifier(_))
+            | Some(Token::LParen)
+            | Some(Token::Sum)
+            | Some(Token::Avg)
+            | Some(Token::Min)
+            | Some(Token::Max)
+            | Some(Token::If)
+            | Some(Token::Lookup)
+            | Some(Token::XLookup)
+            | Some(Token::PriorYear)
+            | Some(Token::PriorQuarter)
+            | Some(Token::YearToDate)
+            | Some(Token::QuarterToDate)
+            | Some(Token::PeriodToDate)
+            | Some(Token::YearOverYear)
+            | Some(Token::QuarterOverQuarter)
+    )
+}
+
 fn infix_binding_power(op: &BinaryOp) -> (u8, u8) {
     match op {
-        BinaryOp::Add | BinaryOp::Sub => (1, 2),
-        BinaryOp::Mul | BinaryOp::Div => (3, 4),
+        BinaryOp::Eq | BinaryOp::NotEq | BinaryOp::Lt | BinaryOp::Lte | BinaryOp::Gt | BinaryOp::Gte => (1, 2),
+        BinaryOp::Add | BinaryOp::Sub => (3, 4),
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => (5, 6),
     }
 }
 
@@ -253,4 +623,156 @@ mod tests {
 
         assert_eq!(ast2, expected2);
     }
+
+    #[test]
+    fn test_deep_nesting_is_rejected() {
+        let input = format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000));
+        let mut parser = Parser::new(&input);
+        assert_eq!(parser.parse(), Err(ParseError::TooDeep { limit: DEFAULT_MAX_DEPTH }));
+
+        // Nesting within the limit still parses
+        let input = format!("{}1{}", "(".repeat(50), ")".repeat(50));
+        let mut parser = Parser::new(&input);
+        assert_eq!(parser.parse(), Ok(Expr::Literal(1.0)));
+    }
+
+    #[test]
+    fn test_over_length_formula_is_rejected_before_lexing() {
+        let input = "1 + ".repeat(DEFAULT_MAX_FORMULA_LEN) + "1";
+        let mut parser = Parser::new_bounded(&input, DEFAULT_MAX_FORMULA_LEN);
+        assert!(parser.lexer.source().is_empty());
+        assert_eq!(
+            parser.parse(),
+            Err(ParseError::TooLong { len: input.len(), limit: DEFAULT_MAX_FORMULA_LEN })
+        );
+
+        let mut parser = Parser::new_bounded("1 + 1", 5);
+        assert!(parser.parse().is_ok());
+    }
+
+    #[test]
+    fn test_parse_all_errors_reports_each_argument() {
+        // Two independent errors: a dangling `+` before the comma and a dangling `*` before `)`
+        let mut parser = Parser::new("SUM(1 +, 2 * )");
+        let errors = parser.parse_all_errors().unwrap_err();
+        let spans: Vec<Range<usize>> = errors
+            .iter()
+            .map(|e| match e {
+                ParseError::Syntax { span, .. } => span.clone(),
+                other => panic!("unexpected error {:?}", other),
+            })
+            .collect();
+        assert_eq!(spans, vec![7..8, 13..14]);
+
+        // The single-error parse still stops at the first one
+        let mut parser = Parser::new("SUM(1 +, 2 * )");
+        assert!(matches!(parser.parse(), Err(ParseError::Syntax { span, .. }) if span == (7..8)));
+
+        let mut parser = Parser::new("SUM(1, 2)");
+        assert!(parser.parse_all_errors().is_ok());
+    }
+
+    #[test]
+    fn test_percent_literal_and_modulo() {
+        let mut parser = Parser::new("15% == 0.15");
+        assert_eq!(
+            parser.parse(),
+            Ok(Expr::Binary {
+                op: BinaryOp::Eq,
+                lhs: Box::new(Expr::Literal(0.15)),
+                rhs: Box::new(Expr::Literal(0.15)),
+            })
+        );
+
+        let mut parser = Parser::new("10 % 3");
+        assert_eq!(
+            parser.parse(),
+            Ok(Expr::Binary {
+                op: BinaryOp::Mod,
+                lhs: Box::new(Expr::Literal(10.0)),
+                rhs: Box::new(Expr::Literal(3.0)),
+            })
+        );
+
+        // Postfix percent on a non-literal divides by 100
+        let mut parser = Parser::new("[Margin]%");
+        assert_eq!(parser.parse().unwrap().to_string(), "([Margin] / 100)");
+
+        // Postfix percent binds tighter than the operator before it
+        let mut parser = Parser::new("6.5 / 15% * 2");
+        assert_eq!(parser.parse().unwrap().to_string(), "((6.5 / 0.15) * 2)");
+    }
+
+    #[test]
+    fn test_excel_prefix_is_stripped() {
+        use crate::atom_script::interp::EvalContext;
+
+        let expr = Parser::new("=1+2").parse_excel().unwrap();
+        assert_eq!(expr.eval_direct(&EvalContext::new()).unwrap(), 3.0);
+        assert_eq!(Parser::new("=SUM(1, 2)").parse_excel(), Parser::new("SUM(1, 2)").parse());
+        assert_eq!(Parser::new("1 + 2").parse_excel(), Parser::new("1 + 2").parse());
+
+        // `==` is still equality, never a prefix followed by `=`
+        let mut parser = Parser::new("1 == 2");
+        assert!(matches!(parser.parse_excel(), Ok(Expr::Binary { op: BinaryOp::Eq, .. })));
+        assert!(Parser::new("==1").parse_excel().is_err());
+        assert!(Parser::new("=").parse_excel().is_err());
+        assert!(Parser::new("=1+2").parse().is_err());
+    }
+
+    #[test]
+    fn test_ternary_desugars_to_if() {
+        let compile = |input: &str| {
+            let mut parser = Parser::new(input);
+            crate::atom_script::compiler::Compiler::new().compile(&parser.parse().expect("Parse failed"))
+        };
+        for (ternary, if_call) in [("1 > 0 ? 10 : 20", "IF(1 > 0, 10, 20)"), ("[A] > 0 ? 10 : 20", "IF([A] > 0, 10, 20)")] {
+            let (ternary, if_call) = (compile(ternary), compile(if_call));
+            assert_eq!(ternary.code, if_call.code);
+            assert_eq!(ternary.constants, if_call.constants);
+        }
+
+        // Right-associative: the else branch holds the nested ternary
+        let mut parser = Parser::new("[A] ? 1 : [B] ? 2 : 3");
+        assert_eq!(parser.parse().unwrap().to_string(), "IF([A], 1, IF([B], 2, 3))");
+
+        let mut parser = Parser::new("[A] ? 1");
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn test_hierarchy_function_errors_are_targeted() {
+        let message = |input: &str| match Parser::new(input).parse() {
+            Err(ParseError::Syntax { message, span }) => (message, span),
+            other => panic!("expected a syntax error, got {:?}", other),
+        };
+
+        let (msg, span) = message("@");
+        assert!(msg.contains("'@' must be followed by a hierarchy function name"), "{}", msg);
+        assert_eq!(span, 0..1);
+        assert!(message("SUM(@ + 1)").0.contains("'@' must be followed"));
+
+        let (msg, span) = message("@Children + 1");
+        assert!(msg.contains("hierarchy functions must be called with arguments"), "{}", msg);
+        assert!(msg.contains("@Children("), "{}", msg);
+        assert_eq!(span, 10..11);
+        assert!(message("@Children").0.contains("must be called with arguments"));
+    }
+
+    #[test]
+    fn test_whitespace_inside_dimension_ref() {
+        // Tabs and line breaks around the name are trimmed
+        assert_eq!(Parser::new("[\tRegion ]").parse(), Ok(Expr::DimensionRef("Region".to_string())));
+        assert_eq!(Parser::new("[\n Revenue\t\n] * 2").parse(), Parser::new("[Revenue] * 2").parse());
+
+        // Inside the name they are rejected, pointing at the reference
+        let (message, span) = match Parser::new("SUM([North\nAmerica], 1)").parse() {
+            Err(ParseError::Syntax { message, span }) => (message, span),
+            other => panic!("expected a syntax error, got {:?}", other),
+        };
+        assert!(message.contains("dimension reference [North\\nAmerica] contains a tab or line break"), "{}", message);
+        assert_eq!(span, 4..19);
+        assert!(Parser::new("[Gross\tMargin]").parse().is_err());
+        assert!(Parser::new("CELL([Region]=[North\nAmerica])").parse().unwrap_err().to_string().contains("line break"));
+    }
 }
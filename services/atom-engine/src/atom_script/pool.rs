@@ -0,0 +1,140 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::atom_script::chunk::PreparedChunk;
+use crate::atom_script::value::Value;
+use crate::atom_script::vm::{InterpretResult, DEFAULT_STACK_SIZE, VM};
+
+/// Stacks each thread keeps for reuse by default. A thread runs one plan at a time, so a
+/// handful covers nested pool use without holding much memory per worker.
+pub const DEFAULT_POOL_IDLE_PER_THREAD: usize = 4;
+
+thread_local! {
+    // Shared by every pool on the thread; a buffer only carries capacity, never values
+    static FREE_STACKS: RefCell<Vec<Vec<Value>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Runs plans on VMs whose stacks come from a per-thread free list, so a server evaluating
+/// many short plans does not allocate (and free) a stack for every one.
+/// VMs borrow their data context (arena, resolvers) for a single run, so what is pooled is
+/// the stack buffer: each run builds a VM around a recycled buffer and takes it back after.
+/// At most `max_idle` buffers are kept per thread (`DEFAULT_POOL_IDLE_PER_THREAD`); a buffer
+/// holds `stack_size` values (`DEFAULT_STACK_SIZE`).
+pub struct VmPool {
+    stack_size: usize,
+    max_idle: usize,
+    allocations: AtomicUsize,
+    reuses: AtomicUsize,
+}
+
+impl Default for VmPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VmPool {
+    pub fn new() -> Self {
+        Self {
+            stack_size: DEFAULT_STACK_SIZE,
+            max_idle: DEFAULT_POOL_IDLE_PER_THREAD,
+            allocations: AtomicUsize::new(0),
+            reuses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Gives pooled VMs a stack of `size` values (see `VM::with_stack_size`).
+    pub fn with_stack_size(mut self, size: usize) -> Self {
+        self.stack_size = size;
+        self
+    }
+
+    /// Keeps at most `max_idle` stacks per thread between runs.
+    pub fn with_max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// Runs `chunk` on a pooled VM. `configure` attaches the data context, as on a fresh VM:
+    /// `pool.run(chunk, |vm| vm.with_arena(&arena))`. Pass a `PreparedChunk` when running the
+    /// same plan repeatedly, so it is validated once rather than on every run.
+    pub fn run<'a>(&self, chunk: impl Into<PreparedChunk>, configure: impl FnOnce(VM<'a>) -> VM<'a>) -> InterpretResult {
+        let stack = match FREE_STACKS.with(|free| free.borrow_mut().pop()) {
+            Some(mut stack) => {
+                self.reuses.fetch_add(1, Ordering::Relaxed);
+                stack.reserve(self.stack_size);
+                stack
+            }
+            None => {
+                self.allocations.fetch_add(1, Ordering::Relaxed);
+                Vec::with_capacity(self.stack_size)
+            }
+        };
+
+        let mut vm = configure(VM::with_stack_buffer(chunk, stack, self.stack_size));
+        let result = vm.run();
+
+        let mut stack = vm.into_stack_buffer();
+        stack.clear();
+        FREE_STACKS.with(|free| {
+            let mut free = free.borrow_mut();
+            if free.len() < self.max_idle {
+                free.push(stack);
+            }
+        });
+        result
+    }
+
+    /// Stacks this pool had to allocate because its thread's free list was empty.
+    pub fn allocations(&self) -> usize {
+        self.allocations.load(Ordering::Relaxed)
+    }
+
+    /// Runs served with a recycled stack.
+    pub fn reuses(&self) -> usize {
+        self.reuses.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+    use crate::atom_script::compiler::Compiler;
+    use crate::atom_script::parser::Parser;
+    use crate::lattice::arena::LatticeArena;
+    use crate::lattice::coordinate::coordinate_hash;
+
+    #[test]
+    fn test_pool_reuses_stacks_across_concurrent_plans() {
+        let arena = LatticeArena::new(64);
+        for i in 0..100 {
+            arena.set_cell(coordinate_hash(&[("Measure", "Revenue"), ("Region", &format!("R{}", i))]), i as f64);
+        }
+        let plans: Vec<PreparedChunk> = ["[Revenue] * 2", "SUM([Revenue], 1, 2)", "IF([Revenue] > 50, [Revenue], 0)"]
+            .iter()
+            .map(|f| PreparedChunk::new(Compiler::new().compile(&Parser::new(f).parse().unwrap())))
+            .collect();
+        let expected = |plan: usize, v: f64| match plan {
+            0 => v * 2.0,
+            1 => v + 3.0,
+            _ => if v > 50.0 { v } else { 0.0 },
+        };
+
+        let threads = 4;
+        let workers = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        let pool = VmPool::new();
+        let runs = 3000;
+        workers.install(|| {
+            (0..runs).into_par_iter().for_each(|i| {
+                let region = format!("R{}", i % 100);
+                let coordinate = vec![("Region".to_string(), region)];
+                let result = pool.run(plans[i % 3].clone(), |vm| vm.with_arena(&arena).with_coordinate(coordinate));
+                assert_eq!(result, InterpretResult::Ok(expected(i % 3, (i % 100) as f64)));
+            });
+        });
+
+        // One stack per worker thread at most; every other run reused one
+        assert!(pool.allocations() <= threads, "{} allocations", pool.allocations());
+        assert_eq!(pool.allocations() + pool.reuses(), runs);
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Host-defined function body: receives the evaluated arguments in call order.
+pub type NativeFn = Arc<dyn Fn(&[f64]) -> f64 + Send + Sync>;
+
+/// A function registered by the embedder, e.g. a proprietary `FX_RATE(from, to)`.
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    func: NativeFn,
+}
+
+impl NativeFunction {
+    pub fn call(&self, args: &[f64]) -> f64 {
+        (self.func)(args)
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+/// Function Registry: the extension point for host-defined functions.
+/// The compiler consults it for names it does not recognise natively, checks the call
+/// against the registered arity and emits `OpCode::CallNative(id, argc)`; the VM dispatches
+/// `id` back through the same registry, so the compiler and VM must share one registry instance.
+#[derive(Debug, Clone, Default)]
+pub struct FunctionRegistry {
+    functions: Vec<NativeFunction>,
+    by_name: HashMap<String, usize>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `name` with a fixed `arity` and returns its id.
+    /// Re-registering a name replaces its implementation and keeps the id.
+    pub fn register(
+        &mut self,
+        name: &str,
+        arity: usize,
+        func: impl Fn(&[f64]) -> f64 + Send + Sync + 'static,
+    ) -> usize {
+        let function = NativeFunction {
+            name: name.to_string(),
+            arity,
+            func: Arc::new(func),
+        };
+        if let Some(&id) = self.by_name.get(name) {
+            self.functions[id] = function;
+            return id;
+        }
+        let id = self.functions.len();
+        self.functions.push(function);
+        self.by_name.insert(name.to_string(), id);
+        id
+    }
+
+    /// Id of the function registered as `name`.
+    pub fn id(&self, name: &str) -> Option<usize> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&NativeFunction> {
+        self.functions.get(id)
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use crate::atom_script::ast::{Expr, Statement};
+use crate::atom_script::chunk::Chunk;
+use crate::atom_script::compiler::Compiler;
+use crate::atom_script::error::EngineError;
+use crate::atom_script::parser::Parser;
+use crate::atom_script::value::Value;
+use crate::atom_script::vm::VM;
+use crate::lattice::arena::LatticeArena;
+
+/// A REPL/notebook session: evaluates one input at a time against the arena, remembering
+/// `let` bindings between calls. Each input is compiled on its own, with earlier bindings
+/// inlined as constants, so nothing already evaluated is recompiled.
+pub struct Session {
+    arena: Arc<LatticeArena>,
+    bindings: Arc<HashMap<String, f64>>,
+    definitions: Vec<Definition>, // Current `let` of each bound name, in the order they were bound
+}
+
+/// The `let` a binding's value came from, kept for `Session::dump`.
+struct Definition {
+    name: String,
+    formula: Expr,
+    chunk: Arc<Chunk>,
+}
+
+impl Session {
+    pub fn new(arena: Arc<LatticeArena>) -> Self {
+        Self { arena, bindings: Arc::new(HashMap::new()), definitions: Vec::new() }
+    }
+
+    pub fn arena(&self) -> &LatticeArena {
+        &self.arena
+    }
+
+    /// Value bound to `name` by an earlier `let`, if any.
+    pub fn binding(&self, name: &str) -> Option<f64> {
+        self.bindings.get(name).copied()
+    }
+
+    /// Evaluates a formula or a `let name = expr` statement and returns its value.
+    /// A failed `let` leaves the bindings unchanged; rebinding a name replaces its value.
+    pub fn eval(&mut self, input: &str) -> Result<f64, EngineError> {
+        let (name, expr) = match Parser::new(input).parse_statement()? {
+            Statement::Let { name, value } => (Some(name), value),
+            Statement::Expr(expr) => (None, expr),
+        };
+        let chunk = Arc::new(Compiler::new().with_bindings(Arc::clone(&self.bindings)).try_compile(&expr)?);
+        let value = VM::new(Arc::clone(&chunk)).with_arena(&self.arena).run().into_number()?;
+        if let Some(name) = name {
+            Arc::make_mut(&mut self.bindings).insert(name.clone(), value);
+            self.definitions.retain(|d| d.name != name);
+            self.definitions.push(Definition { name, formula: expr, chunk });
+        }
+        Ok(value)
+    }
+
+    /// Lists the bindings in the order they were bound: a `let name = formula  ; value` line
+    /// each, followed by its compiled chunk (`Chunk::disassemble`) indented. The formula is
+    /// printed in canonical form, which parses back to the same tree; the chunk has the
+    /// values of earlier bindings inlined as constants.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        for definition in &self.definitions {
+            let value = self.bindings.get(&definition.name).copied().map_or(Value::Empty, Value::Num);
+            let _ = writeln!(out, "let {} = {}  ; {}", definition.name, definition.formula, value);
+            for line in definition.chunk.disassemble() {
+                let _ = writeln!(out, "    {}", line);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atom_script::error::RuntimeError;
+    use crate::lattice::coordinate::coordinate_hash;
+
+    #[test]
+    fn test_bindings_persist_across_calls() {
+        let arena = Arc::new(LatticeArena::new(16));
+        arena.set_cell(coordinate_hash(&[("Measure", "Revenue")]), 100.0);
+        let mut session = Session::new(arena);
+
+        assert_eq!(session.eval("let a = 5"), Ok(5.0));
+        assert_eq!(session.eval("a + 1"), Ok(6.0));
+        assert_eq!(session.eval("let b = a * [Revenue]"), Ok(500.0));
+        assert_eq!(session.eval("let a = b - a"), Ok(495.0));
+        assert_eq!(session.binding("a"), Some(495.0));
+
+        assert_eq!(
+            session.eval("missing + 1"),
+            Err(EngineError::Runtime(RuntimeError::ErrorValue("#NAME?".to_string())))
+        );
+        assert!(session.eval("let c = ").is_err());
+        assert_eq!(session.binding("c"), None);
+    }
+
+    #[test]
+    fn test_dump_lists_bindings_with_values() {
+        let arena = Arc::new(LatticeArena::new(16));
+        arena.set_cell(coordinate_hash(&[("Measure", "Revenue")]), 100.0);
+        let mut session = Session::new(arena);
+        session.eval("let rate = 0.25").unwrap();
+        session.eval("let tax = [Revenue]*rate").unwrap();
+        session.eval("tax + 1").unwrap();
+
+        let dump = session.dump();
+        let lets: Vec<&str> = dump.lines().filter(|line| line.starts_with("let ")).collect();
+        assert_eq!(lets, vec!["let rate = 0.25  ; 0.25", "let tax = ([Revenue] * rate)  ; 25"]);
+        assert!(dump.contains("LoadDimension(0)  ; Measure=Revenue"), "{}", dump);
+
+        // The listed formula parses back to the one that was bound
+        let formula = lets[1].trim_start_matches("let tax = ").split("  ;").next().unwrap();
+        assert_eq!(Parser::new(formula).parse().unwrap().to_string(), "([Revenue] * rate)");
+
+        // Rebinding moves the name to the end with its new formula
+        session.eval("let rate = 0.5").unwrap();
+        let last = session.dump().lines().rfind(|line| line.starts_with("let ")).map(str::to_string);
+        assert_eq!(last.as_deref(), Some("let rate = 0.5  ; 0.5"));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::atom_script::ast::{BinaryOp, Expr};
+
+/// Rewrites `expr` with the algebraic identities `x + 0`, `x - 0`, `x * 1`, `x / 1` → `x`,
+/// `x * 0` → `0` and `x - x` → `0` (literal operands on either side of `+` and `*`).
+/// Children are simplified first, so `([A] * 2 + 0) - [A] * 2` becomes `0`.
+/// The `→ x` identities only apply when `x` is known to be a number (a literal or an
+/// arithmetic result): arithmetic turns an empty cell or a boolean into a number, so
+/// `[A] + 0` is 0 for an empty `[A]` where `[A]` alone would stay empty.
+/// An operand is only dropped (`x * 0`, `x - x`) when evaluating it can do nothing but read
+/// data: cell and dimension references, literals and arithmetic over them. Function calls are
+/// kept, since LOOKUP or a registered function may consult a backend on every evaluation.
+/// Dropping an operand also drops what it would have evaluated to at runtime: an error value,
+/// NaN or a type error in `[A] * 0` is 0 after simplification.
+pub fn simplify(expr: Expr) -> Expr {
+    match expr {
+        Expr::Binary { op, lhs, rhs } => simplify_binary(op, simplify(*lhs), simplify(*rhs)),
+        Expr::FunctionCall { name, args } => Expr::FunctionCall { name, args: args.into_iter().map(simplify).collect() },
+        Expr::HierarchyCall { name, args } => Expr::HierarchyCall { name, args: args.into_iter().map(simplify).collect() },
+        Expr::TimeTravel { lhs, rhs } => Expr::TimeTravel { lhs: Box::new(simplify(*lhs)), rhs: Box::new(simplify(*rhs)) },
+        Expr::In { value, candidates } => Expr::In {
+            value: Box::new(simplify(*value)),
+            candidates: candidates.into_iter().map(simplify).collect(),
+        },
+        Expr::TimeModifier { base, shift_type } => Expr::TimeModifier { base: Box::new(simplify(*base)), shift_type },
+        leaf => leaf,
+    }
+}
+
+fn simplify_binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+    let literal = |e: &Expr, v: f64| matches!(e, Expr::Literal(l) if *l == v);
+    match op {
+        BinaryOp::Add if literal(&rhs, 0.0) && is_numeric(&lhs) => lhs,
+        BinaryOp::Add if literal(&lhs, 0.0) && is_numeric(&rhs) => rhs,
+        BinaryOp::Sub if literal(&rhs, 0.0) && is_numeric(&lhs) => lhs,
+        BinaryOp::Sub if lhs == rhs && is_pure(&lhs) => Expr::Literal(0.0),
+        BinaryOp::Mul if literal(&rhs, 1.0) && is_numeric(&lhs) => lhs,
+        BinaryOp::Mul if literal(&lhs, 1.0) && is_numeric(&rhs) => rhs,
+        BinaryOp::Mul if (literal(&rhs, 0.0) && is_pure(&lhs)) || (literal(&lhs, 0.0) && is_pure(&rhs)) => Expr::Literal(0.0),
+        BinaryOp::Div if literal(&rhs, 1.0) && is_numeric(&lhs) => lhs,
+        _ => Expr::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs) },
+    }
+}
+
+/// Whether `expr` always evaluates to a number (or an error), so arithmetic with an identity
+/// element leaves its value unchanged.
+fn is_numeric(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_) => true,
+        Expr::Binary { op, .. } => matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod),
+        _ => false,
+    }
+}
+
+/// Whether evaluating `expr` only reads literals and cells, so skipping it is unobservable
+/// apart from the value it would have produced.
+fn is_pure(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_) | Expr::StringLiteral(_) | Expr::Identifier(_) | Expr::DimensionRef(_) | Expr::CellRef(_) => true,
+        Expr::Binary { lhs, rhs, .. } | Expr::TimeTravel { lhs, rhs } => is_pure(lhs) && is_pure(rhs),
+        Expr::In { value, candidates } => is_pure(value) && candidates.iter().all(is_pure),
+        Expr::TimeModifier { base, .. } => is_pure(base),
+        // A hierarchy call pushes one value per member, so it is never a single dropped operand
+        Expr::FunctionCall { .. } | Expr::HierarchyCall { .. } => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atom_script::parser::Parser;
+
+    fn simplified(input: &str) -> Expr {
+        simplify(Parser::new(input).parse().expect("Parse failed"))
+    }
+
+    fn parsed(input: &str) -> Expr {
+        Parser::new(input).parse().expect("Parse failed")
+    }
+
+    #[test]
+    fn test_algebraic_identities() {
+        assert_eq!(simplified("[A] - [A]"), Expr::Literal(0.0));
+        assert_eq!(simplified("[A] * 0"), Expr::Literal(0.0));
+        assert_eq!(simplified("0 * CELL([Region]=USA)"), Expr::Literal(0.0));
+        assert_eq!(simplified("([A] + [B]) * 1"), parsed("[A] + [B]"));
+        assert_eq!(simplified("1 * ([A] / [B])"), parsed("[A] / [B]"));
+        assert_eq!(simplified("([A] % 2) + 0"), parsed("[A] % 2"));
+        assert_eq!(simplified("0 + ([A] - [B])"), parsed("[A] - [B]"));
+        assert_eq!(simplified("([A] * [B]) - 0"), parsed("[A] * [B]"));
+        assert_eq!(simplified("([A] * [B]) / 1"), parsed("[A] * [B]"));
+        assert_eq!(simplified("2 * 1"), Expr::Literal(2.0));
+        // Inner rewrites expose outer ones, including inside function arguments
+        assert_eq!(simplified("([A] * 2 + 0) - [A] * 2"), Expr::Literal(0.0));
+        assert_eq!(simplified("SUM(([A] + [B]) * 1, [B] - [B])"), parsed("SUM([A] + [B], 0)"));
+        // A reference may be empty (or a comparison boolean), which arithmetic turns into a number
+        assert_eq!(simplified("[A] * 1"), parsed("[A] * 1"));
+        assert_eq!(simplified("0 + [A]"), parsed("0 + [A]"));
+        assert_eq!(simplified("([A] > 1) - 0"), parsed("([A] > 1) - 0"));
+        // Not identities
+        assert_eq!(simplified("[A] - [B]"), parsed("[A] - [B]"));
+        assert_eq!(simplified("0 - [A]"), parsed("0 - [A]"));
+        assert_eq!(simplified("1 / [A]"), parsed("1 / [A]"));
+    }
+
+    #[test]
+    fn test_calls_are_not_cancelled() {
+        let lookup = "LOOKUP([Code], [Codes], [Names])";
+        let difference = format!("{} - {}", lookup, lookup);
+        assert_eq!(simplified(&difference), parsed(&difference));
+        let product = format!("{} * 0", lookup);
+        assert_eq!(simplified(&product), parsed(&product));
+        // Identities that keep the call still apply
+        let sum = format!("{} + {}", lookup, lookup);
+        assert_eq!(simplified(&format!("({}) * 1", sum)), parsed(&sum));
+    }
+}
@@ -1,6 +1,6 @@
 use crate::atom_script::ast::Expr;
 use crate::atom_script::compiler::Compiler;
-use crate::atom_script::vm::{VM, InterpretResult};
+use crate::atom_script::vm::VM;
 
 /// Represents the mathematical configuration for a Goal Seek operation.
 pub struct GoalSeekConfig {
@@ -77,20 +77,12 @@ impl InverseSolver {
         // In Phase 3.2, since we decouple the LatticeArena for safety, we rely on the 
         // compiled output (which uses mock variables heavily right now).
         // For actual gradient descent to work, the VM needs an injected Variable context.
-        match vm.run() {
-            InterpretResult::Ok(val) => Ok(val),
-            InterpretResult::CompileError => Err("Compilation Error in Solver".to_string()),
-            InterpretResult::RuntimeError => Err("Runtime Error in Solver".to_string()),
-            InterpretResult::EvaluationTimeout => Err("Evaluation Timeout in Solver".to_string()),
-        }
+        vm.run().into_number().map_err(|e| format!("Solver evaluation failed: {}", e))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use crate::atom_script::ast::BinaryOp;
-
     #[test]
     fn test_goal_seek_linear() {
         // We want to simulate the equation: 2 * x = 100
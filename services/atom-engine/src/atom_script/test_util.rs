@@ -0,0 +1,103 @@
+//! Helpers for property tests of the parse -> compile -> evaluate pipeline.
+
+use crate::atom_script::compiler::Compiler;
+use crate::atom_script::error::EngineError;
+use crate::atom_script::parser::Parser;
+use crate::atom_script::vm::VM;
+
+/// Parses, type-checks, compiles and runs a formula without an arena.
+pub fn evaluate(formula: &str) -> Result<f64, EngineError> {
+    let expr = Parser::new(formula).parse()?;
+    let chunk = Compiler::new().try_compile(&expr)?;
+    VM::new(chunk).run().into_number()
+}
+
+/// Deterministic xorshift64 generator, so a failing case reproduces from its seed.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // splitmix64 finalizer: spreads small seeds and never yields the all-zero state
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self((z ^ (z >> 31)) | 1)
+    }
+
+    /// Uniform-ish value in `0..bound`.
+    pub fn next(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+/// Generates a random arithmetic formula of at most `depth` nested operations, together with
+/// its value computed directly in Rust. Operators are printed with only the parentheses
+/// precedence requires (plus some redundant ones) and random spacing, so the oracle
+/// catches precedence, associativity, lexing and constant-folding regressions.
+/// The value is the error value the formula evaluates to instead when it fails (a MIN/MAX
+/// whose operands are all NaN is `#N/A`, and the error propagates to the whole formula).
+/// The same `(depth, seed)` always yields the same formula.
+pub fn gen_expr(depth: u32, seed: u64) -> (String, Result<f64, &'static str>) {
+    let (text, value, _) = gen(&mut Rng::new(seed), depth);
+    (text, value)
+}
+
+const ATOM: u8 = 3; // Binding strength of literals, calls and parenthesized expressions
+
+// Returns (source, value, binding strength): 1 for + -, 2 for * / %.
+fn gen(rng: &mut Rng, depth: u32) -> (String, Result<f64, &'static str>, u8) {
+    if depth == 0 || rng.next(4) == 0 {
+        let n = rng.next(100) as f64 / [1.0, 4.0][rng.next(2) as usize];
+        return if rng.next(8) == 0 {
+            (format!("{}%", n), Ok(n / 100.0), ATOM) // Postfix percent
+        } else {
+            (n.to_string(), Ok(n), ATOM)
+        };
+    }
+    match rng.next(6) {
+        0 => {
+            // SUM/MIN/MAX: the VM folds operands last-first, so the oracle does too
+            let name = ["SUM", "MIN", "MAX"][rng.next(3) as usize];
+            let args: Vec<_> = (0..1 + rng.next(3)).map(|_| gen(rng, depth - 1)).collect();
+            let value = args.iter().rev().map(|a| a.1).collect::<Result<Vec<f64>, _>>().and_then(|values| {
+                // MIN/MAX skip NaN operands, and have nothing to return if all of them are
+                let folded = match name {
+                    "SUM" => return Ok(values.iter().sum()),
+                    "MIN" => values.iter().fold(f64::NAN, |m, &v| m.min(v)),
+                    _ => values.iter().fold(f64::NAN, |m, &v| m.max(v)),
+                };
+                if folded.is_nan() { Err("#N/A") } else { Ok(folded) }
+            });
+            let args: Vec<String> = args.into_iter().map(|a| a.0).collect();
+            (format!("{}({})", name, args.join(&spacing(rng, ","))), value, ATOM)
+        }
+        1 => {
+            let (text, value, _) = gen(rng, depth - 1);
+            (format!("({})", text), value, ATOM)
+        }
+        _ => {
+            let (symbol, strength) = [("+", 1), ("-", 1), ("*", 2), ("/", 2), ("%", 2)][rng.next(5) as usize];
+            let (lhs, l, l_strength) = gen(rng, depth - 1);
+            let (rhs, r, r_strength) = gen(rng, depth - 1);
+            let value = l.and_then(|l| r.map(|r| match symbol {
+                "+" => l + r,
+                "-" => l - r,
+                "*" => l * r,
+                "/" => l / r,
+                _ => l - r * (l / r).floor(),
+            }));
+            // Left-associative: an equal-strength right operand needs parentheses, a left one does not
+            let lhs = if l_strength < strength { format!("({})", lhs) } else { lhs };
+            let rhs = if r_strength <= strength { format!("({})", rhs) } else { rhs };
+            (format!("{}{}{}", lhs, spacing(rng, symbol), rhs), value, strength)
+        }
+    }
+}
+
+fn spacing(rng: &mut Rng, symbol: &str) -> String {
+    let pad = |rng: &mut Rng| " ".repeat(rng.next(3) as usize);
+    format!("{}{}{}", pad(rng), symbol, pad(rng))
+}
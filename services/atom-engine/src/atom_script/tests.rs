@@ -1,6 +1,18 @@
 use crate::atom_script::parser::Parser;
-use crate::atom_script::compiler::Compiler;
-use crate::atom_script::chunk::OpCode;
+use crate::atom_script::compiler::{compile_batch, CompileError, Compiler, CompilerOptions};
+use crate::atom_script::chunk::{Chunk, OpCode};
+use crate::atom_script::vm::{
+    BatchResult, CellError, InterpretResult, MissingPolicy, RuntimeFault, SliceReport, CANCEL_CHECK_INTERVAL, MAX_EMA_LOOKBACK, VM,
+};
+use crate::atom_script::registry::FunctionRegistry;
+use crate::lattice::arena::LatticeArena;
+use crate::lattice::coordinate::{coordinate_hash, CoordinateSpec, Dimension, Member};
+use crate::lattice::metadata::{HierarchyResolver, MapHierarchyResolver, RetryingResolver};
+use crate::lattice::period::{ListPeriodResolver, PeriodResolver};
+use crate::lattice::slice::GridSlice;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 #[test]
 fn test_hierarchy_children_expansion() {
@@ -16,16 +28,17 @@ fn test_hierarchy_children_expansion() {
 
     // 3. Verification
     // We expect:
-    // - 3 Constants (placeholders for USA, Canada, Mexico)
+    // - 3 LoadDimension ops (USA, Canada, Mexico)
     // - OpCode::Sum(3)
     
     // Check if OpCode::Sum(3) is present
-    let has_sum_3 = chunk.code.iter().any(|op| *op == OpCode::Sum(3));
+    let has_sum_3 = chunk.code.contains(&OpCode::Sum(3));
     assert!(has_sum_3, "Chunk should contain OpCode::Sum(3). Code: {:?}", chunk.code);
 
-    // Check if we have constants loaded. 
-    // Since DimensionRef currently pushes a 0.0 constant, we should have at least 3 constants.
-    assert!(chunk.constants.len() >= 3, "Should have at least 3 constants for the children");
+    // Check that each child is loaded from the arena, addressed within the Region dimension.
+    let loads = chunk.code.iter().filter(|op| matches!(op, OpCode::LoadDimension(_))).count();
+    assert_eq!(loads, 3, "Should have 3 cell loads for the children");
+    assert!(chunk.coordinates.contains(&vec![("Region".to_string(), "Canada".to_string())]));
 }
 
 #[test]
@@ -44,6 +57,11 @@ fn test_lookup_and_time_travel() {
     let expr = parser.parse().expect("Parse failed");
     let compiler = Compiler::new();
     let chunk = compiler.compile(&expr);
+    assert!(chunk.code.contains(&OpCode::CalendarOffset(0, -1)));
+
+    // A step that is not a known shift keeps the generic Shift
+    let mut parser = Parser::new("[Revenue] -> [Budget]");
+    let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
     assert!(chunk.code.contains(&OpCode::Shift));
 }
 
@@ -60,3 +78,1230 @@ fn test_basic_arithmetic() {
     assert!(chunk.code.contains(&OpCode::Constant(0))); 
     assert_eq!(chunk.constants[0], 3.0);
 }
+
+#[test]
+fn test_chunk_json_export() {
+    let mut parser = Parser::new("1 + 2");
+    let expr = parser.parse().expect("Parse failed");
+    let chunk = Compiler::new().compile(&expr);
+
+    let json = chunk.to_json();
+    assert_eq!(json["constants"], serde_json::json!([3.0]));
+    assert_eq!(json["code"], serde_json::json!([{ "Constant": 0 }, "Return"]));
+
+    // Operands such as aggregation counts must survive the round-trip
+    let mut parser = Parser::new("SUM(@Children([Region], [North America]))");
+    let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+    let restored = Chunk::from_json(&chunk.to_json()).expect("Round-trip failed");
+    assert_eq!(restored.code, chunk.code);
+    assert!(restored.code.contains(&OpCode::Sum(3)));
+}
+
+#[test]
+fn test_concat_and_text_functions() {
+    let mut parser = Parser::new(r#"CONCAT("a", "b")"#);
+    let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+    assert_eq!(VM::new(chunk).run(), InterpretResult::Text("ab".to_string()));
+
+    let mut parser = Parser::new(r#"TEXT(1234.5, "0.00")"#);
+    let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+    assert_eq!(VM::new(chunk).run(), InterpretResult::Text("1234.50".to_string()));
+
+    // Numeric arguments are stringified: integral values print without a fraction
+    let mut parser = Parser::new(r#"CONCAT("Units: ", 40 + 2)"#);
+    let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+    assert_eq!(VM::new(chunk).run(), InterpretResult::Text("Units: 42".to_string()));
+}
+
+#[test]
+fn test_comparison_precedence() {
+    // Comparisons bind looser than arithmetic: (1 + 2) > 2
+    let mut parser = Parser::new("1 + 2 > 2");
+    let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+    assert_eq!(chunk.code, vec![OpCode::Constant(0), OpCode::Constant(1), OpCode::Greater, OpCode::Return]);
+    assert_eq!(VM::new(chunk).run(), InterpretResult::Ok(1.0));
+}
+
+#[test]
+fn test_in_membership_operator() {
+    let mut parser = Parser::new(r#""USA" IN ("USA", "Canada")"#);
+    let expr = parser.parse().expect("Parse failed");
+    let chunk = Compiler::new().compile(&expr);
+    assert!(chunk.code.contains(&OpCode::In(2)));
+    assert_eq!(VM::new(chunk).run(), InterpretResult::Ok(1.0));
+
+    let mut parser = Parser::new(r#""Mexico" IN ("USA", "Canada")"#);
+    let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+    assert_eq!(VM::new(chunk).run(), InterpretResult::Ok(0.0));
+
+    // Numeric membership, with the test value computed
+    let mut parser = Parser::new("1 + 1 IN (1, 2, 3)");
+    let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+    assert_eq!(VM::new(chunk).run(), InterpretResult::Ok(1.0));
+}
+
+#[test]
+fn test_rolling_average_over_periods() {
+    let months: Vec<String> = ["Jan", "Feb", "Mar", "Apr", "May"].iter().map(|m| m.to_string()).collect();
+    let periods = ListPeriodResolver::new("Time", months.clone());
+    let arena = LatticeArena::new(64);
+    for (i, month) in months.iter().enumerate() {
+        let hash = coordinate_hash(&[("Region", "USA"), ("Time", month), ("Measure", "Revenue")]);
+        arena.set_cell(hash, (i as f64 + 1.0) * 10.0); // 10, 20, 30, 40, 50
+    }
+
+    let mut parser = Parser::new("ROLLING_AVG([Revenue], 3)");
+    let expr = parser.parse().expect("Parse failed");
+
+    let eval_at = |month: &str| {
+        let chunk = Compiler::new().compile(&expr);
+        let coordinate = vec![
+            ("Region".to_string(), "USA".to_string()),
+            ("Time".to_string(), month.to_string()),
+        ];
+        VM::new(chunk).with_arena(&arena).with_periods(&periods).with_coordinate(coordinate).run()
+    };
+
+    assert_eq!(eval_at("Mar"), InterpretResult::Ok(20.0)); // (10 + 20 + 30) / 3
+    assert_eq!(eval_at("May"), InterpretResult::Ok(40.0)); // (30 + 40 + 50) / 3
+    // Start of series: only the available periods are averaged
+    assert_eq!(eval_at("Jan"), InterpretResult::Ok(10.0));
+    assert_eq!(eval_at("Feb"), InterpretResult::Ok(15.0));
+
+    // A non-literal window is rejected with an error value
+    let mut parser = Parser::new("ROLLING_AVG([Revenue], [Window])");
+    let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+    assert_eq!(VM::new(chunk).run(), InterpretResult::ErrorValue("#VALUE!".to_string()));
+}
+
+#[test]
+fn test_ema_over_periods() {
+    let months: Vec<String> = ["Jan", "Feb", "Mar", "Apr", "May"].iter().map(|m| m.to_string()).collect();
+    let periods = ListPeriodResolver::new("Time", months.clone());
+    let arena = LatticeArena::new(64);
+    for (i, month) in months.iter().enumerate() {
+        let hash = coordinate_hash(&[("Time", month.as_str()), ("Measure", "Revenue")]);
+        arena.set_cell(hash, (i as f64 + 1.0) * 10.0); // 10, 20, 30, 40, 50
+    }
+
+    let mut parser = Parser::new("EMA([Revenue], 50%)");
+    let expr = parser.parse().expect("Parse failed");
+    let eval_at = |month: &str| {
+        let chunk = Compiler::new().compile(&expr);
+        let coordinate = vec![("Time".to_string(), month.to_string())];
+        VM::new(chunk).with_arena(&arena).with_periods(&periods).with_coordinate(coordinate).run()
+    };
+
+    assert_eq!(eval_at("Jan"), InterpretResult::Ok(10.0)); // Seeded with the first value
+    assert_eq!(eval_at("Feb"), InterpretResult::Ok(15.0)); // 0.5 * 20 + 0.5 * 10
+    assert_eq!(eval_at("Mar"), InterpretResult::Ok(22.5));
+    assert_eq!(eval_at("May"), InterpretResult::Ok(40.625));
+
+    // Alpha outside (0, 1] is rejected with an error value
+    let mut parser = Parser::new("EMA([Revenue], 2)");
+    let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+    assert_eq!(VM::new(chunk).run(), InterpretResult::ErrorValue("#VALUE!".to_string()));
+}
+
+#[test]
+fn test_ema_lookback_is_bounded() {
+    // Periods "P<n>" for every integer n: the series has no start
+    struct Endless;
+    impl PeriodResolver for Endless {
+        fn dimension(&self) -> &str {
+            "Time"
+        }
+        fn shift(&self, period: &str, offset: i64) -> Option<String> {
+            let n: i64 = period.strip_prefix('P')?.parse().ok()?;
+            Some(format!("P{}", n.checked_add(offset)?))
+        }
+    }
+    let arena = LatticeArena::new(64);
+    let eval = || {
+        let chunk = Compiler::new().compile(&Parser::new("COALESCE(EMA([Revenue], 50%), 99)").parse().expect("Parse failed"));
+        let coordinate = vec![("Time".to_string(), "P0".to_string())];
+        VM::new(chunk).with_arena(&arena).with_periods(&Endless).with_coordinate(coordinate).run()
+    };
+    let set = |n: i64, value: f64| arena.set_cell(coordinate_hash(&[("Time", format!("P{}", n).as_str()), ("Measure", "Revenue")]), value);
+
+    // Only the last MAX_EMA_LOOKBACK periods count
+    set(-(MAX_EMA_LOOKBACK as i64), 1.0);
+    assert_eq!(eval(), InterpretResult::Ok(99.0));
+    set(1 - MAX_EMA_LOOKBACK as i64, 7.0);
+    assert_eq!(eval(), InterpretResult::Ok(7.0));
+}
+
+#[test]
+fn test_lag_and_lead_shift_by_periods() {
+    let months: Vec<String> = ["Jan", "Feb", "Mar", "Apr", "May"].iter().map(|m| m.to_string()).collect();
+    let periods = ListPeriodResolver::new("Time", months.clone());
+    let arena = LatticeArena::new(64);
+    for (i, month) in months.iter().enumerate() {
+        let hash = coordinate_hash(&[("Time", month.as_str()), ("Measure", "Revenue")]);
+        arena.set_cell(hash, (i as f64 + 1.0) * 10.0); // 10, 20, 30, 40, 50
+    }
+
+    let eval_at = |formula: &str, month: &str| {
+        let mut parser = Parser::new(formula);
+        let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+        let coordinate = vec![("Time".to_string(), month.to_string())];
+        VM::new(chunk).with_arena(&arena).with_periods(&periods).with_coordinate(coordinate).run()
+    };
+
+    assert_eq!(eval_at("LAG([Revenue], 2)", "Apr"), InterpretResult::Ok(20.0));
+    assert_eq!(eval_at("LEAD([Revenue], 2)", "Jan"), InterpretResult::Ok(30.0));
+    assert_eq!(eval_at("[Revenue] - LAG([Revenue], 1)", "Mar"), InterpretResult::Ok(10.0));
+    // Before the start of the series the shifted cell is empty
+    assert_eq!(eval_at("COALESCE(LAG([Revenue], 2), 99)", "Feb"), InterpretResult::Ok(99.0));
+    assert_eq!(eval_at("LAG([Revenue], 1.5)", "Mar"), InterpretResult::ErrorValue("#VALUE!".to_string()));
+    assert_eq!(eval_at("LEAD([Revenue], 100000000000000000000)", "Mar"), InterpretResult::ErrorValue("#VALUE!".to_string()));
+}
+
+#[test]
+fn test_period_anchor_resets_time_travel_chain() {
+    let months: Vec<String> = (1..=12).map(|m| format!("2024-{:02}", m)).collect();
+    let periods = ListPeriodResolver::new("Time", months.clone());
+    let arena = LatticeArena::new(64);
+    for (i, month) in months.iter().enumerate() {
+        let hash = coordinate_hash(&[("Time", month.as_str()), ("Measure", "Revenue")]);
+        arena.set_cell(hash, i as f64 + 1.0); // The month number
+    }
+
+    let eval_at = |formula: &str, month: &str| {
+        let mut parser = Parser::new(formula);
+        let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+        let coordinate = vec![("Time".to_string(), month.to_string())];
+        VM::new(chunk).with_arena(&arena).with_periods(&periods).with_coordinate(coordinate).run()
+    };
+
+    // Relative steps compound from the evaluated cell
+    assert_eq!(eval_at("[Revenue] -> [PrevMonth] -> [PrevMonth]", "2024-06"), InterpretResult::Ok(4.0));
+    // An anchor ignores both the evaluated cell and the steps before it
+    assert_eq!(eval_at(r#"[Revenue] -> @Period("2024-01")"#, "2024-06"), InterpretResult::Ok(1.0));
+    assert_eq!(eval_at(r#"[Revenue] -> [PrevMonth] -> @Period("2024-03")"#, "2024-06"), InterpretResult::Ok(3.0));
+    // Steps after an anchor are relative to it
+    assert_eq!(eval_at(r#"[Revenue] -> @Period("2024-01") -> [NextQuarter]"#, "2024-06"), InterpretResult::Ok(4.0));
+    assert_eq!(
+        eval_at(r#"[Revenue] -> @Period("2024-01") -> [NextMonth] -> @Period("2024-10") -> [PrevMonth]"#, "2024-06"),
+        InterpretResult::Ok(9.0)
+    );
+    // The anchor does not leak into the rest of the formula
+    assert_eq!(eval_at(r#"[Revenue] -> @Period("2024-01") + [Revenue] -> [PrevMonth]"#, "2024-06"), InterpretResult::Ok(6.0));
+    // Leaving the series, or anchoring at an unknown period, reads an empty cell
+    assert_eq!(eval_at(r#"COALESCE([Revenue] -> @Period("2024-01") -> [PrevMonth], 99)"#, "2024-06"), InterpretResult::Ok(99.0));
+    assert_eq!(eval_at(r#"COALESCE([Revenue] -> @Period("1999-01"), 99)"#, "2024-06"), InterpretResult::Ok(99.0));
+    // Outside a chain an anchor is meaningless
+    assert_eq!(eval_at(r#"@Period("2024-01")"#, "2024-06"), InterpretResult::ErrorValue("#VALUE!".to_string()));
+
+    // Calendar steps are converted by the resolver: a quarter is one period of a quarterly
+    // series, a month is not a whole number of its periods, and a plain list steps one period per month
+    let quarters: Vec<String> = (1..=4).map(|q| format!("2024-Q{}", q)).collect();
+    for (i, quarter) in quarters.iter().enumerate() {
+        arena.set_cell(coordinate_hash(&[("Time", quarter.as_str()), ("Measure", "Revenue")]), (i + 1) as f64 * 10.0);
+    }
+    let eval_with = |formula: &str, periods: &ListPeriodResolver| {
+        let chunk = Compiler::new().compile(&Parser::new(formula).parse().expect("Parse failed"));
+        let coordinate = vec![("Time".to_string(), "2024-Q3".to_string())];
+        VM::new(chunk).with_arena(&arena).with_periods(periods).with_coordinate(coordinate).run()
+    };
+    let quarterly = ListPeriodResolver::new("Time", quarters.clone()).with_periods_per_year(4);
+    assert_eq!(eval_with("[Revenue] -> [PrevQuarter]", &quarterly), InterpretResult::Ok(20.0));
+    assert_eq!(eval_with("[Revenue] -> [PrevMonth]", &quarterly), InterpretResult::ErrorValue("#VALUE!".to_string()));
+    let uncalendared = ListPeriodResolver::new("Time", quarters);
+    assert_eq!(eval_with("[Revenue] -> [PrevMonth]", &uncalendared), InterpretResult::Ok(20.0));
+}
+
+#[test]
+fn test_member_alias_resolves_to_canonical_key() {
+    let build_resolver = || {
+        let mut resolver = MapHierarchyResolver::new();
+        resolver.add_alias("Measure", "Net Revenue", "REV");
+        resolver.add_child("Region", "NA", "US");
+        resolver.add_alias("Region", "North America", "NA");
+        Arc::new(resolver)
+    };
+
+    let compile = |input: &str| {
+        let mut parser = Parser::new(input);
+        Compiler::with_resolver(build_resolver()).compile(&parser.parse().expect("Parse failed"))
+    };
+
+    // The alias and the canonical key compile to the same coordinate
+    let by_alias = compile("[Net Revenue]");
+    let by_key = compile("[REV]");
+    assert_eq!(by_alias.coordinates, by_key.coordinates);
+    assert_eq!(by_alias.coordinates[0], vec![("Measure".to_string(), "REV".to_string())]);
+
+    // ... and therefore load the same cell
+    let arena = LatticeArena::new(64);
+    arena.set_cell(coordinate_hash(&[("Measure", "REV")]), 125.0);
+    assert_eq!(VM::new(by_alias).with_arena(&arena).run(), InterpretResult::Ok(125.0));
+    assert_eq!(VM::new(by_key).with_arena(&arena).run(), InterpretResult::Ok(125.0));
+
+    // Hierarchy expansion also accepts a display name for the parent
+    let chunk = compile("SUM(@Children([Region], [North America]))");
+    assert!(chunk.code.contains(&OpCode::Sum(1)));
+}
+
+#[test]
+fn test_if_compiles_to_jumps() {
+    let mut parser = Parser::new(r#"IF(2 > 1, "yes", "no")"#);
+    let chunk = Compiler::new().try_compile(&parser.parse().expect("Parse failed")).expect("Compile failed");
+    assert!(chunk.code.iter().any(|op| matches!(op, OpCode::JumpIfFalse(_))));
+    assert_eq!(VM::new(chunk).run(), InterpretResult::Text("yes".to_string()));
+
+    let mut parser = Parser::new("IF(1 > 2, 10, 20)");
+    let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+    assert_eq!(VM::new(chunk).run(), InterpretResult::Ok(20.0));
+
+    // try_compile rejects what the type checker rejects
+    let mut parser = Parser::new(r#"1 + "x""#);
+    assert!(Compiler::new().try_compile(&parser.parse().expect("Parse failed")).is_err());
+}
+
+#[test]
+fn test_filter_children_by_threshold() {
+    let arena = LatticeArena::new(64);
+    for (country, revenue) in [("USA", 1500.0), ("Canada", 800.0), ("Mexico", 1200.0)] {
+        arena.set_cell(coordinate_hash(&[("Measure", "Revenue"), ("Region", country)]), revenue);
+    }
+    let context = vec![("Measure".to_string(), "Revenue".to_string())];
+
+    let mut parser = Parser::new("SUM(FILTER(@Children([Region], [North America]), [Revenue] > 1000))");
+    let chunk = Compiler::new().try_compile(&parser.parse().expect("Parse failed")).expect("Compile failed");
+    assert!(chunk.code.contains(&OpCode::Filter(3)));
+    assert!(chunk.code.contains(&OpCode::Sum(3)));
+    let result = VM::new(chunk).with_arena(&arena).with_coordinate(context.clone()).run();
+    assert_eq!(result, InterpretResult::Ok(2700.0)); // USA + Mexico
+
+    // AVG counts only the members that pass the filter
+    let mut parser = Parser::new("AVG(FILTER(@Children([Region], [North America]), [Revenue] > 1000))");
+    let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+    let result = VM::new(chunk).with_arena(&arena).with_coordinate(context.clone()).run();
+    assert_eq!(result, InterpretResult::Ok(1350.0));
+
+    // Outside an aggregation nothing folds the subset, so the FILTER is an error
+    for formula in [
+        "FILTER(@Children([Region], [North America]), [Revenue] > 1000)",
+        "1 + FILTER(@Children([Region], [North America]), [Revenue] > 1000)",
+        "SUM(1 + FILTER(@Children([Region], [North America]), [Revenue] > 1000))",
+    ] {
+        let chunk = Compiler::new().try_compile(&Parser::new(formula).parse().expect("Parse failed")).expect("Compile failed");
+        assert!(!chunk.code.iter().any(|op| matches!(op, OpCode::Filter(_))), "{}", formula);
+        let result = VM::new(chunk).with_arena(&arena).with_coordinate(context.clone()).run();
+        assert_eq!(result, InterpretResult::ErrorValue("#VALUE!".to_string()), "{}", formula);
+    }
+}
+
+#[test]
+fn test_date_part_extraction() {
+    // 2024-08-15T12:00:00Z
+    let arena = LatticeArena::new(16);
+    arena.set_date(coordinate_hash(&[("Measure", "OrderDate")]), 1_723_723_200_000);
+
+    let eval = |formula: &str| {
+        let mut parser = Parser::new(formula);
+        let chunk = Compiler::new().try_compile(&parser.parse().expect("Parse failed")).expect("Compile failed");
+        VM::new(chunk).with_arena(&arena).run()
+    };
+    assert_eq!(eval("YEAR([OrderDate])"), InterpretResult::Ok(2024.0));
+    assert_eq!(eval("MONTH([OrderDate])"), InterpretResult::Ok(8.0));
+    assert_eq!(eval("DAY([OrderDate])"), InterpretResult::Ok(15.0));
+    assert_eq!(eval("QUARTER([OrderDate])"), InterpretResult::Ok(3.0));
+}
+
+#[test]
+fn test_native_function_registry() {
+    let mut registry = FunctionRegistry::new();
+    registry.register("DOUBLE", 1, |args| args[0] * 2.0);
+    let registry = Arc::new(registry);
+
+    let compile = |formula: &str| {
+        let mut parser = Parser::new(formula);
+        Compiler::new().with_functions(Arc::clone(&registry)).try_compile(&parser.parse().expect("Parse failed"))
+    };
+    let eval = |formula: &str| VM::new(compile(formula).expect("Compile failed")).with_functions(&registry).run();
+    assert_eq!(eval("DOUBLE(21)"), InterpretResult::Ok(42.0));
+    assert_eq!(eval("DOUBLE(20) + 2"), InterpretResult::Ok(42.0));
+
+    // An unknown name leaves no operands behind, whatever its arguments
+    assert_eq!(eval("TRIPLE(5)"), InterpretResult::ErrorValue("#NAME?".to_string()));
+    assert_eq!(eval("1 + NOPE(2, 3)"), InterpretResult::ErrorValue("#NAME?".to_string()));
+
+    // Arity is checked against the registry when compiling
+    assert_eq!(compile("DOUBLE(1, 2)").err(), Some(CompileError::ArgumentCount { function: "DOUBLE".to_string(), found: 2 }));
+    let chunk = Compiler::new().with_functions(Arc::clone(&registry)).compile(&Parser::new("DOUBLE(1, 2)").parse().unwrap());
+    assert!(!chunk.code.iter().any(|op| matches!(op, OpCode::CallNative(..))));
+    assert_eq!(VM::new(chunk).with_functions(&registry).run(), InterpretResult::ErrorValue("#VALUE!".to_string()));
+}
+
+#[test]
+fn test_batch_recalc_stops_when_cancelled() {
+    // A host function trips the cancel flag on the third row, as a UI navigating away would
+    let cancel = Arc::new(AtomicBool::new(false));
+    let rows_seen = Arc::new(AtomicUsize::new(0));
+    let mut registry = FunctionRegistry::new();
+    {
+        let (cancel, rows_seen) = (Arc::clone(&cancel), Arc::clone(&rows_seen));
+        registry.register("TICK", 1, move |args| {
+            if rows_seen.fetch_add(1, Ordering::SeqCst) == 2 {
+                cancel.store(true, Ordering::SeqCst);
+            }
+            args[0]
+        });
+    }
+    let registry = Arc::new(registry);
+
+    let arena = LatticeArena::new(16_384);
+    let inputs: Vec<Vec<(String, String)>> = (0..10_000)
+        .map(|i| vec![("Measure".to_string(), "Margin".to_string()), ("Row".to_string(), i.to_string())])
+        .collect();
+
+    let mut parser = Parser::new("TICK(7)");
+    let chunk = Compiler::new()
+        .with_functions(Arc::clone(&registry))
+        .compile(&parser.parse().expect("Parse failed"));
+    let result = VM::new(chunk)
+        .with_arena(&arena)
+        .with_functions(&registry)
+        .run_batch_cancellable(&inputs, &cancel);
+
+    let BatchResult::Cancelled(completed) = result else {
+        panic!("batch should have been cancelled");
+    };
+    assert_eq!(completed.len(), CANCEL_CHECK_INTERVAL);
+    assert!(completed.iter().all(|r| *r == InterpretResult::Ok(7.0)));
+    // Every completed row was written whole; nothing after the cancellation point was
+    assert_eq!(arena.iter_cells().count(), CANCEL_CHECK_INTERVAL);
+    assert_eq!(arena.get_cell(coordinate_hash(&[("Measure", "Margin"), ("Row", "0")])), 7.0);
+}
+
+#[test]
+fn test_aggregations_skip_empty_cells() {
+    let arena = LatticeArena::new(16);
+    arena.set_cell(coordinate_hash(&[("Measure", "Revenue"), ("Region", "USA")]), 100.0);
+    arena.set_cell(coordinate_hash(&[("Measure", "Revenue"), ("Region", "Mexico")]), 0.0);
+    let context = vec![("Measure".to_string(), "Revenue".to_string())];
+
+    let eval = |formula: &str| {
+        let mut parser = Parser::new(formula);
+        let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+        VM::new(chunk).with_arena(&arena).with_coordinate(context.clone()).run()
+    };
+    // Canada was never set: a stored zero counts, an empty cell does not
+    assert_eq!(eval("AVG(@Children([Region], [North America]))"), InterpretResult::Ok(50.0));
+    assert_eq!(eval("SUM(@Children([Region], [North America]))"), InterpretResult::Ok(100.0));
+    assert_eq!(eval("[Unset] + 1"), InterpretResult::Ok(1.0));
+    assert_eq!(eval("AVG([Unset], [AlsoUnset])"), InterpretResult::ErrorValue("#DIV/0!".to_string()));
+}
+
+#[test]
+fn test_conditional_aggregation() {
+    let arena = LatticeArena::new(64);
+    for (country, revenue) in [("USA", 1500.0), ("Canada", 800.0), ("Mexico", 1200.0)] {
+        arena.set_cell(coordinate_hash(&[("Measure", "Revenue"), ("Region", country)]), revenue);
+    }
+    let context = vec![("Measure".to_string(), "Revenue".to_string())];
+
+    let eval = |formula: &str| {
+        let mut parser = Parser::new(formula);
+        let chunk = Compiler::new().try_compile(&parser.parse().expect("Parse failed")).expect("Compile failed");
+        VM::new(chunk).with_arena(&arena).with_coordinate(context.clone()).run()
+    };
+    assert_eq!(eval("SUMIF(@Children([Region], [North America]), [Revenue] > 1000)"), InterpretResult::Ok(2700.0));
+    assert_eq!(eval("AVERAGEIF(@Children([Region], [North America]), [Revenue] > 1000)"), InterpretResult::Ok(1350.0));
+
+    // No member matches
+    assert_eq!(eval("SUMIF(@Children([Region], [North America]), [Revenue] > 5000)"), InterpretResult::Ok(0.0));
+    assert_eq!(
+        eval("AVERAGEIF(@Children([Region], [North America]), [Revenue] > 5000)"),
+        InterpretResult::ErrorValue("#DIV/0!".to_string())
+    );
+}
+
+#[test]
+fn test_percentage_of_parent_and_total() {
+    let resolver = || {
+        let mut resolver = MapHierarchyResolver::new();
+        resolver.add_child("Region", "North America", "USA");
+        resolver.add_child("Region", "North America", "Canada");
+        resolver.add_child("Region", "Europe", "UK");
+        Arc::new(resolver)
+    };
+
+    let arena = LatticeArena::new(64);
+    for (region, revenue) in [("North America", 1000.0), ("USA", 600.0), ("Canada", 400.0), ("Europe", 0.0), ("UK", 0.0)] {
+        arena.set_cell(coordinate_hash(&[("Measure", "Revenue"), ("Region", region)]), revenue);
+    }
+    arena.set_cell(coordinate_hash(&[("Measure", "TotalRevenue")]), 2000.0);
+    arena.set_cell(coordinate_hash(&[("Measure", "Revenue")]), 500.0);
+    let context = vec![("Measure".to_string(), "Revenue".to_string())];
+
+    let eval = |formula: &str| {
+        let mut parser = Parser::new(formula);
+        let chunk = Compiler::with_resolver(resolver())
+            .try_compile(&parser.parse().expect("Parse failed"))
+            .expect("Compile failed");
+        VM::new(chunk).with_arena(&arena).with_coordinate(context.clone()).run()
+    };
+    assert_eq!(eval("PCT_OF_PARENT([Region], [Canada])"), InterpretResult::Ok(0.4));
+    assert_eq!(eval("PCT_OF_PARENT([Region], [UK])"), InterpretResult::ErrorValue("#DIV/0!".to_string()));
+    assert_eq!(eval("PCT_OF_PARENT([Region], [North America])"), InterpretResult::ErrorValue("#N/A".to_string()));
+    assert_eq!(eval("PCT_OF_TOTAL([Revenue], [TotalRevenue])"), InterpretResult::Ok(0.25));
+}
+
+#[test]
+fn test_coalesce_first_non_empty() {
+    let arena = LatticeArena::new(16);
+    arena.set_cell(coordinate_hash(&[("Measure", "Forecast")]), 120.0);
+    arena.set_cell(coordinate_hash(&[("Measure", "Zero")]), 0.0);
+
+    let eval = |formula: &str| {
+        let mut parser = Parser::new(formula);
+        let chunk = Compiler::new().try_compile(&parser.parse().expect("Parse failed")).expect("Compile failed");
+        VM::new(chunk).with_arena(&arena).run()
+    };
+    // [Actual] was never set, so the second argument is picked
+    assert_eq!(eval("COALESCE([Actual], [Forecast], 0)"), InterpretResult::Ok(120.0));
+    // A stored zero is a value, not an empty
+    assert_eq!(eval("COALESCE([Zero], [Forecast])"), InterpretResult::Ok(0.0));
+    assert_eq!(eval(r#"COALESCE(PCT_OF_TOTAL(1, 0), "n/a")"#), InterpretResult::Text("n/a".to_string()));
+    // All empty or errors: the last argument is returned
+    assert_eq!(eval("COALESCE([Actual], PCT_OF_TOTAL(1, 0))"), InterpretResult::ErrorValue("#DIV/0!".to_string()));
+}
+
+#[test]
+fn test_percent_and_modulo_evaluation() {
+    let arena = LatticeArena::new(16);
+    arena.set_cell(coordinate_hash(&[("Measure", "Margin")]), 25.0);
+
+    let eval = |formula: &str| {
+        let mut parser = Parser::new(formula);
+        let chunk = Compiler::new().try_compile(&parser.parse().expect("Parse failed")).expect("Compile failed");
+        VM::new(chunk).with_arena(&arena).run()
+    };
+    assert_eq!(eval("15% == 0.15"), InterpretResult::Ok(1.0));
+    assert_eq!(eval("10 % 3"), InterpretResult::Ok(1.0));
+    assert_eq!(eval("[Margin] % 7"), InterpretResult::Ok(4.0));
+    assert_eq!(eval("(0 - 7) % 3"), InterpretResult::Ok(2.0));
+    assert_eq!(eval("200 * [Margin]%"), InterpretResult::Ok(50.0));
+}
+
+#[test]
+fn test_eval_slice_into_arena_writes_every_cell() {
+    let regions = ["USA", "Canada", "Mexico"];
+    let months = ["Jan", "Feb", "Mar", "Apr"];
+    let arena = LatticeArena::new(64);
+    for (r, region) in regions.iter().enumerate() {
+        for (m, month) in months.iter().enumerate() {
+            let revenue = (100 * (r + 1) + m) as f64;
+            arena.set_cell(coordinate_hash(&[("Measure", "Revenue"), ("Region", region), ("Time", month)]), revenue);
+        }
+    }
+
+    let slice = GridSlice::new()
+        .fix("Measure", "Tax")
+        .iterate("Region", regions.iter().map(|r| r.to_string()).collect())
+        .iterate("Time", months.iter().map(|m| m.to_string()).collect());
+    let mut parser = Parser::new("[Revenue] * 0.5");
+    let chunk = Arc::new(Compiler::new().compile(&parser.parse().expect("Parse failed")));
+
+    let report = VM::eval_slice_into_arena(&chunk, &slice, &arena).unwrap();
+    assert_eq!(report, SliceReport { evaluated: 12, failures: vec![] });
+
+    for (r, region) in regions.iter().enumerate() {
+        for (m, month) in months.iter().enumerate() {
+            let tax = arena.get_cell(coordinate_hash(&[("Measure", "Tax"), ("Region", region), ("Time", month)]));
+            assert_eq!(tax, (100 * (r + 1) + m) as f64 * 0.5);
+        }
+    }
+}
+
+#[test]
+fn test_compiler_options_disable_optimizations() {
+    let compile = |formula: &str, options: CompilerOptions| {
+        let mut parser = Parser::new(formula);
+        Compiler::new().with_options(options).compile(&parser.parse().expect("Parse failed"))
+    };
+    let raw = CompilerOptions { fold_constants: false, cse: false, peephole: false, fold_case: false, ..CompilerOptions::default() };
+
+    let unfolded = compile("1 + 2", raw);
+    assert_eq!(unfolded.code, vec![OpCode::Constant(0), OpCode::Constant(1), OpCode::Add, OpCode::Return]);
+    assert_eq!(VM::new(unfolded).run(), InterpretResult::Ok(3.0));
+    let folded = compile("1 + 2", CompilerOptions::default());
+    assert_eq!(folded.code, vec![OpCode::Constant(0), OpCode::Return]);
+
+    // CSE shares the coordinate of a repeated reference
+    assert_eq!(compile("[A] + [A]", raw).coordinates.len(), 2);
+    assert_eq!(compile("[A] + [A]", CompilerOptions::default()).coordinates.len(), 1);
+
+    // The peephole pass sends the inner IF's exit jump straight past the outer else branch
+    let nested = "IF([A] > 0, IF([B] > 0, 1, 2), 3)";
+    let (plain, threaded) = (compile(nested, raw), compile(nested, CompilerOptions { peephole: true, ..raw }));
+    assert_ne!(plain.code, threaded.code);
+    let end = threaded.code.len() - 1;
+    assert!(threaded.code.iter().all(|op| !matches!(op, OpCode::Jump(t) if *t != end)));
+    let arena = LatticeArena::new(16);
+    arena.set_cell(coordinate_hash(&[("Measure", "A")]), 1.0);
+    assert_eq!(VM::new(threaded).with_arena(&arena).run(), InterpretResult::Ok(2.0));
+}
+
+#[test]
+fn test_dimension_refs_are_normalized() {
+    let compile = |formula: &str, options: CompilerOptions| {
+        let mut parser = Parser::new(formula);
+        Compiler::new().with_options(options).compile(&parser.parse().expect("Parse failed"))
+    };
+    let plain = CompilerOptions::default();
+    let folding = CompilerOptions { fold_case: true, ..plain };
+
+    // Padding is always insignificant
+    assert_eq!(compile("[ Revenue ]", plain).coordinates, compile("[Revenue]", plain).coordinates);
+    // Case only collides when folding is on
+    assert_ne!(compile("[revenue]", plain).coordinates, compile("[Revenue]", plain).coordinates);
+    assert_eq!(compile("[ REVENUE ]", folding).coordinates, compile("[Revenue]", folding).coordinates);
+    // Quoted CELL members are normalized by the compiler too
+    assert_eq!(compile("CELL([Measure]=\" Revenue \")", plain).coordinates, compile("[Revenue]", plain).coordinates);
+
+    let arena = LatticeArena::new(16);
+    arena.set_cell(coordinate_hash(&[("Measure", "Revenue")]), 42.0);
+    assert_eq!(VM::new(compile("[ Revenue ]", plain)).with_arena(&arena).run(), InterpretResult::Ok(42.0));
+}
+
+#[test]
+fn test_reference_missing_required_dimension_is_flagged() {
+    let arena = LatticeArena::new(16);
+    arena.set_cell(coordinate_hash(&[("Measure", "Revenue"), ("Region", "USA"), ("Time", "Jan")]), 100.0);
+    let spec = CoordinateSpec::new(&["Measure", "Region", "Time"]);
+
+    let mut parser = Parser::new("[Revenue] * 2");
+    let chunk = Arc::new(Compiler::new().compile(&parser.parse().expect("Parse failed")));
+    let eval = |coordinate: &[(&str, &str)], spec: Option<&CoordinateSpec>| {
+        let coordinate = coordinate.iter().map(|(d, m)| (d.to_string(), m.to_string())).collect();
+        let mut vm = VM::new(Arc::clone(&chunk)).with_arena(&arena).with_coordinate(coordinate);
+        if let Some(spec) = spec {
+            vm = vm.with_spec(spec);
+        }
+        vm.run()
+    };
+
+    // Without a spec the missing Time dimension silently reads an empty cell
+    assert_eq!(eval(&[("Region", "USA")], None), InterpretResult::Ok(0.0));
+    assert_eq!(eval(&[("Region", "USA")], Some(&spec)), InterpretResult::ErrorValue("#REF!".to_string()));
+    assert_eq!(eval(&[("Region", "USA"), ("Time", "Jan")], Some(&spec)), InterpretResult::Ok(200.0));
+}
+
+#[test]
+fn test_parallel_compilation_shares_one_resolver() {
+    use rayon::prelude::*;
+    use crate::lattice::metadata::HierarchyResolver;
+
+    fn assert_send<T: Send>() {}
+    assert_send::<Compiler>();
+
+    let mut resolver = MapHierarchyResolver::new();
+    resolver.add_child("Region", "NA", "US");
+    resolver.add_child("Region", "NA", "CA");
+    let resolver: Arc<dyn HierarchyResolver + Send + Sync> = Arc::new(resolver);
+
+    let arena = LatticeArena::new(16);
+    arena.set_cell(coordinate_hash(&[("Region", "US")]), 3.0);
+    arena.set_cell(coordinate_hash(&[("Region", "CA")]), 4.0);
+
+    let results: Vec<InterpretResult> = (1..=64)
+        .into_par_iter()
+        .map(|i| {
+            let formula = format!("SUM(@Children([Region], [NA])) * {}", i);
+            let mut parser = Parser::new(&formula);
+            let chunk = Compiler::with_resolver(Arc::clone(&resolver)).compile(&parser.parse().expect("Parse failed"));
+            VM::new(chunk).with_arena(&arena).run()
+        })
+        .collect();
+    for (i, result) in (1..=64).zip(results) {
+        assert_eq!(result, InterpretResult::Ok(7.0 * i as f64));
+    }
+}
+
+#[test]
+fn test_large_aggregation_streams_within_budget() {
+    let leaves: Vec<String> = (0..10_000).map(|i| format!("L{}", i)).collect();
+    let mut resolver = MapHierarchyResolver::new();
+    let arena = LatticeArena::new(16_384);
+    for (i, leaf) in leaves.iter().enumerate() {
+        resolver.add_child("Account", "Total", leaf);
+        // Every tenth leaf is left empty, and must be skipped like in the in-memory opcodes
+        if i % 10 != 0 {
+            arena.set_cell(coordinate_hash(&[("Account", leaf)]), ((i * 37) % 1000) as f64);
+        }
+    }
+    let resolver: Arc<MapHierarchyResolver> = Arc::new(resolver);
+    let values: Vec<f64> = (0..leaves.len()).filter(|i| i % 10 != 0).map(|i| ((i * 37) % 1000) as f64).collect();
+    let reference_sum: f64 = values.iter().sum();
+
+    let eval = |formula: &str, budget: usize| {
+        let mut parser = Parser::new(formula);
+        let options = CompilerOptions { aggregate_budget: budget, ..CompilerOptions::default() };
+        let chunk = Compiler::with_resolver(resolver.clone()).with_options(options).compile(&parser.parse().expect("Parse failed"));
+        VM::new(chunk).with_arena(&arena).run()
+    };
+
+    assert_eq!(eval("SUM(@Children([Account], [Total]))", 64), InterpretResult::Ok(reference_sum));
+    assert_eq!(eval("AVG(@Children([Account], [Total]))", 64), InterpretResult::Ok(reference_sum / values.len() as f64));
+    let reference_min = values.iter().copied().fold(f64::MAX, f64::min);
+    let reference_max = values.iter().copied().fold(f64::MIN, f64::max);
+    assert_eq!(eval("MIN(@Children([Account], [Total]), 5000)", 64), InterpretResult::Ok(reference_min));
+    assert_eq!(eval("MAX(@Children([Account], [Total]))", 64), InterpretResult::Ok(reference_max));
+    // Materializing every operand at once overflows the VM stack
+    assert_eq!(
+        eval("SUM(@Children([Account], [Total]))", usize::MAX),
+        InterpretResult::RuntimeError(RuntimeFault::StackOverflow)
+    );
+}
+
+#[test]
+fn test_aggregate_expands_each_hierarchy_once() {
+    struct Counting {
+        inner: MapHierarchyResolver,
+        calls: AtomicUsize,
+    }
+    impl HierarchyResolver for Counting {
+        fn get_children(&self, dimension: &Dimension, member: &Member) -> Vec<Member> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.inner.get_children(dimension, member)
+        }
+        fn get_parent(&self, dimension: &Dimension, member: &Member) -> Option<Member> {
+            self.inner.get_parent(dimension, member)
+        }
+        fn get_descendants(&self, dimension: &Dimension, member: &Member) -> Vec<Member> {
+            self.inner.get_descendants(dimension, member)
+        }
+    }
+    let mut inner = MapHierarchyResolver::new();
+    for leaf in ["A", "B", "C"] {
+        inner.add_child("Account", "Total", leaf);
+    }
+    let resolver = Arc::new(Counting { inner, calls: AtomicUsize::new(0) });
+    let expr = Parser::new("SUM(@Children([Account], [Total]))").parse().expect("Parse failed");
+
+    // Whether the operands fit the budget (stack opcodes) or not (streamed)
+    for budget in [usize::MAX, 2] {
+        resolver.calls.store(0, Ordering::SeqCst);
+        let options = CompilerOptions { aggregate_budget: budget, ..CompilerOptions::default() };
+        Compiler::with_resolver(resolver.clone()).with_options(options).compile(&expr);
+        assert_eq!(resolver.calls.load(Ordering::SeqCst), 1, "budget {}", budget);
+    }
+}
+
+#[test]
+fn test_stack_size_fits_estimated_depth() {
+    // 300 operands held on the stack at once, with streaming disabled
+    let formula = format!("SUM({})", vec!["1"; 300].join(", "));
+    let options = CompilerOptions { aggregate_budget: usize::MAX, ..CompilerOptions::default() };
+    let compile = || Compiler::new().with_options(options).compile(&Parser::new(&formula).parse().expect("Parse failed"));
+
+    let chunk = compile();
+    let depth = chunk.estimate_cost().unwrap().max_stack;
+    assert_eq!(depth, 300);
+    assert_eq!(VM::new(chunk).run(), InterpretResult::RuntimeError(RuntimeFault::StackOverflow));
+    assert_eq!(VM::with_stack_size(compile(), 512).run(), InterpretResult::Ok(300.0));
+    assert_eq!(VM::with_stack_size(compile(), depth).run(), InterpretResult::Ok(300.0));
+    assert_eq!(
+        VM::with_stack_size(compile(), depth - 1).run(),
+        InterpretResult::RuntimeError(RuntimeFault::StackOverflow)
+    );
+}
+
+#[test]
+fn test_missing_cell_policy() {
+    let arena = LatticeArena::new(16);
+    arena.set_cell(coordinate_hash(&[("Measure", "Jan")]), 10.0);
+    arena.set_cell(coordinate_hash(&[("Measure", "Feb")]), 20.0);
+    // [Mar] was never loaded
+
+    let eval = |input: &str, policy: MissingPolicy| {
+        let mut parser = Parser::new(input);
+        let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+        VM::new(chunk).with_arena(&arena).with_missing_policy(policy).run()
+    };
+    let avg = "AVG([Jan], [Feb], [Mar])";
+
+    assert_eq!(eval(avg, MissingPolicy::Empty), InterpretResult::Ok(15.0));
+    assert_eq!(eval(avg, MissingPolicy::Zero), InterpretResult::Ok(10.0));
+    assert!(matches!(eval(avg, MissingPolicy::NaN), InterpretResult::Ok(v) if v.is_nan()));
+    assert_eq!(eval(avg, MissingPolicy::Custom(30.0)), InterpretResult::Ok(20.0));
+    assert_eq!(eval("[Mar] + 1", MissingPolicy::Error), InterpretResult::ErrorValue("#N/A".to_string()));
+    // Stored cells are unaffected
+    assert_eq!(eval("[Jan] + 1", MissingPolicy::Error), InterpretResult::Ok(11.0));
+}
+
+#[test]
+fn test_min_max_nan_policy() {
+    let arena = LatticeArena::new(16);
+    arena.set_cell(coordinate_hash(&[("Measure", "Gap")]), f64::NAN);
+
+    let eval = |input: &str, strict: bool, budget: usize| {
+        let options = CompilerOptions { aggregate_budget: budget, ..CompilerOptions::default() };
+        let chunk = Compiler::new().with_options(options).compile(&Parser::new(input).parse().expect("Parse failed"));
+        VM::new(chunk).with_arena(&arena).with_strict_math(strict).run()
+    };
+    // Stack-based and streaming (one operand per block) aggregation agree
+    for budget in [usize::MAX, 1] {
+        // Skipped by default, wherever the NaN sits
+        assert_eq!(eval("MIN(1, [Gap], 2)", false, budget), InterpretResult::Ok(1.0));
+        assert_eq!(eval("MIN([Gap], 2, 1)", false, budget), InterpretResult::Ok(1.0));
+        assert_eq!(eval("MAX(1, 2, [Gap])", false, budget), InterpretResult::Ok(2.0));
+        // Propagated under strict math
+        for formula in ["MIN(1, [Gap], 2)", "MIN([Gap], 2, 1)", "MAX(1, 2, [Gap])"] {
+            assert!(matches!(eval(formula, true, budget), InterpretResult::Ok(v) if v.is_nan()), "{}", formula);
+        }
+        assert_eq!(eval("MIN(3, 1, 2)", true, budget), InterpretResult::Ok(1.0));
+        // Nothing left once empty cells (and skipped NaNs) are dropped
+        let not_available = InterpretResult::ErrorValue("#N/A".to_string());
+        assert_eq!(eval("MIN([Unset], [AlsoUnset])", false, budget), not_available);
+        assert_eq!(eval("MAX([Unset], [Gap])", false, budget), not_available);
+    }
+}
+
+#[test]
+fn test_math_functions() {
+    let arena = LatticeArena::new(16);
+    arena.set_cell(coordinate_hash(&[("Measure", "Base")]), 16.0);
+    arena.set_cell(coordinate_hash(&[("Measure", "Neg")]), -1.0);
+    let eval = |input: &str| {
+        let mut parser = Parser::new(input);
+        let chunk = Compiler::new().compile(&parser.parse().expect("Parse failed"));
+        VM::new(chunk).with_arena(&arena).run()
+    };
+
+    assert_eq!(eval("SQRT(16) == 4"), InterpretResult::Ok(1.0));
+    assert_eq!(eval("SQRT([Base])"), InterpretResult::Ok(4.0));
+    assert!(matches!(eval("LN(EXP(1))"), InterpretResult::Ok(v) if (v - 1.0).abs() < 1e-12));
+    assert!(matches!(eval("LOG([Base], 2)"), InterpretResult::Ok(v) if (v - 4.0).abs() < 1e-12));
+
+    // Literal arguments fold to a single constant
+    let mut parser = Parser::new("LOG(1000, 10)");
+    let chunk = Compiler::new().compile(&parser.parse().unwrap());
+    assert!(matches!(chunk.code.as_slice(), [OpCode::Constant(_), OpCode::Return]));
+
+    // Domain errors are error values, whether caught at compile time or at runtime
+    assert_eq!(eval("SQRT(0 - 1)"), InterpretResult::ErrorValue("#NUM!".to_string()));
+    assert_eq!(eval("SQRT([Neg])"), InterpretResult::ErrorValue("#NUM!".to_string()));
+    assert_eq!(eval("LN(0)"), InterpretResult::ErrorValue("#NUM!".to_string()));
+    assert_eq!(eval("LOG([Base], 1)"), InterpretResult::ErrorValue("#DIV/0!".to_string()));
+    assert_eq!(eval("LOG([Base])"), InterpretResult::ErrorValue("#VALUE!".to_string()));
+}
+
+#[test]
+fn test_generated_formulas_match_oracle() {
+    use crate::atom_script::error::{EngineError, RuntimeError};
+    use crate::atom_script::test_util::{evaluate, gen_expr};
+
+    assert_eq!(gen_expr(4, 7), gen_expr(4, 7));
+    for seed in 0..1000 {
+        let (formula, expected) = gen_expr(5, seed);
+        let matches = match (evaluate(&formula), expected) {
+            (Ok(actual), Ok(expected)) => actual == expected || (actual.is_nan() && expected.is_nan()),
+            (Err(EngineError::Runtime(RuntimeError::ErrorValue(actual))), Err(expected)) => actual == expected,
+            _ => false,
+        };
+        assert!(matches, "seed {}: {} evaluated to {:?}, expected {:?}", seed, formula, evaluate(&formula), expected);
+    }
+}
+
+
+#[test]
+fn test_try_compile_surfaces_resolver_failure() {
+    // A metadata store that is down for good
+    struct Unavailable;
+    impl HierarchyResolver for Unavailable {
+        fn get_children(&self, _dimension: &Dimension, _member: &Member) -> Vec<Member> {
+            Vec::new()
+        }
+        fn try_get_children(&self, _dimension: &Dimension, _member: &Member) -> anyhow::Result<Vec<Member>> {
+            anyhow::bail!("connection refused")
+        }
+        fn get_parent(&self, _dimension: &Dimension, _member: &Member) -> Option<Member> {
+            None
+        }
+        fn get_descendants(&self, _dimension: &Dimension, _member: &Member) -> Vec<Member> {
+            Vec::new()
+        }
+    }
+
+    let resolver = Arc::new(RetryingResolver::new(Unavailable).with_max_retries(1).with_base_delay(Duration::ZERO));
+    let mut parser = Parser::new("SUM(@Children([Region], [North America]))");
+    let expr = parser.parse().expect("Parse failed");
+
+    match Compiler::with_resolver(resolver.clone()).try_compile(&expr) {
+        Err(CompileError::Resolver(message)) => {
+            assert!(message.contains("after 2 attempts") && message.contains("connection refused"), "{}", message)
+        }
+        other => panic!("expected a resolver error, got {:?}", other.map(|c| c.code)),
+    }
+    // The infallible path still compiles, expanding to no members: a SUM of nothing
+    let chunk = Compiler::with_resolver(resolver).compile(&expr);
+    assert!(!chunk.code.iter().any(|op| matches!(op, OpCode::Sum(_))));
+    assert_eq!(VM::new(chunk).run(), InterpretResult::Ok(0.0));
+}
+
+#[test]
+fn test_strict_members_reject_unknown_references() {
+    let mut resolver = MapHierarchyResolver::new();
+    resolver.add_child("Region", "North America", "USA");
+    resolver.add_child("Region", "North America", "Canada");
+    resolver.add_alias("Region", "United States", "USA");
+    resolver.add_child("Measure", "Income", "Revenue");
+    let resolver = Arc::new(resolver);
+    let strict = CompilerOptions { strict_members: true, ..CompilerOptions::default() };
+    let try_compile = |formula: &str, options: CompilerOptions| {
+        let expr = Parser::new(formula).parse().expect("Parse failed");
+        Compiler::with_resolver(resolver.clone()).with_options(options).try_compile(&expr).map(|_| ())
+    };
+
+    let bogus = "[Revenue] + CELL([Region]=Atlantis, [Measure]=Revenue)";
+    assert_eq!(
+        try_compile(bogus, strict),
+        Err(CompileError::UnknownMember { dimension: "Region".to_string(), member: "Atlantis".to_string() })
+    );
+    assert_eq!(try_compile(bogus, CompilerOptions::default()), Ok(()));
+
+    // Members, aliases and hierarchy parents the resolver knows are confirmed
+    assert_eq!(try_compile("CELL([Region]=[United States], [Measure]=Revenue) + [Revenue]", strict), Ok(()));
+    assert_eq!(try_compile("SUM(@Children([Region], [North America]))", strict), Ok(()));
+    // A dimension the resolver has no metadata for cannot be confirmed
+    assert!(matches!(
+        try_compile("CELL([Time]=Jan, [Measure]=Revenue)", strict),
+        Err(CompileError::UnknownMember { dimension, .. }) if dimension == "Time"
+    ));
+}
+
+#[test]
+fn test_xlookup_match_and_search_modes() {
+    let mut resolver = MapHierarchyResolver::new();
+    let arena = LatticeArena::new(64);
+    let table: [(&str, &str, f64); 12] = [
+        ("Thresholds", "T1", 0.0),
+        ("Thresholds", "T2", 10_000.0),
+        ("Thresholds", "T3", 40_000.0),
+        ("Thresholds", "T4", 90_000.0),
+        ("Rates", "R1", 0.10),
+        ("Rates", "R2", 0.12),
+        ("Rates", "R3", 0.22),
+        ("Rates", "R4", 0.24),
+        ("Codes", "C1", 3.0),
+        ("Codes", "C2", 7.0),
+        ("Codes", "C3", 7.0),
+        ("Codes", "C4", 9.0),
+    ];
+    for (parent, member, value) in table {
+        resolver.add_child("Bracket", parent, member);
+        arena.set_cell(coordinate_hash(&[("Bracket", member)]), value);
+    }
+    resolver.add_child("Bracket", "Short", "R1");
+    let resolver = Arc::new(resolver);
+
+    let eval = |formula: &str| {
+        let mut parser = Parser::new(formula);
+        let chunk = Compiler::with_resolver(resolver.clone()).compile(&parser.parse().expect("Parse failed"));
+        VM::new(chunk).with_arena(&arena).run()
+    };
+    let lookup = |value: &str, keys: &str, rest: &str| {
+        eval(&format!("XLOOKUP({}, @Children([Bracket], [{}]), @Children([Bracket], [Rates]){})", value, keys, rest))
+    };
+
+    // Exact hit, and a miss with and without an if-not-found default
+    assert_eq!(lookup("40000", "Thresholds", ""), InterpretResult::Ok(0.22));
+    assert_eq!(lookup("5", "Thresholds", ", 99"), InterpretResult::Ok(99.0));
+    assert_eq!(lookup("5", "Thresholds", ""), InterpretResult::ErrorValue("#N/A".to_string()));
+
+    // Approximate matches: a tax bracket lookup, and the exact key still winning
+    assert_eq!(lookup("50000", "Thresholds", ", 0, 0 - 1"), InterpretResult::Ok(0.22));
+    assert_eq!(lookup("50000", "Thresholds", ", 0, 1"), InterpretResult::Ok(0.24));
+    assert_eq!(lookup("10000", "Thresholds", ", 0, 1"), InterpretResult::Ok(0.12));
+    assert_eq!(lookup("100000", "Thresholds", ", 0, 1"), InterpretResult::Ok(0.0));
+
+    // The search mode picks between duplicate keys
+    assert_eq!(lookup("7", "Codes", ""), InterpretResult::Ok(0.12));
+    assert_eq!(lookup("7", "Codes", ", 0, 0, 0 - 1"), InterpretResult::Ok(0.22));
+    assert_eq!(lookup("8", "Codes", ", 0, 0 - 1, 0 - 1"), InterpretResult::Ok(0.22));
+
+    // Unknown modes and arrays of different lengths are rejected
+    assert_eq!(lookup("7", "Codes", ", 0, 2"), InterpretResult::ErrorValue("#VALUE!".to_string()));
+    assert_eq!(lookup("7", "Short", ""), InterpretResult::ErrorValue("#VALUE!".to_string()));
+}
+
+#[test]
+fn test_cell_reference_loads_multi_dimension_cell() {
+    let arena = LatticeArena::new(64);
+    let target = coordinate_hash(&[("Region", "USA"), ("Time", "2024"), ("Measure", "Revenue")]);
+    arena.set_cell(target, 1250.0);
+    arena.set_cell(coordinate_hash(&[("Region", "Canada"), ("Time", "2024"), ("Measure", "Revenue")]), 80.0);
+
+    let expr = Parser::new("CELL([Region]=USA, [Time]=2024, [Measure]=Revenue) * 2").parse().expect("Parse failed");
+    let chunk = Compiler::new().compile(&expr);
+    assert_eq!(chunk.code.iter().filter(|op| matches!(op, OpCode::LoadDimension(_))).count(), 1);
+    assert_eq!(coordinate_hash(&chunk.coordinates[0]), target);
+    assert_eq!(VM::new(chunk).with_arena(&arena).run(), InterpretResult::Ok(2500.0));
+
+    // Every dimension is named, so the evaluated cell does not move the reference
+    let here = vec![
+        ("Region".to_string(), "Canada".to_string()),
+        ("Time".to_string(), "2023".to_string()),
+        ("Measure".to_string(), "COGS".to_string()),
+    ];
+    let chunk = Compiler::new().compile(&expr);
+    assert_eq!(VM::new(chunk).with_arena(&arena).with_coordinate(here.clone()).run(), InterpretResult::Ok(2500.0));
+
+    // Unnamed dimensions follow the evaluated cell, as for `[X]`
+    let partial = Compiler::new().compile(&Parser::new("CELL([Time]=2024, [Measure]=Revenue)").parse().unwrap());
+    assert_eq!(VM::new(partial).with_arena(&arena).with_coordinate(here).run(), InterpretResult::Ok(80.0));
+
+    // Display re-parses to the same tree; a repeated dimension is a syntax error
+    assert_eq!(Parser::new(&expr.to_string()).parse(), Ok(expr));
+    assert!(Parser::new("CELL([Region]=USA, [Region]=Canada)").parse().is_err());
+    assert!(Parser::new("CELL()").parse().is_err());
+}
+
+#[test]
+fn test_runtime_error_reports_source_span() {
+    fn run(input: &str, bytecode: bool) -> (InterpretResult, Option<&str>) {
+        let (expr, spans) = Parser::new(input).parse_with_spans().expect("Parse failed");
+        let chunk = Compiler::new().with_source_map(spans).compile(&expr);
+        assert_eq!(chunk.spans.len(), chunk.code.len());
+        let mut vm = VM::new(chunk);
+        if bytecode {
+            vm = vm.with_bytecode();
+        }
+        let result = vm.run();
+        (result, vm.error_span().map(|span| &input[span]))
+    }
+
+    let div_zero = InterpretResult::ErrorValue("#DIV/0!".to_string());
+    for bytecode in [false, true] {
+        assert_eq!(run("PCT_OF_TOTAL(1, 0)", bytecode), (div_zero.clone(), Some("PCT_OF_TOTAL(1, 0)")));
+        assert_eq!(run("SUM(2, PCT_OF_TOTAL([A], 0)) + 1", bytecode), (div_zero.clone(), Some("PCT_OF_TOTAL([A], 0)")));
+        assert_eq!(run("LOG(7, 1) * 2", bytecode), (div_zero.clone(), Some("LOG(7, 1)")));
+        assert!(matches!(run(r#"[A] + 1 + "x""#, bytecode), (InterpretResult::TypeError(_), Some(r#"[A] + 1 + "x""#))));
+        assert_eq!(run("1 + 2", bytecode), (InterpretResult::Ok(3.0), None));
+    }
+
+    // Without a source map the chunk carries no spans
+    let chunk = Compiler::new().compile(&Parser::new("PCT_OF_TOTAL(1, 0)").parse().unwrap());
+    assert!(chunk.spans.is_empty());
+    let mut vm = VM::new(chunk);
+    assert_eq!(vm.run(), div_zero);
+    assert_eq!(vm.error_span(), None);
+}
+
+#[test]
+fn test_runtime_error_span_tells_identical_subexpressions_apart() {
+    let input = "IF([Flag] > 1, PCT_OF_TOTAL([A], [B]), 1) + IF([Flag] > 0, PCT_OF_TOTAL([A], [B]), 1)";
+    let (expr, spans) = Parser::new(input).parse_with_spans().expect("Parse failed");
+    let chunk = Compiler::new().with_source_map(spans).compile(&expr);
+
+    // Both calls would divide by zero, but with [Flag] = 0.5 only the second one runs, so the
+    // error must point at the second occurrence rather than the identical first one
+    let arena = LatticeArena::new(16);
+    arena.set_cell(coordinate_hash(&[("Measure", "Flag")]), 0.5);
+    arena.set_cell(coordinate_hash(&[("Measure", "A")]), 1.0);
+    arena.set_cell(coordinate_hash(&[("Measure", "B")]), 0.0);
+    let mut vm = VM::new(chunk).with_arena(&arena);
+    assert_eq!(vm.run(), InterpretResult::ErrorValue("#DIV/0!".to_string()));
+    let second = input.rfind("PCT_OF_TOTAL").unwrap();
+    assert_eq!(vm.error_span(), Some(second..second + "PCT_OF_TOTAL([A], [B])".len()));
+}
+
+#[test]
+fn test_compile_batch_preserves_input_order() {
+    let mut resolver = MapHierarchyResolver::new();
+    resolver.add_child("Region", "NA", "US");
+    resolver.add_child("Region", "NA", "CA");
+    let resolver: Arc<dyn HierarchyResolver + Send + Sync> = Arc::new(resolver);
+
+    let formulas: Vec<String> = (0..1000)
+        .map(|i| match i % 4 {
+            0 => format!("SUM(@Children([Region], [NA])) * {}", i),
+            1 => format!("[Revenue] - {}", i),
+            2 => format!("{} +", i), // Parse error
+            _ => format!("{} + \"x\"", i), // Type error
+        })
+        .collect();
+    let parallel = compile_batch(&formulas, Arc::clone(&resolver));
+    assert_eq!(parallel.len(), formulas.len());
+
+    for (formula, result) in formulas.iter().zip(parallel) {
+        let serial = Parser::new(formula)
+            .parse()
+            .map_err(Into::into)
+            .and_then(|expr| Ok(Compiler::with_resolver(Arc::clone(&resolver)).try_compile(&expr)?));
+        match (result, serial) {
+            (Ok(parallel), Ok(serial)) => {
+                assert_eq!(parallel.code, serial.code, "{}", formula);
+                assert_eq!(parallel.constants, serial.constants, "{}", formula);
+                assert_eq!(parallel.coordinates, serial.coordinates, "{}", formula);
+            }
+            (parallel, serial) => assert_eq!(parallel.err(), serial.err(), "{}", formula),
+        }
+    }
+}
+
+#[test]
+fn test_isblank_and_counta_distinguish_never_set_from_zero() {
+    let mut resolver = MapHierarchyResolver::new();
+    for member in ["US", "CA", "MX"] {
+        resolver.add_child("Region", "NA", member);
+    }
+    let resolver: Arc<MapHierarchyResolver> = Arc::new(resolver);
+    let arena = LatticeArena::new(16);
+    arena.set_cell(coordinate_hash(&[("Region", "US")]), 5.0);
+    arena.set_cell(coordinate_hash(&[("Region", "CA")]), 0.0); // Explicit zero
+    // MX was never set
+
+    let eval = |input: &str, policy: MissingPolicy| {
+        let expr = Parser::new(input).parse().expect("Parse failed");
+        let chunk = Compiler::with_resolver(resolver.clone()).try_compile(&expr).expect("Compile failed");
+        VM::new(chunk).with_arena(&arena).with_missing_policy(policy).run()
+    };
+    let empty = MissingPolicy::Empty;
+
+    assert_eq!(eval("ISBLANK(CELL([Region]=MX))", empty), InterpretResult::Ok(1.0));
+    assert_eq!(eval("ISBLANK(CELL([Region]=CA))", empty), InterpretResult::Ok(0.0));
+    assert_eq!(eval("ISBLANK(CELL([Region]=US) * 2)", empty), InterpretResult::Ok(0.0));
+    assert_eq!(eval("COUNTA(@Children([Region], [NA]))", empty), InterpretResult::Ok(2.0));
+    assert_eq!(eval("COUNTA(@Children([Region], [NA]), CELL([Region]=MX), 7)", empty), InterpretResult::Ok(3.0));
+
+    // A policy that fills missing cells leaves nothing blank
+    assert_eq!(eval("ISBLANK(CELL([Region]=MX))", MissingPolicy::Zero), InterpretResult::Ok(0.0));
+    assert_eq!(eval("COUNTA(@Children([Region], [NA]))", MissingPolicy::Zero), InterpretResult::Ok(3.0));
+
+    assert_eq!(eval("ISBLANK(@Children([Region], [NA]))", empty), InterpretResult::ErrorValue("#VALUE!".to_string()));
+}
+
+#[test]
+fn test_batch_recalc_reports_failed_cell_and_keeps_the_rest() {
+    let regions: Vec<String> = (0..50).map(|i| format!("R{}", i)).collect();
+    let arena = LatticeArena::new(256);
+    for (i, region) in regions.iter().enumerate() {
+        arena.set_cell(coordinate_hash(&[("Measure", "Revenue"), ("Region", region)]), 10.0 * i as f64);
+        // R7 sold nothing, so its price is a division by zero
+        let units = if i == 7 { 0.0 } else { i as f64 + 1.0 };
+        arena.set_cell(coordinate_hash(&[("Measure", "Units"), ("Region", region)]), units);
+    }
+    let chunk = Arc::new(Compiler::new().compile(&Parser::new("PCT_OF_TOTAL([Revenue], [Units])").parse().unwrap()));
+    let price = |region: &str| arena.get_cell_opt(coordinate_hash(&[("Measure", "Price"), ("Region", region)]));
+    let div_zero = InterpretResult::ErrorValue("#DIV/0!".to_string());
+    let failed_coordinate = vec![("Measure".to_string(), "Price".to_string()), ("Region".to_string(), "R7".to_string())];
+
+    let inputs: Vec<Vec<(String, String)>> = regions
+        .iter()
+        .map(|r| vec![("Measure".to_string(), "Price".to_string()), ("Region".to_string(), r.clone())])
+        .collect();
+    let report = VM::new(Arc::clone(&chunk)).with_arena(&arena).run_batch(&inputs);
+    assert_eq!(report.failed, 1);
+    assert_eq!(report.outcomes.len(), 50);
+    assert_eq!(
+        report.outcomes[7],
+        Err(CellError { coordinate: failed_coordinate.clone(), hash: coordinate_hash(&failed_coordinate), result: div_zero.clone() })
+    );
+    for (i, region) in regions.iter().enumerate().filter(|&(i, _)| i != 7) {
+        let expected = 10.0 * i as f64 / (i as f64 + 1.0);
+        assert_eq!(report.outcomes[i], Ok(expected));
+        assert_eq!(price(region), Some(expected));
+    }
+    assert_eq!(price("R7"), None);
+
+    // The fused slice pass reports the same cell, and stores it as NaN
+    let slice = GridSlice::new().fix("Measure", "Price").iterate("Region", regions.clone());
+    let report = VM::eval_slice_into_arena(&chunk, &slice, &arena).unwrap();
+    assert_eq!(report.evaluated, 50);
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(report.failures[0].coordinate, failed_coordinate);
+    assert_eq!(report.failures[0].result, div_zero);
+    assert!(price("R7").unwrap().is_nan());
+    assert_eq!(price("R49"), Some(490.0 / 50.0));
+}
+
+#[test]
+fn test_aggregations_over_empty_expansion() {
+    let resolver = Arc::new(MapHierarchyResolver::new()); // Knows no members
+    let eval = |input: &str, options: CompilerOptions| {
+        let expr = Parser::new(input).parse().expect("Parse failed");
+        let chunk = Compiler::with_resolver(resolver.clone()).with_options(options).compile(&expr);
+        assert!(chunk.validate().is_ok(), "{}: {:?}", input, chunk.code);
+        VM::new(chunk).run()
+    };
+    let defaults = CompilerOptions::default();
+    let error = |e: &str| InterpretResult::ErrorValue(e.to_string());
+
+    assert_eq!(eval("SUM(@Children([Region], [Atlantis]))", defaults), InterpretResult::Ok(0.0));
+    assert_eq!(eval("AVG(@Children([Region], [Atlantis]))", defaults), error("#DIV/0!"));
+    assert_eq!(eval("MIN(@Children([Region], [Atlantis]))", defaults), error("#N/A"));
+    assert_eq!(eval("MAX(@Children([Region], [Atlantis]))", defaults), error("#N/A"));
+    assert_eq!(eval("SUM(@Children([Region], [Atlantis])) + 1", defaults), InterpretResult::Ok(1.0));
+
+    let nan_avg = CompilerOptions { empty_avg_nan: true, ..defaults };
+    assert!(matches!(eval("AVG(@Children([Region], [Atlantis]))", nan_avg), InterpretResult::Ok(v) if v.is_nan()));
+
+    // Only a compile-time empty set is affected; a set with operands keeps its opcode
+    assert_eq!(eval("MAX(@Children([Region], [Atlantis]), 4)", defaults), InterpretResult::Ok(4.0));
+}
+
+#[test]
+fn test_simplify_option_shrinks_chunk() {
+    let arena = LatticeArena::new(16);
+    arena.set_cell(coordinate_hash(&[("Measure", "A")]), 7.0);
+    arena.set_cell(coordinate_hash(&[("Measure", "B")]), 3.0);
+    let compile = |options: CompilerOptions| {
+        Compiler::new().with_options(options).compile(&Parser::new("[A] * 1 + ([B] - [B])").parse().expect("Parse failed"))
+    };
+
+    let plain = compile(CompilerOptions::default());
+    let simplified = compile(CompilerOptions { simplify: true, ..CompilerOptions::default() });
+    assert!(simplified.code.len() < plain.code.len(), "{:?}", simplified.code);
+    assert_eq!(VM::new(plain).with_arena(&arena).run(), InterpretResult::Ok(7.0));
+    assert_eq!(VM::new(simplified).with_arena(&arena).run(), InterpretResult::Ok(7.0));
+
+    // An empty cell stays empty under an identity only when simplification keeps the arithmetic
+    let run = |input: &str, simplify: bool| {
+        let options = CompilerOptions { simplify, ..CompilerOptions::default() };
+        let chunk = Compiler::new().with_options(options).compile(&Parser::new(input).parse().expect("Parse failed"));
+        VM::new(chunk).with_arena(&arena).run()
+    };
+    for formula in ["ISBLANK([Unset] * 1)", "ISBLANK(0 + [Unset])", "ISBLANK([Unset] - 0)", "ISBLANK([Unset] / 1)"] {
+        assert_eq!(run(formula, true), InterpretResult::Ok(0.0), "{}", formula);
+        assert_eq!(run(formula, true), run(formula, false), "{}", formula);
+    }
+}
+
+#[test]
+fn test_switch_picks_first_matching_case() {
+    let arena = LatticeArena::new(16);
+    let eval = |input: &str, code: f64| {
+        arena.set_cell(coordinate_hash(&[("Measure", "Code")]), code);
+        let chunk = Arc::new(Compiler::new().compile(&Parser::new(input).parse().expect("Parse failed")));
+        assert!(chunk.validate().is_ok(), "{}: {:?}", input, chunk.code);
+        (VM::new(Arc::clone(&chunk)).with_arena(&arena).run(), chunk)
+    };
+
+    let formula = "SWITCH([Code], 1, 100, 2, 200, 2, 999, 5) * 10";
+    let (result, chunk) = eval(formula, 2.0);
+    assert_eq!(result, InterpretResult::Ok(2000.0));
+    // The subject is loaded once, however many cases compare against it
+    assert_eq!(chunk.code.iter().filter(|op| matches!(op, OpCode::LoadDimension(_))).count(), 1);
+    // No case matches: the default
+    assert_eq!(eval(formula, 3.0).0, InterpretResult::Ok(50.0));
+
+    let (result, _) = eval(r#"SWITCH("Canada", "USA", 1, "Canada", 2, 0)"#, 0.0);
+    assert_eq!(result, InterpretResult::Ok(2.0));
+}
+
+#[test]
+fn test_switch_requires_pairs_and_a_default() {
+    // The subject, then two value/result pairs and no default
+    let expr = Parser::new("SWITCH([Code], 1, 100, 2, 200)").parse().expect("Parse failed");
+    assert_eq!(
+        Compiler::new().try_compile(&expr).err(),
+        Some(CompileError::ArgumentCount { function: "SWITCH".to_string(), found: 5 })
+    );
+    assert_eq!(VM::new(Compiler::new().compile(&expr)).run(), InterpretResult::ErrorValue("#VALUE!".to_string()));
+}
@@ -0,0 +1,177 @@
+use crate::atom_script::ast::{BinaryOp, Expr};
+use crate::atom_script::compiler::CompileError;
+
+/// Static type of an AtomScript expression.
+/// Cell references and identifiers are `Unknown`: their type is only known at runtime,
+/// so they are accepted wherever a concrete type is expected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Num,
+    Text,
+    Bool,
+    Unknown,
+}
+
+impl Type {
+    fn is_numeric(self) -> bool {
+        matches!(self, Type::Num | Type::Bool | Type::Unknown)
+    }
+}
+
+/// Infers the type of `expr`, rejecting formulas that can only fail at runtime
+/// (`1 + "x"`, `SUM("a", "b")`, `IF([A] + 1, ...)`).
+pub fn infer(expr: &Expr) -> Result<Type, CompileError> {
+    match expr {
+        Expr::Literal(_) => Ok(Type::Num),
+        Expr::StringLiteral(_) => Ok(Type::Text),
+        Expr::Identifier(_) | Expr::DimensionRef(_) | Expr::CellRef(_) => Ok(Type::Unknown),
+        Expr::Binary { op, lhs, rhs } => {
+            let l = infer(lhs)?;
+            let r = infer(rhs)?;
+            match op {
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
+                    expect_numeric(lhs, l)?;
+                    expect_numeric(rhs, r)?;
+                    Ok(Type::Num)
+                }
+                BinaryOp::Eq | BinaryOp::NotEq => Ok(Type::Bool),
+                BinaryOp::Lt | BinaryOp::Lte | BinaryOp::Gt | BinaryOp::Gte => {
+                    // Text orders against text, numbers against numbers
+                    if (l == Type::Text && r.is_numeric() && r != Type::Unknown)
+                        || (r == Type::Text && l.is_numeric() && l != Type::Unknown)
+                    {
+                        return Err(mismatch(expr, if l == Type::Text { r } else { l }, Type::Text));
+                    }
+                    Ok(Type::Bool)
+                }
+            }
+        }
+        Expr::FunctionCall { name, args } => {
+            let types = args.iter().map(infer).collect::<Result<Vec<_>, _>>()?;
+            match name.as_str() {
+                "IF" => {
+                    if let (Some(cond), Some(&cond_type)) = (args.first(), types.first()) {
+                        if !matches!(cond_type, Type::Bool | Type::Unknown) {
+                            return Err(mismatch(cond, Type::Bool, cond_type));
+                        }
+                    }
+                    // Both branches agreeing gives a concrete type, otherwise it's runtime-dependent
+                    match (types.get(1), types.get(2)) {
+                        (Some(a), Some(b)) if a == b => Ok(*a),
+                        _ => Ok(Type::Unknown),
+                    }
+                }
+                // The subject, then an odd number of arguments: value/result pairs and the default
+                "SWITCH" => {
+                    if args.len() < 4 || args.len() % 2 != 0 {
+                        return Err(CompileError::ArgumentCount { function: name.clone(), found: args.len() });
+                    }
+                    // The results and the default agreeing gives a concrete type
+                    let mut outcomes = types[2..types.len() - 1].iter().step_by(2).chain(types.last());
+                    let first = outcomes.next().copied();
+                    match first {
+                        Some(t) if outcomes.all(|&o| o == t) => Ok(t),
+                        _ => Ok(Type::Unknown),
+                    }
+                }
+                "CONCAT" => Ok(Type::Text),
+                "ISBLANK" | "COUNTA" => Ok(Type::Num),
+                // Any argument may be the one returned, so only agreeing arguments give a concrete type
+                "COALESCE" => match types.split_first() {
+                    Some((first, rest)) if rest.iter().all(|t| t == first) => Ok(*first),
+                    _ => Ok(Type::Unknown),
+                },
+                "FILTER" | "SUMIF" | "AVERAGEIF" => {
+                    if let (Some(pred), Some(&t)) = (args.get(1), types.get(1)) {
+                        if !matches!(t, Type::Bool | Type::Unknown) {
+                            return Err(mismatch(pred, Type::Bool, t));
+                        }
+                    }
+                    Ok(Type::Num)
+                }
+                "TEXT" => {
+                    if let (Some(arg), Some(&t)) = (args.first(), types.first()) {
+                        expect_numeric(arg, t)?;
+                    }
+                    if let (Some(arg), Some(&t)) = (args.get(1), types.get(1)) {
+                        if !matches!(t, Type::Text | Type::Unknown) {
+                            return Err(mismatch(arg, Type::Text, t));
+                        }
+                    }
+                    Ok(Type::Text)
+                }
+                "SUM" | "AVG" | "MIN" | "MAX" | "SQRT" | "EXP" | "LN" | "LOG" | "ROLLING_AVG" | "EMA" | "LAG" | "LEAD" | "YEAR" | "MONTH" | "DAY" | "QUARTER"
+                | "PCT_OF_PARENT" | "PCT_OF_TOTAL" => {
+                    for (arg, &t) in args.iter().zip(types.iter()) {
+                        expect_numeric(arg, t)?;
+                    }
+                    Ok(Type::Num)
+                }
+                _ => Ok(Type::Unknown),
+            }
+        }
+        Expr::HierarchyCall { .. } => Ok(Type::Num),
+        Expr::In { value, candidates } => {
+            infer(value)?;
+            for candidate in candidates {
+                infer(candidate)?;
+            }
+            Ok(Type::Bool)
+        }
+        Expr::TimeTravel { lhs, rhs } => {
+            expect_numeric(lhs, infer(lhs)?)?;
+            expect_numeric(rhs, infer(rhs)?)?;
+            Ok(Type::Num)
+        }
+        Expr::TimeModifier { base, .. } => {
+            expect_numeric(base, infer(base)?)?;
+            Ok(Type::Num)
+        }
+    }
+}
+
+fn expect_numeric(expr: &Expr, found: Type) -> Result<(), CompileError> {
+    if found.is_numeric() {
+        Ok(())
+    } else {
+        Err(mismatch(expr, Type::Num, found))
+    }
+}
+
+fn mismatch(expr: &Expr, expected: Type, found: Type) -> CompileError {
+    CompileError::TypeMismatch {
+        expected,
+        found,
+        expr: expr.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atom_script::parser::Parser;
+
+    fn check(input: &str) -> Result<Type, CompileError> {
+        let mut parser = Parser::new(input);
+        infer(&parser.parse().expect("Parse failed"))
+    }
+
+    #[test]
+    fn test_rejects_text_plus_number() {
+        let err = check(r#"1 + "x""#).unwrap_err();
+        assert_eq!(
+            err,
+            CompileError::TypeMismatch { expected: Type::Num, found: Type::Text, expr: "\"x\"".to_string() }
+        );
+
+        assert!(check(r#"SUM("a", "b")"#).is_err());
+        assert!(check("IF([A] + 1, 1, 2)").is_err());
+    }
+
+    #[test]
+    fn test_accepts_valid_mixed_formula() {
+        assert_eq!(check(r#"IF([Revenue] > 1000, "High", "Low")"#), Ok(Type::Text));
+        assert_eq!(check(r#"CONCAT("Total: ", TEXT(SUM([A], [B]), "0.00"))"#), Ok(Type::Text));
+        assert_eq!(check("[A] * 2 + 1"), Ok(Type::Num));
+    }
+}
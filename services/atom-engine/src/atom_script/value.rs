@@ -0,0 +1,152 @@
+use std::fmt;
+
+/// A tagged VM stack slot. Numeric formulas only ever see `Num`,
+/// commentary formulas (CONCAT, TEXT) produce `Text`, comparisons produce `Bool`
+/// and `Err` carries a spreadsheet-style error value (e.g. `#N/A`) through the calculation.
+/// `Empty` marks a slot with no value (e.g. a member removed by FILTER); aggregations skip it
+/// and arithmetic treats it as 0.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Num(f64),
+    Text(String),
+    Bool(bool),
+    Err(String),
+    Empty,
+}
+
+impl Value {
+    /// Returns the numeric payload, or None if the value is not a number.
+    pub fn as_num(&self) -> Option<f64> {
+        match self {
+            Value::Num(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Numeric view used by arithmetic: booleans coerce to 1.0/0.0 (Excel semantics),
+    /// text and errors do not coerce.
+    pub fn coerce_num(&self) -> Option<f64> {
+        match self {
+            Value::Num(n) => Some(*n),
+            Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            Value::Empty => Some(0.0),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Num(_) => "Num",
+            Value::Text(_) => "Text",
+            Value::Bool(_) => "Bool",
+            Value::Err(_) => "Err",
+            Value::Empty => "Empty",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    /// Stringification used by CONCAT: integral numbers print without a fraction
+    /// (`1234`), everything else uses Rust's shortest round-trip form (`1234.5`).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Num(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Value::Num(n) => write!(f, "{}", n),
+            Value::Text(s) => write!(f, "{}", s),
+            Value::Bool(b) => write!(f, "{}", if *b { "TRUE" } else { "FALSE" }),
+            Value::Err(e) => write!(f, "{}", e),
+            Value::Empty => Ok(()),
+        }
+    }
+}
+
+/// Spreadsheet MOD: the result takes the sign of the divisor (`-7 % 3` is 2, not -1).
+pub fn modulo(a: f64, b: f64) -> f64 {
+    a - b * (a / b).floor()
+}
+
+// Strict math: an input outside a function's domain gives `#NUM!` (Excel semantics) rather
+// than NaN, so one bad cell shows up as an error instead of silently poisoning every total.
+
+pub fn sqrt(x: f64) -> Result<f64, &'static str> {
+    if x < 0.0 { Err("#NUM!") } else { Ok(x.sqrt()) }
+}
+
+/// Overflow (`EXP(710)`) is a `#NUM!` as well.
+pub fn exp(x: f64) -> Result<f64, &'static str> {
+    let result = x.exp();
+    if result.is_infinite() { Err("#NUM!") } else { Ok(result) }
+}
+
+pub fn ln(x: f64) -> Result<f64, &'static str> {
+    if x <= 0.0 { Err("#NUM!") } else { Ok(x.ln()) }
+}
+
+/// `LOG(x, base)`. Base 1 has a zero logarithm and is a `#DIV/0!`.
+pub fn log(x: f64, base: f64) -> Result<f64, &'static str> {
+    if x <= 0.0 || base <= 0.0 {
+        Err("#NUM!")
+    } else if base == 1.0 {
+        Err("#DIV/0!")
+    } else {
+        Ok(x.log(base))
+    }
+}
+
+/// Formats a number with an Excel-style pattern for `TEXT(number, format)`.
+/// Supported: decimal places (`0`, `0.00`), thousands grouping (`#,##0.00`) and a trailing `%`.
+pub fn format_number(value: f64, format: &str) -> String {
+    let percent = format.ends_with('%');
+    let pattern = format.trim_end_matches('%');
+    let value = if percent { value * 100.0 } else { value };
+
+    let decimals = pattern.split_once('.').map(|(_, frac)| frac.len()).unwrap_or(0);
+    let formatted = format!("{:.*}", decimals, value);
+
+    let formatted = if pattern.contains(',') {
+        group_thousands(&formatted)
+    } else {
+        formatted
+    };
+
+    if percent { format!("{}%", formatted) } else { formatted }
+}
+
+fn group_thousands(formatted: &str) -> String {
+    let (sign, unsigned) = match formatted.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", formatted),
+    };
+    let (int_part, frac_part) = match unsigned.split_once('.') {
+        Some((i, f)) => (i, Some(f)),
+        None => (unsigned, None),
+    };
+
+    let mut grouped = String::with_capacity(int_part.len() + int_part.len() / 3);
+    for (i, c) in int_part.chars().enumerate() {
+        if i > 0 && (int_part.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+
+    match frac_part {
+        Some(f) => format!("{}{}.{}", sign, grouped, f),
+        None => format!("{}{}", sign, grouped),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_number_patterns() {
+        assert_eq!(format_number(1234.5, "0.00"), "1234.50");
+        assert_eq!(format_number(1234.6, "0"), "1235");
+        assert_eq!(format_number(-1234567.891, "#,##0.00"), "-1,234,567.89");
+        assert_eq!(format_number(0.153, "0.0%"), "15.3%");
+        assert_eq!(Value::Num(1234.0).to_string(), "1234");
+        assert_eq!(Value::Num(0.5).to_string(), "0.5");
+    }
+}
@@ -1,177 +1,1191 @@
-use crate::atom_script::chunk::{Chunk, OpCode};
+use crate::atom_script::bytecode::{decode_at, instruction_index};
+use crate::atom_script::chunk::{AggregateKind, Chunk, DatePartKind, MatchMode, OpCode, PreparedChunk, SearchMode, ValidationError};
+use crate::atom_script::lookup::{LookupBackend, LookupCache};
+use crate::atom_script::registry::FunctionRegistry;
+use crate::atom_script::value::{self, format_number, modulo, Value};
+use crate::lattice::arena::LatticeArena;
+use crate::lattice::coordinate::{coordinate_hash, overlay_hash, CoordinateSpec};
+use crate::lattice::period::PeriodResolver;
+use crate::lattice::slice::{GridSlice, SliceError};
+use rayon::prelude::*;
+use chrono::{Datelike, NaiveDateTime};
+use thiserror::Error;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-pub struct VM {
-    chunk: Chunk,
-    stack: Vec<f64>,
+// Batch recalcs poll the cancel flag once per this many rows.
+pub const CANCEL_CHECK_INTERVAL: usize = 1024;
+
+// Values the stack holds unless the VM is built with `with_stack_size`.
+pub const DEFAULT_STACK_SIZE: usize = 256;
+
+// Periods EMA reads, counting back from the current one. Bounds the work on long (or endless,
+// generated) series; an older period's weight is at most (1 - alpha)^1023 of the result.
+pub const MAX_EMA_LOOKBACK: usize = 1024;
+
+pub struct VM<'a> {
+    chunk: Arc<Chunk>, // Shared so cached chunks can be evaluated without copying
+    stack: Vec<Value>,
+    stack_size: usize, // Pushing past this many values is a StackOverflow fault
+    accumulators: Vec<Accumulator>, // Open streaming aggregations, innermost last
     ip: usize, // Instruction Pointer
+    at: usize, // Position of the instruction being executed (`ip` before it was fetched)
+    error_at: Option<usize>, // Position of the instruction that first produced an error this run
+
+    // Data Context: without an arena, every cell load reads as Empty (sparse default)
+    arena: Option<&'a LatticeArena>,
+    periods: Option<&'a dyn PeriodResolver>,
+    functions: Option<&'a FunctionRegistry>,
+    lookup: Option<&'a dyn LookupBackend>,
+    lookup_cache: Option<&'a LookupCache>,
+    spec: Option<&'a CoordinateSpec>,
+    missing: MissingPolicy,
+    strict_math: bool, // MIN/MAX propagate NaN operands instead of skipping them
+    coordinate: Vec<(String, String)>, // The cell being evaluated; references resolve relative to it
+    anchor: Option<String>, // Period set by SetPeriod, consumed by the next PeriodOffset
+    fast_dispatch: bool, // The chunk passed `Chunk::validate`, so instruction fetches skip bounds checks
+    numeric_only: bool, // `Chunk::is_numeric_only`: `run` tries the untagged f64 stack first
+    num_stack: Vec<f64>, // Stack of the numeric path, kept across runs for its capacity
+    bytecode: Option<Arc<[u8]>>, // Set by `with_bytecode`: dispatch decodes these bytes instead of `chunk.code`
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum InterpretResult {
     Ok(f64),
+    Text(String), // Commentary formulas (CONCAT, TEXT)
+    ErrorValue(String), // The formula evaluated to an error value (e.g. #N/A)
+    TypeError(String), // An operator received operands of the wrong type (e.g. "a" + 1)
     CompileError,
-    RuntimeError,
+    RuntimeError(RuntimeFault),
     EvaluationTimeout, // Ultra Diamond: Vector 1 DoS Protection
 }
 
-impl VM {
-    pub fn new(chunk: Chunk) -> Self {
+/// Why the VM aborted. Index faults come from malformed (e.g. corrupt deserialized) chunks
+/// and are reported instead of panicking on an out-of-range pool access.
+#[derive(Debug, Clone, Copy, PartialEq, Error)]
+pub enum RuntimeFault {
+    #[error("constant index {0} out of range")]
+    BadConstantIndex(usize),
+    #[error("string index {0} out of range")]
+    BadStringIndex(usize),
+    #[error("coordinate index {0} out of range")]
+    BadCoordinateIndex(usize),
+    #[error("instruction pointer {0} out of range")]
+    BadInstructionPointer(usize),
+    #[error("stack underflow")]
+    StackUnderflow,
+    #[error("stack overflow")]
+    StackOverflow,
+    #[error("no period resolver or current period for a time-series function")]
+    MissingPeriodContext,
+    #[error("native function {0} is not registered")]
+    UnknownFunction(usize),
+}
+
+/// Outcome of a batch recalculation. Results are in input order; a cancelled batch
+/// carries the results of the rows that completed before the flag was observed.
+#[derive(Debug, PartialEq)]
+pub enum BatchResult {
+    Completed(Vec<InterpretResult>),
+    Cancelled(Vec<InterpretResult>),
+}
+
+/// What a reference to a cell that was never set reads as.
+/// The default (`Empty`) keeps sparse data out of averages and counts; the others are for
+/// formulas where "no data" must be visible (`NaN`, `Error`) or has a known value.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MissingPolicy {
+    /// Skipped by aggregations, 0 in arithmetic. Only under this policy do ISBLANK and
+    /// COUNTA see a never-set cell as blank.
+    #[default]
+    Empty,
+    /// An explicit 0, so aggregations count the cell (AVG over 3 refs divides by 3).
+    Zero,
+    /// NaN, which propagates through arithmetic and aggregations.
+    NaN,
+    Custom(f64),
+    /// The `#N/A` error value.
+    Error,
+}
+
+impl MissingPolicy {
+    fn value(self) -> Value {
+        match self {
+            MissingPolicy::Empty => Value::Empty,
+            MissingPolicy::Zero => Value::Num(0.0),
+            MissingPolicy::NaN => Value::Num(f64::NAN),
+            MissingPolicy::Custom(v) => Value::Num(v),
+            MissingPolicy::Error => Value::Err("#N/A".to_string()),
+        }
+    }
+}
+
+/// Running state of a streaming aggregation: enough to finish SUM/AVG/MIN/MAX without keeping
+/// the operands. Empty operands are skipped, exactly as in the stack-based opcodes.
+struct Accumulator {
+    kind: AggregateKind,
+    strict_math: bool,
+    sum: f64,
+    count: usize,
+    min: f64,
+    max: f64,
+    saw_nan: bool,
+}
+
+impl Accumulator {
+    fn new(kind: AggregateKind, strict_math: bool) -> Self {
+        Self { kind, strict_math, sum: 0.0, count: 0, min: f64::NAN, max: f64::NAN, saw_nan: false }
+    }
+
+    fn feed(&mut self, operands: &[f64]) {
+        for &v in operands {
+            self.sum += v;
+            // NaN until the first number, since f64::min/max skip a NaN operand
+            self.min = self.min.min(v);
+            self.max = self.max.max(v);
+            self.saw_nan |= v.is_nan();
+        }
+        self.count += operands.len();
+    }
+
+    fn finish(&self) -> Value {
+        match self.kind {
+            AggregateKind::Sum => Value::Num(self.sum),
+            AggregateKind::Avg if self.count == 0 => Value::Err("#DIV/0!".to_string()),
+            AggregateKind::Avg => Value::Num(self.sum / self.count as f64),
+            AggregateKind::Min | AggregateKind::Max if self.strict_math && self.saw_nan => Value::Num(f64::NAN),
+            AggregateKind::Min => min_max_result(self.min),
+            AggregateKind::Max => min_max_result(self.max),
+        }
+    }
+}
+
+/// Outcome of `VM::eval_slice_into_arena`. Failed cells are stored as NaN so that stale
+/// values never survive a recompute; the failures list says why.
+#[derive(Debug, Default, PartialEq)]
+pub struct SliceReport {
+    pub evaluated: usize,
+    pub failures: Vec<CellError>,
+}
+
+/// A cell a batch recalc could not compute: where it is and what its formula produced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellError {
+    pub coordinate: Vec<(String, String)>,
+    pub hash: u128,
+    pub result: InterpretResult,
+}
+
+/// Per-cell outcome of `VM::run_batch`, in input order, so a UI can show the cells that
+/// computed next to error badges on the rest. `failed` counts the `Err` outcomes.
+#[derive(Debug, Default, PartialEq)]
+pub struct BatchReport {
+    pub outcomes: Vec<Result<f64, CellError>>,
+    pub failed: usize,
+}
+
+impl<'a> VM<'a> {
+    /// Creates a VM for `chunk`. A plain `Chunk` or `Arc<Chunk>` is validated here; pass a
+    /// `PreparedChunk` to build many VMs for the same chunk without repeating the check.
+    pub fn new(chunk: impl Into<PreparedChunk>) -> Self {
+        Self::with_stack_size(chunk, DEFAULT_STACK_SIZE)
+    }
+
+    /// Creates a VM whose stack holds up to `size` values instead of `DEFAULT_STACK_SIZE`,
+    /// for deeply nested formulas or large aggregations compiled without streaming.
+    /// `Chunk::estimate_cost` reports the exact depth a chunk needs as `max_stack`.
+    pub fn with_stack_size(chunk: impl Into<PreparedChunk>, size: usize) -> Self {
+        Self::with_stack_buffer(chunk, Vec::with_capacity(size), size)
+    }
+
+    /// Creates a VM that uses `stack` (cleared first) as its stack instead of allocating one,
+    /// so `VmPool` can hand the same buffer to one VM after another.
+    pub(crate) fn with_stack_buffer(chunk: impl Into<PreparedChunk>, mut stack: Vec<Value>, size: usize) -> Self {
+        let prepared: PreparedChunk = chunk.into();
+        let chunk = Arc::clone(prepared.chunk());
+        stack.clear();
         Self {
+            fast_dispatch: prepared.validation().is_ok(),
+            numeric_only: prepared.is_numeric_only(),
+            num_stack: Vec::new(),
             chunk,
-            stack: Vec::with_capacity(256), // Typical stack depth
+            stack,
+            stack_size: size,
+            accumulators: Vec::new(),
             ip: 0,
+            at: 0,
+            error_at: None,
+            arena: None,
+            periods: None,
+            functions: None,
+            lookup: None,
+            lookup_cache: None,
+            spec: None,
+            missing: MissingPolicy::default(),
+            strict_math: false,
+            coordinate: Vec::new(),
+            anchor: None,
+            bytecode: None,
         }
     }
 
+    /// Creates a VM for a chunk from an untrusted source (e.g. received over the wire),
+    /// rejecting it up-front if it fails `Chunk::validate`.
+    pub fn try_new(chunk: impl Into<PreparedChunk>) -> Result<Self, ValidationError> {
+        let chunk: PreparedChunk = chunk.into();
+        chunk.validation().clone()?;
+        Ok(Self::new(chunk))
+    }
+
+    /// Reads cell references from `arena`.
+    pub fn with_arena(mut self, arena: &'a LatticeArena) -> Self {
+        self.arena = Some(arena);
+        self
+    }
+
+    /// Enables time-series opcodes (ROLLING_AVG) by resolving period moves through `periods`.
+    pub fn with_periods(mut self, periods: &'a dyn PeriodResolver) -> Self {
+        self.periods = Some(periods);
+        self
+    }
+
+    /// Dispatches `CallNative` opcodes to host-defined functions in `functions`.
+    pub fn with_functions(mut self, functions: &'a FunctionRegistry) -> Self {
+        self.functions = Some(functions);
+        self
+    }
+
+    /// Evaluates `LOOKUP` through `backend`.
+    pub fn with_lookup(mut self, backend: &'a dyn LookupBackend) -> Self {
+        self.lookup = Some(backend);
+        self
+    }
+
+    /// Serves repeated `LOOKUP`s with identical arguments from `cache`, which may be shared
+    /// with the other VMs of a recalc pass.
+    pub fn with_lookup_cache(mut self, cache: &'a LookupCache) -> Self {
+        self.lookup_cache = Some(cache);
+        self
+    }
+
+    /// Rejects cell references that do not cover every dimension of `spec`: they read as
+    /// `#REF!` instead of as an empty cell that can never have been stored.
+    pub fn with_spec(mut self, spec: &'a CoordinateSpec) -> Self {
+        self.spec = Some(spec);
+        self
+    }
+
+    /// Chooses what references to cells that were never set read as (see `MissingPolicy`).
+    pub fn with_missing_policy(mut self, policy: MissingPolicy) -> Self {
+        self.missing = policy;
+        self
+    }
+
+    /// Makes MIN/MAX return NaN when any operand is NaN. By default NaN operands are skipped,
+    /// as `NonFinitePolicy::Zero` keeps them out of a spread; strict math surfaces them, as
+    /// `NonFinitePolicy::Reject` does. SUM and AVG propagate NaN either way.
+    pub fn with_strict_math(mut self, strict: bool) -> Self {
+        self.strict_math = strict;
+        self
+    }
+
+    /// Sets the coordinate of the cell being evaluated (dimension=member pairs).
+    pub fn with_coordinate(mut self, coordinate: Vec<(String, String)>) -> Self {
+        self.coordinate = coordinate;
+        self
+    }
+
+    /// Runs the chunk from its byte encoding (`Chunk::to_bytecode`) instead of the `OpCode`
+    /// array: `ip` becomes a byte offset and each instruction is decoded as it is fetched.
+    /// Results are identical to the enum path. Only a chunk that passes `Chunk::validate` is
+    /// switched over, so the encoding always decodes; any other chunk keeps the checked enum path.
+    pub fn with_bytecode(mut self) -> Self {
+        if self.fast_dispatch {
+            self.bytecode = Some(self.chunk.to_bytecode().into());
+        }
+        self
+    }
+
+    /// Evaluates the chunk. A `Chunk::is_numeric_only` chunk first runs on a plain f64 stack,
+    /// skipping the tagged `Value` stack; if it meets something only the tagged path can
+    /// represent, it is rerun there from the start, so results are the same either way.
     pub fn run(&mut self) -> InterpretResult {
+        if self.numeric_only {
+            if let Some(result) = self.run_numeric() {
+                return InterpretResult::Ok(result);
+            }
+        }
+
         let mut op_count = 0;
         const MAX_OPS: usize = 10_000_000; // Circuit Breaker: Maximum instruction cycles
 
+        // Hoisted so the fetch below does not go through `self` on every instruction
+        let chunk = Arc::clone(&self.chunk);
+        let code = chunk.code.as_slice();
+        let bytecode = self.bytecode.clone();
+
         loop {
             if op_count >= MAX_OPS {
                 return InterpretResult::EvaluationTimeout;
             }
             op_count += 1;
+            self.at = self.ip;
+
+            let instruction = if let Some(bytecode) = bytecode.as_deref() {
+                match decode_at(bytecode, self.ip) {
+                    Ok((instruction, next)) => {
+                        self.ip = next;
+                        instruction
+                    }
+                    Err(_) => return InterpretResult::RuntimeError(RuntimeFault::BadInstructionPointer(self.ip)),
+                }
+            } else if self.fast_dispatch {
+                debug_assert!(self.ip < code.len());
+                // SAFETY: `Chunk::validate` proved every jump target is in range and that the
+                // code ends with Return, which always finishes the program, so sequential
+                // execution and jumps both keep `ip` below `code.len()`.
+                let instruction = unsafe { *code.get_unchecked(self.ip) };
+                self.ip += 1;
+                instruction
+            } else {
+                match code.get(self.ip) {
+                    Some(&instruction) => {
+                        self.ip += 1;
+                        instruction
+                    }
+                    None => return InterpretResult::RuntimeError(RuntimeFault::BadInstructionPointer(self.ip)),
+                }
+            };
 
-            if self.ip >= self.chunk.code.len() {
-                return InterpretResult::RuntimeError;
+            match self.step(instruction) {
+                Ok(Some(result)) => return result,
+                Ok(None) => {}
+                Err(e) => {
+                    self.error_at.get_or_insert(self.at);
+                    return e;
+                }
             }
+        }
+    }
 
-            let instruction = self.chunk.code[self.ip];
-            self.ip += 1;
+    /// The numeric path of `run`. None when a cell reads as anything but a number (empty, an
+    /// error), a result would be an error value (e.g. MIN of only NaN) or the stack would overflow;
+    /// a numeric-only chunk has no other effects, so rerunning it on the tagged path is safe.
+    fn run_numeric(&mut self) -> Option<f64> {
+        let chunk = Arc::clone(&self.chunk);
+        let mut stack = std::mem::take(&mut self.num_stack);
+        stack.clear();
+        let result = self.eval_numeric(&chunk, &mut stack);
+        self.num_stack = stack;
+        result
+    }
 
+    fn eval_numeric(&self, chunk: &Chunk, stack: &mut Vec<f64>) -> Option<f64> {
+        for &instruction in &chunk.code {
             match instruction {
-                OpCode::Return => {
-                    return InterpretResult::Ok(self.pop());
+                OpCode::Return => return stack.pop(),
+                OpCode::Constant(idx) => stack.push(*chunk.constants.get(idx)?),
+                OpCode::LoadDimension(idx) => match self.load_reference(chunk.coordinates.get(idx)?) {
+                    Value::Num(n) => stack.push(n),
+                    _ => return None,
+                },
+                OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div | OpCode::Mod => {
+                    let b = stack.pop()?;
+                    let a = stack.last_mut()?;
+                    *a = match instruction {
+                        OpCode::Add => *a + b,
+                        OpCode::Sub => *a - b,
+                        OpCode::Mul => *a * b,
+                        OpCode::Div => *a / b,
+                        _ => modulo(*a, b),
+                    };
                 }
-                OpCode::Constant(idx) => {
-                    let constant = self.chunk.constants[idx];
-                    if let Err(e) = self.push(constant) { return e; }
+                OpCode::Negate => {
+                    let a = stack.last_mut()?;
+                    *a = -*a;
                 }
-                OpCode::Add => {
-                    let b = self.pop();
-                    let a = self.pop();
-                    if let Err(e) = self.push(a + b) { return e; }
+                OpCode::Sum(count) | OpCode::Avg(count) | OpCode::Min(count) | OpCode::Max(count) => {
+                    let kind = match instruction {
+                        OpCode::Sum(_) => AggregateKind::Sum,
+                        OpCode::Avg(_) => AggregateKind::Avg,
+                        OpCode::Min(_) => AggregateKind::Min,
+                        _ => AggregateKind::Max,
+                    };
+                    // Folded where they lie, in the order the tagged path pops them (last first)
+                    let start = stack.len().checked_sub(count)?;
+                    let result = aggregate_values(kind, stack[start..].iter().rev().copied(), self.strict_math);
+                    stack.truncate(start);
+                    match result {
+                        Value::Num(n) => stack.push(n),
+                        _ => return None,
+                    }
                 }
-                OpCode::Sub => {
-                    let b = self.pop();
-                    let a = self.pop();
-                    if let Err(e) = self.push(a - b) { return e; }
+                _ => return None,
+            }
+            if stack.len() > self.stack_size {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Source range of the instruction that made the last run fail: the first one to produce
+    /// an error value (e.g. the `/` of `1/0`), or the one that raised a type error or fault.
+    /// None if nothing failed or the chunk has no spans (see `Compiler::with_source_map`).
+    /// An error value later discarded (e.g. skipped by COALESCE) is still reported.
+    pub fn error_span(&self) -> Option<Range<usize>> {
+        let at = self.error_at?;
+        let index = match self.bytecode.as_deref() {
+            Some(bytecode) => instruction_index(bytecode, at)?,
+            None => at,
+        };
+        self.chunk.spans.get(index).cloned()
+    }
+
+    /// Evaluates the chunk at every coordinate in `inputs` and, with an arena attached,
+    /// writes each numeric result back to the evaluated cell. A cell that fails is reported
+    /// with its coordinate and does not stop the rest; its arena value is left as it was.
+    pub fn run_batch(&mut self, inputs: &[Vec<(String, String)>]) -> BatchReport {
+        let (BatchResult::Completed(results) | BatchResult::Cancelled(results)) =
+            self.run_batch_cancellable(inputs, &AtomicBool::new(false));
+        let mut report = BatchReport::default();
+        for (coordinate, result) in inputs.iter().zip(results) {
+            let outcome = match result {
+                InterpretResult::Ok(value) => Ok(value),
+                result => {
+                    report.failed += 1;
+                    Err(CellError { coordinate: coordinate.clone(), hash: coordinate_hash(coordinate), result })
                 }
-                OpCode::Mul => {
-                    let b = self.pop();
-                    let a = self.pop();
-                    if let Err(e) = self.push(a * b) { return e; }
+            };
+            report.outcomes.push(outcome);
+        }
+        report
+    }
+
+    /// Evaluates the chunk at every coordinate in `inputs` and, with an arena attached,
+    /// writes each numeric result back to the evaluated cell.
+    /// `cancel` is polled every `CANCEL_CHECK_INTERVAL` rows and only between rows, so a
+    /// cancelled batch leaves every row either fully written or untouched (no rollback of
+    /// rows already written).
+    pub fn run_batch_cancellable(
+        &mut self,
+        inputs: &[Vec<(String, String)>],
+        cancel: &AtomicBool,
+    ) -> BatchResult {
+        let mut results = Vec::with_capacity(inputs.len());
+        for (row, coordinate) in inputs.iter().enumerate() {
+            if row % CANCEL_CHECK_INTERVAL == 0 && cancel.load(Ordering::Relaxed) {
+                return BatchResult::Cancelled(results);
+            }
+
+            self.reset();
+            self.coordinate.clone_from(coordinate);
+            let result = self.run();
+
+            if let (InterpretResult::Ok(value), Some(arena)) = (&result, self.arena) {
+                arena.set_computed(coordinate_hash(coordinate), *value);
+            }
+            results.push(result);
+        }
+        BatchResult::Completed(results)
+    }
+
+    /// Evaluates `chunk` at every cell of `slice` and writes each result straight into `arena`
+    /// in one fused parallel pass, with no intermediate result vector.
+    /// Each Rayon worker reuses one VM (stack allocation included) across the cells it handles.
+    /// A cell whose formula does not produce a number is stored as NaN and evaluation continues.
+    pub fn eval_slice_into_arena(
+        chunk: &Arc<Chunk>,
+        slice: &GridSlice,
+        arena: &LatticeArena,
+    ) -> Result<SliceReport, SliceError> {
+        // Enforces the slice's cell limit before any work is done
+        let cells = slice.cell_count()?;
+        let chunk = PreparedChunk::new(Arc::clone(chunk));
+
+        let failures: Vec<CellError> = (0..cells)
+            .into_par_iter()
+            .map_init(
+                || VM::new(chunk.clone()).with_arena(arena),
+                |vm, index| {
+                    vm.reset();
+                    vm.coordinate = slice.coordinate_at(index);
+                    let result = vm.run();
+
+                    let hash = coordinate_hash(&vm.coordinate);
+                    match result {
+                        InterpretResult::Ok(value) => {
+                            arena.set_computed(hash, value);
+                            None
+                        }
+                        result => {
+                            arena.set_computed(hash, f64::NAN);
+                            Some(CellError { coordinate: std::mem::take(&mut vm.coordinate), hash, result })
+                        }
+                    }
+                },
+            )
+            .flatten()
+            .collect();
+
+        Ok(SliceReport { evaluated: cells, failures })
+    }
+
+    /// Gives up the VM's stack buffer for reuse (see `with_stack_buffer`).
+    pub(crate) fn into_stack_buffer(self) -> Vec<Value> {
+        self.stack
+    }
+
+    /// Prepares the VM to run its chunk again from the start.
+    fn reset(&mut self) {
+        self.ip = 0;
+        self.error_at = None;
+        self.stack.clear();
+        self.accumulators.clear();
+        self.anchor = None;
+    }
+
+    /// Executes `instruction`; `ip` already points past it. Returns `Some(result)` once the
+    /// program has finished.
+    fn step(&mut self, instruction: OpCode) -> Result<Option<InterpretResult>, InterpretResult> {
+        match instruction {
+            OpCode::Return => {
+                return Ok(Some(self.pop_value()?.into()));
+            }
+            OpCode::Constant(idx) => {
+                let constant = self.read_constant(idx)?;
+                self.push(constant)?;
+            }
+            OpCode::LoadDimension(idx) => {
+                let value = self.load_reference(self.coordinate_overrides(idx)?);
+                self.push_value(value)?;
+            }
+            OpCode::ErrorConstant(idx) => {
+                let error = self.string(idx)?;
+                self.push_value(Value::Err(error))?;
+            }
+            OpCode::Add => self.binary_arith(instruction, |a, b| a + b)?,
+            OpCode::Sub => self.binary_arith(instruction, |a, b| a - b)?,
+            OpCode::Mul => self.binary_arith(instruction, |a, b| a * b)?,
+            OpCode::Div => self.binary_arith(instruction, |a, b| a / b)?,
+            OpCode::Mod => self.binary_arith(instruction, modulo)?,
+            OpCode::Equal | OpCode::NotEqual | OpCode::Less
+            | OpCode::LessEqual | OpCode::Greater | OpCode::GreaterEqual => self.compare(instruction)?,
+            OpCode::In(count) => {
+                let start = self.stack.len().checked_sub(count).ok_or(InterpretResult::RuntimeError(RuntimeFault::StackUnderflow))?;
+                let candidates: Vec<Value> = self.stack.drain(start..).collect();
+                let value = self.pop_value()?;
+                let found = candidates.iter().any(|c| values_equal(&value, c));
+                self.push_value(Value::Bool(found))?;
+            }
+            OpCode::JumpIfFalse(target) => {
+                if !truthy(self.pop_value()?)? {
+                    self.ip = target;
                 }
-                OpCode::Div => {
-                    let b = self.pop();
-                    let a = self.pop();
-                    if let Err(e) = self.push(a / b) { return e; }
+            }
+            OpCode::Jump(target) => {
+                self.ip = target;
+            }
+            OpCode::Dup => {
+                let top = self.stack.last().cloned().ok_or(InterpretResult::RuntimeError(RuntimeFault::StackUnderflow))?;
+                self.push_value(top)?;
+            }
+            OpCode::Pop => {
+                self.pop_value()?;
+            }
+            OpCode::Ratio => {
+                let whole = self.pop()?;
+                let part = self.pop()?;
+                if whole == 0.0 {
+                    self.push_value(Value::Err("#DIV/0!".to_string()))?;
+                } else {
+                    self.push(part / whole)?;
                 }
-                OpCode::Negate => {
-                    let a = self.pop();
-                    if let Err(e) = self.push(-a) { return e; }
-                }
-                // Ultra Diamond: Aggregation
-                OpCode::Sum(count) => {
-                    let mut sum = 0.0;
-                    for _ in 0..count {
-                        sum += self.pop();
-                    }
-                    if let Err(e) = self.push(sum) { return e; }
+            }
+            OpCode::Negate => {
+                let a = self.pop()?;
+                self.push(-a)?;
+            }
+            OpCode::Sqrt | OpCode::Exp | OpCode::Ln => {
+                let x = self.pop()?;
+                let result = match instruction {
+                    OpCode::Sqrt => value::sqrt(x),
+                    OpCode::Exp => value::exp(x),
+                    _ => value::ln(x),
+                };
+                self.push_math(result)?;
+            }
+            OpCode::Log => {
+                let base = self.pop()?;
+                let x = self.pop()?;
+                self.push_math(value::log(x, base))?;
+            }
+            // Ultra Diamond: Aggregation
+            OpCode::Sum(count) => self.aggregate(AggregateKind::Sum, count)?,
+            OpCode::Avg(count) => self.aggregate(AggregateKind::Avg, count)?,
+            OpCode::Min(count) => self.aggregate(AggregateKind::Min, count)?,
+            OpCode::Max(count) => self.aggregate(AggregateKind::Max, count)?,
+            OpCode::AccBegin(kind) => self.accumulators.push(Accumulator::new(kind, self.strict_math)),
+            OpCode::AccFeed(count) => {
+                let operands = self.pop_aggregate(count)?;
+                self.accumulators
+                    .last_mut()
+                    .ok_or(InterpretResult::RuntimeError(RuntimeFault::StackUnderflow))?
+                    .feed(&operands);
+            }
+            OpCode::AccEnd => {
+                let accumulator = self.accumulators.pop().ok_or(InterpretResult::RuntimeError(RuntimeFault::StackUnderflow))?;
+                self.push_value(accumulator.finish())?;
+            }
+            // First valid value wins; if every argument is empty or an error, the last one is kept
+            OpCode::Coalesce(count) => {
+                let start = self.stack.len().checked_sub(count).ok_or(InterpretResult::RuntimeError(RuntimeFault::StackUnderflow))?;
+                let mut values: Vec<Value> = self.stack.drain(start..).collect();
+                let chosen = match values.iter().position(|v| !matches!(v, Value::Empty | Value::Err(_))) {
+                    Some(idx) => values.swap_remove(idx),
+                    None => values.pop().unwrap_or(Value::Empty),
+                };
+                self.push_value(chosen)?;
+            }
+            OpCode::IsBlank => {
+                let blank = matches!(self.pop_value()?, Value::Empty);
+                self.push(if blank { 1.0 } else { 0.0 })?;
+            }
+            OpCode::CountA(count) => {
+                let start = self.stack.len().checked_sub(count).ok_or(InterpretResult::RuntimeError(RuntimeFault::StackUnderflow))?;
+                let filled = self.stack.drain(start..).filter(|v| !matches!(v, Value::Empty)).count();
+                self.push(filled as f64)?;
+            }
+            // Runtime FILTER: the stack holds N (value, mask) pairs. Masked-out values become
+            // Empty so the enclosing aggregation keeps its static operand count but skips them.
+            OpCode::Filter(count) => {
+                let start = count
+                    .checked_mul(2)
+                    .and_then(|operands| self.stack.len().checked_sub(operands))
+                    .ok_or(InterpretResult::RuntimeError(RuntimeFault::StackUnderflow))?;
+                let pairs: Vec<Value> = self.stack.drain(start..).collect();
+                for pair in pairs.chunks(2) {
+                    let keep = match &pair[1] {
+                        Value::Bool(b) => *b,
+                        Value::Num(n) => *n != 0.0,
+                        Value::Empty => false,
+                        Value::Err(e) => return Err(InterpretResult::ErrorValue(e.clone())),
+                        other => return Err(type_error("filter predicate", other)),
+                    };
+                    self.push_value(if keep { pair[0].clone() } else { Value::Empty })?;
                 }
-                OpCode::Avg(count) => {
-                    let mut sum = 0.0;
-                    for _ in 0..count {
-                        sum += self.pop();
-                    }
-                    if let Err(e) = self.push(sum / count as f64) { return e; }
+            }
+            // Ultra Diamond: Lookups & Time Travel (Phase 12 Kernels)
+            // In Phase 12, the VM will be injected with an unsafe pointer to the LatticeArena.
+            // These opcodes will execute an O(1) atomic pointer jump without evaluating the grid.
+            OpCode::Shift => {
+                let offset = self.pop()?; // e.g. [PrevMonth]
+                let value = self.pop()?;  // e.g. [Revenue]
+                
+                // Phase 12: unsafe { value_ptr.offset(offset as isize) }
+                // For now, securely pop and return the base value to guarantee stack safety.
+                self.push(value + offset)?;
+            }
+            OpCode::Lookup => {
+                let result_rng = self.pop_value()?;
+                let search_rng = self.pop_value()?;
+                let value = self.pop_value()?;
+                if let Value::Err(e) = value {
+                    return Err(InterpretResult::ErrorValue(e));
                 }
-                OpCode::Min(count) => {
-                    let mut min_val = f64::MAX;
-                    for _ in 0..count {
-                        let v = self.pop();
-                        if v < min_val { min_val = v; }
+                let found = match (self.lookup, self.lookup_cache) {
+                    // Fallback to safe 0.0 when the host injects no lookup backend
+                    (None, _) => Value::Num(0.0),
+                    (Some(backend), None) => backend.lookup(&value, &search_rng, &result_rng),
+                    (Some(backend), Some(cache)) => {
+                        let version = self.arena.map_or(0, LatticeArena::version);
+                        cache.get_or_lookup([&value, &search_rng, &result_rng], version, || {
+                            backend.lookup(&value, &search_rng, &result_rng)
+                        })
                     }
-                    if let Err(e) = self.push(min_val) { return e; }
+                };
+                self.push_value(found)?;
+            }
+            OpCode::XLookup(count, match_mode, search_mode) => {
+                let default = self.pop_value()?;
+                let start = count
+                    .checked_mul(2)
+                    .and_then(|keys_and_results| keys_and_results.checked_add(1))
+                    .and_then(|operands| self.stack.len().checked_sub(operands))
+                    .ok_or(InterpretResult::RuntimeError(RuntimeFault::StackUnderflow))?;
+                let mut operands: Vec<Value> = self.stack.drain(start..).collect();
+                let mut results = operands.split_off(count + 1);
+                let value = operands.remove(0);
+                if let Value::Err(e) = value {
+                    return Err(InterpretResult::ErrorValue(e));
                 }
-                OpCode::Max(count) => {
-                    let mut max_val = f64::MIN;
-                    for _ in 0..count {
-                        let v = self.pop();
-                        if v > max_val { max_val = v; }
+                let found = match xlookup_index(&value, &operands, match_mode, search_mode) {
+                    Some(idx) => results.swap_remove(idx),
+                    None => default,
+                };
+                self.push_value(found)?;
+            }
+            // Time-Series Windows
+            // At the start of a series fewer than `window` periods exist; the average is
+            // taken over the periods that do exist rather than padding with zeros.
+            OpCode::RollingAvg(idx, window) => {
+                let missing = InterpretResult::RuntimeError(RuntimeFault::MissingPeriodContext);
+                let periods = self.periods.ok_or(missing.clone())?;
+                let current = self.current_period(periods).ok_or(missing)?;
+
+                let mut sum = 0.0;
+                let mut available = 0;
+                for k in 0..window {
+                    let Some(period) = periods.shift(&current, -(k as i64)) else { break };
+                    let mut overrides = self.coordinate_overrides(idx)?.clone();
+                    overrides.push((periods.dimension().to_string(), period));
+                    // Empty periods are skipped, like any other aggregation
+                    if let Value::Num(v) = self.load_reference(&overrides) {
+                        sum += v;
+                        available += 1;
                     }
-                    if let Err(e) = self.push(max_val) { return e; }
-                }
-                // Ultra Diamond: Lookups & Time Travel (Phase 12 Kernels)
-                // In Phase 12, the VM will be injected with an unsafe pointer to the LatticeArena.
-                // These opcodes will execute an O(1) atomic pointer jump without evaluating the grid.
-                OpCode::Shift => {
-                    let offset = self.pop(); // e.g. [PrevMonth]
-                    let value = self.pop();  // e.g. [Revenue]
-                    
-                    // Phase 12: unsafe { value_ptr.offset(offset as isize) }
-                    // For now, securely pop and return the base value to guarantee stack safety.
-                    if let Err(e) = self.push(value + offset) { return e; }
-                }
-                OpCode::Lookup => {
-                    let _return_rng = self.pop();
-                    let _search_rng = self.pop();
-                    let _lookup_val = self.pop();
-                    
-                    // Phase 12: SIMD accelerated scan across the search_rng pointer.
-                    // Fallback to safe 0.0 until kernel is injected.
-                    if let Err(e) = self.push(0.0) { return e; }
-                }
-                OpCode::XLookup(count) => {
-                    for _ in 0..count {
-                        let _arg = self.pop();
+                }
+                if available == 0 {
+                    self.push_value(Value::Empty)?;
+                } else {
+                    self.push(sum / available as f64)?;
+                }
+            }
+            // ema_t = alpha * x_t + (1 - alpha) * ema_{t-1}, seeded with the first value of the
+            // series (or of the last MAX_EMA_LOOKBACK periods). Recomputed from there rather than read
+            // back from stored EMA cells, so it never depends on evaluation order. Empty periods
+            // carry the EMA forward.
+            OpCode::Ema(idx, alpha_idx) => {
+                let missing = InterpretResult::RuntimeError(RuntimeFault::MissingPeriodContext);
+                let periods = self.periods.ok_or(missing.clone())?;
+                let current = self.current_period(periods).ok_or(missing)?;
+                let alpha = self.read_constant(alpha_idx)?;
+
+                let mut series = Vec::new();
+                for offset in 0..MAX_EMA_LOOKBACK as i64 {
+                    let Some(period) = periods.shift(&current, -offset) else { break };
+                    let mut overrides = self.coordinate_overrides(idx)?.clone();
+                    overrides.push((periods.dimension().to_string(), period));
+                    series.push(self.load_reference(&overrides));
+                }
+
+                let ema = series.iter().rev().fold(None, |ema, value| match (ema, value) {
+                    (None, Value::Num(v)) => Some(*v),
+                    (Some(prev), Value::Num(v)) => Some(alpha * v + (1.0 - alpha) * prev),
+                    (ema, _) => ema,
+                });
+                match ema {
+                    Some(v) => self.push(v)?,
+                    None => self.push_value(Value::Empty)?,
+                }
+            }
+            OpCode::SetPeriod(idx) => {
+                self.anchor = Some(self.string(idx)?);
+            }
+            // Moving past either end of the series reads as an empty cell, like any other
+            // missing data, rather than failing the formula.
+            OpCode::PeriodOffset(idx, offset) => self.period_offset(idx, offset)?,
+            // A calendar step is only meaningful when the series has a whole number of
+            // periods per step, e.g. [PrevQuarter] on months or quarters but not [PrevMonth] on quarters
+            OpCode::CalendarOffset(idx, months) => {
+                let periods = self.periods.ok_or(InterpretResult::RuntimeError(RuntimeFault::MissingPeriodContext))?;
+                // A series that is not calendar-based moves one period per month, as chains always did
+                let per_year = periods.periods_per_year().unwrap_or(12);
+                let offset = months.checked_mul(i64::from(per_year)).filter(|scaled| scaled % 12 == 0);
+                match offset {
+                    Some(scaled) => self.period_offset(idx, scaled / 12)?,
+                    None => {
+                        self.anchor = None;
+                        self.push_value(Value::Err("#VALUE!".to_string()))?;
                     }
-                    // Phase 12: B-Tree or SIMD scan based on XLookup heuristics.
-                    if let Err(e) = self.push(0.0) { return e; }
-                }
-                // Phase 3: Time-Intelligence Shifts
-                OpCode::TimeShift(shift_code) => {
-                    let base_val = self.pop(); // The calculated or raw value of the base metric
-                    
-                    // In a production LatticeArena, we would shift the underlying memory pointer
-                    // here without evaluating the calculation tree again. (O(1) Jump)
-                    //
-                    // let shifted_ptr = match shift_code {
-                    //    1 => unsafe { base_ptr.offset(-12) }, // PY
-                    //    2 => unsafe { base_ptr.offset(-3) },  // PQ
-                    //    3 => calculate_ytd_simd(base_ptr),    // YTD
-                    //    _ => base_ptr
-                    // };
-                    
-                    // For the Phase 3 VM, we will simulate the shifted value.
-                    let simulated_shift = match shift_code {
-                        1 => base_val * 0.90, // Simulate PY as 90% of current
-                        2 => base_val * 0.95, // Simulate PQ as 95% of current
-                        3 => base_val * 6.0,  // Simulate YTD (assuming mid-year)
-                        4 => base_val * 2.0,  // Simulate QTD (assuming mid-quarter)
-                        5 => base_val,        // PTD
-                        _ => base_val,
-                    };
-                    
-                    if let Err(e) = self.push(simulated_shift) { return e; }
                 }
             }
+            // Phase 3: Time-Intelligence Shifts
+            OpCode::TimeShift(shift_code) => {
+                let base_val = self.pop()?; // The calculated or raw value of the base metric
+                
+                // In a production LatticeArena, we would shift the underlying memory pointer
+                // here without evaluating the calculation tree again. (O(1) Jump)
+                //
+                // let shifted_ptr = match shift_code {
+                //    1 => unsafe { base_ptr.offset(-12) }, // PY
+                //    2 => unsafe { base_ptr.offset(-3) },  // PQ
+                //    3 => calculate_ytd_simd(base_ptr),    // YTD
+                //    _ => base_ptr
+                // };
+                
+                // For the Phase 3 VM, we will simulate the shifted value.
+                let simulated_shift = match shift_code {
+                    1 => base_val * 0.90, // Simulate PY as 90% of current
+                    2 => base_val * 0.95, // Simulate PQ as 95% of current
+                    3 => base_val * 6.0,  // Simulate YTD (assuming mid-year)
+                    4 => base_val * 2.0,  // Simulate QTD (assuming mid-quarter)
+                    5 => base_val,        // PTD
+                    _ => base_val,
+                };
+                
+                self.push(simulated_shift)?;
+            }
+            // Commentary Formulas: String Ops
+            OpCode::StringConstant(idx) => {
+                let text = self.string(idx)?;
+                self.push_value(Value::Text(text))?;
+            }
+            OpCode::Concat(count) => {
+                let start = self.stack.len().checked_sub(count).ok_or(InterpretResult::RuntimeError(RuntimeFault::StackUnderflow))?;
+                let joined: String = self.stack.drain(start..).map(|v| v.to_string()).collect();
+                self.push_value(Value::Text(joined))?;
+            }
+            OpCode::Text => {
+                let format = match self.pop_value()? {
+                    Value::Text(f) => f,
+                    other => return Err(type_error("TEXT format", &other)),
+                };
+                let number = self.pop()?;
+                self.push_value(Value::Text(format_number(number, &format)))?;
+            }
+            // Date cells hold Unix milliseconds (see LatticeArena::set_date), interpreted as UTC
+            OpCode::DatePart(kind) => {
+                let millis = self.pop()?;
+                match date_part(millis, kind) {
+                    Some(part) => self.push(part)?,
+                    None => self.push_value(Value::Err("#NUM!".to_string()))?,
+                }
+            }
+            OpCode::CallNative(id, argc) => {
+                let function = self
+                    .functions
+                    .and_then(|registry| registry.get(id))
+                    .ok_or(InterpretResult::RuntimeError(RuntimeFault::UnknownFunction(id)))?;
+                let mut args = vec![0.0; argc];
+                for slot in args.iter_mut().rev() {
+                    *slot = self.pop()?;
+                }
+                if argc == function.arity {
+                    self.push(function.call(&args))?;
+                } else {
+                    self.push_value(Value::Err("#VALUE!".to_string()))?;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads the cell a formula reference addresses: the evaluated cell with `overrides` applied.
+    fn load_reference(&self, overrides: &[(String, String)]) -> Value {
+        if let Some(spec) = self.spec {
+            if !spec.missing(&self.coordinate, overrides).is_empty() {
+                return Value::Err("#REF!".to_string());
+            }
         }
+        self.load_cell(overlay_hash(&self.coordinate, overrides))
+    }
+
+    /// Reads a cell; a cell that was never set reads as the VM's `MissingPolicy` (Empty by default).
+    /// Date cells read as their Unix millis (exact up to 2^53 ms) for YEAR/MONTH/... and arithmetic.
+    fn load_cell(&self, hash: u128) -> Value {
+        let stored = self.arena.and_then(|arena| {
+            arena.get_cell_opt(hash).or_else(|| arena.get_date(hash).map(|millis| millis as f64))
+        });
+        match stored {
+            Some(value) => Value::Num(value),
+            None => self.missing.value(),
+        }
+    }
+
+    fn current_period(&self, periods: &dyn PeriodResolver) -> Option<String> {
+        self.coordinate
+            .iter()
+            .find(|(d, _)| d == periods.dimension())
+            .map(|(_, m)| m.clone())
+    }
+
+    /// Pushes the referenced cell `offset` periods away. An anchored shift starts from the
+    /// anchor instead of the evaluated cell's period.
+    fn period_offset(&mut self, idx: usize, offset: i64) -> Result<(), InterpretResult> {
+        let missing = InterpretResult::RuntimeError(RuntimeFault::MissingPeriodContext);
+        let periods = self.periods.ok_or(missing.clone())?;
+        let current = match self.anchor.take() {
+            Some(anchor) => anchor,
+            None => self.current_period(periods).ok_or(missing)?,
+        };
+
+        let value = match periods.shift(&current, offset) {
+            Some(period) => {
+                let mut overrides = self.coordinate_overrides(idx)?.clone();
+                overrides.push((periods.dimension().to_string(), period));
+                self.load_reference(&overrides)
+            }
+            None => Value::Empty,
+        };
+        self.push_value(value)
+    }
+
+    /// Pops two operands and applies `f` (see `arith_values`).
+    fn binary_arith(&mut self, op: OpCode, f: impl Fn(f64, f64) -> f64) -> Result<(), InterpretResult> {
+        let b = self.pop_value()?;
+        let a = self.pop_value()?;
+        let result = arith_values(op, &a, &b, f)?;
+        self.push_value(result)
+    }
+
+    /// Pops two operands and compares them (see `compare_values`).
+    fn compare(&mut self, op: OpCode) -> Result<(), InterpretResult> {
+        let b = self.pop_value()?;
+        let a = self.pop_value()?;
+        let result = compare_values(op, &a, &b)?;
+        self.push_value(result)
+    }
+
+    /// Pops `count` operands and pushes their SUM/AVG/MIN/MAX (see `aggregate_values`).
+    fn aggregate(&mut self, kind: AggregateKind, count: usize) -> Result<(), InterpretResult> {
+        let operands = self.pop_aggregate(count)?;
+        self.push_value(aggregate_values(kind, operands.iter().copied(), self.strict_math))
+    }
+
+    /// Pops `count` aggregation operands (last operand first), skipping Empty slots
+    /// (e.g. filtered-out members).
+    fn pop_aggregate(&mut self, count: usize) -> Result<Vec<f64>, InterpretResult> {
+        let mut operands = Vec::with_capacity(count);
+        for _ in 0..count {
+            if let Some(v) = aggregate_operand(self.pop_value()?)? {
+                operands.push(v);
+            }
+        }
+        Ok(operands)
+    }
+
+    fn string(&self, idx: usize) -> Result<String, InterpretResult> {
+        self.chunk.strings.get(idx).cloned()
+            .ok_or(InterpretResult::RuntimeError(RuntimeFault::BadStringIndex(idx)))
+    }
+
+    fn read_constant(&self, idx: usize) -> Result<f64, InterpretResult> {
+        self.chunk.constants.get(idx).copied()
+            .ok_or(InterpretResult::RuntimeError(RuntimeFault::BadConstantIndex(idx)))
+    }
+
+    fn coordinate_overrides(&self, idx: usize) -> Result<&Vec<(String, String)>, InterpretResult> {
+        self.chunk.coordinates.get(idx)
+            .ok_or(InterpretResult::RuntimeError(RuntimeFault::BadCoordinateIndex(idx)))
     }
 
     fn push(&mut self, value: f64) -> Result<(), InterpretResult> {
-        if self.stack.len() >= 256 {
-            return Err(InterpretResult::RuntimeError); // Stack Overflow Protection
+        self.push_value(Value::Num(value))
+    }
+
+    fn push_value(&mut self, value: Value) -> Result<(), InterpretResult> {
+        if self.stack.len() >= self.stack_size {
+            return Err(InterpretResult::RuntimeError(RuntimeFault::StackOverflow)); // Stack Overflow Protection
+        }
+        // Only a chunk with spans can locate the error, so skip the bookkeeping otherwise
+        if !self.chunk.spans.is_empty() && self.error_at.is_none() && matches!(value, Value::Err(_)) {
+            self.error_at = Some(self.at);
         }
         self.stack.push(value);
         Ok(())
     }
 
-    fn pop(&mut self) -> f64 {
-        self.stack.pop().expect("Stack underflow")
+    /// Pushes the result of a math function, or its domain error as an error value.
+    fn push_math(&mut self, result: Result<f64, &'static str>) -> Result<(), InterpretResult> {
+        match result {
+            Ok(v) => self.push(v),
+            Err(e) => self.push_value(Value::Err(e.to_string())),
+        }
+    }
+
+    /// Pops a numeric operand. Booleans coerce to 1/0, an error value aborts the
+    /// evaluation with that error, and text is a type error.
+    fn pop(&mut self) -> Result<f64, InterpretResult> {
+        match self.pop_value()? {
+            Value::Err(e) => Err(InterpretResult::ErrorValue(e)),
+            other => other.coerce_num().ok_or_else(|| type_error("numeric operand", &other)),
+        }
+    }
+
+    fn pop_value(&mut self) -> Result<Value, InterpretResult> {
+        self.stack.pop().ok_or(InterpretResult::RuntimeError(RuntimeFault::StackUnderflow))
+    }
+}
+
+/// The value a formula result converts to (what `OpCode::Return` produces).
+impl From<Value> for InterpretResult {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Num(n) => InterpretResult::Ok(n),
+            Value::Bool(b) => InterpretResult::Ok(if b { 1.0 } else { 0.0 }),
+            Value::Text(s) => InterpretResult::Text(s),
+            Value::Err(e) => InterpretResult::ErrorValue(e),
+            Value::Empty => InterpretResult::Ok(0.0),
+        }
+    }
+}
+
+// The value-level semantics of the VM's operators, shared with the tree-walking interpreter
+// (`Expr::eval_direct`) so both evaluation paths agree exactly.
+
+/// Arithmetic on tagged operands. Numbers take the fast path; booleans coerce to 1/0,
+/// an error operand propagates, and text operands are a type error.
+pub(crate) fn arith_values(op: OpCode, a: &Value, b: &Value, f: impl Fn(f64, f64) -> f64) -> Result<Value, InterpretResult> {
+    match (a, b) {
+        (Value::Num(x), Value::Num(y)) => Ok(Value::Num(f(*x, *y))),
+        (Value::Err(e), _) | (_, Value::Err(e)) => Ok(Value::Err(e.clone())),
+        _ => match (a.coerce_num(), b.coerce_num()) {
+            (Some(x), Some(y)) => Ok(Value::Num(f(x, y))),
+            _ => Err(InterpretResult::TypeError(format!(
+                "cannot apply {:?} to {} and {}", op, a.type_name(), b.type_name()
+            ))),
+        },
+    }
+}
+
+/// Comparisons: numbers (and booleans) compare numerically, text compares lexically.
+/// Mixed text/number operands are never equal, and cannot be ordered.
+pub(crate) fn compare_values(op: OpCode, a: &Value, b: &Value) -> Result<Value, InterpretResult> {
+    if let (Value::Err(e), _) | (_, Value::Err(e)) = (a, b) {
+        return Ok(Value::Err(e.clone()));
+    }
+    let ordering = match (a, b) {
+        (Value::Text(x), Value::Text(y)) => Some(x.cmp(y)),
+        _ => match (a.coerce_num(), b.coerce_num()) {
+            (Some(x), Some(y)) => x.partial_cmp(&y),
+            _ => None,
+        },
+    };
+    let mixed = a.coerce_num().is_some() != b.coerce_num().is_some();
+    let result = match op {
+        OpCode::Equal => values_equal(a, b),
+        OpCode::NotEqual => !values_equal(a, b),
+        _ if mixed => {
+            return Err(InterpretResult::TypeError(format!(
+                "cannot compare {} with {}", a.type_name(), b.type_name()
+            )))
+        }
+        OpCode::Less => ordering == Some(std::cmp::Ordering::Less),
+        OpCode::LessEqual => matches!(ordering, Some(std::cmp::Ordering::Less | std::cmp::Ordering::Equal)),
+        OpCode::Greater => ordering == Some(std::cmp::Ordering::Greater),
+        _ => matches!(ordering, Some(std::cmp::Ordering::Greater | std::cmp::Ordering::Equal)),
+    };
+    Ok(Value::Bool(result))
+}
+
+/// How an aggregation reads one operand: None for an Empty slot (skipped), an error
+/// propagates, text is a type error.
+pub(crate) fn aggregate_operand(value: Value) -> Result<Option<f64>, InterpretResult> {
+    match value {
+        Value::Empty => Ok(None),
+        Value::Err(e) => Err(InterpretResult::ErrorValue(e)),
+        other => other.coerce_num().map(Some).ok_or_else(|| type_error("numeric operand", &other)),
     }
 }
 
+/// SUM/AVG/MIN/MAX over the non-empty operands, in the order the VM pops them (last first),
+/// since the order affects floating-point rounding. MIN/MAX skip NaN operands, or return NaN
+/// if there is one under `strict_math`; with no operands left they are `#N/A`, as when the
+/// compiler sees an empty operand set.
+pub(crate) fn aggregate_values<I>(kind: AggregateKind, operands: I, strict_math: bool) -> Value
+where
+    I: ExactSizeIterator<Item = f64> + Clone,
+{
+    match kind {
+        AggregateKind::Sum => Value::Num(operands.sum()),
+        AggregateKind::Avg if operands.len() == 0 => Value::Err("#DIV/0!".to_string()),
+        AggregateKind::Avg => {
+            let count = operands.len() as f64;
+            Value::Num(operands.sum::<f64>() / count)
+        }
+        AggregateKind::Min | AggregateKind::Max if strict_math && operands.clone().any(|v| v.is_nan()) => Value::Num(f64::NAN),
+        // f64::min/max return the other operand when one is NaN, whatever the order
+        AggregateKind::Min => min_max_result(operands.fold(f64::NAN, f64::min)),
+        AggregateKind::Max => min_max_result(operands.fold(f64::NAN, f64::max)),
+    }
+}
+
+/// A MIN/MAX fold seeded with NaN is still NaN only when it saw no number.
+fn min_max_result(folded: f64) -> Value {
+    if folded.is_nan() { Value::Err("#N/A".to_string()) } else { Value::Num(folded) }
+}
+
+/// How IF (`JumpIfFalse`) reads its condition: numbers are true when non-zero, an empty cell is
+/// false, an error propagates and text is a type error.
+pub(crate) fn truthy(condition: Value) -> Result<bool, InterpretResult> {
+    match condition {
+        Value::Bool(b) => Ok(b),
+        Value::Num(n) => Ok(n != 0.0),
+        Value::Empty => Ok(false),
+        Value::Err(e) => Err(InterpretResult::ErrorValue(e)),
+        other => Err(type_error("condition", &other)),
+    }
+}
+
+/// Equality shared by `==`, `!=` and `IN`: text compares exactly, numbers and booleans
+/// compare numerically, and mixed text/number operands are never equal.
+/// An empty cell equals both 0 and "" (spreadsheet semantics).
+fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Text(x), Value::Text(y)) => x == y,
+        (Value::Empty, Value::Text(t)) | (Value::Text(t), Value::Empty) => t.is_empty(),
+        _ => match (a.coerce_num(), b.coerce_num()) {
+            (Some(x), Some(y)) => x == y,
+            _ => false,
+        },
+    }
+}
+
+/// Position of the key XLOOKUP picks for `value`, scanning `keys` in `search` order.
+/// An exact match always wins; the approximate modes otherwise take the closest key on their
+/// side, numbers only. Empty keys (cells that were never set) never match.
+fn xlookup_index(value: &Value, keys: &[Value], mode: MatchMode, search: SearchMode) -> Option<usize> {
+    let order: Box<dyn Iterator<Item = usize>> = match search {
+        SearchMode::FirstToLast => Box::new(0..keys.len()),
+        SearchMode::LastToFirst => Box::new((0..keys.len()).rev()),
+    };
+    let target = value.as_num();
+    let mut closest: Option<(usize, f64)> = None;
+    for idx in order {
+        let key = &keys[idx];
+        if matches!(key, Value::Empty) {
+            continue;
+        }
+        if values_equal(value, key) {
+            return Some(idx);
+        }
+        let (Some(target), Some(k)) = (target, key.as_num()) else {
+            continue;
+        };
+        let closer = match mode {
+            MatchMode::Exact => false,
+            MatchMode::ExactOrNextSmaller => k < target && closest.is_none_or(|(_, best)| k > best),
+            MatchMode::ExactOrNextLarger => k > target && closest.is_none_or(|(_, best)| k < best),
+        };
+        if closer {
+            closest = Some((idx, k));
+        }
+    }
+    closest.map(|(idx, _)| idx)
+}
+
+/// Extracts a calendar component from Unix milliseconds in UTC.
+/// Returns None for non-finite or out-of-range timestamps.
+fn date_part(millis: f64, kind: DatePartKind) -> Option<f64> {
+    if !millis.is_finite() {
+        return None;
+    }
+    let date = NaiveDateTime::from_timestamp_millis(millis as i64)?;
+    let part = match kind {
+        DatePartKind::Year => date.year() as f64,
+        DatePartKind::Month => date.month() as f64,
+        DatePartKind::Day => date.day() as f64,
+        DatePartKind::Quarter => ((date.month() - 1) / 3 + 1) as f64,
+    };
+    Some(part)
+}
+
+fn type_error(expected: &str, got: &Value) -> InterpretResult {
+    InterpretResult::TypeError(format!("expected {}, got {}", expected, got.type_name()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -208,4 +1222,204 @@ mod tests {
             panic!("YTD Shift failed");
         }
     }
+
+    #[test]
+    fn test_tagged_stack_type_checks() {
+        // "a" + 1 is a runtime type error rather than a silent coercion
+        let mut chunk = Chunk::new();
+        let s = chunk.add_string("a");
+        let n = chunk.add_constant(1.0);
+        chunk.write_chunk(OpCode::StringConstant(s));
+        chunk.write_chunk(OpCode::Constant(n));
+        chunk.write_chunk(OpCode::Add);
+        chunk.write_chunk(OpCode::Return);
+        assert!(matches!(VM::new(chunk).run(), InterpretResult::TypeError(_)));
+
+        // A string result surfaces as Text
+        let mut chunk = Chunk::new();
+        let s = chunk.add_string("Commentary");
+        chunk.write_chunk(OpCode::StringConstant(s));
+        chunk.write_chunk(OpCode::Return);
+        assert_eq!(VM::new(chunk).run(), InterpretResult::Text("Commentary".to_string()));
+
+        // Comparisons produce Bool, surfaced through Ok as 1.0/0.0
+        let mut chunk = Chunk::new();
+        let a = chunk.add_string("USA");
+        let b = chunk.add_string("USA");
+        chunk.write_chunk(OpCode::StringConstant(a));
+        chunk.write_chunk(OpCode::StringConstant(b));
+        chunk.write_chunk(OpCode::Equal);
+        chunk.write_chunk(OpCode::Return);
+        assert_eq!(VM::new(chunk).run(), InterpretResult::Ok(1.0));
+    }
+
+    #[test]
+    fn test_out_of_range_pool_indices_fail_cleanly() {
+        let mut chunk = Chunk::new();
+        chunk.write_chunk(OpCode::Constant(99));
+        chunk.write_chunk(OpCode::Return);
+        assert_eq!(
+            VM::new(chunk).run(),
+            InterpretResult::RuntimeError(RuntimeFault::BadConstantIndex(99))
+        );
+
+        for (op, fault) in [
+            (OpCode::StringConstant(3), RuntimeFault::BadStringIndex(3)),
+            (OpCode::ErrorConstant(4), RuntimeFault::BadStringIndex(4)),
+            (OpCode::LoadDimension(5), RuntimeFault::BadCoordinateIndex(5)),
+            (OpCode::Jump(42), RuntimeFault::BadInstructionPointer(42)),
+            // Instructions that pop from an empty stack
+            (OpCode::Pop, RuntimeFault::StackUnderflow),
+            (OpCode::IsBlank, RuntimeFault::StackUnderflow),
+            (OpCode::Lookup, RuntimeFault::StackUnderflow),
+            (OpCode::XLookup(1, MatchMode::Exact, SearchMode::FirstToLast), RuntimeFault::StackUnderflow),
+        ] {
+            let mut chunk = Chunk::new();
+            chunk.write_chunk(op);
+            chunk.write_chunk(OpCode::Return);
+            assert_eq!(VM::new(chunk).run(), InterpretResult::RuntimeError(fault));
+        }
+
+        // Untrusted chunks are rejected before they run
+        let mut chunk = Chunk::new();
+        chunk.write_chunk(OpCode::Constant(99));
+        chunk.write_chunk(OpCode::Return);
+        assert_eq!(VM::try_new(chunk).err(), Some(ValidationError::BadConstantIndex { at: 0, index: 99 }));
+    }
+
+    #[test]
+    fn test_fast_dispatch_matches_safe_path() {
+        use crate::atom_script::compiler::Compiler;
+        use crate::atom_script::parser::Parser;
+
+        let arena = LatticeArena::new(16);
+        arena.set_cell(coordinate_hash(&[("Measure", "A")]), 3.0);
+        let formulas = ["[A] * 2 + 1", "IF([A] > 2, SUM([A], 4, 5), 0)", r#"CONCAT("A=", [A])"#, "[B] / [A]"];
+
+        for formula in formulas {
+            let mut parser = Parser::new(formula);
+            let chunk = Arc::new(Compiler::new().compile(&parser.parse().expect("Parse failed")));
+            let mut fast = VM::new(Arc::clone(&chunk)).with_arena(&arena);
+            let mut safe = VM::new(Arc::clone(&chunk)).with_arena(&arena);
+            let mut bytes = VM::new(Arc::clone(&chunk)).with_arena(&arena).with_bytecode();
+            assert!(fast.fast_dispatch && bytes.bytecode.is_some());
+            // Compare the dispatch modes of the tagged path
+            for vm in [&mut fast, &mut safe, &mut bytes] {
+                vm.numeric_only = false;
+            }
+            safe.fast_dispatch = false;
+            let expected = safe.run();
+            assert_eq!(fast.run(), expected, "{}", formula);
+            assert_eq!(bytes.run(), expected, "{}", formula);
+        }
+
+        // A chunk that fails validation falls back to checked fetches and reports the fault
+        let mut chunk = Chunk::new();
+        chunk.write_chunk(OpCode::Jump(7));
+        let mut vm = VM::new(chunk).with_bytecode();
+        assert!(!vm.fast_dispatch && vm.bytecode.is_none());
+        assert_eq!(vm.run(), InterpretResult::RuntimeError(RuntimeFault::BadInstructionPointer(7)));
+    }
+
+    #[test]
+    #[ignore] // Timing only: cargo test --release -- --ignored test_fast_dispatch_timing --nocapture
+    fn test_fast_dispatch_timing() {
+        use crate::atom_script::compiler::{Compiler, CompilerOptions};
+        use crate::atom_script::parser::Parser;
+        use std::time::Instant;
+
+        // Literal arithmetic with folding off, so instruction dispatch dominates the run time
+        let formula = (1..=500).map(|i| format!("3 * {}", i)).collect::<Vec<_>>().join(" + ");
+        let options = CompilerOptions { fold_constants: false, ..CompilerOptions::default() };
+        let parsed = Parser::new(&formula).parse().expect("Parse failed");
+        let chunk = Arc::new(Compiler::new().with_options(options).compile(&parsed));
+        let expected = InterpretResult::Ok(3.0 * 500.0 * 501.0 / 2.0);
+
+        // Printed rather than asserted: the difference is small next to run-to-run noise
+        for fast_dispatch in [false, true] {
+            let mut vm = VM::new(Arc::clone(&chunk));
+            vm.fast_dispatch = fast_dispatch;
+            vm.numeric_only = false;
+            let start = Instant::now();
+            for _ in 0..5_000 {
+                vm.reset();
+                assert_eq!(vm.run(), expected);
+            }
+            println!("[BENCH] {} instructions, fast_dispatch={}: 5k evaluations in {:?}", chunk.code.len(), fast_dispatch, start.elapsed());
+        }
+    }
+
+    #[test]
+    fn test_numeric_path_matches_tagged_path() {
+        use crate::atom_script::compiler::Compiler;
+        use crate::atom_script::parser::Parser;
+
+        let arena = LatticeArena::new(16);
+        arena.set_cell(coordinate_hash(&[("Measure", "A")]), 3.0);
+        arena.set_cell(coordinate_hash(&[("Measure", "Zero")]), 0.0);
+        arena.set_cell(coordinate_hash(&[("Measure", "Gap")]), f64::NAN);
+        let compile = |formula: &str| Arc::new(Compiler::new().compile(&Parser::new(formula).parse().expect("Parse failed")));
+
+        // Last three: an empty cell and a zero divisor (cell and literal)
+        let formulas = [
+            "[A] * 2 + 1",
+            "([A] + 1) * ([A] - 1) / 2 + [A] * 3",
+            "[A] % 2 - 7 / [A]",
+            "SUM([A], 4, 5) + AVG([A], 1) * MAX(1, [A], 2) - MIN([A], 2)",
+            "MIN(1, [Gap], 2) + [Gap]",
+            "[B] + 1",
+            "[A] / [Zero]",
+            "AVG([A], 1) % 0",
+        ];
+        for formula in formulas {
+            let chunk = compile(formula);
+            assert!(chunk.is_numeric_only(), "{}", formula);
+            let mut tagged = VM::new(Arc::clone(&chunk)).with_arena(&arena);
+            tagged.numeric_only = false;
+            let expected = tagged.run();
+            let actual = VM::new(Arc::clone(&chunk)).with_arena(&arena).run();
+            match (&expected, &actual) {
+                (InterpretResult::Ok(e), InterpretResult::Ok(a)) if e.is_nan() => assert!(a.is_nan(), "{}", formula),
+                _ => assert_eq!(actual, expected, "{}", formula),
+            }
+        }
+        let overflow = compile(&format!("SUM({})", ["[A]"; 8].join(", ")));
+        assert_eq!(VM::with_stack_size(Arc::clone(&overflow), 4).with_arena(&arena).run(), InterpretResult::RuntimeError(RuntimeFault::StackOverflow));
+        assert_eq!(VM::with_stack_size(overflow, 8).with_arena(&arena).run(), InterpretResult::Ok(24.0));
+
+        for formula in [r#"CONCAT("A=", [A])"#, "IF([A] > 2, 1, 0)", "[A] > 2", "SQRT([A])"] {
+            assert!(!compile(formula).is_numeric_only(), "{}", formula);
+        }
+
+    }
+
+    #[test]
+    #[ignore] // Timing only: cargo test --release -- --ignored test_numeric_path_timing --nocapture
+    fn test_numeric_path_timing() {
+        use crate::atom_script::compiler::Compiler;
+        use crate::atom_script::parser::Parser;
+        use std::time::Instant;
+
+        let arena = LatticeArena::new(16);
+        arena.set_cell(coordinate_hash(&[("Measure", "A")]), 3.0);
+        let formula = (1..=200).map(|i| format!("SUM([A], {}) * 2 - [A] / {}", i, i)).collect::<Vec<_>>().join(" + ");
+        let chunk = Arc::new(Compiler::new().compile(&Parser::new(&formula).parse().expect("Parse failed")));
+        assert!(chunk.is_numeric_only());
+
+        let expected = VM::new(Arc::clone(&chunk)).with_arena(&arena).run();
+        let mut timings = Vec::new();
+        for numeric_only in [false, true] {
+            let mut vm = VM::new(Arc::clone(&chunk)).with_arena(&arena);
+            vm.numeric_only = numeric_only;
+            let start = Instant::now();
+            for _ in 0..5_000 {
+                vm.reset();
+                assert_eq!(vm.run(), expected);
+            }
+            let elapsed = start.elapsed();
+            println!("[BENCH] {} instructions, numeric_only={}: 5k evaluations in {:?}", chunk.code.len(), numeric_only, elapsed);
+            timings.push(elapsed);
+        }
+        assert!(timings[1] < timings[0], "numeric path took {:?}, tagged {:?}", timings[1], timings[0]);
+    }
 }
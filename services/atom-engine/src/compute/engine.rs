@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use rayon::prelude::*;
+use crate::atom_script::chunk::Chunk;
+use crate::atom_script::vm::{InterpretResult, VM};
+use crate::compute::graph::DependencyGraph;
+use crate::lattice::arena::LatticeArena;
+use crate::lattice::coordinate::{overlay_hash, DEFAULT_REF_DIMENSION};
+
+/// Outcome of a recalculation. Failed formulas leave their target cell untouched.
+#[derive(Debug, Default, PartialEq)]
+pub struct RecalcReport {
+    pub evaluated: usize,
+    pub failures: Vec<(String, InterpretResult)>,
+}
+
+/// The Engine orchestrates a full recalculation: it walks the dependency graph level by level
+/// and evaluates every formula of a level in parallel, writing results back to the arena
+/// before the next level starts.
+/// Graph node `N` is the cell `Measure=N` within the engine's coordinate context.
+#[derive(Default)]
+pub struct Engine {
+    coordinate: Vec<(String, String)>,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the coordinate (e.g. Region=USA, Time=Jan) the recalculation runs in.
+    pub fn with_coordinate(mut self, coordinate: Vec<(String, String)>) -> Self {
+        self.coordinate = coordinate;
+        self
+    }
+
+    /// Recalculates every node in `graph` that has a formula. Nodes without a formula are
+    /// inputs and are read as stored. Fails only if the graph has a cycle.
+    pub fn recalculate(
+        &self,
+        graph: &DependencyGraph,
+        formulas: &HashMap<String, Arc<Chunk>>,
+        arena: &LatticeArena,
+    ) -> Result<RecalcReport, String> {
+        let mut report = RecalcReport::default();
+
+        for level in graph.resolve_levels()? {
+            // Within a level no node depends on another, so evaluation order is irrelevant;
+            // all reads of this level see the writes of the previous levels only.
+            let results: Vec<(String, InterpretResult)> = level
+                .par_iter()
+                .filter_map(|node| formulas.get(node).map(|chunk| (node, chunk)))
+                .map(|(node, chunk)| {
+                    let target = vec![(DEFAULT_REF_DIMENSION.to_string(), node.clone())];
+                    let mut cell = self.coordinate.clone();
+                    cell.retain(|(d, _)| d != DEFAULT_REF_DIMENSION);
+                    cell.extend(target.iter().cloned());
+
+                    let result = VM::new(Arc::clone(chunk)).with_arena(arena).with_coordinate(cell).run();
+                    if let InterpretResult::Ok(value) = result {
+                        arena.set_computed(overlay_hash(&self.coordinate, &target), value);
+                    }
+                    (node.clone(), result)
+                })
+                .collect();
+
+            report.evaluated += results.len();
+            report.failures.extend(results.into_iter().filter(|(_, r)| !matches!(r, InterpretResult::Ok(_))));
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atom_script::compiler::Compiler;
+    use crate::atom_script::parser::Parser;
+    use crate::lattice::arena::COMPUTED_SOURCE;
+    use crate::lattice::coordinate::coordinate_hash;
+
+    fn compile(formula: &str) -> Arc<Chunk> {
+        let mut parser = Parser::new(formula);
+        Arc::new(Compiler::new().compile(&parser.parse().expect("Parse failed")))
+    }
+
+    #[test]
+    fn test_recalculate_dependency_chain() {
+        // Revenue (input) -> Tax = Revenue * 0.2 -> Net = Revenue - Tax
+        let mut graph = DependencyGraph::new();
+        graph.add_dependency("Tax", "Revenue");
+        graph.add_dependency("Net", "Tax");
+        graph.add_dependency("Net", "Revenue");
+
+        let formulas: HashMap<String, Arc<Chunk>> = [
+            ("Tax".to_string(), compile("[Revenue] * 0.2")),
+            ("Net".to_string(), compile("[Revenue] - [Tax]")),
+        ]
+        .into_iter()
+        .collect();
+
+        let arena = LatticeArena::new(64);
+        let cell = |measure: &str| coordinate_hash(&[("Region", "USA"), ("Measure", measure)]);
+        arena.set_cell(cell("Revenue"), 1000.0);
+
+        let engine = Engine::new().with_coordinate(vec![("Region".to_string(), "USA".to_string())]);
+        let report = engine.recalculate(&graph, &formulas, &arena).unwrap();
+
+        assert_eq!(report, RecalcReport { evaluated: 2, failures: vec![] });
+        assert_eq!(arena.get_cell(cell("Tax")), 200.0);
+        assert_eq!(arena.get_cell(cell("Net")), 800.0);
+        assert_eq!(arena.get_source(cell("Net")).as_deref(), Some(COMPUTED_SOURCE));
+    }
+
+    #[test]
+    fn test_levels_group_independent_nodes() {
+        let mut graph = DependencyGraph::new();
+        graph.add_dependency("B", "A");
+        graph.add_dependency("C", "A");
+        graph.add_dependency("D", "B");
+        graph.add_dependency("D", "C");
+
+        let mut levels = graph.resolve_levels().unwrap();
+        levels.iter_mut().for_each(|l| l.sort());
+        assert_eq!(levels, vec![vec!["A"], vec!["B", "C"], vec!["D"]]);
+
+        graph.add_dependency("A", "D");
+        assert!(graph.resolve_levels().is_err());
+    }
+}
@@ -1,6 +1,19 @@
 use petgraph::graph::{DiGraph, NodeIndex};
-use petgraph::algo::toposort;
-use std::collections::HashMap;
+use petgraph::Direction;
+use petgraph::algo::{tarjan_scc, toposort};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/// Result of `DependencyGraph::resolve_order_partial`.
+#[derive(Debug, Default, PartialEq)]
+pub struct PartialOrder {
+    /// Nodes that can be calculated, in execution order.
+    pub order: Vec<String>,
+    /// Each cycle's members, sorted by name.
+    pub cycles: Vec<Vec<String>>,
+    /// Nodes that depend (directly or not) on a cycle, in execution order.
+    pub blocked: Vec<String>,
+}
 
 /// The Dependency Graph tracks relationships between Atoms/Dimensions.
 /// e.g. "Net Income" -> "Tax" -> "Revenue"
@@ -9,6 +22,12 @@ pub struct DependencyGraph {
     node_map: HashMap<String, NodeIndex>,
 }
 
+impl Default for DependencyGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DependencyGraph {
     pub fn new() -> Self {
         Self {
@@ -50,4 +69,136 @@ impl DependencyGraph {
             Err(_) => Err("Cycle detected in dependency graph!".to_string()),
         }
     }
+
+    /// Like `resolve_order`, but a cycle does not fail the whole graph: returns every node
+    /// that can be calculated in execution order, plus each cycle (a strongly connected
+    /// component, including a node that depends on itself) with its members sorted by name.
+    /// Nodes downstream of a cycle would read its unresolved values, so they are returned
+    /// separately as blocked (in execution order) rather than scheduled.
+    pub fn resolve_order_partial(&self) -> PartialOrder {
+        let mut result = PartialOrder::default();
+        // Cycle members and everything depending on them
+        let mut unresolved = HashSet::new();
+        // Tarjan yields components in reverse topological order
+        for component in tarjan_scc(&self.graph).into_iter().rev() {
+            match component.as_slice() {
+                &[idx] if !self.graph.contains_edge(idx, idx) => {
+                    if self.graph.neighbors_directed(idx, Direction::Incoming).any(|dep| unresolved.contains(&dep)) {
+                        unresolved.insert(idx);
+                        result.blocked.push(self.graph[idx].clone());
+                    } else {
+                        result.order.push(self.graph[idx].clone());
+                    }
+                }
+                _ => {
+                    let mut names: Vec<String> = component.iter().map(|&idx| self.graph[idx].clone()).collect();
+                    names.sort();
+                    result.cycles.push(names);
+                    unresolved.extend(component);
+                }
+            }
+        }
+        result
+    }
+
+    /// Groups nodes into execution levels: every node's dependencies sit in earlier levels,
+    /// so the nodes within one level are independent and can be calculated in parallel.
+    pub fn resolve_levels(&self) -> Result<Vec<Vec<String>>, String> {
+        let order = toposort(&self.graph, None).map_err(|_| "Cycle detected in dependency graph!".to_string())?;
+
+        let mut level_of: HashMap<NodeIndex, usize> = HashMap::with_capacity(order.len());
+        let mut levels: Vec<Vec<String>> = Vec::new();
+        for idx in order {
+            let level = self
+                .graph
+                .neighbors_directed(idx, Direction::Incoming)
+                .map(|dep| level_of[&dep] + 1)
+                .max()
+                .unwrap_or(0);
+            level_of.insert(idx, level);
+            if levels.len() <= level {
+                levels.resize_with(level + 1, Vec::new);
+            }
+            levels[level].push(self.graph[idx].clone());
+        }
+        Ok(levels)
+    }
+
+    /// Renders the graph as Graphviz DOT for auditing (`dot -Tsvg`): one node per name and an
+    /// edge from each dependency to its dependent, in insertion order. Nodes and edges inside
+    /// a cycle (see `resolve_order_partial`) are drawn in red.
+    pub fn to_dot(&self) -> String {
+        // Node -> the cycle it belongs to
+        let mut cycle_of = HashMap::new();
+        for (cycle, component) in tarjan_scc(&self.graph).into_iter().enumerate() {
+            if component.len() > 1 || self.graph.contains_edge(component[0], component[0]) {
+                cycle_of.extend(component.into_iter().map(|idx| (idx, cycle)));
+            }
+        }
+        let quote = |idx: NodeIndex| format!("\"{}\"", self.graph[idx].replace('\\', "\\\\").replace('"', "\\\""));
+        let color = |cycle: bool| if cycle { " [color=red]" } else { "" };
+
+        let mut dot = String::from("digraph dependencies {\n");
+        for idx in self.graph.node_indices() {
+            let _ = writeln!(dot, "    {}{};", quote(idx), color(cycle_of.contains_key(&idx)));
+        }
+        for edge in self.graph.raw_edges() {
+            let (from, to) = (edge.source(), edge.target());
+            // Both ends in a cycle is not enough: the edge may join two different cycles
+            let in_cycle = cycle_of.get(&from).is_some_and(|cycle| cycle_of.get(&to) == Some(cycle));
+            let _ = writeln!(dot, "    {} -> {}{};", quote(from), quote(to), color(in_cycle));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dot_export_draws_edges_and_cycles() {
+        let mut graph = DependencyGraph::new();
+        graph.add_dependency("NetIncome", "Revenue");
+        graph.add_dependency("NetIncome", "Tax");
+        graph.add_dependency("Tax", "Rate \"Fed\"");
+        graph.add_dependency("Rate \"Fed\"", "Tax");
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph dependencies {\n") && dot.ends_with("}\n"), "{}", dot);
+        assert!(dot.contains("    \"Revenue\" -> \"NetIncome\";\n"), "{}", dot);
+        assert!(dot.contains("    \"Revenue\";\n"), "{}", dot);
+        // The Tax <-> Rate cycle is highlighted; the edge leaving it is not
+        assert!(dot.contains("    \"Tax\" [color=red];\n"), "{}", dot);
+        assert!(dot.contains("    \"Tax\" -> \"Rate \\\"Fed\\\"\" [color=red];\n"), "{}", dot);
+        assert!(dot.contains("    \"Tax\" -> \"NetIncome\";\n"), "{}", dot);
+    }
+
+    #[test]
+    fn test_partial_order_schedules_around_a_cycle() {
+        let mut graph = DependencyGraph::new();
+        for i in 1..50 {
+            graph.add_dependency(&format!("n{}", i), &format!("n{}", i - 1));
+        }
+        // A two-node cycle hanging off n10, and a self-reference
+        graph.add_dependency("A", "n10");
+        graph.add_dependency("B", "A");
+        graph.add_dependency("A", "B");
+        graph.add_dependency("C", "C");
+        // D reads the A/B cycle and E reads D, so neither can be calculated
+        graph.add_dependency("D", "B");
+        graph.add_dependency("E", "D");
+        graph.add_dependency("E", "n3");
+        assert!(graph.resolve_order().is_err());
+
+        let partial = graph.resolve_order_partial();
+        assert_eq!(partial.cycles.len(), 2);
+        assert!(partial.cycles.contains(&vec!["A".to_string(), "B".to_string()]));
+        assert!(partial.cycles.contains(&vec!["C".to_string()]));
+        assert_eq!(partial.blocked, ["D", "E"]);
+
+        let expected: Vec<String> = (0..50).map(|i| format!("n{}", i)).collect();
+        assert_eq!(partial.order, expected);
+    }
 }
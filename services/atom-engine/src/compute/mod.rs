@@ -1,2 +1,3 @@
 pub mod simd;
 pub mod graph;
+pub mod engine;
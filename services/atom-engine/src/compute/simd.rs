@@ -1,4 +1,32 @@
 use rayon::prelude::*;
+use thiserror::Error;
+
+/// How `proportional_spread` treats NaN/Inf inputs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NonFinitePolicy {
+    /// Substitute 0.0 for every non-finite input (a non-finite target spreads nothing).
+    Zero,
+    /// Refuse to spread and report the first offending input.
+    Reject,
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SpreadError {
+    #[error("non-finite spread target")]
+    NonFiniteTarget,
+    #[error("non-finite {field} at index {index}")]
+    NonFiniteInput { field: &'static str, index: usize },
+}
+
+// Output rows (input columns) handled per parallel task in `transpose`.
+const TRANSPOSE_BLOCK: usize = 64;
+
+// Elements compared per parallel task by the mask builders; each task runs a plain
+// compare loop that the compiler vectorizes.
+const MASK_BLOCK: usize = 4096;
+
+// Elements summed sequentially per parallel task by `kahan_sum`.
+const KAHAN_BLOCK: usize = 4096;
 
 /// VectorOps provides SIMD-accelerated arithmetic on standard vectors.
 /// We use Rayon to parallelize the loop, and the Rust compiler auto-vectorizes
@@ -40,24 +68,167 @@ impl VectorOps {
          .collect()
     }
 
+    /// Divides a by b element-wise, yielding `on_zero` wherever the divisor is zero (either
+    /// sign) instead of an infinity or NaN, for planning math such as growth rates over
+    /// empty prior periods. Other non-finite results are left as `div` produces them.
+    pub fn div_safe(a: &[f64], b: &[f64], on_zero: f64) -> Vec<f64> {
+        a.par_iter()
+         .zip(b.par_iter())
+         .map(|(x, &y)| if y == 0.0 { on_zero } else { x / y })
+         .collect()
+    }
+
     /// Calculates the validation checksum (Sum) of the vector.
+    /// Fast path: plain floating-point addition, so small values added to a large running
+    /// total are rounded away (`1e16 + 1.0 == 1e16`) and the result depends on how Rayon
+    /// splits the work. See `kahan_sum`.
     pub fn sum(a: &[f64]) -> f64 {
         a.par_iter().sum()
     }
 
+    /// Compensated (Kahan-Babuska-Neumaier) sum: each parallel block carries the rounding
+    /// error of its additions alongside its total, and the reduction combines the pairs the
+    /// same way, so the result is accurate to about one rounding of the true sum regardless of
+    /// magnitude spread or thread count.
+    /// Prefer it over `sum` when the total is compared or reconciled (checksums, control
+    /// totals, allocations that must tie out) or when values span many orders of magnitude;
+    /// it costs a few extra flops per element, so `sum` remains the default for display totals.
+    pub fn kahan_sum(a: &[f64]) -> f64 {
+        a.par_chunks(KAHAN_BLOCK)
+            .map(|block| {
+                let mut sum = CompensatedSum::new();
+                sum.add_all(block);
+                sum
+            })
+            .reduce(CompensatedSum::new, |mut acc, block| {
+                acc.merge(block);
+                acc
+            })
+            .total()
+    }
+
+    // Masks for FILTER/SUMIF and for `proportional_spread`'s `is_locked`. Comparisons follow
+    // IEEE semantics: a NaN element fails every test except `ne_scalar`.
+
+    /// `a[i] > threshold` for every element.
+    pub fn gt_scalar(a: &[f64], threshold: f64) -> Vec<bool> {
+        Self::mask(a, |x| x > threshold)
+    }
+
+    /// `a[i] >= threshold` for every element.
+    pub fn ge_scalar(a: &[f64], threshold: f64) -> Vec<bool> {
+        Self::mask(a, |x| x >= threshold)
+    }
+
+    /// `a[i] < threshold` for every element.
+    pub fn lt_scalar(a: &[f64], threshold: f64) -> Vec<bool> {
+        Self::mask(a, |x| x < threshold)
+    }
+
+    /// `a[i] <= threshold` for every element.
+    pub fn le_scalar(a: &[f64], threshold: f64) -> Vec<bool> {
+        Self::mask(a, |x| x <= threshold)
+    }
+
+    /// `a[i] == value` for every element (exact comparison).
+    pub fn eq_scalar(a: &[f64], value: f64) -> Vec<bool> {
+        Self::mask(a, |x| x == value)
+    }
+
+    /// `a[i] != value` for every element (exact comparison).
+    pub fn ne_scalar(a: &[f64], value: f64) -> Vec<bool> {
+        Self::mask(a, |x| x != value)
+    }
+
+    fn mask(a: &[f64], predicate: impl Fn(f64) -> bool + Sync) -> Vec<bool> {
+        let mut out = vec![false; a.len()];
+        out.par_chunks_mut(MASK_BLOCK)
+            .zip(a.par_chunks(MASK_BLOCK))
+            .for_each(|(out, a)| {
+                for (o, &x) in out.iter_mut().zip(a) {
+                    *o = predicate(x);
+                }
+            });
+        out
+    }
+
+    /// Transposes a row-major `rows` x `cols` matrix into a row-major `cols` x `rows` one
+    /// (equivalently, converts column-major data to row-major for `matvec` style kernels).
+    /// Parallelized over blocks of output rows; each task streams contiguous input row segments.
+    /// Panic: `matrix.len()` must equal `rows * cols`.
+    pub fn transpose(matrix: &[f64], rows: usize, cols: usize) -> Vec<f64> {
+        assert_eq!(
+            rows.checked_mul(cols),
+            Some(matrix.len()),
+            "transpose: {} elements do not form a {}x{} matrix",
+            matrix.len(),
+            rows,
+            cols
+        );
+        let mut out = vec![0.0; matrix.len()];
+        if out.is_empty() {
+            return out;
+        }
+
+        out.par_chunks_mut(rows * TRANSPOSE_BLOCK)
+            .enumerate()
+            .for_each(|(block, chunk)| {
+                let first_col = block * TRANSPOSE_BLOCK;
+                let block_cols = chunk.len() / rows;
+                for i in 0..rows {
+                    let start = i * cols + first_col;
+                    for (k, &v) in matrix[start..start + block_cols].iter().enumerate() {
+                        chunk[k * rows + i] = v;
+                    }
+                }
+            });
+        out
+    }
+
     /// Spreads a `target` value proportionally across cells based on `reference_values`.
     /// Respects the `is_locked` bitmask to prevent overwriting explicit bottom-up entries.
     /// The remaining target is spread across the unlocked cells.
+    /// Non-finite inputs are zeroed (`NonFinitePolicy::Zero`), so the result is always finite.
     pub fn proportional_spread(
         target: f64, 
         current_values: &[f64], 
         reference_values: &[f64], 
         is_locked: &[bool]
     ) -> Vec<f64> {
+        Self::proportional_spread_checked(target, current_values, reference_values, is_locked, NonFinitePolicy::Zero)
+            .expect("Zero policy never rejects")
+    }
+
+    /// `proportional_spread` with an explicit policy for NaN/Inf inputs.
+    /// A single NaN weight would otherwise poison `unlocked_ref_sum` and every output cell.
+    pub fn proportional_spread_checked(
+        target: f64,
+        current_values: &[f64],
+        reference_values: &[f64],
+        is_locked: &[bool],
+        policy: NonFinitePolicy,
+    ) -> Result<Vec<f64>, SpreadError> {
+        let target = match (target.is_finite(), policy) {
+            (true, _) => target,
+            (false, NonFinitePolicy::Zero) => 0.0,
+            (false, NonFinitePolicy::Reject) => return Err(SpreadError::NonFiniteTarget),
+        };
+
+        if policy == NonFinitePolicy::Reject {
+            if let Some(index) = current_values.par_iter().position_first(|v| !v.is_finite()) {
+                return Err(SpreadError::NonFiniteInput { field: "current value", index });
+            }
+            if let Some(index) = reference_values.par_iter().position_first(|v| !v.is_finite()) {
+                return Err(SpreadError::NonFiniteInput { field: "reference value", index });
+            }
+        }
+
+        let finite = |v: f64| if v.is_finite() { v } else { 0.0 };
+
         // Step 1: Calculate the total locked value that has already been spoken for.
         let locked_sum: f64 = current_values.par_iter()
             .zip(is_locked.par_iter())
-            .filter_map(|(&val, &locked)| if locked { Some(val) } else { None })
+            .filter_map(|(&val, &locked)| if locked { Some(finite(val)) } else { None })
             .sum();
 
         let remaining_target = target - locked_sum;
@@ -65,25 +236,372 @@ impl VectorOps {
         // Step 2: Calculate the total reference weight of the UNLOCKED cells.
         let unlocked_ref_sum: f64 = reference_values.par_iter()
             .zip(is_locked.par_iter())
-            .filter_map(|(&ref_val, &locked)| if !locked { Some(ref_val) } else { None })
+            .filter_map(|(&ref_val, &locked)| if !locked { Some(finite(ref_val)) } else { None })
             .sum();
 
         // Avoid divide by zero if all unlocked reference cells sum to 0
-        let safe_ref_sum = if unlocked_ref_sum == 0.0 { 1.0 } else { unlocked_ref_sum };
+        let safe_ref_sum = if unlocked_ref_sum == 0.0 || !unlocked_ref_sum.is_finite() { 1.0 } else { unlocked_ref_sum };
 
         // Step 3: Compute the new values in a single wait-free parallel pass.
-        current_values.par_iter()
+        Ok(current_values.par_iter()
             .zip(reference_values.par_iter())
             .zip(is_locked.par_iter())
             .map(|((&cur, &ref_val), &locked)| {
-                if locked {
-                    cur // Keep the explicit bottom-up entry
+                let out = if locked {
+                    finite(cur) // Keep the explicit bottom-up entry
                 } else if unlocked_ref_sum == 0.0 {
                     0.0 // Could distribute evenly here, but default to 0 to prevent Sparsity Explosion
                 } else {
-                    (ref_val / safe_ref_sum) * remaining_target
-                }
+                    (finite(ref_val) / safe_ref_sum) * remaining_target
+                };
+                finite(out) // Overflowing weights must not leak Inf into the plan
+            })
+            .collect())
+    }
+
+    /// `proportional_spread` that gives every unlocked cell at least `min_alloc`: the floors
+    /// are reserved first and only what is left of the target is spread by weight (evenly if
+    /// the unlocked weights sum to 0, since every cell is non-zero anyway).
+    /// If the floors alone exceed what the locked cells leave of the target, the floor cannot
+    /// be honoured and the remainder is shared equally instead, so the total always matches.
+    /// Non-finite inputs are zeroed as in `proportional_spread`.
+    pub fn proportional_spread_with_floor(
+        target: f64,
+        current_values: &[f64],
+        reference_values: &[f64],
+        is_locked: &[bool],
+        min_alloc: f64,
+    ) -> Vec<f64> {
+        let finite = |v: f64| if v.is_finite() { v } else { 0.0 };
+        let (target, min_alloc) = (finite(target), finite(min_alloc));
+
+        // One pass for the locked total, the unlocked weight and the unlocked count
+        let (locked_sum, unlocked_ref_sum, unlocked) = current_values.par_iter()
+            .zip(reference_values.par_iter())
+            .zip(is_locked.par_iter())
+            .map(|((&cur, &ref_val), &locked)| {
+                if locked { (finite(cur), 0.0, 0usize) } else { (0.0, finite(ref_val), 1) }
+            })
+            .reduce(|| (0.0, 0.0, 0), |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2));
+
+        let remaining_target = target - locked_sum;
+        let floors = min_alloc * unlocked as f64;
+        let (floor, surplus) = if floors <= remaining_target {
+            (min_alloc, remaining_target - floors)
+        } else {
+            (remaining_target / unlocked.max(1) as f64, 0.0)
+        };
+        let even = unlocked_ref_sum == 0.0 || !unlocked_ref_sum.is_finite();
+
+        current_values.par_iter()
+            .zip(reference_values.par_iter())
+            .zip(is_locked.par_iter())
+            .map(|((&cur, &ref_val), &locked)| {
+                let out = if locked {
+                    finite(cur)
+                } else if even {
+                    floor + surplus / unlocked as f64
+                } else {
+                    floor + (finite(ref_val) / unlocked_ref_sum) * surplus
+                };
+                finite(out)
             })
             .collect()
     }
 }
+
+/// Running compensated sum (the accumulator behind `VectorOps::kahan_sum`), for totals built
+/// up incrementally, e.g. over streamed batches. Adding the same values in the same order
+/// always gives the same bits, so a receiver can recompute a sender's total exactly.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CompensatedSum {
+    sum: f64,
+    compensation: f64, // Low-order bits the additions into `sum` lost
+}
+
+impl CompensatedSum {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, x: f64) {
+        let total = self.sum + x;
+        self.compensation += if self.sum.abs() >= x.abs() { (self.sum - total) + x } else { (x - total) + self.sum };
+        self.sum = total;
+    }
+
+    pub fn add_all(&mut self, values: &[f64]) {
+        for &x in values {
+            self.add(x);
+        }
+    }
+
+    /// Folds in a sum accumulated separately (e.g. on another thread).
+    pub fn merge(&mut self, other: CompensatedSum) {
+        self.add(other.sum);
+        self.compensation += other.compensation;
+    }
+
+    pub fn total(&self) -> f64 {
+        self.sum + self.compensation
+    }
+}
+
+/// ComputeContext confines VectorOps to a dedicated Rayon pool instead of the global one,
+/// so an embedding application can bound how many cores the engine may use.
+pub struct ComputeContext {
+    pool: rayon::ThreadPool,
+}
+
+impl ComputeContext {
+    /// Builds a context backed by a new pool of `num_threads` workers.
+    pub fn new(num_threads: usize) -> Result<Self, rayon::ThreadPoolBuildError> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .thread_name(|i| format!("atom-compute-{}", i))
+            .build()?;
+        Ok(Self { pool })
+    }
+
+    /// Wraps an existing pool owned by the host application.
+    pub fn from_pool(pool: rayon::ThreadPool) -> Self {
+        Self { pool }
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Runs `op` inside the context's pool; any Rayon work it spawns stays on that pool.
+    pub fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        self.pool.install(op)
+    }
+
+    pub fn add(&self, a: &[f64], b: &[f64]) -> Vec<f64> {
+        self.install(|| VectorOps::add(a, b))
+    }
+
+    pub fn sub(&self, a: &[f64], b: &[f64]) -> Vec<f64> {
+        self.install(|| VectorOps::sub(a, b))
+    }
+
+    pub fn mul(&self, a: &[f64], b: &[f64]) -> Vec<f64> {
+        self.install(|| VectorOps::mul(a, b))
+    }
+
+    pub fn div(&self, a: &[f64], b: &[f64]) -> Vec<f64> {
+        self.install(|| VectorOps::div(a, b))
+    }
+
+    pub fn div_safe(&self, a: &[f64], b: &[f64], on_zero: f64) -> Vec<f64> {
+        self.install(|| VectorOps::div_safe(a, b, on_zero))
+    }
+
+    pub fn sum(&self, a: &[f64]) -> f64 {
+        self.install(|| VectorOps::sum(a))
+    }
+
+    pub fn kahan_sum(&self, a: &[f64]) -> f64 {
+        self.install(|| VectorOps::kahan_sum(a))
+    }
+
+    pub fn proportional_spread(
+        &self,
+        target: f64,
+        current_values: &[f64],
+        reference_values: &[f64],
+        is_locked: &[bool]
+    ) -> Vec<f64> {
+        self.install(|| VectorOps::proportional_spread(target, current_values, reference_values, is_locked))
+    }
+
+    pub fn proportional_spread_with_floor(
+        &self,
+        target: f64,
+        current_values: &[f64],
+        reference_values: &[f64],
+        is_locked: &[bool],
+        min_alloc: f64,
+    ) -> Vec<f64> {
+        self.install(|| {
+            VectorOps::proportional_spread_with_floor(target, current_values, reference_values, is_locked, min_alloc)
+        })
+    }
+
+    pub fn transpose(&self, matrix: &[f64], rows: usize, cols: usize) -> Vec<f64> {
+        self.install(|| VectorOps::transpose(matrix, rows, cols))
+    }
+
+    pub fn gt_scalar(&self, a: &[f64], threshold: f64) -> Vec<bool> {
+        self.install(|| VectorOps::gt_scalar(a, threshold))
+    }
+
+    pub fn ge_scalar(&self, a: &[f64], threshold: f64) -> Vec<bool> {
+        self.install(|| VectorOps::ge_scalar(a, threshold))
+    }
+
+    pub fn lt_scalar(&self, a: &[f64], threshold: f64) -> Vec<bool> {
+        self.install(|| VectorOps::lt_scalar(a, threshold))
+    }
+
+    pub fn le_scalar(&self, a: &[f64], threshold: f64) -> Vec<bool> {
+        self.install(|| VectorOps::le_scalar(a, threshold))
+    }
+
+    pub fn eq_scalar(&self, a: &[f64], value: f64) -> Vec<bool> {
+        self.install(|| VectorOps::eq_scalar(a, value))
+    }
+
+    pub fn ne_scalar(&self, a: &[f64], value: f64) -> Vec<bool> {
+        self.install(|| VectorOps::ne_scalar(a, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_context_runs_on_bounded_pool() {
+        let ctx = ComputeContext::new(2).expect("pool build failed");
+        assert_eq!(ctx.num_threads(), 2);
+
+        let a: Vec<f64> = (0..10_000).map(|i| i as f64).collect();
+        let b: Vec<f64> = (0..10_000).map(|i| (i * 2) as f64).collect();
+        let result = ctx.add(&a, &b);
+        assert_eq!(result.len(), 10_000);
+        assert!(result.iter().enumerate().all(|(i, &v)| v == (i * 3) as f64));
+
+        // Work installed on the context sees the bounded pool, not the global one
+        assert_eq!(ctx.install(rayon::current_num_threads), 2);
+    }
+
+    #[test]
+    fn test_proportional_spread_guards_non_finite() {
+        let current = [10.0, 0.0, 0.0, 0.0];
+        let reference = [0.0, 1.0, f64::NAN, 3.0];
+        let locked = [true, false, false, false];
+
+        let result = VectorOps::proportional_spread(110.0, &current, &reference, &locked);
+        assert!(result.iter().all(|v| v.is_finite()));
+        assert_eq!(result[0], 10.0); // Locked entry untouched
+        assert_eq!(result[2], 0.0); // NaN weight zeroed
+        let unlocked_total: f64 = result[1..].iter().sum();
+        assert!((unlocked_total - 100.0).abs() < 1e-9, "target must be conserved, got {}", unlocked_total);
+
+        let rejected = VectorOps::proportional_spread_checked(110.0, &current, &reference, &locked, NonFinitePolicy::Reject);
+        assert_eq!(rejected, Err(SpreadError::NonFiniteInput { field: "reference value", index: 2 }));
+    }
+
+    #[test]
+    fn test_spread_with_floor_reserves_minimum_per_unlocked_cell() {
+        let current = [40.0, 0.0, 0.0, 0.0];
+        let reference = [0.0, 1.0, 99.0, 0.0];
+        let locked = [true, false, false, false];
+
+        let result = VectorOps::proportional_spread_with_floor(100.0, &current, &reference, &locked, 5.0);
+        assert_eq!(result[0], 40.0);
+        assert!(result[1..].iter().all(|&v| v >= 5.0), "{:?}", result);
+        assert!((result.iter().sum::<f64>() - 100.0).abs() < 1e-9);
+        // The 45 left after the floors follows the weights
+        assert!((result[1] - 5.45).abs() < 1e-9 && (result[2] - 49.55).abs() < 1e-9 && result[3] == 5.0);
+
+        // All-zero weights split the surplus evenly
+        let even = VectorOps::proportional_spread_with_floor(100.0, &current, &[0.0; 4], &locked, 5.0);
+        assert_eq!(&even[1..], &[20.0, 20.0, 20.0]);
+    }
+
+    #[test]
+    fn test_spread_with_floor_shares_target_when_floors_exceed_it() {
+        let current = [40.0, 0.0, 0.0, 0.0];
+        let reference = [1.0, 1.0, 2.0, 3.0];
+        let locked = [true, false, false, false];
+
+        let result = VectorOps::proportional_spread_with_floor(70.0, &current, &reference, &locked, 20.0);
+        assert_eq!(result, vec![40.0, 10.0, 10.0, 10.0]);
+        assert_eq!(result.iter().sum::<f64>(), 70.0);
+    }
+
+    #[test]
+    fn test_div_safe_substitutes_only_zero_divisors() {
+        let a = [10.0, 4.0, 7.0, -3.0, 0.0];
+        let b = [2.0, 0.0, -0.0, 3.0, 0.0];
+        assert_eq!(VectorOps::div_safe(&a, &b, 0.0), vec![5.0, 0.0, 0.0, -1.0, 0.0]);
+        assert_eq!(VectorOps::div_safe(&a, &b, 99.0), vec![5.0, 99.0, 99.0, -1.0, 99.0]);
+
+        // Raw division keeps the IEEE results
+        let raw = VectorOps::div(&a, &b);
+        assert_eq!((raw[1], raw[2]), (f64::INFINITY, f64::NEG_INFINITY));
+        assert!(raw[4].is_nan());
+    }
+
+    #[test]
+    fn test_kahan_sum_recovers_small_terms() {
+        // Every 1.0 is below half an ulp of 1e16, so a naive left-to-right sum drops all of them
+        let mut a = vec![1e16];
+        a.extend(std::iter::repeat_n(1.0, 100_000));
+        let naive = a.iter().fold(0.0, |acc, &x| acc + x);
+        assert_eq!(naive, 1e16);
+        assert_eq!(VectorOps::kahan_sum(&a), 1e16 + 100_000.0);
+
+        // Cancellation across blocks leaves only the small terms
+        a.push(-1e16);
+        assert_eq!(VectorOps::kahan_sum(&a), 100_000.0);
+
+        let ctx = ComputeContext::new(3).expect("pool build failed");
+        assert_eq!(ctx.kahan_sum(&a), 100_000.0);
+        assert_eq!(VectorOps::kahan_sum(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_scalar_masks_match_reference() {
+        // Spans several parallel blocks and includes NaN and exact hits on the threshold
+        let a: Vec<f64> = (0..10_000)
+            .map(|i| if i % 997 == 0 { f64::NAN } else { (i % 100) as f64 })
+            .collect();
+        let t = 50.0;
+        let check = |mask: Vec<bool>, reference: fn(f64, f64) -> bool| {
+            assert_eq!(mask.len(), a.len());
+            for (i, (&m, &x)) in mask.iter().zip(&a).enumerate() {
+                assert_eq!(m, reference(x, t), "element {} ({})", i, x);
+            }
+        };
+        check(VectorOps::gt_scalar(&a, t), |x, t| x > t);
+        check(VectorOps::ge_scalar(&a, t), |x, t| x >= t);
+        check(VectorOps::lt_scalar(&a, t), |x, t| x < t);
+        check(VectorOps::le_scalar(&a, t), |x, t| x <= t);
+        check(VectorOps::eq_scalar(&a, t), |x, t| x == t);
+        check(VectorOps::ne_scalar(&a, t), |x, t| x != t);
+        assert!(!VectorOps::ge_scalar(&a, t)[0] && VectorOps::ne_scalar(&a, t)[0]);
+
+        // A mask feeds straight into the spread as its lock set
+        let current = [5.0, 40.0, 10.0, 60.0];
+        let locked = VectorOps::ge_scalar(&current, 40.0);
+        assert_eq!(locked, vec![false, true, false, true]);
+        let spread = VectorOps::proportional_spread(200.0, &current, &[1.0, 1.0, 3.0, 1.0], &locked);
+        assert_eq!(spread, vec![25.0, 40.0, 75.0, 60.0]);
+    }
+
+    #[test]
+    fn test_transpose_2x3() {
+        // [[1, 2, 3],
+        //  [4, 5, 6]]
+        let m = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let t = VectorOps::transpose(&m, 2, 3);
+        assert_eq!(t, vec![1.0, 4.0, 2.0, 5.0, 3.0, 6.0]);
+        assert_eq!(VectorOps::transpose(&t, 3, 2), m.to_vec());
+
+        // Large enough to span several parallel blocks
+        let (rows, cols) = (37, 300);
+        let big: Vec<f64> = (0..rows * cols).map(|i| i as f64).collect();
+        let t = VectorOps::transpose(&big, rows, cols);
+        assert_eq!(t[5 * rows + 7], big[7 * cols + 5]);
+        assert_eq!(VectorOps::transpose(&t, cols, rows), big);
+    }
+
+    #[test]
+    #[should_panic(expected = "do not form a 2x2 matrix")]
+    fn test_transpose_rejects_bad_shape() {
+        VectorOps::transpose(&[1.0, 2.0, 3.0], 2, 2);
+    }
+}
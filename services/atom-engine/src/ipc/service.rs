@@ -1,16 +1,235 @@
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
 
 use arrow_flight::{
     flight_service_server::FlightService, Action, ActionType, Criteria, Empty, FlightData,
-    FlightDescriptor, FlightInfo, HandshakeRequest, HandshakeResponse, PutResult, SchemaResult,
-    Ticket,
+    FlightDescriptor, FlightEndpoint, FlightInfo, HandshakeRequest, HandshakeResponse, PutResult,
+    SchemaResult, Ticket,
 };
+use arrow::array::{Array, ArrayRef, BinaryArray, Float64Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use arrow_flight::utils::batches_to_flight_data;
 use futures::Stream;
+use rayon::prelude::*;
+use serde::Deserialize;
 use tonic::{Request, Response, Status, Streaming};
+use crate::atom_script::chunk::{Chunk, PreparedChunk};
+use crate::atom_script::compiler::Compiler;
+use crate::atom_script::parser::{Parser, DEFAULT_MAX_FORMULA_LEN};
+use crate::atom_script::pool::VmPool;
+use crate::atom_script::vm::InterpretResult;
+use crate::compute::simd::CompensatedSum;
+use crate::lattice::arena::LatticeArena;
+use crate::lattice::coordinate::{coordinate_hash, coordinate_hash_to_bytes};
+use crate::lattice::slice::DEFAULT_MAX_SLICE_CELLS;
+use crate::lattice::metadata::{HierarchyResolver, MockHierarchyResolver};
+use crate::mdf::molecule::MoleculeSchema;
+use crate::mdf::reader::mdf_row_count;
+
+/// Returns the arena checksum as 16 big-endian bytes (see `LatticeArena::checksum`), followed
+/// by a second result with the control total of every numeric cell as a big-endian f64.
+/// The total is compensated (`CompensatedSum`) and adds the cells in coordinate order, like
+/// the checksum, so replicas holding the same cells report the same total.
+pub const CHECKSUM_ACTION: &str = "CHECKSUM";
+
+/// Takes a formula (or a chunk exported with `Chunk::to_json`) as the body and returns
+/// `{ "disassembly": [...], "cost": {...} }` as JSON, without evaluating anything.
+pub const EXPLAIN_ACTION: &str = "EXPLAIN";
+
+// Rows per record batch streamed by do_get.
+pub const DEFAULT_DO_GET_BATCH_ROWS: usize = 8192;
+
+/// A do_get ticket that evaluates a formula at each coordinate instead of streaming the arena:
+/// `{"formula": "[Revenue] * 2", "coordinates": [[["Region", "USA"]], [["Region", "Canada"]]]}`.
+/// The results stream as one row per coordinate: `coordinate_hash`, then `numeric_value`,
+/// `text_value` or `error`, whichever the evaluation produced.
+#[derive(Debug, Deserialize)]
+struct FormulaPlan {
+    formula: String,
+    coordinates: Vec<Vec<(String, String)>>,
+}
 
 #[derive(Clone)]
-pub struct FlightServiceImpl;
+pub struct FlightServiceImpl {
+    arena: Arc<LatticeArena>,
+    resolver: Arc<dyn HierarchyResolver + Send + Sync>,
+    batch_rows: usize,
+    data_dir: Option<PathBuf>,
+    vm_pool: Arc<VmPool>, // Stacks for formula plans, shared by every clone of the service
+}
+
+impl FlightServiceImpl {
+    pub fn new(arena: Arc<LatticeArena>) -> Self {
+        Self {
+            arena,
+            resolver: Arc::new(MockHierarchyResolver),
+            batch_rows: DEFAULT_DO_GET_BATCH_ROWS,
+            data_dir: None,
+            vm_pool: Arc::new(VmPool::new()),
+        }
+    }
+
+    /// Overrides how many rows each record batch streamed by do_get holds.
+    pub fn with_batch_rows(mut self, rows: usize) -> Self {
+        self.batch_rows = rows.max(1);
+        self
+    }
+
+    /// Resolves hierarchy functions in EXPLAINed formulas and formula plans through `resolver`.
+    pub fn with_resolver(mut self, resolver: Arc<dyn HierarchyResolver + Send + Sync>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Lists the MDF (`*.parquet`) files in `dir` as flights. Without a data directory,
+    /// list_flights returns no flights.
+    pub fn with_data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = Some(dir.into());
+        self
+    }
+
+    /// One FlightInfo per `*.parquet` file directly in the data directory whose path starts
+    /// with `prefix`, sorted by path. The path is both the ticket and the descriptor; the
+    /// schema is `MoleculeSchema` and the row count comes from the file footer. Files whose
+    /// footer cannot be read are not MDF files and are left out.
+    fn list_datasets(&self, prefix: &str) -> anyhow::Result<Vec<FlightInfo>> {
+        let Some(dir) = &self.data_dir else {
+            return Ok(Vec::new());
+        };
+        let mut paths: Vec<String> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "parquet"))
+            .filter_map(|path| path.to_str().map(str::to_string))
+            .filter(|path| path.starts_with(prefix))
+            .collect();
+        paths.sort();
+
+        let schema = MoleculeSchema::schema();
+        let mut flights = Vec::with_capacity(paths.len());
+        for path in paths {
+            let Ok(rows) = mdf_row_count(&path) else { continue };
+            flights.push(
+                FlightInfo::new()
+                    .try_with_schema(&schema)?
+                    .with_descriptor(FlightDescriptor::new_path(vec![path.clone()]))
+                    .with_endpoint(FlightEndpoint::new().with_ticket(Ticket::new(path)))
+                    .with_total_records(rows),
+            );
+        }
+        Ok(flights)
+    }
+
+    /// Builds the EXPLAIN response; errors are reported to the client as InvalidArgument.
+    fn explain(&self, body: &[u8]) -> Result<serde_json::Value, String> {
+        let text = std::str::from_utf8(body).map_err(|_| "EXPLAIN body must be UTF-8".to_string())?;
+        let chunk = match serde_json::from_str::<serde_json::Value>(text) {
+            Ok(json) if json.get("code").is_some() => Chunk::from_json(&json)?,
+            _ => {
+                let expr = Parser::new_bounded(text, DEFAULT_MAX_FORMULA_LEN).parse().map_err(|e| e.to_string())?;
+                Compiler::with_resolver(Arc::clone(&self.resolver))
+                    .try_compile(&expr)
+                    .map_err(|e| e.to_string())?
+            }
+        };
+        let cost = chunk.estimate_cost().map_err(|e| e.to_string())?;
+        Ok(serde_json::json!({ "disassembly": chunk.disassemble(), "cost": cost }))
+    }
+
+    /// Encodes the arena as the do_get stream (see `batch_stream`).
+    fn arena_stream(&self) -> anyhow::Result<Vec<FlightData>> {
+        self.batch_stream(self.arena.to_record_batch()?)
+    }
+
+    /// Compiles and validates a plan's formula once for all of its coordinates; errors are
+    /// reported to the client as InvalidArgument.
+    fn compile_plan(&self, plan: &FormulaPlan) -> Result<PreparedChunk, String> {
+        if plan.coordinates.len() > DEFAULT_MAX_SLICE_CELLS {
+            return Err(format!("plan of {} coordinates exceeds the limit of {}", plan.coordinates.len(), DEFAULT_MAX_SLICE_CELLS));
+        }
+        let expr = Parser::new_bounded(&plan.formula, DEFAULT_MAX_FORMULA_LEN).parse().map_err(|e| e.to_string())?;
+        let chunk = Compiler::with_resolver(Arc::clone(&self.resolver)).try_compile(&expr).map_err(|e| e.to_string())?;
+        Ok(PreparedChunk::new(chunk))
+    }
+
+    /// Evaluates `chunk` at each coordinate of `plan` on pooled VMs and encodes the results as
+    /// the do_get stream (see `batch_stream`).
+    fn plan_stream(&self, plan: &FormulaPlan, chunk: &PreparedChunk) -> anyhow::Result<Vec<FlightData>> {
+        let arena = self.arena.as_ref();
+        let results: Vec<InterpretResult> = plan
+            .coordinates
+            .par_iter()
+            .map(|coordinate| self.vm_pool.run(chunk.clone(), |vm| vm.with_arena(arena).with_coordinate(coordinate.clone())))
+            .collect();
+        self.batch_stream(plan_results_batch(&plan.coordinates, &results)?)
+    }
+
+    /// The do_get stream for `cells`: the schema, one message per batch of `batch_rows` rows,
+    /// then the trailer carrying the running checksum of every batch sent.
+    fn batch_stream(&self, cells: RecordBatch) -> anyhow::Result<Vec<FlightData>> {
+        let batches: Vec<RecordBatch> = (0..cells.num_rows())
+            .step_by(self.batch_rows)
+            .map(|offset| cells.slice(offset, self.batch_rows.min(cells.num_rows() - offset)))
+            .collect();
+
+        let mut checksum = CompensatedSum::new();
+        for batch in &batches {
+            add_numeric_values(&mut checksum, batch);
+        }
+        let mut frames = batches_to_flight_data(cells.schema().as_ref(), batches)?;
+        frames.push(FlightData::new().with_app_metadata(checksum.total().to_be_bytes().to_vec()));
+        Ok(frames)
+    }
+}
+
+/// One row per evaluated coordinate of a `FormulaPlan`.
+fn plan_results_batch(coordinates: &[Vec<(String, String)>], results: &[InterpretResult]) -> anyhow::Result<RecordBatch> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("coordinate_hash", DataType::Binary, false),
+        Field::new("numeric_value", DataType::Float64, true),
+        Field::new("text_value", DataType::Utf8, true),
+        Field::new("error", DataType::Utf8, true),
+    ]));
+    let hashes: Vec<[u8; 16]> = coordinates.iter().map(|c| coordinate_hash_to_bytes(coordinate_hash(c))).collect();
+    let error = |result: &InterpretResult| match result {
+        InterpretResult::Ok(_) | InterpretResult::Text(_) => None,
+        InterpretResult::ErrorValue(e) | InterpretResult::TypeError(e) => Some(e.clone()),
+        InterpretResult::CompileError => Some("compile error".to_string()),
+        InterpretResult::RuntimeError(fault) => Some(fault.to_string()),
+        InterpretResult::EvaluationTimeout => Some("evaluation timed out".to_string()),
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(BinaryArray::from_iter_values(hashes.iter())),
+        Arc::new(Float64Array::from_iter(results.iter().map(|r| match r {
+            InterpretResult::Ok(v) => Some(*v),
+            _ => None,
+        }))),
+        Arc::new(StringArray::from_iter(results.iter().map(|r| match r {
+            InterpretResult::Text(t) => Some(t.as_str()),
+            _ => None,
+        }))),
+        Arc::new(StringArray::from_iter(results.iter().map(error))),
+    ];
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+/// Compensated sum of every numeric cell in coordinate order, so the total does not depend on
+/// shard layout or insertion order.
+fn control_total(arena: &LatticeArena) -> f64 {
+    let mut cells: Vec<(u128, f64)> = arena.iter_cells().collect();
+    cells.sort_unstable_by_key(|&(hash, _)| hash);
+    let mut total = CompensatedSum::new();
+    cells.iter().for_each(|&(_, value)| total.add(value));
+    total.total()
+}
+
+/// Adds a batch's non-null `numeric_value`s to `checksum`, in row order.
+fn add_numeric_values(checksum: &mut CompensatedSum, batch: &RecordBatch) {
+    if let Some(values) = batch.column_by_name("numeric_value").and_then(|c| c.as_any().downcast_ref::<Float64Array>()) {
+        values.iter().flatten().for_each(|v| checksum.add(v));
+    }
+}
 
 #[tonic::async_trait]
 impl FlightService for FlightServiceImpl {
@@ -31,9 +250,16 @@ impl FlightService for FlightServiceImpl {
 
     async fn list_flights(
         &self,
-        _request: Request<Criteria>,
+        request: Request<Criteria>,
     ) -> Result<Response<Self::ListFlightsStream>, Status> {
-        Err(Status::unimplemented("ListFlights not implemented"))
+        // The criteria expression, if any, is a UTF-8 path prefix
+        let criteria = request.into_inner();
+        let prefix = std::str::from_utf8(&criteria.expression)
+            .map_err(|_| Status::invalid_argument("ListFlights criteria must be a UTF-8 path prefix"))?;
+        let flights = self.list_datasets(prefix).map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(
+            Box::pin(futures::stream::iter(flights.into_iter().map(Ok))) as Self::ListFlightsStream,
+        ))
     }
 
     async fn get_flight_info(
@@ -66,19 +292,22 @@ impl FlightService for FlightServiceImpl {
         println!("do_get executing plan: {}", plan_id);
 
         // Ultra Diamond: Zero-Copy Streaming
-        // In a real implementation, the compiled AtomScript runs and produces Arrow RecordBatches.
-        // We stream these batches asynchronously to the Go Orchestrator.
+        // A `FormulaPlan` ticket streams the formula's result at each coordinate; any other
+        // ticket streams the arena's cells as MDF record batches. The last frame has no data,
+        // only `app_metadata`: the compensated sum (`CompensatedSum`) of every non-null
+        // numeric_value sent, as a big-endian f64. A client that recomputes it over the
+        // batches it received detects a truncated stream.
+        let frames = match serde_json::from_slice::<FormulaPlan>(&ticket.ticket) {
+            Ok(plan) => {
+                let chunk = self.compile_plan(&plan).map_err(Status::invalid_argument)?;
+                self.plan_stream(&plan, &chunk).map_err(|e| Status::internal(e.to_string()))?
+            }
+            Err(_) => self.arena_stream().map_err(|e| Status::internal(e.to_string()))?,
+        };
         let (tx, rx) = tokio::sync::mpsc::channel(2);
 
         tokio::spawn(async move {
-            // Simulate generating 2 chunks of grid data
-            for i in 0..2 {
-                let data = FlightData {
-                    flight_descriptor: None,
-                    data_header: vec![].into(),
-                    app_metadata: vec![].into(),
-                    data_body: vec![i as u8].into(), // Simulated Arrow IPC message body
-                };
+            for data in frames {
                 if tx.send(Ok(data)).await.is_err() {
                     break; // receiver closed
                 }
@@ -99,16 +328,47 @@ impl FlightService for FlightServiceImpl {
 
     async fn do_action(
         &self,
-        _request: Request<Action>,
+        request: Request<Action>,
     ) -> Result<Response<Self::DoActionStream>, Status> {
-        Err(Status::unimplemented("DoAction not implemented"))
+        let action = request.into_inner();
+        match action.r#type.as_str() {
+            CHECKSUM_ACTION => {
+                let checksum = self.arena.checksum();
+                let total = control_total(&self.arena);
+                let results = [
+                    arrow_flight::Result { body: checksum.to_be_bytes().to_vec().into() },
+                    arrow_flight::Result { body: total.to_be_bytes().to_vec().into() },
+                ];
+                Ok(Response::new(
+                    Box::pin(futures::stream::iter(results.map(Ok))) as Self::DoActionStream,
+                ))
+            }
+            EXPLAIN_ACTION => {
+                let plan = self.explain(&action.body).map_err(Status::invalid_argument)?;
+                let result = arrow_flight::Result { body: plan.to_string().into_bytes().into() };
+                Ok(Response::new(
+                    Box::pin(futures::stream::iter([Ok(result)])) as Self::DoActionStream,
+                ))
+            }
+            other => Err(Status::unimplemented(format!("Unknown action: {}", other))),
+        }
     }
 
     async fn list_actions(
         &self,
         _request: Request<Empty>,
     ) -> Result<Response<Self::ListActionsStream>, Status> {
-        Err(Status::unimplemented("ListActions not implemented"))
+        let checksum = ActionType {
+            r#type: CHECKSUM_ACTION.to_string(),
+            description: "Order-independent checksum of every arena cell (16 bytes, big-endian), then the control total (f64, big-endian)".to_string(),
+        };
+        let explain = ActionType {
+            r#type: EXPLAIN_ACTION.to_string(),
+            description: "Disassembly and cost estimate of a formula or exported chunk (JSON)".to_string(),
+        };
+        Ok(Response::new(
+            Box::pin(futures::stream::iter([Ok(checksum), Ok(explain)])) as Self::ListActionsStream,
+        ))
     }
 
     async fn do_exchange(
@@ -118,3 +378,182 @@ impl FlightService for FlightServiceImpl {
         Err(Status::unimplemented("DoExchange not implemented"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    async fn checksum_results(arena: Arc<LatticeArena>) -> Vec<Vec<u8>> {
+        let service = FlightServiceImpl::new(arena);
+        let action = Action { r#type: CHECKSUM_ACTION.to_string(), body: vec![].into() };
+        let stream = service.do_action(Request::new(action)).await.unwrap().into_inner();
+        stream.map(|r| r.unwrap().body.to_vec()).collect().await
+    }
+
+    async fn checksum_of(arena: Arc<LatticeArena>) -> Vec<u8> {
+        checksum_results(arena).await.swap_remove(0)
+    }
+
+    #[tokio::test]
+    async fn test_checksum_action_matches_replicas() {
+        let primary = Arc::new(LatticeArena::new(64));
+        let replica = Arc::new(LatticeArena::new(64));
+        for (hash, value) in [(1u128, 10.0), (2, 20.0), (3, 30.0)] {
+            primary.set_cell(hash, value);
+        }
+        for (hash, value) in [(3u128, 30.0), (1, 10.0), (2, 20.0)] {
+            replica.set_cell(hash, value);
+        }
+
+        let in_sync = checksum_of(Arc::clone(&primary)).await;
+        assert_eq!(in_sync.len(), 16);
+        assert_eq!(in_sync, checksum_of(Arc::clone(&replica)).await);
+
+        replica.set_cell(2, 21.0);
+        assert_ne!(in_sync, checksum_of(replica).await);
+    }
+
+    #[tokio::test]
+    async fn test_checksum_action_reports_compensated_total() {
+        let arena = Arc::new(LatticeArena::new(64));
+        arena.set_cell(0, 1e16);
+        for hash in 1..=1000u128 {
+            arena.set_cell(hash, 1.0);
+        }
+        arena.set_cell(1001, -1e16);
+
+        let results = checksum_results(arena).await;
+        assert_eq!(results.len(), 2);
+        let total = f64::from_be_bytes(results[1].as_slice().try_into().unwrap());
+        assert_eq!(total, 1000.0);
+    }
+
+    #[tokio::test]
+    async fn test_do_get_trailer_checksum_detects_dropped_batch() {
+        let arena = Arc::new(LatticeArena::new(64));
+        arena.set_cell(0, 1e16);
+        for hash in 1..=2500u128 {
+            arena.set_cell(hash, 0.1 * hash as f64);
+        }
+        arena.set_date(5000, 1_700_000_000_000);
+        let service = FlightServiceImpl::new(arena).with_batch_rows(1000);
+
+        let ticket = Ticket { ticket: b"plan-1".to_vec().into() };
+        let stream = service.do_get(Request::new(ticket)).await.unwrap().into_inner();
+        let mut frames: Vec<FlightData> = stream.map(|frame| frame.unwrap()).collect().await;
+
+        let trailer = frames.pop().unwrap();
+        assert!(trailer.data_header.is_empty() && trailer.data_body.is_empty());
+        let expected = f64::from_be_bytes(trailer.app_metadata.as_ref().try_into().unwrap());
+
+        // Client side: decode what arrived and recompute the running checksum
+        let recompute = |frames: &[FlightData]| {
+            let mut checksum = CompensatedSum::new();
+            for batch in arrow_flight::utils::flight_data_to_batches(frames).unwrap() {
+                let values = batch.column_by_name("numeric_value").unwrap().as_any().downcast_ref::<Float64Array>().unwrap();
+                for value in values.iter().flatten() {
+                    checksum.add(value);
+                }
+            }
+            checksum.total()
+        };
+        assert_eq!(frames.len(), 1 + 3); // Schema, then 2501 cells and a date in batches of 1000
+        assert_eq!(recompute(&frames).to_bits(), expected.to_bits());
+
+        frames.remove(2);
+        assert_ne!(recompute(&frames), expected);
+    }
+
+    #[tokio::test]
+    async fn test_do_get_evaluates_formula_plan_on_pooled_vms() {
+        let arena = Arc::new(LatticeArena::new(256));
+        for i in 0..200 {
+            arena.set_cell(coordinate_hash(&[("Measure", "Revenue"), ("Region", &format!("R{}", i))]), i as f64);
+        }
+        let service = FlightServiceImpl::new(arena).with_batch_rows(64);
+        let coordinates: Vec<Vec<(String, String)>> = (0..200).map(|i| vec![("Region".to_string(), format!("R{}", i))]).collect();
+        let plan = |formula: &str| {
+            let ticket = serde_json::json!({ "formula": formula, "coordinates": coordinates });
+            Request::new(Ticket { ticket: ticket.to_string().into_bytes().into() })
+        };
+
+        let stream = service.do_get(plan("[Revenue] * 2")).await.unwrap().into_inner();
+        let mut frames: Vec<FlightData> = stream.map(|frame| frame.unwrap()).collect().await;
+        frames.pop(); // Checksum trailer
+        let mut values = Vec::new();
+        for batch in arrow_flight::utils::flight_data_to_batches(&frames).unwrap() {
+            let column = batch.column_by_name("numeric_value").unwrap().as_any().downcast_ref::<Float64Array>().unwrap();
+            values.extend(column.iter().map(Option::unwrap));
+            assert_eq!(batch.column_by_name("error").unwrap().null_count(), batch.num_rows());
+        }
+        assert_eq!(values, (0..200).map(|i| 2.0 * i as f64).collect::<Vec<_>>());
+
+        // Each worker thread allocated at most one stack; every other run reused one
+        let pool = &service.vm_pool;
+        assert_eq!(pool.allocations() + pool.reuses(), 200);
+        assert!(pool.allocations() <= rayon::current_num_threads(), "{} allocations", pool.allocations());
+
+        let err = service.do_get(plan("SUM(")).await.err().unwrap();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_explain_action_disassembles_formula() {
+        let service = FlightServiceImpl::new(Arc::new(LatticeArena::new(16)));
+        let body = "SUM(@Children([Region], [North America]))";
+        let action = Action { r#type: EXPLAIN_ACTION.to_string(), body: body.as_bytes().to_vec().into() };
+        let mut stream = service.do_action(Request::new(action)).await.unwrap().into_inner();
+        let response = stream.next().await.unwrap().unwrap().body;
+
+        let plan: serde_json::Value = serde_json::from_slice(&response).unwrap();
+        let lines: Vec<&str> = plan["disassembly"].as_array().unwrap().iter().filter_map(|l| l.as_str()).collect();
+        assert!(lines.iter().any(|l| l.contains("Sum(3)")), "{:?}", lines);
+        assert!(lines.iter().any(|l| l.contains("Region=Mexico")), "{:?}", lines);
+        assert_eq!(plan["cost"]["arena_reads"], 3);
+
+        for body in ["SUM(".to_string(), "1+".repeat(DEFAULT_MAX_FORMULA_LEN) + "1"] {
+            let action = Action { r#type: EXPLAIN_ACTION.to_string(), body: body.into_bytes().into() };
+            let err = service.do_action(Request::new(action)).await.err().unwrap();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_flights_enumerates_mdf_files() {
+        let dir = std::env::temp_dir().join(format!("list_flights_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, cells) in [("actuals.parquet", 3u128), ("budget.parquet", 5)] {
+            let arena = LatticeArena::new(16);
+            for hash in 0..cells {
+                arena.set_cell(hash, hash as f64);
+            }
+            arena.persist(dir.join(name).to_str().unwrap()).unwrap();
+        }
+        std::fs::write(dir.join("notes.txt"), "not a dataset").unwrap();
+
+        let service = FlightServiceImpl::new(Arc::new(LatticeArena::new(16))).with_data_dir(&dir);
+        let list = |prefix: String| {
+            let service = service.clone();
+            async move {
+                let criteria = Criteria { expression: prefix.into_bytes().into() };
+                let stream = service.list_flights(Request::new(criteria)).await.unwrap().into_inner();
+                stream.map(|info| info.unwrap()).collect::<Vec<FlightInfo>>().await
+            }
+        };
+
+        let flights = list(String::new()).await;
+        let listed: Vec<(String, i64)> = flights
+            .iter()
+            .map(|f| (String::from_utf8(f.endpoint[0].ticket.as_ref().unwrap().ticket.to_vec()).unwrap(), f.total_records))
+            .collect();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        assert_eq!(listed, vec![(path("actuals.parquet"), 3), (path("budget.parquet"), 5)]);
+        assert_eq!(flights[0].clone().try_decode_schema().unwrap(), *MoleculeSchema::schema());
+
+        let filtered = list(path("bud")).await;
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].total_records, 5);
+    }
+}
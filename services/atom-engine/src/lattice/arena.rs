@@ -1,73 +1,269 @@
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use arrow::array::{new_null_array, Array, ArrayRef, BinaryArray, Float64Array, Int64Array, StringArray, UInt64Array};
+use arrow::record_batch::RecordBatch;
+use anyhow::{anyhow, Result};
+use crate::lattice::clock::VectorClock;
+use crate::lattice::coordinate::{coordinate_hash_from_bytes, coordinate_hash_to_bytes, fnv1a, FNV_OFFSET_BASIS};
+use crate::mdf::molecule::MoleculeSchema;
+use crate::mdf::reader::read_mdf_arrow;
+use crate::mdf::writer::write_mdf_arrow;
 
-const SHARD_COUNT: usize = 64;
+// Part of the placement contract of `shard_index_of`: changing it re-shards every arena.
+pub const SHARD_COUNT: usize = 64;
+
+// Rows per RecordBatch when persisting (matches the reader's SIMD-friendly batch size).
+const PERSIST_BATCH_ROWS: usize = 8192;
+pub(crate) const PERSIST_SOURCE_SYSTEM: &str = "atom-engine";
+
+/// Source tag of cells written by formula evaluation rather than loaded from a source system.
+pub const COMPUTED_SOURCE: &str = "computed";
+
+// Source id of cells written without provenance (`set_cell`); never interned.
+const NO_SOURCE: u32 = 0;
 
 /// A single shard of the arena.
 struct ArenaShard {
     values: RwLock<Vec<f64>>,  // Type 0
-    dates: RwLock<Vec<i64>>,   // Type 1
+    sources: RwLock<Vec<u32>>, // Interned source system per value, parallel to `values`
+    dates: RwLock<Vec<i64>>,   // Type 1: Unix millis, kept as i64 so no precision is lost
     strings: RwLock<Vec<String>>, 
     index_map: RwLock<HashMap<u128, usize>>,
+    date_index: RwLock<HashMap<u128, usize>>,
+    clocks: RwLock<HashMap<u128, VectorClock>>, // Only cells written through `set_cell_with_clock`
+    locked: RwLock<Vec<u64>>, // Bitmap over `values` slots; grown only as far as the highest locked slot
+    writes: AtomicU64, // Bumped by every value or date write; see `LatticeArena::version`
 }
 
 impl ArenaShard {
     fn new(capacity: usize) -> Self {
         Self {
             values: RwLock::new(Vec::with_capacity(capacity)),
+            sources: RwLock::new(Vec::with_capacity(capacity)),
             dates: RwLock::new(Vec::with_capacity(capacity)),
             strings: RwLock::new(Vec::with_capacity(capacity)),
             index_map: RwLock::new(HashMap::with_capacity(capacity)),
+            date_index: RwLock::new(HashMap::new()),
+            clocks: RwLock::new(HashMap::new()),
+            locked: RwLock::new(Vec::new()),
+            writes: AtomicU64::new(0),
         }
     }
 }
 
+// Lock Poisoning Policy: a thread that panics while holding a shard lock poisons it.
+// Every mutation of a shard is a single push/assignment, so the data behind a poisoned lock is
+// still structurally valid (at worst one write is lost). We recover the guard instead of
+// propagating the panic, so one failed request cannot cascade into taking down the whole server.
+fn read_lock<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn write_lock<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
 // Circuit Breaker: Prevent infinite memory growth (e.g., from runaway scripts or excessive data loading)
 // 5M cells per shard * 64 shards = 320M cells absolute max per node.
 const MAX_SHARD_CAPACITY: usize = 5_000_000;
 
+/// Result of `LatticeArena::set_cell_with_clock`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockedWrite {
+    Applied,
+    /// The write's clock precedes (or equals) the stored one; the cell was left untouched.
+    Stale,
+    /// Neither clock precedes the other; the cell was left untouched for the caller to resolve.
+    Concurrent,
+}
+
+/// Source system names, interned so each cell carries a u32 instead of a String.
+/// Id `NO_SOURCE` (0) is reserved for cells without provenance.
+#[derive(Default)]
+struct SourceInterner {
+    names: Vec<String>,
+    ids: HashMap<String, u32>,
+}
+
 /// The LatticeArena manages the memory for all cells in a Grid View.
 /// Ultra-Diamond: Uses Sharded Locking for massive concurrency (5000+ writers).
 pub struct LatticeArena {
     shards: Vec<ArenaShard>,
+    sources: RwLock<SourceInterner>,
 }
 
 impl LatticeArena {
+    /// Creates an arena for `capacity` cells, split evenly (rounded up) across the shards.
     pub fn new(capacity: usize) -> Self {
-        let shard_cap = capacity / SHARD_COUNT + 1;
-        let mut shards = Vec::with_capacity(SHARD_COUNT);
-        for _ in 0..SHARD_COUNT {
-            shards.push(ArenaShard::new(shard_cap));
-        }
-        Self { shards }
+        let shard_cap = capacity.div_ceil(SHARD_COUNT);
+        Self::with_capacity_hints(&[shard_cap; SHARD_COUNT])
+    }
+
+    /// Creates an arena with a per-shard pre-allocation hint, for skewed key distributions.
+    /// `hints[i]` sizes shard `i` (see `shard_index_of`); missing hints default to 0 and hints
+    /// beyond `SHARD_COUNT` are ignored.
+    pub fn with_capacity_hints(hints: &[usize]) -> Self {
+        let shards = (0..SHARD_COUNT)
+            .map(|i| ArenaShard::new(hints.get(i).copied().unwrap_or(0)))
+            .collect();
+        Self { shards, sources: RwLock::new(SourceInterner::default()) }
+    }
+
+    /// Index of the shard that stores `hash`: `hash % SHARD_COUNT`.
+    /// The mapping is stable across versions, so tests and diagnostics can rely on placement
+    /// (hashes that are equal mod 64 share a shard and contend on the same locks).
+    pub fn shard_index_of(hash: u128) -> usize {
+        (hash % SHARD_COUNT as u128) as usize
     }
 
     fn get_shard(&self, hash: u128) -> &ArenaShard {
-        let idx = (hash % SHARD_COUNT as u128) as usize;
-        &self.shards[idx]
+        &self.shards[Self::shard_index_of(hash)]
     }
 
-    /// Allocates or updates a cell value.
+    /// Allocates or updates a cell value. The cell has no recorded source system afterwards.
     pub fn set_cell(&self, hash: u128, value: f64) -> usize {
+        self.set_cell_tagged(hash, value, NO_SOURCE)
+    }
+
+    /// Allocates or updates a cell value loaded from `source` (the `source_system` column).
+    pub fn set_cell_with_source(&self, hash: u128, value: f64, source: &str) -> usize {
+        let source_id = self.intern_source(source);
+        self.set_cell_tagged(hash, value, source_id)
+    }
+
+    /// Writes a value produced by formula evaluation, tagged with `COMPUTED_SOURCE`.
+    pub fn set_computed(&self, hash: u128, value: f64) -> usize {
+        self.set_cell_with_source(hash, value, COMPUTED_SOURCE)
+    }
+
+    /// Writes a cell only if `clock` is causally newer than the clock of the last clocked write
+    /// to it, so a delayed or replayed replication message cannot overwrite fresher data.
+    /// Cells never written with a clock accept any clocked write.
+    pub fn set_cell_with_clock(&self, hash: u128, value: f64, clock: &VectorClock) -> ClockedWrite {
         let shard = self.get_shard(hash);
-        
+        // Held across the compare and the write, so two clocked writers cannot interleave
+        let mut clocks = write_lock(&shard.clocks);
+        if let Some(stored) = clocks.get(&hash) {
+            if !stored.happens_before(clock) {
+                return if stored.concurrent(clock) { ClockedWrite::Concurrent } else { ClockedWrite::Stale };
+            }
+        }
+        self.set_cell(hash, value);
+        clocks.insert(hash, clock.clone());
+        ClockedWrite::Applied
+    }
+
+    /// Writes a cell unless it is locked (an explicit bottom-up entry), returning whether the
+    /// write happened. This is the path for top-down writes such as spreads; `set_cell` still
+    /// overwrites locked cells.
+    pub fn set_cell_respecting_lock(&self, hash: u128, value: f64) -> bool {
+        let shard = self.get_shard(hash);
+        let slot = read_lock(&shard.index_map).get(&hash).copied();
+        // Held across the write so the cell cannot be locked between the check and the write.
+        // `set_locked` never holds `index_map` while taking this lock, so the order is safe.
+        let locked = read_lock(&shard.locked);
+        if slot.is_some_and(|idx| bit_is_set(&locked, idx)) {
+            return false;
+        }
+        self.set_cell(hash, value);
+        true
+    }
+
+    /// Locks or unlocks a stored cell against `set_cell_respecting_lock`.
+    /// Returns false (and does nothing) if the cell has never been set.
+    pub fn set_locked(&self, hash: u128, locked: bool) -> bool {
+        let shard = self.get_shard(hash);
+        let Some(idx) = read_lock(&shard.index_map).get(&hash).copied() else {
+            return false;
+        };
+        set_bit(&mut write_lock(&shard.locked), idx, locked);
+        true
+    }
+
+    pub fn is_locked(&self, hash: u128) -> bool {
+        let shard = self.get_shard(hash);
+        let Some(idx) = read_lock(&shard.index_map).get(&hash).copied() else {
+            return false;
+        };
+        bit_is_set(&read_lock(&shard.locked), idx)
+    }
+
+    /// Records `clock` as the cell's last clocked write without comparing it to the stored
+    /// one, for loaders that have already settled the ordering. None clears the stored clock,
+    /// for a write that carried none.
+    pub(crate) fn store_clock(&self, hash: u128, clock: Option<VectorClock>) {
+        let mut clocks = write_lock(&self.get_shard(hash).clocks);
+        match clock {
+            Some(clock) => clocks.insert(hash, clock),
+            None => clocks.remove(&hash),
+        };
+    }
+
+    /// Clock of the last clocked write to the cell, if any.
+    pub fn get_clock(&self, hash: u128) -> Option<VectorClock> {
+        read_lock(&self.get_shard(hash).clocks).get(&hash).cloned()
+    }
+
+    /// The source system that produced the cell, or None if the cell is absent or was
+    /// written without provenance.
+    pub fn get_source(&self, hash: u128) -> Option<String> {
+        let shard = self.get_shard(hash);
+        let map = read_lock(&shard.index_map);
+        let &idx = map.get(&hash)?;
+        let id = read_lock(&shard.sources)[idx];
+        if id == NO_SOURCE {
+            return None;
+        }
+        read_lock(&self.sources).names.get(id as usize - 1).cloned()
+    }
+
+    fn intern_source(&self, source: &str) -> u32 {
+        if let Some(&id) = read_lock(&self.sources).ids.get(source) {
+            return id;
+        }
+        let mut interner = write_lock(&self.sources);
+        if let Some(&id) = interner.ids.get(source) {
+            return id;
+        }
+        interner.names.push(source.to_string());
+        let id = interner.names.len() as u32;
+        interner.ids.insert(source.to_string(), id);
+        id
+    }
+
+    fn set_cell_tagged(&self, hash: u128, value: f64, source: u32) -> usize {
+        let idx = self.store_value(hash, value, source);
+        // After the write, so a reader that sees the new version also sees the value
+        self.get_shard(hash).writes.fetch_add(1, Ordering::Release);
+        idx
+    }
+
+    fn store_value(&self, hash: u128, value: f64, source: u32) -> usize {
+        let shard = self.get_shard(hash);
+
         // Fast path: Check if exists (Read Lock)
         {
-            let map = shard.index_map.read().unwrap();
+            let map = read_lock(&shard.index_map);
             if let Some(&idx) = map.get(&hash) {
-                let mut vals = shard.values.write().unwrap();
+                let mut vals = write_lock(&shard.values);
+                let mut sources = write_lock(&shard.sources);
                 vals[idx] = value;
+                sources[idx] = source;
                 return idx;
             }
         }
 
         // Slow path: Insert new (Write Lock)
-        let mut map = shard.index_map.write().unwrap();
-        let mut vals = shard.values.write().unwrap();
+        let mut map = write_lock(&shard.index_map);
+        let mut vals = write_lock(&shard.values);
+        let mut sources = write_lock(&shard.sources);
 
         // Double check
         if let Some(&idx) = map.get(&hash) {
             vals[idx] = value;
+            sources[idx] = source;
             return idx;
         }
 
@@ -78,20 +274,105 @@ impl LatticeArena {
 
         let idx = vals.len();
         vals.push(value);
+        sources.push(source);
         map.insert(hash, idx);
         
         idx
     }
 
+    /// Writes a loaded cell with its source, clock and lock bit. If a cell is already stored
+    /// under `hash`, `settle` is first given its lock bit and clock and the write only happens
+    /// if it returns true. The check and the write are made under the shard's write locks, so
+    /// no other writer can land between them. Returns whether the cell was written.
+    pub(crate) fn load_cell(
+        &self,
+        hash: u128,
+        value: f64,
+        source: Option<&str>,
+        clock: Option<VectorClock>,
+        locked: bool,
+        settle: impl FnOnce(bool, Option<&VectorClock>) -> bool,
+    ) -> bool {
+        let source = source.map_or(NO_SOURCE, |s| self.intern_source(s));
+        let shard = self.get_shard(hash);
+        // The order `remove_cell` takes them in
+        let mut clocks = write_lock(&shard.clocks);
+        let mut locks = write_lock(&shard.locked);
+        let mut map = write_lock(&shard.index_map);
+        let mut vals = write_lock(&shard.values);
+        let mut sources = write_lock(&shard.sources);
+
+        let idx = match map.get(&hash).copied() {
+            Some(idx) => {
+                if !settle(bit_is_set(&locks, idx), clocks.get(&hash)) {
+                    return false;
+                }
+                vals[idx] = value;
+                sources[idx] = source;
+                idx
+            }
+            None => {
+                if vals.len() >= MAX_SHARD_CAPACITY {
+                    panic!("Circuit Breaker Tripped: Shard capacity exceeded {} cells. OOM Protection engaged.", MAX_SHARD_CAPACITY);
+                }
+                vals.push(value);
+                sources.push(source);
+                map.insert(hash, vals.len() - 1);
+                vals.len() - 1
+            }
+        };
+        // A winner without a clock must not inherit the loser's
+        match clock {
+            Some(clock) => clocks.insert(hash, clock),
+            None => clocks.remove(&hash),
+        };
+        set_bit(&mut locks, idx, locked);
+        shard.writes.fetch_add(1, Ordering::Release);
+        true
+    }
+
+    /// Deletes a numeric cell, returning its value, or None if it was never set. Its lock and
+    /// clock go with it. The shard's last cell moves into the freed slot, and finding it scans
+    /// the shard's index, so deletes suit scenario edits rather than bulk unloads.
+    pub fn remove_cell(&self, hash: u128) -> Option<f64> {
+        let shard = self.get_shard(hash);
+        // `clocks` and `locked` before `index_map`, the order the clocked and lock-respecting writes use
+        let mut clocks = write_lock(&shard.clocks);
+        let mut locked = write_lock(&shard.locked);
+        let mut map = write_lock(&shard.index_map);
+        let mut vals = write_lock(&shard.values);
+        let mut sources = write_lock(&shard.sources);
+
+        let idx = map.remove(&hash)?;
+        shard.writes.fetch_add(1, Ordering::Release);
+        let last = vals.len() - 1;
+        if idx != last {
+            if let Some(moved) = map.values_mut().find(|slot| **slot == last) {
+                *moved = idx;
+            }
+            let moved_locked = bit_is_set(&locked, last);
+            set_bit(&mut locked, idx, moved_locked);
+        }
+        set_bit(&mut locked, last, false);
+        sources.swap_remove(idx);
+        clocks.remove(&hash);
+        Some(vals.swap_remove(idx))
+    }
+
     /// Retrieves a cell value. Returns 0.0 if not found (sparse).
+    /// Hot path for arithmetic; use `get_cell_opt` where an absent cell must not count as zero.
     pub fn get_cell(&self, hash: u128) -> f64 {
+        self.get_cell_opt(hash).unwrap_or(0.0)
+    }
+
+    /// Retrieves a cell value, or None if the cell was never set
+    /// (distinguishes an empty cell from a stored 0.0).
+    pub fn get_cell_opt(&self, hash: u128) -> Option<f64> {
         let shard = self.get_shard(hash);
-        let map = shard.index_map.read().unwrap();
-        if let Some(&idx) = map.get(&hash) {
-            let vals = shard.values.read().unwrap();
-            return vals[idx]; // Safe because shard lock protects index bounds
-        }
-        0.0
+        let map = read_lock(&shard.index_map);
+        let &idx = map.get(&hash)?;
+        let vals = read_lock(&shard.values);
+        Some(vals[idx]) // Safe because shard lock protects index bounds
     }
 
     /// Returns a combined vector for SIMD processing (expensive copy, uses rayon).
@@ -101,22 +382,593 @@ impl LatticeArena {
         // Parallel implementation: Map-Reduce would be better here.
         let mut combined = Vec::new();
         for shard in &self.shards {
-            let vals = shard.values.read().unwrap();
+            let vals = read_lock(&shard.values);
             combined.extend_from_slice(&vals);
         }
         combined
     }
     
+    /// Snapshot of every numeric cell as parallel vectors: `values[i]` is stored at `keys[i]`.
+    /// Order: shards in `shard_index_of` order, and within a shard the order cells were first
+    /// written, the same order `get_vector` returns on an unchanged arena. A SIMD transform of
+    /// `values` can therefore be written back by zipping it with `keys`.
+    /// Each shard is read under its locks, so keys and values always agree; writes to other
+    /// shards during the call may or may not be included.
+    pub fn get_values_and_keys(&self) -> (Vec<u128>, Vec<f64>) {
+        let mut keys = Vec::new();
+        let mut values = Vec::new();
+        for shard in &self.shards {
+            let map = read_lock(&shard.index_map);
+            let vals = read_lock(&shard.values);
+            let offset = keys.len();
+            keys.resize(offset + vals.len(), 0);
+            for (&hash, &idx) in map.iter() {
+                keys[offset + idx] = hash;
+            }
+            values.extend_from_slice(&vals);
+        }
+        (keys, values)
+    }
+
+    /// Iterates over every stored (coordinate hash, value) pair.
+    /// Each shard is snapshotted under its read locks when the iterator reaches it, so the
+    /// result is consistent per shard but not a global point-in-time view of the arena.
+    pub fn iter_cells(&self) -> impl Iterator<Item = (u128, f64)> + '_ {
+        self.shards.iter().flat_map(|shard| {
+            let map = read_lock(&shard.index_map);
+            let vals = read_lock(&shard.values);
+            map.iter()
+                .map(|(&hash, &idx)| (hash, vals[idx]))
+                .collect::<Vec<_>>()
+        })
+    }
+
+    /// Iterates over every stored (coordinate hash, date millis) pair, with the same
+    /// per-shard snapshot semantics as `iter_cells`.
+    pub fn iter_dates(&self) -> impl Iterator<Item = (u128, i64)> + '_ {
+        self.shards.iter().flat_map(|shard| {
+            let map = read_lock(&shard.date_index);
+            let dates = read_lock(&shard.dates);
+            map.iter()
+                .map(|(&hash, &idx)| (hash, dates[idx]))
+                .collect::<Vec<_>>()
+        })
+    }
+
+    /// Persists every numeric and date cell to an MDF (Parquet) file conforming to `MoleculeSchema`.
+    /// Coordinate hashes are stored as `coordinate_hash_to_bytes` (16 bytes, big-endian) and
+    /// each cell's source system goes to `source_system` (the engine's own name when it has
+    /// none) and its vector clock to `causality_clock`; columns the arena does not track
+    /// (commentary, other rich types) are written as nulls.
+    pub fn persist(&self, path: &str) -> Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        let cells = self.stored_cells();
+
+        let mut batches = Vec::new();
+        for chunk in cells.chunks(PERSIST_BATCH_ROWS) {
+            batches.push(cells_to_batch(chunk, timestamp)?);
+        }
+        if batches.is_empty() {
+            batches.push(cells_to_batch(&[], timestamp)?);
+        }
+        write_mdf_arrow(path, &batches)
+    }
+
+    /// Every numeric and date cell as a single `MoleculeSchema` RecordBatch, laid out exactly as
+    /// `persist` writes it, for Arrow compute kernels and Flight `do_get`.
+    /// `coordinate_hash` is variable-length `Binary` holding 16 bytes per row rather than
+    /// `FixedSizeBinary(16)`, because that is the type `MoleculeSchema` declares and `load`,
+    /// `load_batch` and Flight clients read.
+    pub fn to_record_batch(&self) -> Result<RecordBatch> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        cells_to_batch(&self.stored_cells(), timestamp)
+    }
+
+    fn stored_cells(&self) -> Vec<StoredCell> {
+        self.iter_cells()
+            .map(|(hash, v)| StoredCell {
+                hash,
+                value: StoredValue::Number(v),
+                source: self.get_source(hash),
+                clock: self.get_clock(hash),
+            })
+            .chain(self.iter_dates().map(|(hash, d)| StoredCell { hash, value: StoredValue::Date(d), source: None, clock: None }))
+            .collect()
+    }
+
+    /// Loads an arena from an MDF file written by `persist`.
+    /// Rows with neither a numeric nor a date value are skipped; stored clocks are restored.
+    pub fn load(path: &str) -> Result<LatticeArena> {
+        let batches = read_mdf_arrow(path)?;
+        let rows = batches.iter().map(|b| b.num_rows()).sum();
+        let arena = LatticeArena::new(rows);
+
+        for batch in &batches {
+            let hashes = batch
+                .column_by_name("coordinate_hash")
+                .and_then(|c| c.as_any().downcast_ref::<BinaryArray>())
+                .ok_or_else(|| anyhow!("MDF file has no binary coordinate_hash column"))?;
+            let values = batch
+                .column_by_name("numeric_value")
+                .and_then(|c| c.as_any().downcast_ref::<Float64Array>())
+                .ok_or_else(|| anyhow!("MDF file has no Float64 numeric_value column"))?;
+            let dates = batch
+                .column_by_name("date_value")
+                .and_then(|c| c.as_any().downcast_ref::<Int64Array>());
+            let sources = batch
+                .column_by_name("source_system")
+                .and_then(|c| c.as_any().downcast_ref::<StringArray>());
+            let clocks = batch
+                .column_by_name("causality_clock")
+                .and_then(|c| c.as_any().downcast_ref::<BinaryArray>());
+
+            for row in 0..batch.num_rows() {
+                let date = dates.filter(|d| !d.is_null(row)).map(|d| d.value(row));
+                if values.is_null(row) && date.is_none() {
+                    continue;
+                }
+                let hash = coordinate_hash_from_bytes(hashes.value(row))
+                    .ok_or_else(|| anyhow!("coordinate_hash at row {} is not 16 bytes", row))?;
+                if !values.is_null(row) {
+                    match sources.map(|s| s.value(row)).filter(|s| *s != PERSIST_SOURCE_SYSTEM) {
+                        Some(source) => arena.set_cell_with_source(hash, values.value(row), source),
+                        None => arena.set_cell(hash, values.value(row)),
+                    };
+                    if let Some(clock) = clocks.filter(|c| !c.is_null(row)) {
+                        let clock = VectorClock::from_bytes(clock.value(row))
+                            .ok_or_else(|| anyhow!("causality_clock at row {} is malformed", row))?;
+                        arena.store_clock(hash, Some(clock));
+                    }
+                }
+                if let Some(date) = date {
+                    arena.set_date(hash, date);
+                }
+            }
+        }
+        Ok(arena)
+    }
+
     // Ultra Diamond: Rich Type Setters
     pub fn set_string(&self, hash: u128, val: String) -> usize {
         let shard = self.get_shard(hash);
-        let mut strs = shard.strings.write().unwrap();
+        let mut strs = write_lock(&shard.strings);
         let idx = strs.len();
         strs.push(val);
         idx
     }
 
+    /// Allocates or updates a date cell (Unix millis) in the shard's date column.
     pub fn set_date(&self, hash: u128, val: i64) -> usize {
-        self.set_cell(hash, val as f64)
+        let shard = self.get_shard(hash);
+        let mut map = write_lock(&shard.date_index);
+        let mut dates = write_lock(&shard.dates);
+        shard.writes.fetch_add(1, Ordering::Release);
+        if let Some(&idx) = map.get(&hash) {
+            dates[idx] = val;
+            return idx;
+        }
+        let idx = dates.len();
+        dates.push(val);
+        map.insert(hash, idx);
+        idx
+    }
+
+    /// Retrieves a date cell (Unix millis), or None if no date was set at `hash`.
+    pub fn get_date(&self, hash: u128) -> Option<i64> {
+        let shard = self.get_shard(hash);
+        let map = read_lock(&shard.date_index);
+        let &idx = map.get(&hash)?;
+        Some(read_lock(&shard.dates)[idx])
+    }
+
+    /// Changes whenever a numeric or date cell is written or removed, so a cache derived from
+    /// cell values can tell it is stale by comparing versions. Counted per shard, so writers
+    /// do not contend on a shared counter; it says nothing about *which* cells changed.
+    pub fn version(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.writes.load(Ordering::Acquire)).sum()
+    }
+
+    /// Order-independent fingerprint of every numeric and date cell, for replicas to verify
+    /// they are in sync. Cells are hashed in coordinate order (FNV-1a 128 over the raw bits),
+    /// so the result does not depend on shard layout or insertion order.
+    /// Taken shard by shard, like `iter_cells`; concurrent writes may or may not be included.
+    pub fn checksum(&self) -> u128 {
+        let mut cells: Vec<(u128, u8, u64)> = self
+            .iter_cells()
+            .map(|(hash, value)| (hash, 0, value.to_bits()))
+            .chain(self.iter_dates().map(|(hash, millis)| (hash, 1, millis as u64)))
+            .collect();
+        cells.sort_unstable();
+
+        cells.iter().fold(FNV_OFFSET_BASIS, |acc, (hash, kind, bits)| {
+            let acc = fnv1a(acc, &hash.to_be_bytes());
+            let acc = fnv1a(acc, &[*kind]);
+            fnv1a(acc, &bits.to_be_bytes())
+        })
+    }
+}
+
+fn bit_is_set(bitmap: &[u64], idx: usize) -> bool {
+    bitmap.get(idx / 64).is_some_and(|w| w & (1u64 << (idx % 64)) != 0)
+}
+
+fn set_bit(bitmap: &mut Vec<u64>, idx: usize, on: bool) {
+    let (word, bit) = (idx / 64, 1u64 << (idx % 64));
+    if on {
+        if bitmap.len() <= word {
+            bitmap.resize(word + 1, 0);
+        }
+        bitmap[word] |= bit;
+    } else if let Some(w) = bitmap.get_mut(word) {
+        *w &= !bit;
+    }
+}
+
+/// A persisted cell payload: exactly one of `numeric_value` / `date_value` is set per row.
+enum StoredValue {
+    Number(f64),
+    Date(i64),
+}
+
+/// One persisted row: a cell's payload with its provenance and causality clock.
+struct StoredCell {
+    hash: u128,
+    value: StoredValue,
+    source: Option<String>,
+    clock: Option<VectorClock>,
+}
+
+/// Builds one `MoleculeSchema` RecordBatch from stored cells.
+fn cells_to_batch(cells: &[StoredCell], timestamp: i64) -> Result<RecordBatch> {
+    let schema = MoleculeSchema::schema();
+    let rows = cells.len();
+    let hashes: Vec<[u8; 16]> = cells.iter().map(|c| coordinate_hash_to_bytes(c.hash)).collect();
+    let clocks = cells
+        .iter()
+        .map(|c| match &c.clock {
+            Some(clock) => clock.to_bytes().map(Some).ok_or_else(|| anyhow!("clock of cell {:032x} has an unencodable node name", c.hash)),
+            None => Ok(None),
+        })
+        .collect::<Result<Vec<Option<Vec<u8>>>>>()?;
+
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| -> ArrayRef {
+            match field.name().as_str() {
+                "coordinate_hash" => Arc::new(BinaryArray::from_iter_values(hashes.iter())),
+                "numeric_value" => Arc::new(Float64Array::from_iter(cells.iter().map(|c| match c.value {
+                    StoredValue::Number(n) => Some(n),
+                    StoredValue::Date(_) => None,
+                }))),
+                "date_value" => Arc::new(Int64Array::from_iter(cells.iter().map(|c| match c.value {
+                    StoredValue::Date(d) => Some(d),
+                    StoredValue::Number(_) => None,
+                }))),
+                "timestamp" => Arc::new(Int64Array::from(vec![timestamp; rows])),
+                "source_system" => Arc::new(StringArray::from_iter_values(
+                    cells.iter().map(|c| c.source.as_deref().unwrap_or(PERSIST_SOURCE_SYSTEM)),
+                )),
+                "causality_clock" => Arc::new(BinaryArray::from_iter(clocks.iter().map(|c| c.as_deref()))),
+                "security_mask" => Arc::new(UInt64Array::from(vec![0u64; rows])),
+                _ => new_null_array(field.data_type(), rows),
+            }
+        })
+        .collect();
+
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_poisoned_shard_lock_is_recovered() {
+        let arena = Arc::new(LatticeArena::new(16));
+        arena.set_cell(7, 42.0);
+
+        let writer = Arc::clone(&arena);
+        let result = thread::spawn(move || {
+            let shard = writer.get_shard(7);
+            let _map = shard.index_map.write().unwrap();
+            let _vals = shard.values.write().unwrap();
+            panic!("writer died mid-write");
+        })
+        .join();
+        assert!(result.is_err());
+        assert!(arena.get_shard(7).values.is_poisoned());
+
+        // Reads and writes on the poisoned shard keep working
+        assert_eq!(arena.get_cell(7), 42.0);
+        arena.set_cell(7, 43.0);
+        arena.set_cell(7 + SHARD_COUNT as u128, 1.0);
+        assert_eq!(arena.get_cell(7), 43.0);
+        assert_eq!(arena.get_cell(7 + SHARD_COUNT as u128), 1.0);
+    }
+
+    #[test]
+    fn test_iter_cells_yields_all_pairs() {
+        let arena = LatticeArena::new(16);
+        let expected: HashMap<u128, f64> = [(1, 10.0), (65, 20.0), (1_000_003, 30.0)].into_iter().collect();
+        for (&hash, &value) in &expected {
+            arena.set_cell(hash, value);
+        }
+
+        let cells: HashMap<u128, f64> = arena.iter_cells().collect();
+        assert_eq!(cells, expected);
+    }
+
+    #[test]
+    fn test_persist_and_load_round_trip() {
+        let arena = LatticeArena::new(1024);
+        for i in 0..500u128 {
+            arena.set_cell(i * 0x1_0000_0000_0000_0001, i as f64 * 1.5);
+        }
+        arena.set_date(7, i64::MAX - 1);
+        let mut clock = VectorClock::new();
+        clock.increment("node-1");
+        arena.store_clock(0, Some(clock.clone()));
+        let path = std::env::temp_dir().join(format!("arena_round_trip_{}.mdf", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        arena.persist(path).expect("persist failed");
+        // Persisting again replaces the file rather than rewriting it in place, so a reader
+        // holding the first one still sees all of it
+        let first = std::fs::File::open(path).unwrap();
+        let first_len = first.metadata().unwrap().len();
+        LatticeArena::new(16).persist(path).expect("persist failed");
+        assert_eq!(first.metadata().unwrap().len(), first_len);
+        arena.persist(path).expect("persist failed");
+        let loaded = LatticeArena::load(path).expect("load failed");
+        std::fs::remove_file(path).ok();
+
+        let original: HashMap<u128, f64> = arena.iter_cells().collect();
+        let reloaded: HashMap<u128, f64> = loaded.iter_cells().collect();
+        assert_eq!(reloaded, original);
+        assert_eq!(loaded.get_date(7), Some(i64::MAX - 1));
+        assert_eq!((loaded.get_clock(0), loaded.get_clock(1)), (Some(clock), None));
+    }
+
+    #[test]
+    fn test_record_batch_round_trips_cells() {
+        let arena = LatticeArena::new(64);
+        for i in 0..20u128 {
+            arena.set_cell(i << 100 | i, i as f64 * 0.5);
+        }
+        arena.set_cell_with_source(99, 7.0, "erp");
+        arena.set_date(5, 1_700_000_000_000);
+
+        let batch = arena.to_record_batch().expect("batch failed");
+        assert!(crate::mdf::reader::validate_schema(&batch.schema(), &MoleculeSchema::schema()).is_ok());
+        assert_eq!(batch.num_rows(), 22);
+
+        let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+        let hashes = column("coordinate_hash");
+        let hashes = hashes.as_any().downcast_ref::<BinaryArray>().unwrap();
+        let values = column("numeric_value");
+        let values = values.as_any().downcast_ref::<Float64Array>().unwrap();
+        let sources = column("source_system");
+        let sources = sources.as_any().downcast_ref::<StringArray>().unwrap();
+
+        let mut cells = HashMap::new();
+        for row in 0..batch.num_rows() {
+            let hash = coordinate_hash_from_bytes(hashes.value(row)).unwrap();
+            if values.is_null(row) {
+                assert_eq!(hash, 5); // The date cell
+                continue;
+            }
+            cells.insert(hash, values.value(row));
+            if hash == 99 {
+                assert_eq!(sources.value(row), "erp");
+            }
+        }
+        assert_eq!(cells, arena.iter_cells().collect::<HashMap<_, _>>());
+    }
+
+    #[test]
+    fn test_capacity_hint_avoids_reallocation() {
+        let mut hints = [0; SHARD_COUNT];
+        hints[3] = 1000;
+        let arena = LatticeArena::with_capacity_hints(&hints);
+        let shard = &arena.shards[3];
+        let values_cap = read_lock(&shard.values).capacity();
+        let map_cap = read_lock(&shard.index_map).capacity();
+
+        for i in 0..1000u128 {
+            let hash = 3 + i * SHARD_COUNT as u128;
+            assert_eq!(LatticeArena::shard_index_of(hash), 3);
+            arena.set_cell(hash, i as f64);
+        }
+
+        assert_eq!(read_lock(&shard.values).len(), 1000);
+        assert_eq!(read_lock(&shard.values).capacity(), values_cap);
+        assert_eq!(read_lock(&shard.index_map).capacity(), map_cap);
+    }
+
+    #[test]
+    fn test_default_split_rounds_up() {
+        let arena = LatticeArena::new(SHARD_COUNT * 10 + 1);
+        assert!(arena.shards.iter().all(|s| read_lock(&s.values).capacity() >= 11));
+    }
+
+    #[test]
+    fn test_get_cell_opt_distinguishes_empty_from_zero() {
+        let arena = LatticeArena::new(16);
+        arena.set_cell(1, 0.0);
+
+        assert_eq!(arena.get_cell_opt(1), Some(0.0));
+        assert_eq!(arena.get_cell_opt(2), None);
+        assert_eq!(arena.get_cell(2), 0.0);
+    }
+
+    #[test]
+    fn test_shard_index_of_is_mod_shard_count() {
+        let a = 0xDEAD_BEEF_u128;
+        let colliding = a + 5 * SHARD_COUNT as u128;
+        assert_eq!(LatticeArena::shard_index_of(a), LatticeArena::shard_index_of(colliding));
+        assert_ne!(LatticeArena::shard_index_of(a), LatticeArena::shard_index_of(a + 1));
+        assert_eq!(LatticeArena::shard_index_of(u128::MAX), SHARD_COUNT - 1);
+
+        let arena = LatticeArena::new(16);
+        arena.set_cell(a, 1.0);
+        arena.set_cell(colliding, 2.0);
+        let shard = &arena.shards[LatticeArena::shard_index_of(a)];
+        assert_eq!(read_lock(&shard.values).len(), 2);
+    }
+
+    #[test]
+    fn test_date_round_trip_keeps_full_i64_precision() {
+        let arena = LatticeArena::new(16);
+        let ts = (1_i64 << 53) + 1; // Not representable as f64
+        arena.set_date(42, ts);
+
+        assert_eq!(arena.get_date(42), Some(ts));
+        assert_eq!(arena.get_cell_opt(42), None); // Dates do not live in the numeric column
+        arena.set_date(42, ts + 1);
+        assert_eq!(arena.get_date(42), Some(ts + 1));
+        assert_eq!(arena.iter_dates().count(), 1);
+    }
+
+    #[test]
+    fn test_checksum_is_order_independent() {
+        let a = LatticeArena::new(16);
+        let b = LatticeArena::new(1024);
+        for i in 0..100u128 {
+            a.set_cell(i, i as f64);
+            b.set_cell(99 - i, (99 - i) as f64);
+        }
+        a.set_date(500, 1_700_000_000_000);
+        b.set_date(500, 1_700_000_000_000);
+        assert_eq!(a.checksum(), b.checksum());
+
+        b.set_cell(42, 42.5);
+        assert_ne!(a.checksum(), b.checksum());
+    }
+
+    #[test]
+    fn test_cell_source_survives_set_get_and_persist() {
+        let arena = LatticeArena::new(64);
+        arena.set_cell_with_source(1, 10.0, "erp");
+        arena.set_cell_with_source(2, 20.0, "crm");
+        arena.set_computed(3, 30.0);
+        arena.set_cell(4, 40.0);
+        assert_eq!(arena.get_source(1), Some("erp".to_string()));
+        assert_eq!(arena.get_source(2), Some("crm".to_string()));
+        assert_eq!(arena.get_source(3), Some(COMPUTED_SOURCE.to_string()));
+        assert_eq!(arena.get_source(4), None);
+        assert_eq!(arena.get_source(5), None);
+
+        let path = std::env::temp_dir().join(format!("arena_sources_{}.mdf", std::process::id()));
+        let path = path.to_str().unwrap();
+        arena.persist(path).expect("persist failed");
+        let loaded = LatticeArena::load(path).expect("load failed");
+        std::fs::remove_file(path).ok();
+        for hash in 1..=4 {
+            assert_eq!(loaded.get_source(hash), arena.get_source(hash));
+        }
+
+        // Overwriting without provenance clears the tag
+        arena.set_cell(1, 11.0);
+        assert_eq!(arena.get_source(1), None);
+    }
+
+    #[test]
+    fn test_clocked_writes_reject_stale_and_flag_concurrent() {
+        let arena = LatticeArena::new(16);
+        let mut v1 = VectorClock::new();
+        v1.increment("eu");
+        let mut v2 = v1.clone();
+        v2.increment("eu");
+
+        assert_eq!(arena.set_cell_with_clock(9, 2.0, &v2), ClockedWrite::Applied);
+        // A delayed older write is rejected, as is a replay of the current one
+        assert_eq!(arena.set_cell_with_clock(9, 1.0, &v1), ClockedWrite::Stale);
+        assert_eq!(arena.set_cell_with_clock(9, 1.0, &v2), ClockedWrite::Stale);
+        assert_eq!(arena.get_cell(9), 2.0);
+
+        // A write from another region that has not seen v2 conflicts with it
+        let mut other = v1.clone();
+        other.increment("us");
+        assert_eq!(arena.set_cell_with_clock(9, 3.0, &other), ClockedWrite::Concurrent);
+        assert_eq!(arena.get_cell(9), 2.0);
+
+        // Once the conflict is resolved under the merged clock, the write goes through
+        let mut resolved = v2.clone();
+        resolved.merge(&other);
+        resolved.increment("eu");
+        assert_eq!(arena.set_cell_with_clock(9, 3.0, &resolved), ClockedWrite::Applied);
+        assert_eq!(arena.get_cell(9), 3.0);
+        assert_eq!(arena.get_clock(9), Some(resolved));
+    }
+
+    #[test]
+    fn test_locked_cell_rejects_respecting_writes_only() {
+        let arena = LatticeArena::new(16);
+        arena.set_cell(5, 100.0);
+        arena.set_cell(6, 1.0);
+        assert!(!arena.set_locked(7, true), "absent cells cannot be locked");
+        assert!(arena.set_locked(5, true));
+        assert!(arena.is_locked(5) && !arena.is_locked(6));
+
+        assert!(!arena.set_cell_respecting_lock(5, 1.0));
+        assert_eq!(arena.get_cell(5), 100.0);
+        assert!(arena.set_cell_respecting_lock(6, 2.0));
+        assert!(arena.set_cell_respecting_lock(7, 3.0));
+        assert_eq!((arena.get_cell(6), arena.get_cell(7)), (2.0, 3.0));
+
+        // A forced write still goes through, and unlocking reopens the respecting path
+        arena.set_cell(5, 50.0);
+        assert_eq!(arena.get_cell(5), 50.0);
+        arena.set_locked(5, false);
+        assert!(arena.set_cell_respecting_lock(5, 1.0));
+        assert_eq!(arena.get_cell(5), 1.0);
+    }
+
+    #[test]
+    fn test_remove_cell_moves_last_slot_with_its_state() {
+        // Multiples of 64 share shard 0, so removing the first moves the last into its slot
+        let arena = LatticeArena::new(16);
+        arena.set_cell(0, 1.0);
+        arena.set_cell(64, 2.0);
+        arena.set_cell_with_source(128, 3.0, "erp");
+        arena.set_locked(128, true);
+
+        assert_eq!(arena.remove_cell(0), Some(1.0));
+        assert_eq!(arena.remove_cell(0), None);
+        assert_eq!(arena.get_cell_opt(0), None);
+        assert_eq!((arena.get_cell_opt(64), arena.get_cell_opt(128)), (Some(2.0), Some(3.0)));
+        assert!(arena.is_locked(128) && !arena.is_locked(64));
+        assert_eq!(arena.get_source(128).as_deref(), Some("erp"));
+
+        let (keys, values) = arena.get_values_and_keys();
+        assert_eq!((keys, values), (vec![128, 64], vec![3.0, 2.0]));
+
+        // A re-added cell starts unlocked in the freed tail slot
+        arena.set_cell(0, 4.0);
+        assert!(!arena.is_locked(0));
+    }
+
+    #[test]
+    fn test_values_and_keys_write_back_after_vector_transform() {
+        use crate::compute::simd::VectorOps;
+
+        let arena = LatticeArena::new(256);
+        for hash in 0..200u128 {
+            arena.set_cell(hash * 7919, hash as f64);
+        }
+
+        let (keys, values) = arena.get_values_and_keys();
+        assert_eq!(values, arena.get_vector());
+        let scaled = VectorOps::mul(&values, &vec![1.5; values.len()]);
+        for (&hash, &value) in keys.iter().zip(scaled.iter()) {
+            arena.set_cell(hash, value);
+        }
+
+        for hash in 0..200u128 {
+            assert_eq!(arena.get_cell(hash * 7919), hash as f64 * 1.5);
+        }
     }
 }
@@ -18,8 +18,9 @@ pub struct AttributionEngine;
 impl AttributionEngine {
     /// Analyzes a top-level variance and traverses the HierarchyResolver to find the Top N 
     /// statistical drivers that caused the variance, pulling live data from the LatticeArena.
-    pub fn calculate_drivers<'a, R: HierarchyResolver>(
-        resolver: &'a R,
+    #[allow(clippy::too_many_arguments)]
+    pub fn calculate_drivers<R: HierarchyResolver>(
+        resolver: &R,
         arena: &LatticeArena,
         dimension: &str,
         parent_member: &str,
@@ -31,14 +32,14 @@ impl AttributionEngine {
     ) -> Vec<VarianceDriver> {
         
         // 1. Get all immediate children from the metadata hierarchy
-        let children = resolver.get_children(dimension, parent_member);
+        let children = resolver.get_children(&dimension.into(), &parent_member.into());
         
         // 2. Query the LatticeArena for each child concurrently (Ultra-Diamond Rayon Parallelism)
         let mut drivers: Vec<VarianceDriver> = children.par_iter().map(|child| {
             // In a real system, the hash is a combination of the base hash and the child's ID.
             // For Phase 8.2 proof-of-concept, we simulate the specific hashes.
-            let hash_a = base_hash_a.wrapping_add(child.len() as u128); // dummy hash combinator
-            let hash_b = base_hash_b.wrapping_add(child.len() as u128);
+            let hash_a = base_hash_a.wrapping_add(child.as_str().len() as u128); // dummy hash combinator
+            let hash_b = base_hash_b.wrapping_add(child.as_str().len() as u128);
 
             let val_a = arena.get_cell(hash_a);
             let val_b = arena.get_cell(hash_b);
@@ -46,7 +47,7 @@ impl AttributionEngine {
 
             VarianceDriver {
                 dimension: dimension.to_string(),
-                member: child.clone(),
+                member: child.to_string(),
                 scenario_a_val: val_a,
                 scenario_b_val: val_b,
                 variance,
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+
+/// A vector clock: one logical counter per writer node. Comparing two clocks tells whether one
+/// write causally precedes the other or whether they happened concurrently (a conflict).
+/// This is what the MDF `causality_clock` column carries.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VectorClock {
+    counters: BTreeMap<String, u64>, // Absent nodes count as 0
+}
+
+impl VectorClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a new event on `node`.
+    pub fn increment(&mut self, node: &str) {
+        *self.counters.entry(node.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn get(&self, node: &str) -> u64 {
+        self.counters.get(node).copied().unwrap_or(0)
+    }
+
+    /// Takes the element-wise maximum, so the result has seen everything either clock has.
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (node, &count) in &other.counters {
+            let entry = self.counters.entry(node.clone()).or_insert(0);
+            *entry = (*entry).max(count);
+        }
+    }
+
+    /// Whether `self` causally precedes `other`: no counter is ahead of `other` and at least
+    /// one is behind.
+    pub fn happens_before(&self, other: &VectorClock) -> bool {
+        self.dominated_by(other) && self != other
+    }
+
+    /// Whether neither clock precedes the other (and they differ): the writes conflict.
+    pub fn concurrent(&self, other: &VectorClock) -> bool {
+        !self.dominated_by(other) && !other.dominated_by(self)
+    }
+
+    fn dominated_by(&self, other: &VectorClock) -> bool {
+        self.counters.iter().all(|(node, &count)| count <= other.get(node))
+    }
+
+    /// Encoding of the `causality_clock` column: per node, in name order, a big-endian u16
+    /// name length, the UTF-8 name and a big-endian u64 counter. The empty clock is no bytes.
+    /// None if a node name is longer than `u16::MAX` bytes and so cannot be encoded.
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        let mut bytes = Vec::new();
+        for (node, &count) in &self.counters {
+            bytes.extend_from_slice(&u16::try_from(node.len()).ok()?.to_be_bytes());
+            bytes.extend_from_slice(node.as_bytes());
+            bytes.extend_from_slice(&count.to_be_bytes());
+        }
+        Some(bytes)
+    }
+
+    /// Decodes `to_bytes`, or None if the bytes are truncated or a name is not UTF-8.
+    pub fn from_bytes(mut bytes: &[u8]) -> Option<VectorClock> {
+        let mut clock = VectorClock::new();
+        while !bytes.is_empty() {
+            let (len, rest) = bytes.split_first_chunk::<2>()?;
+            let len = u16::from_be_bytes(*len) as usize;
+            if rest.len() < len {
+                return None;
+            }
+            let (node, rest) = rest.split_at(len);
+            let (count, rest) = rest.split_first_chunk::<8>()?;
+            clock.counters.insert(std::str::from_utf8(node).ok()?.to_string(), u64::from_be_bytes(*count));
+            bytes = rest;
+        }
+        Some(clock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_ordering() {
+        let mut a = VectorClock::new();
+        a.increment("node-1");
+        let mut b = a.clone();
+        b.increment("node-2");
+
+        assert!(a.happens_before(&b));
+        assert!(!b.happens_before(&a));
+        assert!(!a.happens_before(&a));
+        assert!(!a.concurrent(&b));
+
+        let mut c = a.clone();
+        c.increment("node-3");
+        assert!(b.concurrent(&c));
+
+        b.merge(&c);
+        assert!(c.happens_before(&b));
+        assert_eq!((b.get("node-1"), b.get("node-2"), b.get("node-3")), (1, 1, 1));
+
+        let bytes = b.to_bytes().unwrap();
+        assert_eq!(VectorClock::from_bytes(&bytes), Some(b.clone()));
+        assert_eq!(VectorClock::from_bytes(&bytes[..5]), None);
+
+        let mut long = VectorClock::new();
+        long.increment(&"n".repeat(u16::MAX as usize + 1));
+        assert_eq!(long.to_bytes(), None);
+    }
+}
@@ -0,0 +1,308 @@
+//! Shared coordinate hashing for the LatticeArena.
+//! Every component that addresses a cell (compiler, slices, loaders) must go through
+//! `coordinate_hash` so that the same dimension=member tuple always lands on the same cell.
+
+use std::fmt;
+
+/// Name of a dimension (e.g. `Region`). Dimensions and members are distinct types so that
+/// passing them in the wrong order is a compile error rather than a silent empty lookup:
+///
+/// ```compile_fail
+/// use atom_engine::lattice::coordinate::{Dimension, Member};
+/// use atom_engine::lattice::metadata::{HierarchyResolver, MockHierarchyResolver};
+///
+/// let region = Dimension::from("Region");
+/// let parent = Member::from("North America");
+/// MockHierarchyResolver.get_children(&parent, &region);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Dimension(pub String);
+
+/// Name of a member within a dimension (e.g. `USA` in `Region`). See `Dimension`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Member(pub String);
+
+macro_rules! name_type {
+    ($name:ident) => {
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+
+            pub fn into_string(self) -> String {
+                self.0
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(name: &str) -> Self {
+                Self(name.to_string())
+            }
+        }
+
+        impl From<&String> for $name {
+            fn from(name: &String) -> Self {
+                Self(name.clone())
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(name: String) -> Self {
+                Self(name)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+name_type!(Dimension);
+name_type!(Member);
+
+/// Anything `coordinate_hash` accepts as a dimension: a `Dimension` or a plain string.
+/// `Member` does not implement it, so a typed pair cannot be passed the wrong way round.
+pub trait DimensionName {
+    fn dimension_name(&self) -> &str;
+}
+
+/// Anything `coordinate_hash` accepts as a member: a `Member` or a plain string.
+pub trait MemberName {
+    fn member_name(&self) -> &str;
+}
+
+impl DimensionName for Dimension {
+    fn dimension_name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl MemberName for Member {
+    fn member_name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl DimensionName for str {
+    fn dimension_name(&self) -> &str {
+        self
+    }
+}
+
+impl MemberName for str {
+    fn member_name(&self) -> &str {
+        self
+    }
+}
+
+impl DimensionName for String {
+    fn dimension_name(&self) -> &str {
+        self
+    }
+}
+
+impl MemberName for String {
+    fn member_name(&self) -> &str {
+        self
+    }
+}
+
+impl<T: DimensionName + ?Sized> DimensionName for &T {
+    fn dimension_name(&self) -> &str {
+        (**self).dimension_name()
+    }
+}
+
+impl<T: MemberName + ?Sized> MemberName for &T {
+    fn member_name(&self) -> &str {
+        (**self).member_name()
+    }
+}
+
+/// Dimension assigned to a bare `[Member]` reference in a formula.
+pub const DEFAULT_REF_DIMENSION: &str = "Measure";
+
+pub(crate) const FNV_OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+const FNV_PRIME: u128 = 0x0000000001000000000000000000013B;
+
+// Separators keep ("AB", "C") and ("A", "BC") from colliding.
+const MEMBER_SEPARATOR: u8 = 0x1F;
+const PAIR_SEPARATOR: u8 = 0x1E;
+
+pub(crate) fn fnv1a(mut hash: u128, bytes: &[u8]) -> u128 {
+    for &b in bytes {
+        hash ^= b as u128;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// The dimensions every cell of a grid is addressed by. A reference that resolves to a
+/// coordinate without one of them hashes to a cell that can never have been stored, so it
+/// would silently read as empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoordinateSpec {
+    dimensions: Vec<String>,
+}
+
+impl CoordinateSpec {
+    pub fn new(dimensions: &[&str]) -> Self {
+        Self { dimensions: dimensions.iter().map(|d| d.to_string()).collect() }
+    }
+
+    /// Required dimensions that `base` with `overrides` applied (see `overlay_hash`) lacks,
+    /// in spec order. Names are compared as given, as `coordinate_hash` hashes them.
+    pub fn missing(&self, base: &[(String, String)], overrides: &[(String, String)]) -> Vec<&str> {
+        self.dimensions
+            .iter()
+            .filter(|required| !base.iter().chain(overrides).any(|(d, _)| d == *required))
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// Normalizes a dimension or member name: surrounding whitespace is never significant, and
+/// `fold_case` additionally lowercases it for case-insensitive models.
+/// Case folding must be applied consistently: a model that compiles formulas with
+/// `CompilerOptions::fold_case` must also load its cells with folded member names.
+pub fn normalize_name(name: &str, fold_case: bool) -> String {
+    if fold_case {
+        name.trim().to_lowercase()
+    } else {
+        name.trim().to_string()
+    }
+}
+
+/// Computes the 128-bit coordinate hash of a cell from its dimension=member pairs (FNV-1a 128).
+/// Pairs are sorted by dimension first, so the hash does not depend on the order they are listed in.
+/// Names are hashed exactly as given, so callers normalize them where they are entered
+/// (the lexer, the compiler, `GridSlice`) with `normalize_name`.
+///
+/// ```compile_fail
+/// use atom_engine::lattice::coordinate::{coordinate_hash, Dimension, Member};
+///
+/// coordinate_hash(&[(Member::from("USA"), Dimension::from("Region"))]);
+/// ```
+pub fn coordinate_hash<D: DimensionName, M: MemberName>(pairs: &[(D, M)]) -> u128 {
+    let mut sorted: Vec<(&str, &str)> = pairs.iter().map(|(d, m)| (d.dimension_name(), m.member_name())).collect();
+    sorted.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut hash = FNV_OFFSET_BASIS;
+    for (dimension, member) in sorted {
+        hash = fnv1a(hash, dimension.as_bytes());
+        hash = fnv1a(hash, &[MEMBER_SEPARATOR]);
+        hash = fnv1a(hash, member.as_bytes());
+        hash = fnv1a(hash, &[PAIR_SEPARATOR]);
+    }
+    hash
+}
+
+/// Encodes a coordinate hash as the 16 bytes of the MDF `coordinate_hash` column.
+/// The byte order is fixed big-endian, so producers in other languages (Go, Python) and this
+/// reader agree regardless of platform: the hash's most significant byte comes first.
+pub fn coordinate_hash_to_bytes(hash: u128) -> [u8; 16] {
+    hash.to_be_bytes()
+}
+
+/// Decodes `coordinate_hash_to_bytes`, or None if `bytes` is not exactly 16 bytes long.
+/// Accepts both Binary and FixedSizeBinary(16) column values.
+pub fn coordinate_hash_from_bytes(bytes: &[u8]) -> Option<u128> {
+    Some(u128::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// Hashes `base` with `overrides` applied: an override replaces the base member of the same
+/// dimension, or adds the dimension if the base does not have it.
+/// This is how a formula reference like `[Revenue]` resolves relative to the cell being evaluated.
+pub fn overlay_hash(base: &[(String, String)], overrides: &[(String, String)]) -> u128 {
+    let mut pairs: Vec<(&str, &str)> = base
+        .iter()
+        .filter(|(d, _)| !overrides.iter().any(|(od, _)| od == d))
+        .map(|(d, m)| (d.as_str(), m.as_str()))
+        .collect();
+    pairs.extend(overrides.iter().map(|(d, m)| (d.as_str(), m.as_str())));
+    coordinate_hash(&pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coordinate_hash_is_order_independent() {
+        let a = coordinate_hash(&[("Region", "USA"), ("Time", "Jan")]);
+        let b = coordinate_hash(&[("Time", "Jan"), ("Region", "USA")]);
+        assert_eq!(a, b);
+
+        let c = coordinate_hash(&[("Region", "Canada"), ("Time", "Jan")]);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_overlay_hash_replaces_matching_dimension() {
+        let base = vec![
+            ("Region".to_string(), "USA".to_string()),
+            ("Measure".to_string(), "COGS".to_string()),
+        ];
+        let overrides = vec![("Measure".to_string(), "Revenue".to_string())];
+        assert_eq!(
+            overlay_hash(&base, &overrides),
+            coordinate_hash(&[("Region", "USA"), ("Measure", "Revenue")])
+        );
+    }
+
+    #[test]
+    fn test_names_hash_as_given() {
+        // Stored cells keep their hashes; padding is stripped on entry, not here
+        assert_ne!(coordinate_hash(&[(" Region ", " USA")]), coordinate_hash(&[("Region", "USA")]));
+        assert_ne!(coordinate_hash(&[("Region", "usa")]), coordinate_hash(&[("Region", "USA")]));
+        assert_eq!(normalize_name(" USA ", true), "usa");
+        assert_eq!(normalize_name(" USA ", false), "USA");
+    }
+
+    #[test]
+    fn test_typed_names_read_like_strings() {
+        use crate::lattice::metadata::{HierarchyResolver, MockHierarchyResolver};
+
+        let region = Dimension::from("Region");
+        let usa = Member::from("USA");
+        assert_eq!(coordinate_hash(&[(&region, &usa)]), coordinate_hash(&[("Region", "USA")]));
+        assert_eq!(
+            coordinate_hash(&[(region.clone(), usa.clone()), ("Time".into(), "Jan".into())]),
+            coordinate_hash(&[("Time", "Jan"), ("Region", "USA")])
+        );
+
+        let children = MockHierarchyResolver.get_children(&region, &"North America".into());
+        assert_eq!(children.first(), Some(&usa));
+        assert_eq!(MockHierarchyResolver.get_parent(&region, &usa).map(Member::into_string), Some("North America".to_string()));
+    }
+
+    #[test]
+    fn test_hash_bytes_are_big_endian() {
+        let hash = 0x0102030405060708090a0b0c0d0e0f10u128;
+        let bytes = coordinate_hash_to_bytes(hash);
+        assert_eq!(bytes, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
+        assert_eq!(coordinate_hash_from_bytes(&bytes), Some(hash));
+
+        let real = coordinate_hash(&[("Region", "USA")]);
+        assert_eq!(coordinate_hash_from_bytes(&coordinate_hash_to_bytes(real)), Some(real));
+        assert_eq!(coordinate_hash_from_bytes(&bytes[..15]), None);
+    }
+
+    #[test]
+    fn test_spec_reports_missing_dimensions() {
+        let spec = CoordinateSpec::new(&["Measure", "Region", "Time"]);
+        let base = vec![("Region".to_string(), "USA".to_string())];
+        let overrides = vec![("Measure".to_string(), "Revenue".to_string())];
+        assert_eq!(spec.missing(&base, &overrides), vec!["Time"]);
+
+        let base = vec![("Region".to_string(), "USA".to_string()), ("Time".to_string(), "Jan".to_string())];
+        assert!(spec.missing(&base, &overrides).is_empty());
+
+        // A padded name would hash to a different cell, so it does not count as present
+        let base = vec![(" Region".to_string(), "USA".to_string()), ("Time".to_string(), "Jan".to_string())];
+        assert_eq!(spec.missing(&base, &overrides), vec!["Region"]);
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::{anyhow, Result};
+use arrow::array::{Array, BinaryArray, BooleanArray, Float64Array, Int64Array, StringArray};
+use arrow::record_batch::RecordBatch;
+use crate::lattice::arena::{LatticeArena, PERSIST_SOURCE_SYSTEM};
+use crate::lattice::clock::VectorClock;
+use crate::lattice::coordinate::coordinate_hash_from_bytes;
+
+/// How `LatticeArena::load_batch` settles a row whose coordinate hash is already stored,
+/// whether by an earlier row of the same batch or by an earlier load.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionPolicy {
+    /// A locked cell beats an unlocked one; otherwise the row replaces the stored cell only if
+    /// its `causality_clock` is causally newer, and is dropped if it is older. Concurrent
+    /// clocks, or a missing clock on either side, are conflicts.
+    #[default]
+    Resolve,
+    /// Never replace a stored cell: every collision is a conflict.
+    Reject,
+}
+
+/// What `load_batch` did with a batch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoadReport {
+    /// Rows written to the arena, including collisions the row won.
+    pub loaded: usize,
+    /// Rows whose coordinate hash was already stored.
+    pub collisions: usize,
+    /// Hashes of collisions the policy could not settle, in row order. The stored cell was kept.
+    pub conflicts: Vec<u128>,
+}
+
+enum Resolution {
+    Keep,
+    Replace,
+    Conflict,
+}
+
+struct StoredState<'a> {
+    locked: bool,
+    clock: Option<&'a VectorClock>,
+}
+
+impl LatticeArena {
+    /// Ingests one `MoleculeSchema` batch, detecting rows that collide with a stored cell
+    /// instead of silently overwriting it (`set_cell` semantics), and settling them per
+    /// `policy`. Call it once per batch: collisions across batches are detected the same way.
+    /// A row's `causality_clock` and `is_locked` columns are stored with its value.
+    /// Each row is checked and written atomically (see `load_cell`), so a concurrent writer
+    /// cannot slip a cell in between: under `Reject` a stored cell is never replaced.
+    /// Date-only rows are written as `load` writes them, without collision checks.
+    pub fn load_batch(&self, batch: &RecordBatch, policy: CollisionPolicy) -> Result<LoadReport> {
+        let hashes = batch
+            .column_by_name("coordinate_hash")
+            .and_then(|c| c.as_any().downcast_ref::<BinaryArray>())
+            .ok_or_else(|| anyhow!("batch has no binary coordinate_hash column"))?;
+        let values = batch
+            .column_by_name("numeric_value")
+            .and_then(|c| c.as_any().downcast_ref::<Float64Array>())
+            .ok_or_else(|| anyhow!("batch has no Float64 numeric_value column"))?;
+        let dates = batch
+            .column_by_name("date_value")
+            .and_then(|c| c.as_any().downcast_ref::<Int64Array>());
+        let sources = batch
+            .column_by_name("source_system")
+            .and_then(|c| c.as_any().downcast_ref::<StringArray>());
+        let clocks = batch
+            .column_by_name("causality_clock")
+            .and_then(|c| c.as_any().downcast_ref::<BinaryArray>());
+        let locks = batch
+            .column_by_name("is_locked")
+            .and_then(|c| c.as_any().downcast_ref::<BooleanArray>());
+
+        let mut report = LoadReport::default();
+        for row in 0..batch.num_rows() {
+            let hash = coordinate_hash_from_bytes(hashes.value(row))
+                .ok_or_else(|| anyhow!("coordinate_hash at row {} is not 16 bytes", row))?;
+
+            if values.is_null(row) {
+                if let Some(date) = dates.filter(|d| !d.is_null(row)).map(|d| d.value(row)) {
+                    self.set_date(hash, date);
+                }
+                continue;
+            }
+
+            let clock = match clocks.filter(|c| !c.is_null(row)) {
+                Some(c) => Some(
+                    VectorClock::from_bytes(c.value(row))
+                        .ok_or_else(|| anyhow!("causality_clock at row {} is malformed", row))?,
+                ),
+                None => None,
+            };
+            let locked = locks.is_some_and(|l| !l.is_null(row) && l.value(row));
+
+            let source = sources.map(|s| s.value(row)).filter(|s| *s != PERSIST_SOURCE_SYSTEM);
+            let incoming = clock.clone();
+            let written = self.load_cell(hash, values.value(row), source, clock, locked, |stored_locked, stored_clock| {
+                report.collisions += 1;
+                let stored = StoredState { locked: stored_locked, clock: stored_clock };
+                match resolve(policy, &stored, locked, incoming.as_ref()) {
+                    Resolution::Replace => true,
+                    Resolution::Keep => false,
+                    Resolution::Conflict => {
+                        report.conflicts.push(hash);
+                        false
+                    }
+                }
+            });
+            if written {
+                report.loaded += 1;
+            }
+        }
+        Ok(report)
+    }
+}
+
+fn resolve(policy: CollisionPolicy, stored: &StoredState, locked: bool, clock: Option<&VectorClock>) -> Resolution {
+    if policy == CollisionPolicy::Reject {
+        return Resolution::Conflict;
+    }
+    if stored.locked != locked {
+        return if locked { Resolution::Replace } else { Resolution::Keep };
+    }
+    match (stored.clock, clock) {
+        (Some(stored), Some(incoming)) if stored.happens_before(incoming) => Resolution::Replace,
+        (Some(stored), Some(incoming)) if stored.concurrent(incoming) => Resolution::Conflict,
+        (Some(_), Some(_)) => Resolution::Keep,
+        _ => Resolution::Conflict,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use arrow::array::{new_null_array, ArrayRef, UInt64Array};
+    use crate::lattice::coordinate::coordinate_hash_to_bytes;
+    use crate::mdf::molecule::MoleculeSchema;
+
+    fn clock(counts: &[(&str, u64)]) -> VectorClock {
+        let mut clock = VectorClock::new();
+        for &(node, n) in counts {
+            for _ in 0..n {
+                clock.increment(node);
+            }
+        }
+        clock
+    }
+
+    fn batch(rows: &[(u128, f64, Option<VectorClock>, bool)]) -> RecordBatch {
+        let schema = MoleculeSchema::schema();
+        let columns = schema
+            .fields()
+            .iter()
+            .map(|field| -> ArrayRef {
+                match field.name().as_str() {
+                    "coordinate_hash" => {
+                        Arc::new(BinaryArray::from_iter_values(rows.iter().map(|r| coordinate_hash_to_bytes(r.0))))
+                    }
+                    "numeric_value" => Arc::new(Float64Array::from_iter_values(rows.iter().map(|r| r.1))),
+                    "causality_clock" => {
+                        Arc::new(BinaryArray::from_iter(rows.iter().map(|r| r.2.as_ref().and_then(VectorClock::to_bytes))))
+                    }
+                    "timestamp" => Arc::new(Int64Array::from(vec![0; rows.len()])),
+                    "source_system" => Arc::new(StringArray::from(vec!["erp"; rows.len()])),
+                    "security_mask" => Arc::new(UInt64Array::from(vec![0; rows.len()])),
+                    "is_locked" => Arc::new(BooleanArray::from_iter(rows.iter().map(|r| Some(r.3)))),
+                    _ => new_null_array(field.data_type(), rows.len()),
+                }
+            })
+            .collect();
+        RecordBatch::try_new(schema, columns).unwrap()
+    }
+
+    #[test]
+    fn test_duplicate_hashes_are_detected_and_resolved() {
+        let arena = LatticeArena::new(16);
+        let first = batch(&[
+            (1, 10.0, Some(clock(&[("a", 1)])), false),
+            (1, 11.0, Some(clock(&[("a", 2)])), false), // newer clock wins
+            (2, 20.0, None, true),
+            (2, 21.0, None, false), // the locked entry stays
+            (3, 30.0, Some(clock(&[("a", 1)])), false),
+            (3, 31.0, Some(clock(&[("b", 1)])), false), // concurrent: a conflict
+        ]);
+        let report = arena.load_batch(&first, CollisionPolicy::Resolve).unwrap();
+        assert_eq!(report, LoadReport { loaded: 4, collisions: 3, conflicts: vec![3] });
+        assert_eq!((arena.get_cell(1), arena.get_cell(2), arena.get_cell(3)), (11.0, 20.0, 30.0));
+        assert!(arena.is_locked(2));
+        assert_eq!(arena.get_source(2).as_deref(), Some("erp"));
+        assert_eq!(arena.get_clock(1), Some(clock(&[("a", 2)])));
+
+        // Across batches: a replayed older write is dropped, an unclocked one conflicts
+        let second = batch(&[(1, 12.0, Some(clock(&[("a", 1)])), false), (4, 40.0, None, false), (4, 41.0, None, false)]);
+        let report = arena.load_batch(&second, CollisionPolicy::Resolve).unwrap();
+        assert_eq!(report, LoadReport { loaded: 1, collisions: 2, conflicts: vec![4] });
+        assert_eq!((arena.get_cell(1), arena.get_cell(4)), (11.0, 40.0));
+
+        // A locked row without a clock replaces a clocked cell and drops its clock
+        let report = arena.load_batch(&batch(&[(1, 14.0, None, true)]), CollisionPolicy::Resolve).unwrap();
+        assert_eq!(report, LoadReport { loaded: 1, collisions: 1, conflicts: vec![] });
+        assert_eq!((arena.get_cell(1), arena.get_clock(1)), (14.0, None));
+        arena.set_locked(1, false);
+        arena.set_cell(1, 11.0);
+
+        let report = arena.load_batch(&batch(&[(1, 13.0, Some(clock(&[("a", 9)])), false)]), CollisionPolicy::Reject).unwrap();
+        assert_eq!(report, LoadReport { loaded: 0, collisions: 1, conflicts: vec![1] });
+        assert_eq!(arena.get_cell(1), 11.0);
+    }
+
+    #[test]
+    fn test_concurrent_reject_loads_never_replace() {
+        // Every loader races for the same cells; under Reject exactly one row per cell lands
+        let arena = Arc::new(LatticeArena::new(256));
+        let loaders = 8;
+        let reports: Vec<LoadReport> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..loaders)
+                .map(|loader| {
+                    let arena = Arc::clone(&arena);
+                    scope.spawn(move || {
+                        let rows: Vec<_> = (0..200u128).map(|hash| (hash, loader as f64, None, false)).collect();
+                        arena.load_batch(&batch(&rows), CollisionPolicy::Reject).unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(reports.iter().map(|r| r.loaded).sum::<usize>(), 200);
+        assert_eq!(reports.iter().map(|r| r.collisions).sum::<usize>(), 200 * (loaders - 1));
+        for report in &reports {
+            assert_eq!(report.conflicts.len(), report.collisions);
+        }
+    }
+}
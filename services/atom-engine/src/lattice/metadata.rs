@@ -1,39 +1,342 @@
+use std::collections::{HashMap, HashSet};
+use std::thread;
+use std::time::Duration;
+use anyhow::Result;
+use crate::lattice::coordinate::{Dimension, Member};
+
 /// Hierarchy Resolver Trait
 /// This trait allows the Compiler to resolve hierarchy relationships at compile time.
 /// It bridges the separation between the Compute Engine and the Metadata Store.
 pub trait HierarchyResolver {
     /// Returns the immediate children of a member.
     /// e.g. "North America" -> ["USA", "Canada", "Mexico"]
-    fn get_children(&self, dimension: &str, member: &str) -> Vec<String>;
+    fn get_children(&self, dimension: &Dimension, member: &Member) -> Vec<Member>;
+
+    /// Fallible `get_children`, for resolvers backed by a store that can fail (e.g. over the
+    /// network). The compiler expands hierarchies through it; in-memory resolvers keep the
+    /// default, which never fails.
+    fn try_get_children(&self, dimension: &Dimension, member: &Member) -> Result<Vec<Member>> {
+        Ok(self.get_children(dimension, member))
+    }
 
     /// Returns the parent of a member.
     /// e.g. "USA" -> "North America"
-    fn get_parent(&self, dimension: &str, member: &str) -> Option<String>;
+    fn get_parent(&self, dimension: &Dimension, member: &Member) -> Option<Member>;
+
+    /// Returns all descendants (recursive children), parents before their children.
+    fn get_descendants(&self, dimension: &Dimension, member: &Member) -> Vec<Member>;
+
+    /// Maps a display name (e.g. "United States") to the canonical member key ("US").
+    /// Returns None when `alias` is not a known alias; callers then use it verbatim.
+    fn resolve_alias(&self, _dimension: &Dimension, _alias: &str) -> Option<Member> {
+        None
+    }
+
+    /// Whether `member` (or an alias of it) exists in `dimension`.
+    /// Returns None when the resolver does not know the dimension at all.
+    fn member_exists(&self, _dimension: &Dimension, _member: &Member) -> Option<bool> {
+        None
+    }
+
+    /// Depth of `member` in its hierarchy (a root is 0), e.g. for indenting report rows.
+    /// None when the resolver knows the member does not exist, or its parent chain loops.
+    fn get_level(&self, dimension: &Dimension, member: &Member) -> Option<u32> {
+        if self.member_exists(dimension, member) == Some(false) {
+            return None;
+        }
+        let mut seen = HashSet::from([member.clone()]);
+        let mut current = member.clone();
+        let mut level = 0;
+        while let Some(parent) = self.get_parent(dimension, &current) {
+            if !seen.insert(parent.clone()) {
+                return None;
+            }
+            current = parent;
+            level += 1;
+        }
+        Some(level)
+    }
 
-    /// Returns all descendants (recursive children).
-    fn get_descendants(&self, dimension: &str, member: &str) -> Vec<String>;
+    /// Whether `member` has no children, so its value is loaded rather than aggregated.
+    fn is_leaf(&self, dimension: &Dimension, member: &Member) -> bool {
+        self.get_children(dimension, member).is_empty()
+    }
 }
 
 /// A Mock Resolver for testing and initial development.
 pub struct MockHierarchyResolver;
 
 impl HierarchyResolver for MockHierarchyResolver {
-    fn get_children(&self, _dimension: &str, member: &str) -> Vec<String> {
-        match member {
-            "North America" => vec!["USA".to_string(), "Canada".to_string(), "Mexico".to_string()],
-            "Europe" => vec!["UK".to_string(), "France".to_string(), "Germany".to_string()],
-            _ => vec![],
-        }
+    fn get_children(&self, _dimension: &Dimension, member: &Member) -> Vec<Member> {
+        let children: &[&str] = match member.as_str() {
+            "North America" => &["USA", "Canada", "Mexico"],
+            "Europe" => &["UK", "France", "Germany"],
+            _ => &[],
+        };
+        children.iter().map(|&c| c.into()).collect()
     }
 
-    fn get_parent(&self, _dimension: &str, member: &str) -> Option<String> {
-        match member {
-            "USA" => Some("North America".to_string()),
+    fn get_parent(&self, _dimension: &Dimension, member: &Member) -> Option<Member> {
+        match member.as_str() {
+            "USA" => Some("North America".into()),
             _ => None,
         }
     }
 
-    fn get_descendants(&self, dimension: &str, member: &str) -> Vec<String> {
+    fn get_descendants(&self, dimension: &Dimension, member: &Member) -> Vec<Member> {
         self.get_children(dimension, member) // Simple mock
     }
 }
+
+/// A Resolver backed by in-memory maps, for models loaded from the Metadata Store.
+/// Children keep their insertion order.
+#[derive(Default)]
+pub struct MapHierarchyResolver {
+    children: HashMap<(Dimension, Member), Vec<Member>>,
+    parents: HashMap<(Dimension, Member), Member>,
+    aliases: HashMap<(Dimension, String), Member>,
+}
+
+impl MapHierarchyResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `child` under `parent` in `dimension`.
+    pub fn add_child(&mut self, dimension: impl Into<Dimension>, parent: impl Into<Member>, child: impl Into<Member>) {
+        let (dimension, parent, child) = (dimension.into(), parent.into(), child.into());
+        self.children
+            .entry((dimension.clone(), parent.clone()))
+            .or_default()
+            .push(child.clone());
+        self.parents.insert((dimension, child), parent);
+    }
+
+    /// Registers `alias` as a display name for the canonical `member` key.
+    pub fn add_alias(&mut self, dimension: impl Into<Dimension>, alias: &str, member: impl Into<Member>) {
+        self.aliases
+            .insert((dimension.into(), alias.to_string()), member.into());
+    }
+}
+
+impl HierarchyResolver for MapHierarchyResolver {
+    fn get_children(&self, dimension: &Dimension, member: &Member) -> Vec<Member> {
+        self.children
+            .get(&(dimension.clone(), member.clone()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn get_parent(&self, dimension: &Dimension, member: &Member) -> Option<Member> {
+        self.parents
+            .get(&(dimension.clone(), member.clone()))
+            .cloned()
+    }
+
+    /// Depth-first pre-order: each member is followed by its whole subtree before its next
+    /// sibling, and siblings keep their insertion order, so the sequence is the same on every
+    /// run. A member reached twice (a cycle, or a child registered under two parents) is only
+    /// listed the first time, and `member` itself never is.
+    fn get_descendants(&self, dimension: &Dimension, member: &Member) -> Vec<Member> {
+        let mut descendants = Vec::new();
+        let mut seen = HashSet::from([member.clone()]);
+        let mut pending: Vec<Member> = self.get_children(dimension, member).into_iter().rev().collect();
+        while let Some(next) = pending.pop() {
+            if !seen.insert(next.clone()) {
+                continue;
+            }
+            pending.extend(self.get_children(dimension, &next).into_iter().rev());
+            descendants.push(next);
+        }
+        descendants
+    }
+
+    fn resolve_alias(&self, dimension: &Dimension, alias: &str) -> Option<Member> {
+        self.aliases
+            .get(&(dimension.clone(), alias.to_string()))
+            .cloned()
+    }
+
+    fn member_exists(&self, dimension: &Dimension, member: &Member) -> Option<bool> {
+        let known_dimension = self.children.keys().chain(self.parents.keys()).any(|(d, _)| d == dimension);
+        if !known_dimension {
+            return None;
+        }
+        let key = (dimension.clone(), member.clone());
+        let alias = (dimension.clone(), member.0.clone());
+        Some(self.children.contains_key(&key) || self.parents.contains_key(&key) || self.aliases.contains_key(&alias))
+    }
+}
+
+pub const DEFAULT_RESOLVER_RETRIES: usize = 3;
+const DEFAULT_RETRY_BASE_DELAY: Duration = Duration::from_millis(50);
+
+/// Wraps a resolver whose `try_get_children` can fail transiently, retrying a failed lookup
+/// up to `max_retries` times with exponential backoff (`base_delay`, then twice that, ...)
+/// before surfacing the last error. The infallible `get_children` reads as empty on failure.
+pub struct RetryingResolver<R> {
+    inner: R,
+    max_retries: usize,
+    base_delay: Duration,
+}
+
+impl<R: HierarchyResolver> RetryingResolver<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, max_retries: DEFAULT_RESOLVER_RETRIES, base_delay: DEFAULT_RETRY_BASE_DELAY }
+    }
+
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn with_base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+}
+
+impl<R: HierarchyResolver> HierarchyResolver for RetryingResolver<R> {
+    fn get_children(&self, dimension: &Dimension, member: &Member) -> Vec<Member> {
+        self.try_get_children(dimension, member).unwrap_or_default()
+    }
+
+    fn try_get_children(&self, dimension: &Dimension, member: &Member) -> Result<Vec<Member>> {
+        let mut delay = self.base_delay;
+        for _ in 0..self.max_retries {
+            if let Ok(children) = self.inner.try_get_children(dimension, member) {
+                return Ok(children);
+            }
+            thread::sleep(delay);
+            delay = delay.saturating_mul(2);
+        }
+        self.inner.try_get_children(dimension, member).map_err(|e| {
+            e.context(format!(
+                "children of {}={} unavailable after {} attempts",
+                dimension,
+                member,
+                self.max_retries + 1
+            ))
+        })
+    }
+
+    fn get_parent(&self, dimension: &Dimension, member: &Member) -> Option<Member> {
+        self.inner.get_parent(dimension, member)
+    }
+
+    fn get_descendants(&self, dimension: &Dimension, member: &Member) -> Vec<Member> {
+        self.inner.get_descendants(dimension, member)
+    }
+
+    fn resolve_alias(&self, dimension: &Dimension, alias: &str) -> Option<Member> {
+        self.inner.resolve_alias(dimension, alias)
+    }
+
+    fn member_exists(&self, dimension: &Dimension, member: &Member) -> Option<bool> {
+        self.inner.member_exists(dimension, member)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Fails the first `failures` lookups, then answers like the mock. Its infallible
+    /// `get_children` reads a failed lookup as no children.
+    struct FlakyResolver {
+        failures: usize,
+        calls: AtomicUsize,
+    }
+
+    impl HierarchyResolver for FlakyResolver {
+        fn get_children(&self, dimension: &Dimension, member: &Member) -> Vec<Member> {
+            self.try_get_children(dimension, member).unwrap_or_default()
+        }
+
+        fn try_get_children(&self, dimension: &Dimension, member: &Member) -> Result<Vec<Member>> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                bail!("metadata store unavailable");
+            }
+            Ok(MockHierarchyResolver.get_children(dimension, member))
+        }
+
+        fn get_parent(&self, _dimension: &Dimension, _member: &Member) -> Option<Member> {
+            None
+        }
+
+        fn get_descendants(&self, dimension: &Dimension, member: &Member) -> Vec<Member> {
+            self.get_children(dimension, member)
+        }
+    }
+
+    #[test]
+    fn test_map_descendants_are_depth_first_pre_order() {
+        let mut resolver = MapHierarchyResolver::new();
+        for (parent, child) in [
+            ("World", "Americas"),
+            ("World", "EMEA"),
+            ("Americas", "US"),
+            ("Americas", "CA"),
+            ("EMEA", "UK"),
+            ("US", "US-East"),
+            ("US", "US-West"),
+            ("UK", "London"),
+        ] {
+            resolver.add_child("Region", parent, child);
+        }
+        let names = |resolver: &MapHierarchyResolver, member: &str| -> Vec<String> {
+            resolver.get_descendants(&"Region".into(), &member.into()).into_iter().map(Member::into_string).collect()
+        };
+        assert_eq!(names(&resolver, "World"), ["Americas", "US", "US-East", "US-West", "CA", "EMEA", "UK", "London"]);
+        assert_eq!(names(&resolver, "US"), ["US-East", "US-West"]);
+        assert!(names(&resolver, "London").is_empty());
+
+        // A cycle back to an ancestor terminates, listing each member once
+        resolver.add_child("Region", "London", "World");
+        resolver.add_child("Region", "CA", "US");
+        assert_eq!(names(&resolver, "World"), ["Americas", "US", "US-East", "US-West", "CA", "EMEA", "UK", "London"]);
+    }
+
+    #[test]
+    fn test_map_level_and_leaf() {
+        let mut resolver = MapHierarchyResolver::new();
+        for (parent, child) in [("World", "Americas"), ("Americas", "US"), ("World", "EMEA")] {
+            resolver.add_child("Region", parent, child);
+        }
+        let region = Dimension::from("Region");
+        let level = |resolver: &MapHierarchyResolver, member: &str| resolver.get_level(&region, &member.into());
+        assert_eq!((level(&resolver, "World"), level(&resolver, "Americas"), level(&resolver, "US")), (Some(0), Some(1), Some(2)));
+        assert_eq!(level(&resolver, "Atlantis"), None);
+        assert!(resolver.is_leaf(&region, &"US".into()));
+        assert!(resolver.is_leaf(&region, &"EMEA".into()));
+        assert!(!resolver.is_leaf(&region, &"Americas".into()));
+
+        // A parent chain that loops has no level
+        resolver.add_child("Region", "US", "World");
+        assert_eq!(level(&resolver, "US"), None);
+    }
+
+    #[test]
+    fn test_retrying_resolver_recovers_from_transient_failures() {
+        let (region, na) = (Dimension::from("Region"), Member::from("North America"));
+        let flaky = |failures| FlakyResolver { failures, calls: AtomicUsize::new(0) };
+
+        let resolver = RetryingResolver::new(flaky(2)).with_base_delay(Duration::from_millis(1));
+        let children = resolver.try_get_children(&region, &na).expect("retries should recover");
+        assert_eq!(children.len(), 3);
+        assert_eq!(resolver.inner.calls.load(Ordering::SeqCst), 3);
+
+        // Derived lookups retry too, so a transient failure does not turn a parent into a leaf
+        let resolver = RetryingResolver::new(flaky(2)).with_base_delay(Duration::ZERO);
+        assert!(!resolver.is_leaf(&region, &na));
+
+        // Out of retries: the last error is surfaced, and the infallible lookup reads as empty
+        let resolver = RetryingResolver::new(flaky(usize::MAX)).with_max_retries(2).with_base_delay(Duration::ZERO);
+        let err = resolver.try_get_children(&region, &na).unwrap_err();
+        assert!(err.to_string().contains("after 3 attempts"), "{}", err);
+        assert_eq!(resolver.inner.calls.load(Ordering::SeqCst), 3);
+        assert!(resolver.get_children(&region, &na).is_empty());
+    }
+}
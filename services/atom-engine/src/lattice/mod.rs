@@ -2,3 +2,9 @@ pub mod arena;
 pub mod metadata;
 pub mod arena_stress;
 pub mod attribution;
+pub mod clock;
+pub mod ingest;
+pub mod coordinate;
+pub mod slice;
+pub mod snapshot;
+pub mod period;
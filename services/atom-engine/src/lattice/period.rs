@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+/// Period Resolver Trait
+/// Resolves relative moves along the time dimension (e.g. "Mar" shifted by -2 is "Jan").
+/// The VM uses it for time-series functions that read neighbouring periods of the current cell.
+pub trait PeriodResolver: Send + Sync {
+    /// Name of the time dimension in coordinates, e.g. "Time".
+    fn dimension(&self) -> &str;
+
+    /// Returns the period `offset` steps away from `period` (negative = earlier),
+    /// or None when the move leaves the series or `period` is unknown.
+    fn shift(&self, period: &str, offset: i64) -> Option<String>;
+
+    /// Periods in a calendar year (12 for months, 4 for quarters), which converts calendar
+    /// steps such as `[PrevQuarter]` into periods. None when the series is not calendar-based;
+    /// each calendar month is then one period.
+    fn periods_per_year(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// A resolver over an explicit, ordered list of period members.
+pub struct ListPeriodResolver {
+    dimension: String,
+    periods: Vec<String>,
+    index: HashMap<String, usize>,
+    periods_per_year: Option<u32>,
+}
+
+impl ListPeriodResolver {
+    pub fn new(dimension: &str, periods: Vec<String>) -> Self {
+        let index = periods
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.clone(), i))
+            .collect();
+        Self {
+            dimension: dimension.to_string(),
+            periods,
+            index,
+            periods_per_year: None,
+        }
+    }
+
+    /// Declares the list a calendar series with `periods_per_year` periods per year.
+    pub fn with_periods_per_year(mut self, periods_per_year: u32) -> Self {
+        self.periods_per_year = Some(periods_per_year);
+        self
+    }
+}
+
+impl PeriodResolver for ListPeriodResolver {
+    fn dimension(&self) -> &str {
+        &self.dimension
+    }
+
+    fn shift(&self, period: &str, offset: i64) -> Option<String> {
+        let pos = (*self.index.get(period)? as i64).checked_add(offset)?;
+        self.periods.get(usize::try_from(pos).ok()?).cloned()
+    }
+
+    fn periods_per_year(&self) -> Option<u32> {
+        self.periods_per_year
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_period_shift() {
+        let months = ["Jan", "Feb", "Mar"].iter().map(|m| m.to_string()).collect();
+        let resolver = ListPeriodResolver::new("Time", months);
+
+        assert_eq!(resolver.shift("Mar", -2), Some("Jan".to_string()));
+        assert_eq!(resolver.shift("Jan", 1), Some("Feb".to_string()));
+        assert_eq!(resolver.shift("Jan", -1), None);
+        assert_eq!(resolver.shift("Mar", 1), None);
+        assert_eq!(resolver.shift("Dec", 0), None);
+        assert_eq!((resolver.shift("Mar", i64::MAX), resolver.shift("Jan", i64::MIN)), (None, None));
+    }
+}
@@ -0,0 +1,258 @@
+use thiserror::Error;
+use crate::lattice::coordinate::{coordinate_hash, normalize_name};
+use crate::lattice::period::PeriodResolver;
+
+// Circuit Breaker: a slice larger than this is almost certainly a runaway request.
+pub const DEFAULT_MAX_SLICE_CELLS: usize = 10_000_000;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SliceError {
+    #[error("slice of {cells} cells exceeds the limit of {limit}")]
+    TooLarge { cells: usize, limit: usize },
+    #[error("slice cell count overflows usize")]
+    Overflow,
+    #[error("unknown period {0}")]
+    UnknownPeriod(String),
+    #[error("period range {start}..{end} is reversed: {end} comes before {start}")]
+    ReversedRange { start: String, end: String },
+}
+
+/// A GridSlice describes a sub-cube of the grid for batch recalculation:
+/// some dimensions are pinned to a single member, others iterate over a member list.
+/// Enumerating the slice yields the cartesian product of coordinate hashes.
+#[derive(Debug, Clone)]
+pub struct GridSlice {
+    fixed: Vec<(String, String)>,
+    iterating: Vec<(String, Vec<String>)>,
+    max_cells: usize,
+}
+
+impl Default for GridSlice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GridSlice {
+    pub fn new() -> Self {
+        Self {
+            fixed: Vec::new(),
+            iterating: Vec::new(),
+            max_cells: DEFAULT_MAX_SLICE_CELLS,
+        }
+    }
+
+    /// Pins `dimension` to a single `member` for every cell of the slice.
+    pub fn fix(mut self, dimension: &str, member: &str) -> Self {
+        self.fixed.push((normalize_name(dimension, false), normalize_name(member, false)));
+        self
+    }
+
+    /// Iterates `dimension` over `members`.
+    pub fn iterate(mut self, dimension: &str, members: Vec<String>) -> Self {
+        let members = members.iter().map(|m| normalize_name(m, false)).collect();
+        self.iterating.push((normalize_name(dimension, false), members));
+        self
+    }
+
+    /// Iterates the time dimension of `periods` over every period from `start` to `end`
+    /// inclusive, e.g. Jan..Mar is Jan, Feb, Mar. Fails if either period is unknown, if `end`
+    /// comes before `start`, or if the range alone has more periods than the cell limit.
+    pub fn time_range(self, periods: &dyn PeriodResolver, start: &str, end: &str) -> Result<Self, SliceError> {
+        for period in [start, end] {
+            if periods.shift(period, 0).is_none() {
+                return Err(SliceError::UnknownPeriod(period.to_string()));
+            }
+        }
+        // Walk forward and backward in lockstep so a reversed range is reported as such no
+        // matter how far apart the periods are; members past the limit are only counted.
+        let mut members = vec![start.to_string()];
+        let (mut ahead, mut behind) = (Some(start.to_string()), Some(start.to_string()));
+        let mut count = 1usize;
+        while ahead.as_deref() != Some(end) {
+            // Both periods are known, so running off the series means `end` lies before `start`
+            if ahead.is_none() || behind.as_deref() == Some(end) {
+                return Err(SliceError::ReversedRange { start: start.to_string(), end: end.to_string() });
+            }
+            ahead = ahead.and_then(|p| periods.shift(&p, 1));
+            behind = behind.and_then(|p| periods.shift(&p, -1));
+            if let Some(next) = &ahead {
+                count += 1;
+                if count <= self.max_cells {
+                    members.push(next.clone());
+                }
+            }
+        }
+        if count > self.max_cells {
+            return Err(SliceError::TooLarge { cells: count, limit: self.max_cells });
+        }
+        let dimension = periods.dimension().to_string();
+        Ok(self.iterate(&dimension, members))
+    }
+
+    /// Overrides the maximum number of cells the slice may enumerate.
+    pub fn with_max_cells(mut self, max_cells: usize) -> Self {
+        self.max_cells = max_cells;
+        self
+    }
+
+    /// Number of cells in the slice (product of the iterating member counts).
+    /// Fails if the slice would exceed the configured cell limit.
+    pub fn cell_count(&self) -> Result<usize, SliceError> {
+        let cells = self
+            .iterating
+            .iter()
+            .try_fold(1usize, |acc, (_, members)| acc.checked_mul(members.len()))
+            .ok_or(SliceError::Overflow)?;
+        if cells > self.max_cells {
+            return Err(SliceError::TooLarge { cells, limit: self.max_cells });
+        }
+        Ok(cells)
+    }
+
+    /// Dimension=member pairs of the `index`-th cell, in the same order `hashes` enumerates
+    /// (the last iterating dimension varies fastest). Random access lets callers split the
+    /// slice across threads without walking the odometer. `index` must be below `cell_count`.
+    pub fn coordinate_at(&self, mut index: usize) -> Vec<(String, String)> {
+        let mut positions = vec![0; self.iterating.len()];
+        for (pos, (_, members)) in self.iterating.iter().enumerate().rev() {
+            positions[pos] = index % members.len();
+            index /= members.len();
+        }
+
+        let mut pairs = self.fixed.clone();
+        for ((dimension, members), &i) in self.iterating.iter().zip(positions.iter()) {
+            pairs.push((dimension.clone(), members[i].clone()));
+        }
+        pairs
+    }
+
+    /// Returns an iterator over the coordinate hashes of every cell in the slice.
+    /// Fails up-front if the slice would exceed the configured cell limit.
+    pub fn hashes(&self) -> Result<GridSliceIter<'_>, SliceError> {
+        let cells = self.cell_count()?;
+        Ok(GridSliceIter {
+            slice: self,
+            cursor: vec![0; self.iterating.len()],
+            remaining: cells,
+        })
+    }
+}
+
+/// Odometer-style iterator over a GridSlice. The last iterating dimension varies fastest.
+pub struct GridSliceIter<'a> {
+    slice: &'a GridSlice,
+    cursor: Vec<usize>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for GridSliceIter<'a> {
+    type Item = u128;
+
+    fn next(&mut self) -> Option<u128> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let mut pairs: Vec<(&str, &str)> = self
+            .slice
+            .fixed
+            .iter()
+            .map(|(d, m)| (d.as_str(), m.as_str()))
+            .collect();
+        for ((dimension, members), &i) in self.slice.iterating.iter().zip(self.cursor.iter()) {
+            pairs.push((dimension.as_str(), members[i].as_str()));
+        }
+        let hash = coordinate_hash(&pairs);
+
+        // Advance the odometer
+        for (pos, (_, members)) in self.slice.iterating.iter().enumerate().rev() {
+            self.cursor[pos] += 1;
+            if self.cursor[pos] < members.len() {
+                break;
+            }
+            self.cursor[pos] = 0;
+        }
+
+        Some(hash)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for GridSliceIter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use crate::lattice::period::ListPeriodResolver;
+
+    #[test]
+    fn test_slice_enumerates_cartesian_product() {
+        let slice = GridSlice::new()
+            .fix("Scenario", "Actual")
+            .iterate("Region", vec!["USA".to_string(), "Canada".to_string()])
+            .iterate("Time", vec!["Jan".to_string(), "Feb".to_string(), "Mar".to_string()]);
+
+        let hashes: Vec<u128> = slice.hashes().unwrap().collect();
+        assert_eq!(hashes.len(), 6);
+
+        let distinct: HashSet<u128> = hashes.iter().copied().collect();
+        assert_eq!(distinct.len(), 6);
+
+        let expected = coordinate_hash(&[("Scenario", "Actual"), ("Region", "Canada"), ("Time", "Feb")]);
+        assert!(distinct.contains(&expected));
+
+        // Random access agrees with the odometer order
+        for (i, &hash) in hashes.iter().enumerate() {
+            let pairs = slice.coordinate_at(i);
+            let refs: Vec<(&str, &str)> = pairs.iter().map(|(d, m)| (d.as_str(), m.as_str())).collect();
+            assert_eq!(coordinate_hash(&refs), hash);
+        }
+    }
+
+    #[test]
+    fn test_time_range_expands_periods() {
+        let months = ["Dec", "Jan", "Feb", "Mar", "Apr"].iter().map(|m| m.to_string()).collect();
+        let periods = ListPeriodResolver::new("Time", months);
+
+        let slice = GridSlice::new().fix("Measure", "Revenue").time_range(&periods, "Jan", "Mar").unwrap();
+        assert_eq!(slice.cell_count(), Ok(3));
+        let times: Vec<String> = (0..3).map(|i| slice.coordinate_at(i)[1].1.clone()).collect();
+        assert_eq!(times, ["Jan", "Feb", "Mar"]);
+        assert_eq!(slice.coordinate_at(2), vec![("Measure".to_string(), "Revenue".to_string()), ("Time".to_string(), "Mar".to_string())]);
+        assert_eq!(GridSlice::new().time_range(&periods, "Feb", "Feb").unwrap().cell_count(), Ok(1));
+
+        let range = |start: &str, end: &str| GridSlice::new().time_range(&periods, start, end).err();
+        assert_eq!(range("Mar", "Jan"), Some(SliceError::ReversedRange { start: "Mar".to_string(), end: "Jan".to_string() }));
+        assert_eq!(range("Jan", "Jun"), Some(SliceError::UnknownPeriod("Jun".to_string())));
+        assert_eq!(range("Smarch", "Mar"), Some(SliceError::UnknownPeriod("Smarch".to_string())));
+        assert_eq!(
+            GridSlice::new().with_max_cells(2).time_range(&periods, "Dec", "Apr").err(),
+            Some(SliceError::TooLarge { cells: 5, limit: 2 })
+        );
+        assert_eq!(
+            GridSlice::new().with_max_cells(2).time_range(&periods, "Apr", "Dec").err(),
+            Some(SliceError::ReversedRange { start: "Apr".to_string(), end: "Dec".to_string() })
+        );
+    }
+
+    #[test]
+    fn test_slice_rejects_oversized_product() {
+        let members: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        let slice = GridSlice::new()
+            .iterate("A", members.clone())
+            .iterate("B", members)
+            .with_max_cells(1000);
+
+        assert_eq!(
+            slice.hashes().err(),
+            Some(SliceError::TooLarge { cells: 10_000, limit: 1000 })
+        );
+        assert_eq!(slice.cell_count(), Err(SliceError::TooLarge { cells: 10_000, limit: 1000 }));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::lattice::arena::LatticeArena;
+
+/// Point-in-time copy of an arena's numeric cells, for comparing a baseline with a scenario.
+/// Cells are kept sorted by coordinate hash, so two snapshots diff in one merged pass.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ArenaSnapshot {
+    cells: Vec<(u128, f64)>,
+}
+
+/// One cell that differs between two snapshots: added (`before` is None), removed (`after`
+/// is None) or changed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellDiff {
+    pub hash: u128,
+    pub before: Option<f64>,
+    pub after: Option<f64>,
+}
+
+impl LatticeArena {
+    /// Copies every numeric cell. Taken shard by shard, like `iter_cells`.
+    pub fn snapshot(&self) -> ArenaSnapshot {
+        let mut cells: Vec<(u128, f64)> = self.iter_cells().collect();
+        cells.sort_unstable_by_key(|&(hash, _)| hash);
+        ArenaSnapshot { cells }
+    }
+}
+
+impl ArenaSnapshot {
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    pub fn get(&self, hash: u128) -> Option<f64> {
+        let idx = self.cells.binary_search_by_key(&hash, |&(h, _)| h).ok()?;
+        Some(self.cells[idx].1)
+    }
+
+    /// Every cell added, removed or changed going from `self` to `other`, in coordinate hash
+    /// order. Values are compared bitwise, so a NaN cell that stays NaN is unchanged.
+    pub fn diff(&self, other: &ArenaSnapshot) -> Vec<CellDiff> {
+        let mut diffs = Vec::new();
+        let (mut before, mut after) = (self.cells.iter().peekable(), other.cells.iter().peekable());
+        loop {
+            let diff = match (before.peek(), after.peek()) {
+                (None, None) => break,
+                (Some(&&(hash, value)), None) => {
+                    before.next();
+                    CellDiff { hash, before: Some(value), after: None }
+                }
+                (None, Some(&&(hash, value))) => {
+                    after.next();
+                    CellDiff { hash, before: None, after: Some(value) }
+                }
+                (Some(&&(b_hash, b_value)), Some(&&(a_hash, a_value))) => {
+                    if b_hash < a_hash {
+                        before.next();
+                        CellDiff { hash: b_hash, before: Some(b_value), after: None }
+                    } else if a_hash < b_hash {
+                        after.next();
+                        CellDiff { hash: a_hash, before: None, after: Some(a_value) }
+                    } else {
+                        before.next();
+                        after.next();
+                        if b_value.to_bits() == a_value.to_bits() {
+                            continue;
+                        }
+                        CellDiff { hash: b_hash, before: Some(b_value), after: Some(a_value) }
+                    }
+                }
+            };
+            diffs.push(diff);
+        }
+        diffs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lists_added_changed_and_removed_cells() {
+        let arena = LatticeArena::new(256);
+        for hash in 0..100u128 {
+            arena.set_cell(hash * 31, hash as f64);
+        }
+        arena.set_cell(7, f64::NAN);
+        let baseline = arena.snapshot();
+        assert_eq!(baseline.len(), 101);
+        assert_eq!(baseline.diff(&baseline), vec![]);
+
+        arena.set_cell(5000, 1.0); // Added
+        arena.set_cell(31 * 40, -40.0); // Changed
+        arena.remove_cell(31 * 90); // Removed
+        arena.set_cell(31 * 10, 10.0); // Rewritten with the same value
+        let scenario = arena.snapshot();
+
+        assert_eq!(
+            baseline.diff(&scenario),
+            vec![
+                CellDiff { hash: 31 * 40, before: Some(40.0), after: Some(-40.0) },
+                CellDiff { hash: 31 * 90, before: Some(90.0), after: None },
+                CellDiff { hash: 5000, before: None, after: Some(1.0) },
+            ]
+        );
+        assert_eq!(scenario.get(5000), Some(1.0));
+        assert_eq!(baseline.get(5000), None);
+    }
+}
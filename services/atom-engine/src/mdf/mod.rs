@@ -1,2 +1,3 @@
 pub mod molecule;
 pub mod reader;
+pub mod writer;
@@ -1,12 +1,24 @@
 
 use arrow::datatypes::{DataType, Field, Schema};
+use anyhow::{bail, Result};
 use std::sync::Arc;
 
 pub struct MoleculeSchema;
 
 impl MoleculeSchema {
+    /// The canonical molecule schema every deployment shares.
     pub fn schema() -> Arc<Schema> {
-        Arc::new(Schema::new(vec![
+        Arc::new(Schema::new(Self::base_fields()))
+    }
+
+    /// Starts a deployment-specific schema from the canonical fields.
+    pub fn builder() -> MoleculeSchemaBuilder {
+        MoleculeSchemaBuilder { fields: Self::base_fields() }
+    }
+
+    fn base_fields() -> Vec<Field> {
+        vec![
+            // 16 bytes, big-endian: see `coordinate_hash_to_bytes`
             Field::new("coordinate_hash", DataType::Binary, false),
             // Map is complex in Arrow, often represented as List of Structs. 
             // For simplicity in v1, we might treat custom_dimensions as List<Struct<Key, Value>>
@@ -30,6 +42,74 @@ impl MoleculeSchema {
             
             // Collision Resolution (Red Team Audit Response)
             Field::new("is_locked", DataType::Boolean, true),
-        ]))
+        ]
+    }
+}
+
+/// Extends the canonical molecule schema with deployment-specific columns (e.g. `scenario_id`).
+/// The extra columns come after the canonical ones, so positional readers of the base columns
+/// are unaffected. Validate files and batches against the built schema with
+/// `reader::validate_schema` (or `read_mdf_ipc_with_schema`).
+pub struct MoleculeSchemaBuilder {
+    fields: Vec<Field>,
+}
+
+impl MoleculeSchemaBuilder {
+    pub fn with_field(mut self, field: Field) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    /// Fails if an extra column reuses the name of another column.
+    pub fn build(self) -> Result<Arc<Schema>> {
+        for (i, field) in self.fields.iter().enumerate() {
+            if self.fields[..i].iter().any(|f| f.name() == field.name()) {
+                bail!("duplicate molecule column {}", field.name());
+            }
+        }
+        Ok(Arc::new(Schema::new(self.fields)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{new_null_array, ArrayRef, BinaryArray, Int64Array, StringArray, UInt64Array};
+    use arrow::record_batch::RecordBatch;
+    use crate::mdf::reader::validate_schema;
+
+    #[test]
+    fn test_builder_appends_extra_columns() {
+        let schema = MoleculeSchema::builder()
+            .with_field(Field::new("scenario_id", DataType::Utf8, false))
+            .build()
+            .unwrap();
+        assert_eq!(schema.fields().len(), MoleculeSchema::schema().fields().len() + 1);
+        assert!(MoleculeSchema::schema().field_with_name("scenario_id").is_err());
+
+        let columns: Vec<ArrayRef> = schema
+            .fields()
+            .iter()
+            .map(|field| -> ArrayRef {
+                match field.name().as_str() {
+                    "coordinate_hash" => Arc::new(BinaryArray::from_iter_values([[1u8; 16]])),
+                    "timestamp" => Arc::new(Int64Array::from(vec![0])),
+                    "source_system" => Arc::new(StringArray::from(vec!["erp"])),
+                    "security_mask" => Arc::new(UInt64Array::from(vec![0])),
+                    "scenario_id" => Arc::new(StringArray::from(vec!["Budget"])),
+                    _ => new_null_array(field.data_type(), 1),
+                }
+            })
+            .collect();
+        let batch = RecordBatch::try_new(Arc::clone(&schema), columns).unwrap();
+        assert!(validate_schema(&batch.schema(), &schema).is_ok());
+        // A plain molecule batch lacks the required extension column
+        assert!(validate_schema(&MoleculeSchema::schema(), &schema).is_err());
+
+        let wrong_type = Schema::new(vec![Field::new("scenario_id", DataType::Int64, false)]);
+        let batch = RecordBatch::try_new(Arc::new(wrong_type), vec![Arc::new(Int64Array::from(vec![1]))]).unwrap();
+        assert!(validate_schema(&batch.schema(), &schema).is_err());
+
+        assert!(MoleculeSchema::builder().with_field(Field::new("timestamp", DataType::Utf8, true)).build().is_err());
     }
 }
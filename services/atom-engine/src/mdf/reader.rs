@@ -1,10 +1,19 @@
 use std::fs::File;
+use std::io::{BufReader, Read};
 use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use arrow::datatypes::Schema;
+use arrow::ipc::reader::{FileReader, StreamReader};
 use arrow::record_batch::RecordBatch;
-use anyhow::Result;
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use memmap2::Mmap;
+use crate::mdf::molecule::MoleculeSchema;
 
-/// Reads an MDF (Parquet) file into a vector of Arrow RecordBatches.
-/// This uses Zero-Copy semantics where possible, mapping the file directly into memory.
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+const ARROW_FILE_MAGIC: &[u8] = b"ARROW1";
+
+/// Reads an MDF (Parquet) file into a vector of Arrow RecordBatches through buffered file I/O.
+/// See `read_mdf_arrow_mmap` for the memory-mapped variant.
 pub fn read_mdf_arrow(path: &str) -> Result<Vec<RecordBatch>> {
     let file = File::open(path)?;
     
@@ -19,3 +28,177 @@ pub fn read_mdf_arrow(path: &str) -> Result<Vec<RecordBatch>> {
     
     Ok(batches?)
 }
+
+/// Reads an MDF (Parquet) file by mapping it into memory instead of reading it through a file
+/// handle. Column chunks are sliced straight out of the mapping, so no copy of the file is made
+/// on the heap; compressed pages are still decompressed into fresh buffers.
+///
+/// # Safety
+///
+/// The file must not be truncated or modified in place while any returned batch is alive;
+/// doing so is undefined behaviour. `write_mdf_arrow` (and so `LatticeArena::persist`) replaces
+/// files by renaming a new one over them, which is safe, but other writers may not.
+pub unsafe fn read_mdf_arrow_mmap(path: &str) -> Result<Vec<RecordBatch>> {
+    let file = File::open(path)?;
+    // SAFETY: the caller guarantees the file is not modified while mapped; the mapping is
+    // read-only and outlives every batch via Bytes.
+    let mmap = Mmap::map(&file)?;
+    let builder = ParquetRecordBatchReaderBuilder::try_new(Bytes::from_owner(mmap))?;
+    let reader = builder.with_batch_size(8192).build()?;
+    let batches: Result<Vec<_>, _> = reader.collect();
+    Ok(batches?)
+}
+
+/// Number of rows in an MDF (Parquet) file, read from its footer without decoding any data.
+pub fn mdf_row_count(path: &str) -> Result<i64> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    Ok(builder.metadata().file_metadata().num_rows())
+}
+
+/// Reads an Arrow IPC file (`ARROW1` file format) or IPC stream into RecordBatches,
+/// rejecting files whose schema conflicts with `MoleculeSchema`.
+pub fn read_mdf_ipc(path: &str) -> Result<Vec<RecordBatch>> {
+    read_mdf_ipc_with_schema(path, &MoleculeSchema::schema())
+}
+
+/// Like `read_mdf_ipc`, for deployments with an extended schema (see `MoleculeSchemaBuilder`).
+pub fn read_mdf_ipc_with_schema(path: &str, expected: &Schema) -> Result<Vec<RecordBatch>> {
+    let batches: Result<Vec<_>, _> = if starts_with(path, ARROW_FILE_MAGIC)? {
+        let reader = FileReader::try_new(BufReader::new(File::open(path)?), None)?;
+        validate_schema(&reader.schema(), expected)?;
+        reader.collect()
+    } else {
+        let reader = StreamReader::try_new(BufReader::new(File::open(path)?), None)?;
+        validate_schema(&reader.schema(), expected)?;
+        reader.collect()
+    };
+    Ok(batches?)
+}
+
+/// Reads an MDF file in any supported format, detected from its magic bytes:
+/// Parquet (`PAR1`), otherwise Arrow IPC file or stream.
+pub fn read_mdf(path: &str) -> Result<Vec<RecordBatch>> {
+    if starts_with(path, PARQUET_MAGIC)? {
+        read_mdf_arrow(path)
+    } else {
+        read_mdf_ipc(path)
+    }
+}
+
+fn starts_with(path: &str, magic: &[u8]) -> Result<bool> {
+    let mut header = vec![0u8; magic.len()];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut header) {
+        Ok(()) => Ok(header == magic),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Checks that every required (non-nullable) column of `expected` is present in `schema` and
+/// that its columns carry their expected types. Columns `expected` does not know are allowed.
+pub fn validate_schema(schema: &Schema, expected: &Schema) -> Result<()> {
+    for expected in expected.fields() {
+        match schema.field_with_name(expected.name()) {
+            Ok(field) if field.data_type() != expected.data_type() => bail!(
+                "column {} has type {}, expected {}",
+                expected.name(),
+                field.data_type(),
+                expected.data_type()
+            ),
+            Ok(_) => {}
+            Err(_) if !expected.is_nullable() => bail!("missing required column {}", expected.name()),
+            Err(_) => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use arrow::array::{new_null_array, ArrayRef, BinaryArray, Float64Array, Int64Array, StringArray, UInt64Array};
+    use arrow::ipc::writer::{FileWriter, StreamWriter};
+    use crate::mdf::writer::write_mdf_arrow;
+
+    fn molecule_batch() -> RecordBatch {
+        let schema = MoleculeSchema::schema();
+        let columns = schema
+            .fields()
+            .iter()
+            .map(|field| -> ArrayRef {
+                match field.name().as_str() {
+                    "coordinate_hash" => Arc::new(BinaryArray::from_iter_values([[1u8; 16], [2u8; 16]])),
+                    "numeric_value" => Arc::new(Float64Array::from(vec![Some(1.5), None])),
+                    "timestamp" => Arc::new(Int64Array::from(vec![10, 20])),
+                    "source_system" => Arc::new(StringArray::from(vec!["erp", "crm"])),
+                    "security_mask" => Arc::new(UInt64Array::from(vec![0, 7])),
+                    _ => new_null_array(field.data_type(), 2),
+                }
+            })
+            .collect();
+        RecordBatch::try_new(schema, columns).unwrap()
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_ipc_file_and_stream_round_trip() {
+        let batch = molecule_batch();
+
+        let file_path = temp_path("molecules.arrow");
+        let mut writer = FileWriter::try_new(File::create(&file_path).unwrap(), &batch.schema()).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+
+        let stream_path = temp_path("molecules.arrows");
+        let mut writer = StreamWriter::try_new(File::create(&stream_path).unwrap(), &batch.schema()).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+
+        let parquet_path = temp_path("molecules.parquet");
+        write_mdf_arrow(&parquet_path, std::slice::from_ref(&batch)).unwrap();
+
+        for path in [&file_path, &stream_path, &parquet_path] {
+            let batches = read_mdf(path).unwrap();
+            std::fs::remove_file(path).ok();
+            assert_eq!(batches, vec![batch.clone()]);
+        }
+    }
+
+    #[test]
+    fn test_ipc_rejects_conflicting_schema() {
+        let schema = Arc::new(Schema::new(vec![arrow::datatypes::Field::new(
+            "numeric_value",
+            arrow::datatypes::DataType::Utf8,
+            true,
+        )]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(StringArray::from(vec!["x"]))]).unwrap();
+
+        let path = temp_path("bad.arrow");
+        let mut writer = FileWriter::try_new(File::create(&path).unwrap(), &schema).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+
+        let result = read_mdf_ipc(&path);
+        std::fs::remove_file(&path).ok();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_mmap_read_matches_buffered_read() {
+        let batch = molecule_batch();
+        let path = temp_path("molecules_mmap.parquet");
+        write_mdf_arrow(&path, &[batch.clone(), batch]).unwrap();
+
+        // SAFETY: nothing else writes this test's file
+        let mapped = unsafe { read_mdf_arrow_mmap(&path) };
+        let buffered = read_mdf_arrow(&path);
+        std::fs::remove_file(&path).ok();
+        assert_eq!(mapped.unwrap(), buffered.unwrap());
+    }
+}
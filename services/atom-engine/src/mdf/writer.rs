@@ -0,0 +1,52 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use arrow::record_batch::RecordBatch;
+use anyhow::{anyhow, Result};
+
+// Distinguishes temporary files of concurrent writes to the same path within a process.
+static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+/// Writes Arrow RecordBatches to an MDF (Parquet) file, replacing any existing file.
+/// All batches must share the schema of the first one (normally `MoleculeSchema::schema()`).
+///
+/// The file is written and fsynced under a temporary name in the same directory, then renamed
+/// over `path`, so readers (including memory maps) never see a partially written file and a
+/// failed write leaves the previous file in place.
+pub fn write_mdf_arrow(path: &str, batches: &[RecordBatch]) -> Result<()> {
+    let schema = batches
+        .first()
+        .map(|b| b.schema())
+        .ok_or_else(|| anyhow!("cannot write an MDF file without record batches"))?;
+
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+
+    let target = Path::new(path);
+    let name = target.file_name().ok_or_else(|| anyhow!("{} does not name a file", path))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".{}.{}.tmp", std::process::id(), NEXT_TEMP.fetch_add(1, Ordering::Relaxed)));
+    let temp = target.with_file_name(temp_name);
+
+    let written = (|| -> Result<()> {
+        let file = File::create(&temp)?;
+        let mut writer = ArrowWriter::try_new(file.try_clone()?, schema, Some(props))?;
+        for batch in batches {
+            writer.write(batch)?;
+        }
+        writer.close()?;
+        file.sync_all()?;
+        fs::rename(&temp, target)?;
+        Ok(())
+    })();
+    if written.is_err() {
+        fs::remove_file(&temp).ok();
+    }
+    written
+}